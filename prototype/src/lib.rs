@@ -1,4 +1 @@
-pub mod scenes;
 pub mod ui;
-
-pub use scenes::*;
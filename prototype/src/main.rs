@@ -1,6 +1,11 @@
 use ash::vk;
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use prototype::ui::U32Checkbox;
+use utopian::renderers::bloom::BloomSettings;
+use utopian::renderers::present::{TonemapSettings, Tonemapper};
+
+// Index in the scene file of the model that is moved with the gizmo
+const SELECTED_MODEL: usize = 0;
 
 #[derive(Clone, Debug, Copy, PartialEq)]
 enum RenderGraphMode {
@@ -20,6 +25,13 @@ struct Application {
     ui: prototype::ui::Ui,
     fps_timer: utopian::FpsTimer,
     shader_watcher: utopian::DirectoryWatcher,
+    scene_watcher: utopian::DirectoryWatcher,
+    scene_path: String,
+    // The scene that is being loaded in the background
+    pending_scene: Option<utopian::scene_loader::PendingScene>,
+    // Root of the first instance of `SELECTED_MODEL`, none until it is loaded
+    selected_node: Option<utopian::NodeId>,
+    asset_loader: utopian::AssetLoader,
     current_frame: usize,      // Should be in VulkanBase
     num_frames_in_flight: u32, // Should be in VulkanBase
     render_graph_mode: RenderGraphMode,
@@ -61,21 +73,28 @@ impl Application {
             eye_pos: camera.get_position(),
             samples_per_frame: 1,
             total_samples: 0,
+            jitter: Vec2::ZERO,
             num_bounces: 5,
             viewport_width: width,
             viewport_height: height,
             time: 0.0,
             num_lights: 0,
+            aperture_diameter: 0.0,
+            focal_length: camera.get_focal_length(),
+            sensor_height: camera.get_sensor_height(),
             sun_dir: Vec3::new(0.0, 0.9, 0.15).normalize(),
             shadows_enabled: 1,
             ssao_enabled: 1,
             fxaa_enabled: 1,
+            taa_enabled: 1,
+            dof_enabled: 0,
             cubemap_enabled: 1,
             ibl_enabled: 1,
             sky_enabled: 1,
             sun_shadow_enabled: 1,
             lights_enabled: 1,
             max_num_lights_used: 10000,
+            light_clusters_debug: 0,
             marching_cubes_enabled: 0,
             temporal_reuse_enabled: 1,
             spatial_reuse_enabled: 1,
@@ -95,6 +114,11 @@ impl Application {
         );
 
         let shader_watcher = utopian::DirectoryWatcher::new("utopian/shaders/");
+        let scene_watcher = utopian::DirectoryWatcher::new("prototype/data/scenes/");
+
+        let scene_path = std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| String::from("prototype/data/scenes/sponza.ron"));
 
         let raytracing_supported = base.device.raytracing_supported;
         let num_frames_in_flight = base.image_count;
@@ -124,6 +148,11 @@ impl Application {
             ui,
             fps_timer: utopian::FpsTimer::new(),
             shader_watcher,
+            scene_watcher,
+            scene_path,
+            pending_scene: None,
+            selected_node: None,
+            asset_loader: utopian::AssetLoader::new(),
             current_frame: 0,
             num_frames_in_flight,
             render_graph_mode: if raytracing_supported {
@@ -168,10 +197,47 @@ impl Application {
     fn create_scene(&mut self) {
         self.renderer.initialize(&self.base.device);
 
-        prototype::scenes::create_scene(&mut self.renderer, &mut self.camera, &self.base.device);
+        let scene = utopian::scene_loader::read_scene(&self.scene_path)
+            .unwrap_or_else(|err| panic!("{}", err));
+
+        self.pending_scene = Some(Application::load_scene(
+            &self.base.device,
+            scene,
+            &self.scene_path,
+            &mut self.renderer,
+            &mut self.camera,
+            &mut self.view_data,
+            &mut self.asset_loader,
+        ));
+    }
+
+    fn load_scene(
+        device: &utopian::Device,
+        scene: utopian::scene_loader::SceneDesc,
+        scene_path: &str,
+        renderer: &mut utopian::Renderer,
+        camera: &mut utopian::Camera,
+        view_data: &mut utopian::ViewUniformData,
+        asset_loader: &mut utopian::AssetLoader,
+    ) -> utopian::scene_loader::PendingScene {
+        let pending_scene = utopian::scene_loader::load_scene(
+            device,
+            scene,
+            scene_path,
+            renderer,
+            camera,
+            view_data,
+            asset_loader,
+        );
+
+        view_data.total_samples = 0;
+
+        pending_scene
+    }
 
-        if let Some(raytracing) = &mut self.renderer.raytracing {
-            raytracing.initialize(&self.base.device, &self.renderer.instances);
+    fn initialize_raytracing(device: &utopian::Device, renderer: &mut utopian::Renderer) {
+        if let Some(raytracing) = &mut renderer.raytracing {
+            raytracing.initialize(device, &renderer.instances, &renderer.scene_graph);
         }
     }
 
@@ -182,15 +248,30 @@ impl Application {
         camera_dir: &mut Vec3,
         fps: u32,
         view_data: &mut utopian::ViewUniformData,
-        selected_transform: &mut Mat4,
+        selected_transform: Option<&mut Mat4>,
         need_environment_map_update: &mut bool,
         num_frames_in_flight: &mut u32,
         render_graph_mode: &mut RenderGraphMode,
+        gpu_driven_rendering: &mut bool,
+        culling_stats: utopian::CullingStats,
+        exposure: &mut utopian::Exposure,
+        tonemap_settings: &mut TonemapSettings,
+        bloom_settings: &mut BloomSettings,
+        lens: &mut utopian::Lens,
     ) {
         egui::Window::new("rust-renderer")
             .auto_sized()
             .show(egui_context, |ui| {
                 ui.label(format!("FPS: {} ({} ms)", fps, 1000.0 / fps as f32));
+                ui.checkbox(gpu_driven_rendering, "GPU-driven rendering");
+                // The GPU-driven draws are culled on the GPU and not counted
+                match *gpu_driven_rendering {
+                    true => ui.label("Meshes drawn: only counted without GPU-driven rendering"),
+                    false => ui.label(format!(
+                        "Meshes drawn: {} culled: {}",
+                        culling_stats.drawn, culling_stats.culled
+                    )),
+                };
                 ui.label(format!(
                     "Accumulated frames: {}",
                     view_data.total_samples.min(view_data.accumulation_limit)
@@ -269,6 +350,137 @@ impl Application {
                         ));
                     });
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Exposure:");
+                    let exposure_options = [
+                        ("Manual", utopian::Exposure::Manual { ev100: 0.0 }),
+                        (
+                            "Physical",
+                            utopian::Exposure::Physical {
+                                aperture: 1.4,
+                                shutter_speed: 1.0 / 60.0,
+                                iso: 800.0,
+                            },
+                        ),
+                        ("Automatic", utopian::Exposure::default()),
+                    ];
+                    let current_exposure = match exposure {
+                        utopian::Exposure::Manual { .. } => "Manual",
+                        utopian::Exposure::Physical { .. } => "Physical",
+                        utopian::Exposure::Automatic { .. } => "Automatic",
+                    };
+                    egui::ComboBox::from_id_source("exposure_dropdown")
+                        .selected_text(current_exposure)
+                        .show_ui(ui, |ui| {
+                            for (name, option) in exposure_options {
+                                if ui.selectable_label(false, name).clicked() {
+                                    *exposure = option;
+                                }
+                            }
+                        });
+                });
+                match exposure {
+                    utopian::Exposure::Manual { ev100 } => {
+                        ui.horizontal(|ui| {
+                            ui.label("EV100:");
+                            ui.add(egui::widgets::Slider::new(ev100, -10.0..=20.0));
+                        });
+                    }
+                    utopian::Exposure::Physical {
+                        aperture,
+                        shutter_speed,
+                        iso,
+                    } => {
+                        ui.horizontal(|ui| {
+                            ui.label("Aperture (f-stops):");
+                            ui.add(egui::widgets::Slider::new(aperture, 1.0..=22.0));
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Shutter speed (s):");
+                            ui.add(
+                                egui::widgets::Slider::new(shutter_speed, 0.0001..=1.0)
+                                    .logarithmic(true),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("ISO:");
+                            ui.add(
+                                egui::widgets::Slider::new(iso, 100.0..=6400.0).logarithmic(true),
+                            );
+                        });
+                    }
+                    utopian::Exposure::Automatic { compensation, .. } => {
+                        ui.horizontal(|ui| {
+                            ui.label("Exposure compensation (EV):");
+                            ui.add(egui::widgets::Slider::new(compensation, -5.0..=5.0));
+                        });
+                    }
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Tonemapper:");
+                    let tonemapper_options = [
+                        Tonemapper::Clamp,
+                        Tonemapper::AcesFitted,
+                        Tonemapper::AgX,
+                        Tonemapper::ReinhardExtended,
+                        Tonemapper::PbrNeutral,
+                    ];
+                    egui::ComboBox::from_id_source("tonemapper_dropdown")
+                        .selected_text(format!("{:?}", tonemap_settings.tonemapper))
+                        .show_ui(ui, |ui| {
+                            for option in tonemapper_options {
+                                if ui
+                                    .selectable_label(false, format!("{:?}", option))
+                                    .clicked()
+                                {
+                                    tonemap_settings.tonemapper = option;
+                                }
+                            }
+                        });
+                });
+                if tonemap_settings.tonemapper == Tonemapper::ReinhardExtended {
+                    ui.horizontal(|ui| {
+                        ui.label("White point:");
+                        ui.add(egui::widgets::Slider::new(
+                            &mut tonemap_settings.white_point,
+                            1.0..=20.0,
+                        ));
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.add(U32Checkbox::new(
+                        &mut view_data.dof_enabled,
+                        "Depth of field:",
+                    ));
+                    ui.checkbox(&mut lens.autofocus, "Autofocus");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Focal distance:");
+                    ui.add(
+                        egui::widgets::Slider::new(&mut lens.focal_distance, 0.1..=100.0)
+                            .logarithmic(true),
+                    );
+                    ui.label("f-stop:");
+                    ui.add(egui::widgets::Slider::new(&mut lens.f_stop, 1.0..=22.0));
+                    ui.label("Sensor height (mm):");
+                    ui.add(egui::widgets::Slider::new(
+                        &mut lens.sensor_height,
+                        5.0..=50.0,
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut bloom_settings.enabled, "Bloom");
+                    ui.label("Intensity:");
+                    ui.add(egui::widgets::Slider::new(
+                        &mut bloom_settings.intensity,
+                        0.0..=0.5,
+                    ));
+                    ui.label("Radius:");
+                    ui.add(egui::widgets::Slider::new(
+                        &mut bloom_settings.radius,
+                        0.5..=4.0,
+                    ));
+                });
                 ui.horizontal(|ui| {
                     egui::Grid::new("settings_grid").show(ui, |ui| {
                         let render_graph_mode_options =
@@ -306,6 +518,7 @@ impl Application {
                         ui.add(U32Checkbox::new(&mut view_data.shadows_enabled, "Shadows:"));
                         ui.add(U32Checkbox::new(&mut view_data.ssao_enabled, "SSAO:"));
                         ui.add(U32Checkbox::new(&mut view_data.fxaa_enabled, "FXAA:"));
+                        ui.add(U32Checkbox::new(&mut view_data.taa_enabled, "TAA:"));
                         ui.add(U32Checkbox::new(&mut view_data.cubemap_enabled, "Cubemap:"));
                         ui.add(U32Checkbox::new(&mut view_data.ibl_enabled, "IBL:"));
                         ui.add(U32Checkbox::new(&mut view_data.sky_enabled, "Sky:"));
@@ -314,6 +527,10 @@ impl Application {
                             &mut view_data.lights_enabled,
                             "Point lights:",
                         ));
+                        ui.add(U32Checkbox::new(
+                            &mut view_data.light_clusters_debug,
+                            "Light clusters:",
+                        ));
                         ui.add(U32Checkbox::new(
                             &mut view_data.rebuild_tlas,
                             "Rebuild TLAS:",
@@ -341,22 +558,24 @@ impl Application {
                 });
             });
 
-        egui::Area::new("Viewport")
-            .fixed_pos((0.0, 0.0))
-            .show(egui_context, |ui| {
-                ui.with_layer_id(egui::LayerId::background(), |ui| {
-                    let gizmo = egui_gizmo::Gizmo::new("Gizmo")
-                        .view_matrix(view_data.view.to_cols_array_2d())
-                        .projection_matrix(view_data.projection.to_cols_array_2d())
-                        .model_matrix(selected_transform.to_cols_array_2d())
-                        .mode(egui_gizmo::GizmoMode::Translate);
-
-                    if let Some(response) = gizmo.interact(ui) {
-                        *selected_transform = Mat4::from_cols_array_2d(&response.transform);
-                        view_data.total_samples = 0; // Reset raytracing when moving objects
-                    }
+        if let Some(selected_transform) = selected_transform {
+            egui::Area::new("Viewport")
+                .fixed_pos((0.0, 0.0))
+                .show(egui_context, |ui| {
+                    ui.with_layer_id(egui::LayerId::background(), |ui| {
+                        let gizmo = egui_gizmo::Gizmo::new("Gizmo")
+                            .view_matrix(view_data.view.to_cols_array_2d())
+                            .projection_matrix(view_data.projection.to_cols_array_2d())
+                            .model_matrix(selected_transform.to_cols_array_2d())
+                            .mode(egui_gizmo::GizmoMode::Translate);
+
+                        if let Some(response) = gizmo.interact(ui) {
+                            *selected_transform = Mat4::from_cols_array_2d(&response.transform);
+                            view_data.total_samples = 0; // Reset raytracing when moving objects
+                        }
+                    });
                 });
-            });
+        }
     }
 
     fn run(&mut self) {
@@ -383,20 +602,46 @@ impl Application {
             let old_max_num_lights_used = self.view_data.max_num_lights_used;
             let old_temporal_reuse_enabled = self.view_data.temporal_reuse_enabled;
             let old_spatial_reuse_enabled = self.view_data.spatial_reuse_enabled;
+            let old_selected_transform = self
+                .selected_node
+                .map(|node| self.renderer.scene_graph.local_transform(node));
+            let mut selected_transform = old_selected_transform;
+            let culling_stats = self.renderer.culling_stats();
+            let mut exposure = self.camera.get_exposure();
+            let old_lens = self.camera.get_lens();
+            let old_dof_enabled = self.view_data.dof_enabled;
+            let mut lens = old_lens;
             Application::update_ui(
                 &self.ui.egui_integration.context(),
                 &mut self.camera.get_position(),
                 &mut self.camera.get_forward(),
                 self.fps_timer.calculate(),
                 &mut self.view_data,
-                &mut self.renderer.instances[1].transform,
+                selected_transform.as_mut(),
                 &mut self.renderer.need_environment_map_update,
                 &mut self.num_frames_in_flight,
                 &mut self.render_graph_mode,
+                &mut self.renderer.gpu_driven_rendering,
+                culling_stats,
+                &mut exposure,
+                &mut self.renderer.tonemap_settings,
+                &mut self.renderer.bloom_settings,
+                &mut lens,
             );
 
+            self.camera.set_exposure(exposure);
+            self.camera.set_lens(lens);
+
             self.view_data.sun_dir = self.view_data.sun_dir.normalize();
 
+            if let (Some(node), Some(transform)) = (self.selected_node, selected_transform) {
+                if selected_transform != old_selected_transform {
+                    self.renderer
+                        .scene_graph
+                        .set_local_transform(node, transform);
+                }
+            }
+
             if self.view_data.samples_per_frame != old_samples_per_frame
                 || self.view_data.num_bounces != old_num_bounces
                 || self.view_data.sun_dir != old_sun_dir
@@ -408,6 +653,8 @@ impl Application {
                 || self.view_data.max_num_lights_used != old_max_num_lights_used
                 || self.view_data.temporal_reuse_enabled != old_temporal_reuse_enabled
                 || self.view_data.spatial_reuse_enabled != old_spatial_reuse_enabled
+                || self.view_data.dof_enabled != old_dof_enabled
+                || lens != old_lens
             {
                 self.view_data.total_samples = 0;
             }
@@ -447,6 +694,60 @@ impl Application {
                 }
             }
 
+            if let Some(path) = self.scene_watcher.check_if_modification() {
+                if path.ends_with(&self.scene_path) {
+                    // The current scene is kept if the edited file is invalid
+                    match utopian::scene_loader::read_scene(&self.scene_path) {
+                        Ok(scene) => {
+                            self.renderer.clear_scene(&self.base.device);
+                            self.asset_loader.clear();
+                            self.selected_node = None;
+
+                            self.pending_scene = Some(Application::load_scene(
+                                &self.base.device,
+                                scene,
+                                &self.scene_path,
+                                &mut self.renderer,
+                                &mut self.camera,
+                                &mut self.view_data,
+                                &mut self.asset_loader,
+                            ));
+                        }
+                        Err(err) => log::error!("{}", err),
+                    }
+                }
+            }
+
+            if let Some(pending_scene) = &mut self.pending_scene {
+                if pending_scene.update(
+                    &self.base.device,
+                    &mut self.renderer,
+                    &mut self.camera,
+                    &mut self.asset_loader,
+                ) {
+                    self.view_data.total_samples = 0;
+                }
+
+                if self.selected_node.is_none() {
+                    self.selected_node = pending_scene.model_roots(SELECTED_MODEL).first().copied();
+                }
+
+                // The acceleration structures are built once for all models, the models
+                // that are added before then are only rasterized
+                if pending_scene.is_loaded() {
+                    Application::initialize_raytracing(&self.base.device, &mut self.renderer);
+                    self.view_data.total_samples = 0;
+                    self.pending_scene = None;
+                }
+            }
+
+            if self
+                .asset_loader
+                .update(&self.base.device, &mut self.renderer)
+            {
+                self.view_data.total_samples = 0;
+            }
+
             if input.key_pressed(winit::event::VirtualKeyCode::Q) {
                 self.graph.profiling_enabled = !self.graph.profiling_enabled;
                 puffin::set_scopes_on(self.graph.profiling_enabled);
@@ -456,13 +757,37 @@ impl Application {
                 self.view_data.total_samples = 0;
             }
 
+            // The jitter is only resolved by the TAA pass in the rasterized graphs
+            if self.view_data.taa_enabled == 1
+                && (self.render_graph_mode == RenderGraphMode::Rasterized
+                    || self.render_graph_mode == RenderGraphMode::Hybrid)
+            {
+                self.camera.next_jitter(
+                    self.view_data.viewport_width,
+                    self.view_data.viewport_height,
+                );
+            } else {
+                self.camera.clear_jitter();
+            }
+
             // Move from here
+            self.view_data.jitter = self.camera.get_jitter();
+            self.view_data.aperture_diameter = match self.view_data.dof_enabled {
+                1 => self.camera.get_aperture_diameter(),
+                _ => 0.0,
+            };
+            self.view_data.focal_length = self.camera.get_focal_length();
+            self.view_data.sensor_height = self.camera.get_sensor_height();
             self.view_data.view = self.camera.get_view();
             self.view_data.projection = self.camera.get_projection();
             self.view_data.inverse_view = self.camera.get_view().inverse();
             self.view_data.inverse_projection = self.camera.get_projection().inverse();
             self.view_data.eye_pos = self.camera.get_position();
-            self.view_data.time = self.fps_timer.elapsed_seconds_from_start();
+
+            let time = self.fps_timer.elapsed_seconds_from_start();
+            self.renderer.update_animations(time - self.view_data.time);
+            self.view_data.time = time;
+            self.renderer.update_lods(&self.view_data);
 
             if self.render_graph_mode == RenderGraphMode::PathTraced {
                 self.view_data.total_samples += self.view_data.samples_per_frame;
@@ -493,6 +818,8 @@ impl Application {
                             &mut self.graph,
                             &self.base.device,
                             &self.base,
+                            &self.renderer,
+                            &self.camera,
                         );
                     } else if self.render_graph_mode == RenderGraphMode::Rasterized
                         || self.render_graph_mode == RenderGraphMode::Hybrid
@@ -511,6 +838,7 @@ impl Application {
                             &mut self.graph,
                             &self.base.device,
                             &self.base,
+                            &self.renderer,
                             &self.view_data,
                             &self.camera,
                         );
@@ -542,8 +870,9 @@ impl Application {
                 },
             );
 
+            // Without the jitter so that the velocity only contains the motion
             self.view_data.prev_frame_projection_view =
-                self.view_data.projection * self.view_data.view;
+                self.camera.get_unjittered_projection() * self.view_data.view;
 
             self.base.submit_commands(self.current_frame);
             self.base.present_frame(present_index, self.current_frame);
@@ -6,21 +6,17 @@
 #include "include/bindless.glsl"
 #include "include/view.glsl"
 
-layout (location = 0) in vec4 pos;
-layout (location = 1) in vec4 normal;
-layout (location = 2) in vec2 uv;
-layout (location = 3) in vec4 color;
-layout (location = 4) in vec4 tangent;
-
 layout (set = 3, binding = 0) uniform UBO_constants
 {
    mat4 projection;
    mat4 world;
 } ubo_constants;
 
-// layout(push_constant) uniform PushConsts {
-//    mat4 view;
-// } pushConsts;
+// Must match the push constants in atmosphere.rs
+layout (push_constant) uniform PushConsts {
+   uint vertex_buffer;
+   uint vertex_layout;
+} pushConsts;
 
 layout (location = 0) out vec3 out_pos_l;
 
@@ -31,6 +27,10 @@ out gl_PerVertex
 
 void main()
 {
+   // The sky sphere is not in the mesh buffer, only the fields used by load_vertex() are set
+   Mesh mesh = Mesh(pushConsts.vertex_buffer, 0, 0, pushConsts.vertex_layout, 0, 0);
+   vec4 pos = load_vertex(mesh, gl_VertexIndex).pos;
+
    out_pos_l = pos.xyz;
 
    // Removes the translation components of the matrix to always keep the skybox at the same distance
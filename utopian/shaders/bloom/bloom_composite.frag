@@ -0,0 +1,32 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "include/bindless.glsl"
+#include "include/view.glsl"
+
+layout (location = 0) in vec2 in_uv;
+layout (location = 0) out vec4 out_color;
+
+layout (set = 2, binding = 0) uniform sampler2D in_color;
+layout (set = 2, binding = 1) uniform sampler2D in_bloom;
+
+layout (std140, set = 3, binding = 0) uniform UBO_settings
+{
+   float intensity;
+   float num_mips;
+} settings_bloom;
+
+void main()
+{
+   vec2 uv = FLIP_UV_Y(in_uv);
+
+   vec4 color = texture(in_color, uv);
+
+   // Every level was added together during the upsampling, averaging them keeps
+   // the energy of the blurred image the same as the input
+   vec3 bloom = texture(in_bloom, uv).rgb / settings_bloom.num_mips;
+
+   out_color = vec4(mix(color.rgb, bloom, settings_bloom.intensity), color.a);
+}
@@ -0,0 +1,70 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "include/bindless.glsl"
+#include "include/view.glsl"
+
+layout (location = 0) in vec2 in_uv;
+layout (location = 0) out vec4 out_color;
+
+layout (set = 2, binding = 0) uniform sampler2D in_texture;
+
+layout (std140, set = 3, binding = 0) uniform UBO_settings
+{
+   uint karis_average;
+} settings_bloom;
+
+// Weighting by the inverse luminance keeps single very bright pixels from flickering
+float karis_weight(vec3 color)
+{
+   return 1.0 / (1.0 + luminance(color));
+}
+
+// 13 tap downsample filter from "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+// by Jorge Jimenez. Five overlapping 2x2 box filters that together approximate a wider kernel.
+void main()
+{
+   vec2 uv = FLIP_UV_Y(in_uv);
+   vec2 texel = 1.0 / vec2(textureSize(in_texture, 0));
+
+   vec3 a = texture(in_texture, uv + texel * vec2(-2.0, -2.0)).rgb;
+   vec3 b = texture(in_texture, uv + texel * vec2( 0.0, -2.0)).rgb;
+   vec3 c = texture(in_texture, uv + texel * vec2( 2.0, -2.0)).rgb;
+   vec3 d = texture(in_texture, uv + texel * vec2(-2.0,  0.0)).rgb;
+   vec3 e = texture(in_texture, uv).rgb;
+   vec3 f = texture(in_texture, uv + texel * vec2( 2.0,  0.0)).rgb;
+   vec3 g = texture(in_texture, uv + texel * vec2(-2.0,  2.0)).rgb;
+   vec3 h = texture(in_texture, uv + texel * vec2( 0.0,  2.0)).rgb;
+   vec3 i = texture(in_texture, uv + texel * vec2( 2.0,  2.0)).rgb;
+   vec3 j = texture(in_texture, uv + texel * vec2(-1.0, -1.0)).rgb;
+   vec3 k = texture(in_texture, uv + texel * vec2( 1.0, -1.0)).rgb;
+   vec3 l = texture(in_texture, uv + texel * vec2(-1.0,  1.0)).rgb;
+   vec3 m = texture(in_texture, uv + texel * vec2( 1.0,  1.0)).rgb;
+
+   // The corner boxes get 1/8 of the weight each and the center box 1/2
+   vec3 boxes[5] = vec3[](
+      (a + b + d + e) * 0.25,
+      (b + c + e + f) * 0.25,
+      (d + e + g + h) * 0.25,
+      (e + f + h + i) * 0.25,
+      (j + k + l + m) * 0.25);
+   float box_weights[5] = float[](0.125, 0.125, 0.125, 0.125, 0.5);
+
+   vec3 color = vec3(0.0);
+   float total_weight = 0.0;
+
+   for (int box = 0; box < 5; box++)
+   {
+      float weight = box_weights[box];
+
+      if (settings_bloom.karis_average == 1)
+         weight *= karis_weight(boxes[box]);
+
+      color += boxes[box] * weight;
+      total_weight += weight;
+   }
+
+   out_color = vec4(color / total_weight, 1.0);
+}
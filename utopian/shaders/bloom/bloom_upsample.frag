@@ -0,0 +1,41 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "include/bindless.glsl"
+#include "include/view.glsl"
+
+layout (location = 0) in vec2 in_uv;
+layout (location = 0) out vec4 out_color;
+
+// The lower resolution level that is upsampled and the downsampled level of this resolution
+layout (set = 2, binding = 0) uniform sampler2D in_lower_mip;
+layout (set = 2, binding = 1) uniform sampler2D in_current_mip;
+
+layout (std140, set = 3, binding = 0) uniform UBO_settings
+{
+   // In texels of the lower resolution level
+   float radius;
+} settings_bloom;
+
+// 3x3 tent filter
+void main()
+{
+   vec2 uv = FLIP_UV_Y(in_uv);
+   vec2 offset = settings_bloom.radius / vec2(textureSize(in_lower_mip, 0));
+
+   vec3 a = texture(in_lower_mip, uv + offset * vec2(-1.0, -1.0)).rgb;
+   vec3 b = texture(in_lower_mip, uv + offset * vec2( 0.0, -1.0)).rgb;
+   vec3 c = texture(in_lower_mip, uv + offset * vec2( 1.0, -1.0)).rgb;
+   vec3 d = texture(in_lower_mip, uv + offset * vec2(-1.0,  0.0)).rgb;
+   vec3 e = texture(in_lower_mip, uv).rgb;
+   vec3 f = texture(in_lower_mip, uv + offset * vec2( 1.0,  0.0)).rgb;
+   vec3 g = texture(in_lower_mip, uv + offset * vec2(-1.0,  1.0)).rgb;
+   vec3 h = texture(in_lower_mip, uv + offset * vec2( 0.0,  1.0)).rgb;
+   vec3 i = texture(in_lower_mip, uv + offset * vec2( 1.0,  1.0)).rgb;
+
+   vec3 upsampled = (e * 4.0 + (b + d + f + h) * 2.0 + (a + c + g + i)) / 16.0;
+
+   out_color = vec4(texture(in_current_mip, uv).rgb + upsampled, 1.0);
+}
@@ -6,6 +6,7 @@
 #include "include/bindless.glsl"
 #include "include/view.glsl"
 #include "include/pbr_lighting.glsl"
+#include "include/clusters.glsl"
 
 layout (location = 0) in vec2 in_uv;
 
@@ -15,13 +16,24 @@ layout (set = 2, binding = 0) uniform sampler2D in_gbuffer_position;
 layout (set = 2, binding = 1) uniform sampler2D in_gbuffer_normal;
 layout (set = 2, binding = 2) uniform sampler2D in_gbuffer_albedo;
 layout (set = 2, binding = 3) uniform sampler2D in_gbuffer_pbr;
-layout (set = 2, binding = 4) uniform sampler2DArray in_shadow_map;
-layout (set = 2, binding = 5) uniform sampler2D in_rt_shadows;
-layout (set = 2, binding = 6) uniform sampler2D in_rt_reflections;
-layout (set = 2, binding = 7) uniform sampler2D in_ssao;
-layout (set = 2, binding = 8) uniform samplerCube in_irradiance_map;
-layout (set = 2, binding = 9) uniform samplerCube in_specular_map;
-layout (set = 2, binding = 10) uniform sampler2D in_brdf_lut;
+layout (set = 2, binding = 4) uniform sampler2D in_gbuffer_emissive;
+layout (set = 2, binding = 5) uniform sampler2DArray in_shadow_map;
+layout (set = 2, binding = 6) uniform sampler2D in_rt_shadows;
+layout (set = 2, binding = 7) uniform sampler2D in_rt_reflections;
+layout (set = 2, binding = 8) uniform sampler2D in_ssao;
+layout (set = 2, binding = 9) uniform samplerCube in_irradiance_map;
+layout (set = 2, binding = 10) uniform samplerCube in_specular_map;
+layout (set = 2, binding = 11) uniform sampler2D in_brdf_lut;
+
+layout (std430, set = 2, binding = 12) readonly buffer ClusterLightCountsSSBO
+{
+   uint counts[];
+} clusterLightCountsSSBO;
+
+layout (std430, set = 2, binding = 13) readonly buffer ClusterLightIndicesSSBO
+{
+   uint indices[];
+} clusterLightIndicesSSBO;
 
 // Todo: set=2 should be dedicated to input textures but the shader reflection
 // does not support gaps in the descriptor sets
@@ -52,14 +64,12 @@ void main() {
     float metallic = texture(in_gbuffer_pbr, uv).r;
     float roughness = texture(in_gbuffer_pbr, uv).g;
     float occlusion = texture(in_gbuffer_pbr, uv).b;
+    vec3 emissive = texture(in_gbuffer_emissive, uv).rgb;
     float ssao = texture(in_ssao, in_uv).r;
 
     roughness *= material.roughness_factor;
     metallic *= material.metallic_factor;
 
-    // From sRGB space to Linear space
-    diffuse_color.rgb = pow(diffuse_color.rgb, vec3(2.2));
-
     PixelParams pixel;
     pixel.position = position;
     pixel.baseColor = diffuse_color.rgb * material.base_color_factor.rgb;
@@ -67,16 +77,25 @@ void main() {
     pixel.metallic = metallic;
     pixel.roughness = roughness;
     pixel.occlusion = occlusion;
+    pixel.transmission = material.transmission_factor;
+    pixel.clearcoat = material.clearcoat_factor;
+    pixel.clearcoatRoughness = material.clearcoat_roughness_factor;
 
     /* Direct lighting */
     vec3 Lo = vec3(0.0);
 
-    Light sun_light = Light(vec4(1.0f), vec3(0.0f), 0.0f, view.sun_dir * vec3(-1, 1, -1), 0.0f, vec3(1.0), 0.0f, vec3(0.0f), 0.0f, vec4(0.0f));
+    Light sun_light = Light(vec4(1.0f), vec3(0.0f), 0.0f, -view.sun_dir, LIGHT_TYPE_DIRECTIONAL, vec3(1.0f), 0.0f, vec4(0.0f), vec4(0.0f));
     Lo += surfaceShading(pixel, sun_light, view.eye_pos.xyz, 1.0f);
 
-    for (int i = 0; i < view.num_lights; i++)
+    // Only the lights that reach the cluster of the pixel, see light_culling.rs
+    uint cluster = cluster_index(gl_FragCoord.xy, position);
+    uint cluster_light_count = clusterLightCountsSSBO.counts[cluster];
+    bool cluster_overflow = cluster_light_count == CLUSTER_OVERFLOW;
+    uint num_lights = cluster_overflow ? view.num_lights : cluster_light_count;
+    for (uint i = 0; i < num_lights; i++)
     {
-       Lo += surfaceShading(pixel, lightsSSBO.lights[i], view.eye_pos.xyz, 1.0f);
+       uint light_index = cluster_overflow ? i : clusterLightIndicesSSBO.indices[cluster * MAX_LIGHTS_PER_CLUSTER + i];
+       Lo += surfaceShading(pixel, lightsSSBO.lights[light_index], view.eye_pos.xyz, 1.0f);
     }
     
 
@@ -114,6 +133,12 @@ void main() {
         color *= ssao;
     }
 
+    color += emissive;
+
+    if (view.light_clusters_debug == 1) {
+        color = cluster_heatmap(cluster_light_count);
+    }
+
     out_color = vec4(color, 1.0f);
 }
 
@@ -0,0 +1,68 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "include/bindless.glsl"
+#include "include/view.glsl"
+#include "include/depth_of_field.glsl"
+
+layout (location = 0) in vec2 in_uv;
+layout (location = 0) out vec4 out_color;
+
+layout (set = 2, binding = 0) uniform sampler2D in_color;
+layout (set = 2, binding = 1) uniform sampler2D in_depth;
+
+layout (std430, set = 2, binding = 2) readonly buffer FocusSSBO
+{
+   float focal_distance;
+} focusSSBO;
+
+#define GOLDEN_ANGLE 2.39996323
+#define MAX_COC_RADIUS 16.0
+// Distance between the samples of the spiral in pixels, lower is smoother but slower
+#define RADIUS_SCALE 1.0
+
+float coc_radius(float depth)
+{
+   return min(abs(circle_of_confusion(depth, focusSSBO.focal_distance)), MAX_COC_RADIUS);
+}
+
+// Based on "Bokeh depth of field in a single pass" by Dennis Gustafsson
+// https://blog.voxagon.se/2018/05/04/bokeh-depth-of-field-in-single-pass.html
+void main()
+{
+   vec2 uv = FLIP_UV_Y(in_uv);
+   vec2 pixel_size = 1.0 / vec2(textureSize(in_color, 0));
+
+   vec4 center = texture(in_color, uv);
+   float center_depth = view_depth(texture(in_depth, uv).r);
+   float center_coc = coc_radius(center_depth);
+
+   vec3 color = center.rgb;
+   float total_weight = 1.0;
+   float radius = RADIUS_SCALE;
+
+   for (float angle = 0.0; radius < MAX_COC_RADIUS; angle += GOLDEN_ANGLE)
+   {
+      vec2 sample_uv = uv + vec2(cos(angle), sin(angle)) * pixel_size * radius;
+
+      vec3 sample_color = texture(in_color, sample_uv).rgb;
+      float sample_depth = view_depth(texture(in_depth, sample_uv).r);
+      float sample_coc = coc_radius(sample_depth);
+
+      // Blurry background samples must not bleed over sharper surfaces in front of them
+      if (sample_depth > center_depth)
+         sample_coc = min(sample_coc, center_coc * 2.0);
+
+      // How much the circle of confusion of the sample covers the center pixel, when
+      // it does not the current average is used to keep the weights even
+      float coverage = smoothstep(radius - 0.5, radius + 0.5, sample_coc);
+      color += mix(color / total_weight, sample_color, coverage);
+      total_weight += 1.0;
+
+      radius += RADIUS_SCALE / radius;
+   }
+
+   out_color = vec4(color / total_weight, center.a);
+}
@@ -6,6 +6,8 @@
 #include "include/bindless.glsl"
 #include "include/view.glsl"
 #include "include/pbr_lighting.glsl"
+#include "include/material.glsl"
+#include "include/clusters.glsl"
 
 layout (location = 0) in vec3 in_pos;
 layout (location = 1) in vec2 in_uv;
@@ -13,11 +15,22 @@ layout (location = 2) in vec3 in_normal;
 layout (location = 3) in vec4 in_color;
 layout (location = 4) in vec4 in_tangent;
 layout (location = 5) in mat3 in_tbn;
+layout (location = 8) flat in uint in_mesh_index;
 
 layout (location = 0) out vec4 out_color;
 
 layout (set = 2, binding = 0) uniform sampler2DArray in_shadow_map;
 
+layout (std430, set = 2, binding = 1) readonly buffer ClusterLightCountsSSBO
+{
+   uint counts[];
+} clusterLightCountsSSBO;
+
+layout (std430, set = 2, binding = 2) readonly buffer ClusterLightIndicesSSBO
+{
+   uint indices[];
+} clusterLightIndicesSSBO;
+
 // Todo: set=2 should be dedicated to input textures but the shader reflection
 // does not support gaps in the descriptor sets
 layout (std140, set = 3, binding = 0) uniform UBO_shadowmapParams
@@ -36,17 +49,21 @@ layout(push_constant) uniform PushConsts {
 } pushConsts;
 
 void main() {
-    Mesh mesh = meshesSSBO.meshes[pushConsts.mesh_index];
+    Mesh mesh = meshesSSBO.meshes[in_mesh_index];
     Material material = materialsSSBO.materials[mesh.material];
 
-    vec4 diffuse_color = texture(samplerColor[material.diffuse_map], in_uv);
-    vec4 normal_map = texture(samplerColor[material.normal_map], in_uv);
-    float metallic = texture(samplerColor[material.metallic_roughness_map], in_uv).b;
-    float roughness = texture(samplerColor[material.metallic_roughness_map], in_uv).g;
-    float occlusion = texture(samplerColor[material.occlusion_map], in_uv).r;
+    vec4 diffuse_color = texture(samplerColor[material.diffuse_map], transform_uv(material, UV_TRANSFORM_DIFFUSE, in_uv));
+    vec4 normal_map = texture(samplerColor[material.normal_map], transform_uv(material, UV_TRANSFORM_NORMAL, in_uv));
+    vec2 metallic_roughness_uv = transform_uv(material, UV_TRANSFORM_METALLIC_ROUGHNESS, in_uv);
+    float metallic = texture(samplerColor[material.metallic_roughness_map], metallic_roughness_uv).b;
+    float roughness = texture(samplerColor[material.metallic_roughness_map], metallic_roughness_uv).g;
+    float occlusion = texture(samplerColor[material.occlusion_map], transform_uv(material, UV_TRANSFORM_OCCLUSION, in_uv)).r;
+
+    metallic *= material.metallic_factor;
+    roughness *= material.roughness_factor;
 
-    // From sRGB space to Linear space
-    diffuse_color.rgb = pow(diffuse_color.rgb, vec3(2.2));
+    if (alpha_test_discard(material, diffuse_color.a * material.base_color_factor.a, gl_FragCoord.xy))
+        discard;
 
     vec3 normal = normalize(in_normal);
     if (in_tangent.xyz != vec3(0.0f))
@@ -55,6 +72,8 @@ void main() {
          normal = normalize(in_tbn * normal);
     }
 
+    normal = double_sided_normal(material, normal, in_pos, view.eye_pos.xyz);
+
     PixelParams pixel;
     pixel.position = in_pos;
     pixel.baseColor = diffuse_color.rgb * material.base_color_factor.rgb;
@@ -62,16 +81,25 @@ void main() {
     pixel.metallic = metallic;
     pixel.roughness = roughness;
     pixel.occlusion = occlusion;
+    pixel.transmission = material.transmission_factor;
+    pixel.clearcoat = material.clearcoat_factor;
+    pixel.clearcoatRoughness = material.clearcoat_roughness_factor;
 
     /* Direct lighting */
     vec3 Lo = vec3(0.0);
 
-    Light sun_light = Light(vec4(1.0f), vec3(0.0f), 0.0f, view.sun_dir * vec3(-1, 1, -1), 0.0f, vec3(1.0), 0.0f, vec3(0.0f), 0.0f, vec4(0.0f));
+    Light sun_light = Light(vec4(1.0f), vec3(0.0f), 0.0f, -view.sun_dir, LIGHT_TYPE_DIRECTIONAL, vec3(1.0f), 0.0f, vec4(0.0f), vec4(0.0f));
     Lo += surfaceShading(pixel, sun_light, view.eye_pos.xyz, 1.0f);
 
-    for (int i = 0; i < view.num_lights; i++)
+    // Only the lights that reach the cluster of the pixel, see light_culling.rs
+    uint cluster = cluster_index(gl_FragCoord.xy, in_pos);
+    uint cluster_light_count = clusterLightCountsSSBO.counts[cluster];
+    bool cluster_overflow = cluster_light_count == CLUSTER_OVERFLOW;
+    uint num_lights = cluster_overflow ? view.num_lights : cluster_light_count;
+    for (uint i = 0; i < num_lights; i++)
     {
-       Lo += surfaceShading(pixel, lightsSSBO.lights[i], view.eye_pos.xyz, 1.0f);
+       uint light_index = cluster_overflow ? i : clusterLightIndicesSSBO.indices[cluster * MAX_LIGHTS_PER_CLUSTER + i];
+       Lo += surfaceShading(pixel, lightsSSBO.lights[light_index], view.eye_pos.xyz, 1.0f);
     }
 
     // Todo: IBL
@@ -91,6 +119,12 @@ void main() {
     }
 
 
+    color += sample_emissive(material, in_uv);
+
+    if (view.light_clusters_debug == 1) {
+        color = cluster_heatmap(cluster_light_count);
+    }
+
     out_color = vec4(color, 1.0f);
 }
 
@@ -3,6 +3,7 @@
 
 #include "include/bindless.glsl"
 #include "include/view.glsl"
+#include "include/material.glsl"
 
 layout (location = 0) in vec3 in_pos;
 layout (location = 1) in vec2 in_uv;
@@ -10,32 +11,39 @@ layout (location = 2) in vec3 in_normal;
 layout (location = 3) in vec4 in_color;
 layout (location = 4) in vec4 in_tangent;
 layout (location = 5) in mat3 in_tbn;
+layout (location = 8) flat in uint in_mesh_index;
+layout (location = 9) in vec4 in_clip_pos;
+layout (location = 10) in vec4 in_prev_clip_pos;
 
 layout (location = 0) out vec4 out_gbuffer_position;
 layout (location = 1) out vec4 out_gbuffer_normal;
 layout (location = 2) out vec4 out_gbuffer_albedo;
 layout (location = 3) out vec4 out_gbuffer_pbr;
+layout (location = 4) out vec4 out_gbuffer_emissive;
+layout (location = 5) out vec2 out_gbuffer_velocity;
 
 layout(push_constant) uniform PushConsts {
     mat4 world;
     vec4 color;
     uint mesh_index;
-    ivec3 pad;
+    uint instance_index;
+    ivec2 pad;
 } pushConsts;
 
 void main()
 {
-    Mesh mesh = meshesSSBO.meshes[pushConsts.mesh_index];
+    Mesh mesh = meshesSSBO.meshes[in_mesh_index];
     Material material = materialsSSBO.materials[mesh.material];
 
-    vec4 diffuse_color = texture(samplerColor[material.diffuse_map], in_uv);
-    vec4 normal_map = texture(samplerColor[material.normal_map], in_uv);
-    float metallic = texture(samplerColor[material.metallic_roughness_map], in_uv).b;
-    float roughness = texture(samplerColor[material.metallic_roughness_map], in_uv).g;
-    float occlusion = texture(samplerColor[material.occlusion_map], in_uv).r;
+    vec4 diffuse_color = texture(samplerColor[material.diffuse_map], transform_uv(material, UV_TRANSFORM_DIFFUSE, in_uv));
+    vec4 normal_map = texture(samplerColor[material.normal_map], transform_uv(material, UV_TRANSFORM_NORMAL, in_uv));
+    vec2 metallic_roughness_uv = transform_uv(material, UV_TRANSFORM_METALLIC_ROUGHNESS, in_uv);
+    float metallic = texture(samplerColor[material.metallic_roughness_map], metallic_roughness_uv).b;
+    float roughness = texture(samplerColor[material.metallic_roughness_map], metallic_roughness_uv).g;
+    float occlusion = texture(samplerColor[material.occlusion_map], transform_uv(material, UV_TRANSFORM_OCCLUSION, in_uv)).r;
 
-    // From sRGB space to Linear space
-    //diffuse_color.rgb = pow(diffuse_color.rgb, vec3(2.2));
+    if (alpha_test_discard(material, diffuse_color.a * material.base_color_factor.a, gl_FragCoord.xy))
+        discard;
 
     vec3 normal = normalize(in_normal);
     if (in_tangent.xyz != vec3(0.0f))
@@ -44,8 +52,16 @@ void main()
         normal = normalize(in_tbn * normal);
     }
 
+    normal = double_sided_normal(material, normal, in_pos, view.eye_pos.xyz);
+
     out_gbuffer_position = vec4(in_pos, 1.0);
     out_gbuffer_normal = vec4(normal, 1.0);
     out_gbuffer_albedo = vec4(diffuse_color.rgb , 1.0);
     out_gbuffer_pbr = vec4(metallic, roughness, occlusion, mesh.material);
+    out_gbuffer_emissive = vec4(sample_emissive(material, in_uv), 1.0);
+
+    // Screen space motion in uv units since the previous frame, without the jitter
+    vec2 ndc = in_clip_pos.xy / in_clip_pos.w - view.jitter;
+    vec2 prev_ndc = in_prev_clip_pos.xy / in_prev_clip_pos.w;
+    out_gbuffer_velocity = (ndc - prev_ndc) * vec2(0.5, -0.5);
 }
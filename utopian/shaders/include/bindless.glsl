@@ -19,33 +19,78 @@ struct Material
    vec4 base_color_factor;
    float metallic_factor;
    float roughness_factor;
-   vec2 padding;
+   float alpha_cutoff;
+   uint alpha_mode;
 
    // Ray tracing properties
    // x = type (0 = lambertian, 1 = metal, 2 = dielectric, 3 = diffuse light)
    // y = metal -> fuzz, dielectric -> index of refractions
    vec4 raytrace_properties;
+
+   uint emissive_map;
+   uint double_sided;
+   float transmission_factor;
+   float ior;
+   vec4 emissive_factor; // rgb = emissive factor * KHR_materials_emissive_strength
+   float clearcoat_factor;
+   float clearcoat_roughness_factor;
+   vec2 padding;
+
+   // KHR_texture_transform, two rows of a 2x3 matrix per texture, see material.glsl
+   vec4 uv_transforms[10];
 };
 
+// Must match VertexLayout in primitive.rs
+#define VERTEX_LAYOUT_FULL 0
+#define VERTEX_LAYOUT_COMPACT 1
+#define VERTEX_LAYOUT_COMPACT_COLOR 2
+
 struct Mesh
 {
    uint vertex_buffer;
    uint index_buffer;
    uint material;
+   uint vertex_layout;
+   uint index_size; // 2 or 4 bytes
+   uint blas_first_triangle;
+};
+
+// Must match GpuInstance in renderer.rs, one for every mesh of every model instance
+struct Instance
+{
+   mat4 world;
+   mat4 prev_world; // Of the previous frame, for motion vectors
+   vec3 aabb_min; // World space
+   uint mesh;
+   vec3 aabb_max;
+   uint first_index; // Of the selected level of detail in the index buffer of the mesh
+   uint index_count;
+   uint cullable; // 0 for deformed meshes since their bounds only cover the rest pose
+   uint pad0;
+   uint pad1;
 };
 
+// Must match the LIGHT_TYPE_* constants in light.rs
+#define LIGHT_TYPE_DIRECTIONAL 0.0
+#define LIGHT_TYPE_POINT 1.0
+#define LIGHT_TYPE_SPOT 2.0
+#define LIGHT_TYPE_RECT_AREA 3.0
+#define LIGHT_TYPE_DISK_AREA 4.0
+
 struct Light
 {
    vec4 color;
    vec3 pos;
    float range;
    vec3 dir;
-   float spot;
-   vec3 att;
    float type;
-   vec3 intensity;
+   vec3 intensity;   // color * intensity in physical units
    float id;
-   vec4 pad;
+   // spot: x = cos(inner cone angle), y = cos(outer cone angle)
+   // rect area: x = width, y = height
+   // disk area: x = radius
+   vec4 params;
+   vec4 tangent;
 };
 
 layout (set = 0, binding = 0) uniform sampler2D samplerColor[];
@@ -60,6 +105,18 @@ layout (scalar, set = 0, binding = 2) readonly buffer IndicesSSBO
    ivec3 indices[];
 } indicesSSBO[];
 
+// The compact vertex layouts and 16-bit indices are read as 32-bit words, use
+// load_vertex(), load_index() and load_triangle() instead of accessing these directly
+layout (scalar, set = 0, binding = 1) readonly buffer PackedVerticesSSBO
+{
+   uint words[];
+} packedVerticesSSBO[];
+
+layout (scalar, set = 0, binding = 2) readonly buffer PackedIndicesSSBO
+{
+   uint words[];
+} packedIndicesSSBO[];
+
 layout (scalar, set = 0, binding = 3) readonly buffer MaterialsSSBO
 {
    Material materials[];
@@ -75,3 +132,59 @@ layout (scalar, set = 0, binding = 5) readonly buffer LightsSSBO
    Light lights[];
 } lightsSSBO;
 
+layout (scalar, set = 0, binding = 6) readonly buffer InstancesSSBO
+{
+   Instance instances[];
+} instancesSSBO;
+
+Vertex load_vertex(Mesh mesh, uint index)
+{
+   if (mesh.vertex_layout == VERTEX_LAYOUT_FULL)
+      return verticesSSBO[mesh.vertex_buffer].vertices[index];
+
+   // See CompactVertex and CompactColorVertex in primitive.rs
+   uint stride = mesh.vertex_layout == VERTEX_LAYOUT_COMPACT_COLOR ? 9 : 8;
+   uint base = index * stride;
+
+   Vertex vertex;
+   vertex.pos = vec4(uintBitsToFloat(packedVerticesSSBO[mesh.vertex_buffer].words[base + 0]),
+                     uintBitsToFloat(packedVerticesSSBO[mesh.vertex_buffer].words[base + 1]),
+                     uintBitsToFloat(packedVerticesSSBO[mesh.vertex_buffer].words[base + 2]),
+                     0.0);
+   vertex.normal = vec4(unpackSnorm2x16(packedVerticesSSBO[mesh.vertex_buffer].words[base + 3]),
+                        unpackSnorm2x16(packedVerticesSSBO[mesh.vertex_buffer].words[base + 4]));
+   vertex.tangent = vec4(unpackSnorm2x16(packedVerticesSSBO[mesh.vertex_buffer].words[base + 5]),
+                         unpackSnorm2x16(packedVerticesSSBO[mesh.vertex_buffer].words[base + 6]));
+   vertex.uv = unpackHalf2x16(packedVerticesSSBO[mesh.vertex_buffer].words[base + 7]);
+   vertex.color = vec4(1.0);
+
+   if (mesh.vertex_layout == VERTEX_LAYOUT_COMPACT_COLOR)
+      vertex.color = unpackUnorm4x8(packedVerticesSSBO[mesh.vertex_buffer].words[base + 8]);
+
+   return vertex;
+}
+
+uint load_index16(uint index_buffer, uint i)
+{
+   uint word = packedIndicesSSBO[index_buffer].words[i / 2];
+   return (i % 2 == 0) ? (word & 0xffff) : (word >> 16);
+}
+
+uint load_index(Mesh mesh, uint i)
+{
+   if (mesh.index_size == 4)
+      return packedIndicesSSBO[mesh.index_buffer].words[i];
+
+   return load_index16(mesh.index_buffer, i);
+}
+
+uvec3 load_triangle(Mesh mesh, uint triangle)
+{
+   if (mesh.index_size == 4)
+      return uvec3(indicesSSBO[mesh.index_buffer].indices[triangle]);
+
+   uint first = triangle * 3;
+   return uvec3(load_index16(mesh.index_buffer, first + 0),
+                load_index16(mesh.index_buffer, first + 1),
+                load_index16(mesh.index_buffer, first + 2));
+}
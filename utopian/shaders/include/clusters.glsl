@@ -0,0 +1,59 @@
+// Clustered light culling, see light_culling.rs. The view frustum is divided into
+// screen space tiles and depth slices that are distributed exponentially between the
+// near and far plane so that the clusters are roughly cubical.
+
+// Must match CLUSTER_GRID and MAX_LIGHTS_PER_CLUSTER in light_culling.rs
+#define CLUSTER_GRID_X 16u
+#define CLUSTER_GRID_Y 9u
+#define CLUSTER_GRID_Z 24u
+#define MAX_LIGHTS_PER_CLUSTER 128u
+
+// Light count of the clusters that more than MAX_LIGHTS_PER_CLUSTER lights reach,
+// they are shaded with all lights instead of dropping some
+#define CLUSTER_OVERFLOW 0xffffffffu
+
+// View depth of the near side of a depth slice, slice CLUSTER_GRID_Z is the far plane
+float cluster_slice_depth(uint slice)
+{
+   float near_depth = view_depth(0.0);
+   float far_depth = view_depth(1.0);
+
+   return near_depth * pow(far_depth / near_depth, float(slice) / float(CLUSTER_GRID_Z));
+}
+
+uint cluster_slice(float depth)
+{
+   float near_depth = view_depth(0.0);
+   float far_depth = view_depth(1.0);
+   float slice = log(max(depth, near_depth) / near_depth) / log(far_depth / near_depth) * float(CLUSTER_GRID_Z);
+
+   return min(uint(slice), CLUSTER_GRID_Z - 1);
+}
+
+uint cluster_index(uvec3 cluster)
+{
+   return cluster.x + cluster.y * CLUSTER_GRID_X + cluster.z * CLUSTER_GRID_X * CLUSTER_GRID_Y;
+}
+
+// The cluster of a pixel shading the surface at world_position
+uint cluster_index(vec2 frag_coord, vec3 world_position)
+{
+   vec2 viewport = vec2(view.viewport_width, view.viewport_height);
+   uvec2 tile = min(uvec2(frag_coord / viewport * vec2(CLUSTER_GRID_X, CLUSTER_GRID_Y)),
+                    uvec2(CLUSTER_GRID_X - 1, CLUSTER_GRID_Y - 1));
+   float depth = -(view.view * vec4(world_position, 1.0)).z;
+
+   return cluster_index(uvec3(tile, cluster_slice(depth)));
+}
+
+// Black for no lights, then blue to green to red as the count approaches the maximum
+vec3 cluster_heatmap(uint light_count)
+{
+   if (light_count == 0u)
+      return vec3(0.0);
+
+   float t = clamp(float(light_count) / float(MAX_LIGHTS_PER_CLUSTER), 0.0, 1.0);
+   vec3 low = mix(vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), clamp(t * 2.0, 0.0, 1.0));
+
+   return mix(low, vec3(1.0, 0.0, 0.0), clamp(t * 2.0 - 1.0, 0.0, 1.0));
+}
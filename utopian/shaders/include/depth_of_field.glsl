@@ -0,0 +1,9 @@
+// Radius in pixels of the circle of confusion of a thin lens. Negative in front of
+// the focal plane and positive behind it.
+float circle_of_confusion(float depth, float focal_distance)
+{
+   float magnification = view.focal_length / max(focal_distance - view.focal_length, 1e-4);
+   float coc_diameter = view.aperture_diameter * magnification * (depth - focal_distance) / depth;
+
+   return 0.5 * coc_diameter / view.sensor_height * float(view.viewport_height);
+}
@@ -20,7 +20,7 @@ vec3 fxaa(sampler2D inputTexture, vec2 uv)
    vec3 colorCenter = texture(inputTexture, uv).rgb;
    vec3 outColor = colorCenter;
 
-   if (settings_fxaa.enabled_debug_threshold.x != 1.0)
+   if (settings_present.enabled_debug_threshold.x != 1.0)
    {
       return colorCenter;
    }
@@ -46,7 +46,7 @@ vec3 fxaa(sampler2D inputTexture, vec2 uv)
    float lumaRange = lumaMax - lumaMin;
 
    // If the luma variation is lower that a threshold (or if we are in a really dark area), we are not on an edge, don't perform any AA.
-   if(lumaRange < max(EDGE_THRESHOLD_MIN, lumaMax * settings_fxaa.enabled_debug_threshold.z))
+   if(lumaRange < max(EDGE_THRESHOLD_MIN, lumaMax * settings_present.enabled_debug_threshold.z))
    {
       return colorCenter;
    }
@@ -244,7 +244,7 @@ vec3 fxaa(sampler2D inputTexture, vec2 uv)
    vec3 finalColor = texture(inputTexture, finalUv).rgb;
    outColor = finalColor;
 
-   if (settings_fxaa.enabled_debug_threshold.y == 1.0)
+   if (settings_present.enabled_debug_threshold.y == 1.0)
    {
       if (isHorizontal)
          outColor = vec3(1.0, 0.0, 0.0);
@@ -0,0 +1,80 @@
+// Light evaluation shared by the rasterization and ray tracing shaders, see utopian/src/light.rs
+// Note: light.intensity is color * intensity in physical units (candela, lux or nits)
+
+const float LIGHT_PI = 3.14159265359;
+
+// Smooth falloff to zero at the light range as recommended by KHR_lights_punctual
+float light_range_window(float distance_to_light, float range)
+{
+   if (range <= 0.0)
+      return 1.0;
+
+   float x = distance_to_light / range;
+   float window = clamp(1.0 - x * x * x * x, 0.0, 1.0);
+   return window * window;
+}
+
+float light_area(const Light light)
+{
+   if (light.type == LIGHT_TYPE_RECT_AREA)
+      return light.params.x * light.params.y;
+   else if (light.type == LIGHT_TYPE_DISK_AREA)
+      return LIGHT_PI * light.params.x * light.params.x;
+
+   return 0.0;
+}
+
+// Returns a point on the surface of area lights, rnd is uniform in [0, 1].
+// vec2(0.5) gives the center which is used when only a single sample is taken.
+vec3 light_sample_position(const Light light, vec2 rnd)
+{
+   vec3 bitangent = cross(light.dir, light.tangent.xyz);
+
+   if (light.type == LIGHT_TYPE_RECT_AREA)
+   {
+      return light.pos + light.tangent.xyz * (rnd.x - 0.5) * light.params.x
+                       + bitangent * (rnd.y - 0.5) * light.params.y;
+   }
+   else if (light.type == LIGHT_TYPE_DISK_AREA)
+   {
+      float r = light.params.x * sqrt(rnd.x);
+      float phi = 2.0 * LIGHT_PI * rnd.y;
+      return light.pos + light.tangent.xyz * r * cos(phi) + bitangent * r * sin(phi);
+   }
+
+   return light.pos;
+}
+
+// Radiance arriving at `position` from `light_pos` on the light, without the cosine term
+// at the receiving surface. L is the direction towards the light.
+vec3 light_incident_radiance(const Light light, vec3 position, vec3 light_pos, out vec3 L, out float distance_to_light)
+{
+   if (light.type == LIGHT_TYPE_DIRECTIONAL)
+   {
+      L = -light.dir;
+      distance_to_light = 1e30;
+      return light.intensity;
+   }
+
+   vec3 to_light = light_pos - position;
+   distance_to_light = length(to_light);
+   L = to_light / max(distance_to_light, 0.0001);
+   float distance_sq = max(distance_to_light * distance_to_light, 0.0001);
+
+   if (light.type == LIGHT_TYPE_POINT)
+   {
+      return light.intensity * light_range_window(distance_to_light, light.range) / distance_sq;
+   }
+   else if (light.type == LIGHT_TYPE_SPOT)
+   {
+      float cos_theta = dot(-L, light.dir);
+      float t = clamp((cos_theta - light.params.y) / max(light.params.x - light.params.y, 0.0001), 0.0, 1.0);
+      return light.intensity * t * t * light_range_window(distance_to_light, light.range) / distance_sq;
+   }
+
+   // Area lights emit on the side facing light.dir. The area is added to the denominator
+   // to avoid the singularity when the surface is close to the light.
+   float area = light_area(light);
+   float cos_light = max(dot(-L, light.dir), 0.0);
+   return light.intensity * area * cos_light / (distance_sq + area);
+}
@@ -0,0 +1,59 @@
+// Must match AlphaMode in gltf_loader.rs
+#define ALPHA_MODE_OPAQUE 0
+#define ALPHA_MODE_MASK 1
+#define ALPHA_MODE_BLEND 2
+
+// Index into Material.uv_transforms
+#define UV_TRANSFORM_DIFFUSE 0
+#define UV_TRANSFORM_NORMAL 1
+#define UV_TRANSFORM_METALLIC_ROUGHNESS 2
+#define UV_TRANSFORM_OCCLUSION 3
+#define UV_TRANSFORM_EMISSIVE 4
+
+// KHR_texture_transform
+vec2 transform_uv(const Material material, uint texture_slot, vec2 uv)
+{
+   vec3 uv1 = vec3(uv, 1.0);
+   return vec2(dot(material.uv_transforms[texture_slot * 2].xyz, uv1),
+               dot(material.uv_transforms[texture_slot * 2 + 1].xyz, uv1));
+}
+
+vec3 sample_emissive(const Material material, vec2 uv)
+{
+   // Emissive textures use an sRGB format so the sampled value is already linear
+   vec3 emissive = texture(samplerColor[material.emissive_map], transform_uv(material, UV_TRANSFORM_EMISSIVE, uv)).rgb;
+
+   return emissive * material.emissive_factor.rgb;
+}
+
+// Blended materials use screen-door transparency with a 4x4 Bayer pattern since
+// the rasterization passes have blending disabled.
+// Todo: sorted forward pass with alpha blending
+bool alpha_test_discard(const Material material, float alpha, vec2 frag_coord)
+{
+   if (material.alpha_mode == ALPHA_MODE_MASK)
+   {
+      return alpha < material.alpha_cutoff;
+   }
+   else if (material.alpha_mode == ALPHA_MODE_BLEND)
+   {
+      const float bayer[16] = float[](0.0, 8.0, 2.0, 10.0,
+                                      12.0, 4.0, 14.0, 6.0,
+                                      3.0, 11.0, 1.0, 9.0,
+                                      15.0, 7.0, 13.0, 5.0);
+      ivec2 p = ivec2(frag_coord) % 4;
+      return alpha <= (bayer[p.y * 4 + p.x] + 0.5) / 16.0;
+   }
+
+   return false;
+}
+
+// Back faces of double-sided materials are shaded with the flipped normal.
+// Note: single-sided materials are not culled since the pipelines use cull mode NONE
+vec3 double_sided_normal(const Material material, vec3 normal, vec3 position, vec3 eye_pos)
+{
+   if (material.double_sided == 1 && dot(normal, eye_pos - position) < 0.0)
+      return -normal;
+
+   return normal;
+}
@@ -0,0 +1,52 @@
+// Only for vertex shaders, resolves the vertex of both regular and indirect mesh draws
+
+// Push constant mesh index of indirect draws whose world transform and mesh come from
+// the instance buffer instead. Must match INDIRECT_DRAW_MESH_INDEX in renderer.rs
+#define INDIRECT_DRAW_MESH_INDEX 0xffffffffu
+
+// Push constant instance index of regular draws of meshes that are not in the instance
+// buffer. Must match NO_INSTANCE_INDEX in renderer.rs
+#define NO_INSTANCE_INDEX 0xffffffffu
+
+struct MeshDraw
+{
+   mat4 world;
+   uint mesh_index;
+   Vertex vertex;
+};
+
+MeshDraw load_mesh_draw(mat4 world, uint mesh_index)
+{
+   MeshDraw draw;
+   draw.world = world;
+   draw.mesh_index = mesh_index;
+
+   // Regular draws are indexed so the vertex index is the index from the index buffer
+   uint vertex_index = gl_VertexIndex;
+
+   // The instance is the first instance of the indirect draw command. Those draws are not
+   // indexed, the vertex index is a position in the index buffer of the mesh instead.
+   if (mesh_index == INDIRECT_DRAW_MESH_INDEX)
+   {
+      Instance instance = instancesSSBO.instances[gl_InstanceIndex];
+      draw.world = instance.world;
+      draw.mesh_index = instance.mesh;
+      vertex_index = load_index(meshesSSBO.meshes[draw.mesh_index], gl_VertexIndex);
+   }
+
+   draw.vertex = load_vertex(meshesSSBO.meshes[draw.mesh_index], vertex_index);
+
+   return draw;
+}
+
+// The world transform of the previous frame, `instance_index` is only used by regular draws
+mat4 load_prev_world(MeshDraw draw, uint mesh_index, uint instance_index)
+{
+   if (mesh_index == INDIRECT_DRAW_MESH_INDEX)
+      return instancesSSBO.instances[gl_InstanceIndex].prev_world;
+
+   if (instance_index == NO_INSTANCE_INDEX)
+      return draw.world;
+
+   return instancesSSBO.instances[instance_index].prev_world;
+}
@@ -3,6 +3,7 @@
 // PBR code reused from https://github.com/simplerr/UtopianEngine
 
 #include "include/brdf.glsl"
+#include "include/lights.glsl"
 
 
 
@@ -15,6 +16,9 @@ struct PixelParams
    float metallic;
    float roughness;
    float occlusion;
+   float transmission;        // KHR_materials_transmission, replaces the diffuse lobe
+   float clearcoat;           // KHR_materials_clearcoat
+   float clearcoatRoughness;
 };
 
 vec3 surfaceShading(const PixelParams pixel, const Light light, const vec3 eyePos, float lightColorFactor)
@@ -29,34 +33,17 @@ vec3 surfaceShading(const PixelParams pixel, const Light light, const vec3 eyePo
    vec3 F0 = vec3(0.04);
    F0 = mix(F0, pixel.baseColor, pixel.metallic);
 
+   // Area lights are approximated with a single sample at their center
    vec3 L = vec3(0.0);
-   float attenuation = 1.0f;
-   vec3 posToLight = light.pos - pixel.position;
-
-   if(light.type == 0.0f) // Directional light
-   {
-      L = normalize(light.dir * vec3(-1,1,-1));
-      attenuation = 1.0f;
-   }
-   else if(light.type == 1.0f) // Point light
-   {
-      L = normalize(posToLight);
-      float d = length(posToLight);
-      attenuation = 1.0f / dot(light.att, vec3(1.0f, d, d*d));
-   }
-   else if(light.type == 2.0f) // Spot light
-   {
-      L = normalize(posToLight);
-      float d = length(posToLight);
-      float spot = pow(max(dot(L, normalize(light.dir)), 0.0f), light.spot);
-      attenuation = spot / dot(light.att, vec3(1.0f, d, d*d));
-   }
+   float distanceToLight = 0.0f;
+   vec3 lightPos = light_sample_position(light, vec2(0.5f));
+   vec3 incidentRadiance = light_incident_radiance(light, pixel.position, lightPos, L, distanceToLight);
 
    // Reflectance equation
    vec3 Lo = vec3(0.0);
 
    vec3 H = normalize(V + L);
-   vec3 radiance     = light.color.rgb * attenuation * lightColorFactor;
+   vec3 radiance     = incidentRadiance * lightColorFactor;
 
    // Cook-torrance brdf
    float NDF = DistributionGGX(N, H, pixel.roughness);
@@ -66,6 +53,7 @@ vec3 surfaceShading(const PixelParams pixel, const Light light, const vec3 eyePo
    vec3 kS = F;
    vec3 kD = vec3(1.0) - kS;
    kD *= 1.0 - pixel.metallic;
+   kD *= 1.0 - pixel.transmission; // Note: the transmitted light itself is only handled by the path tracer
 
    vec3 numerator    = NDF * G * F;
    float denominator = 4.0 * max(dot(N, V), 0.0) * max(dot(N, L), 0.0) + 0.0001;
@@ -75,6 +63,17 @@ vec3 surfaceShading(const PixelParams pixel, const Light light, const vec3 eyePo
    float NdotL = max(dot(N, L), 0.0);
    color = (kD * pixel.baseColor / PI + specular) * radiance * NdotL;
 
+   // Clearcoat layer on top of the base material, uses the same normal as the base layer
+   if (pixel.clearcoat > 0.0)
+   {
+      float clearcoatNDF = DistributionGGX(N, H, pixel.clearcoatRoughness);
+      float clearcoatG   = GeometrySmith(N, V, L, pixel.clearcoatRoughness);
+      vec3 clearcoatF    = fresnelSchlick(max(dot(H, V), 0.0), vec3(0.04)) * pixel.clearcoat;
+      vec3 clearcoatSpecular = clearcoatNDF * clearcoatG * clearcoatF / denominator;
+
+      color = color * (1.0 - clearcoatF) + clearcoatSpecular * radiance * NdotL;
+   }
+
    return color;
 }
 
@@ -91,6 +90,7 @@ vec3 imageBasedLighting(const PixelParams pixel, const vec3 eyePos, samplerCube
    vec3 kS = F;
    vec3 kD = 1.0 - kS;
    kD *= 1.0 - pixel.metallic;
+   kD *= 1.0 - pixel.transmission;
 
    vec3 irradiance = texture(in_irradiance_map, pixel.normal).rgb;
    vec3 diffuse    = irradiance * pixel.baseColor;
@@ -104,5 +104,15 @@ vec3 imageBasedLighting(const PixelParams pixel, const vec3 eyePos, samplerCube
 
    vec3 ambient = (kD * diffuse + specular) * pixel.occlusion;
 
+   if (pixel.clearcoat > 0.0)
+   {
+      vec3 clearcoatF = fresnelSchlickRoughness(max(dot(pixel.normal, V), 0.0), vec3(0.04), pixel.clearcoatRoughness) * pixel.clearcoat;
+      vec3 clearcoatPrefiltered = textureLod(in_specular_map, R, pixel.clearcoatRoughness * MAX_REFLECTION_LOD).rgb;
+      vec2 clearcoatBrdf = texture(in_brdf_lut, vec2(max(dot(pixel.normal, V), 0.0), 1.0f - pixel.clearcoatRoughness)).rg;
+      vec3 clearcoatSpecular = clearcoatPrefiltered * (clearcoatF * clearcoatBrdf.x + clearcoatBrdf.y);
+
+      ambient = ambient * (1.0 - clearcoatF) + clearcoatSpecular * pixel.occlusion;
+   }
+
    return ambient;
 }
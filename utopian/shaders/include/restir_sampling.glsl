@@ -46,6 +46,8 @@ How are two reservoirs combined?
    [2] https://benedikt-bitterli.me/restir/bitterli20restir.pdf
 */
 
+#include "include/lights.glsl"
+
 #define RIS_CANDIDATES_LIGHTS 32
 
 struct Reservoir
@@ -56,16 +58,13 @@ struct Reservoir
    int M;         // number of samples
 };
 
-vec3 get_light_intensity(in Light light, float distance_to_light)
-{
-   return light.intensity / pow(distance_to_light, 2.0);
-}
-
 float target_function(int light_index, in vec3 hit_position)
 {
    Light light = lightsSSBO.lights[light_index];
-   float distance_to_light = distance(light.pos, hit_position);
-   return luminance(get_light_intensity(light, distance_to_light));
+   vec3 L;
+   float distance_to_light;
+   vec3 light_pos = light_sample_position(light, vec2(0.5));
+   return luminance(light_incident_radiance(light, hit_position, light_pos, L, distance_to_light));
 }
 
 void sample_light_uniform(inout uint rngState, out int selected_light_index, out float light_sample_weight)
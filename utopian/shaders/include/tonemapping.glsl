@@ -0,0 +1,118 @@
+
+// Must match Tonemapper in present.rs
+#define TONEMAPPER_CLAMP 0
+#define TONEMAPPER_ACES_FITTED 1
+#define TONEMAPPER_AGX 2
+#define TONEMAPPER_REINHARD_EXTENDED 3
+#define TONEMAPPER_PBR_NEUTRAL 4
+
+// ACES fit by Stephen Hill (@self_shadow)
+// https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+vec3 aces_fitted(vec3 color)
+{
+   // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+   const mat3 aces_input = mat3(
+      0.59719, 0.07600, 0.02840,
+      0.35458, 0.90834, 0.13383,
+      0.04823, 0.01566, 0.83777);
+
+   // ODT_SAT => XYZ => D60_2_D65 => sRGB
+   const mat3 aces_output = mat3(
+       1.60475, -0.10208, -0.00327,
+      -0.53108,  1.10813, -0.07276,
+      -0.07367, -0.00605,  1.07602);
+
+   color = aces_input * color;
+
+   // RRT and ODT fit
+   vec3 a = color * (color + 0.0245786) - 0.000090537;
+   vec3 b = color * (0.983729 * color + 0.4329510) + 0.238081;
+   color = a / b;
+
+   return clamp(aces_output * color, 0.0, 1.0);
+}
+
+// Minimal AgX with the default look by Benjamin Wrensch
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+vec3 agx_default_contrast(vec3 x)
+{
+   vec3 x2 = x * x;
+   vec3 x4 = x2 * x2;
+
+   return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 color)
+{
+   const mat3 agx_inset = mat3(
+      0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+      0.0784335999999992, 0.878468636469772, 0.0784336,
+      0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+
+   const mat3 agx_outset = mat3(
+      1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+      -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+      -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+
+   const float min_ev = -12.47393;
+   const float max_ev = 4.026069;
+
+   color = agx_inset * color;
+   color = clamp(log2(max(color, vec3(1e-10))), min_ev, max_ev);
+   color = (color - min_ev) / (max_ev - min_ev);
+   color = agx_default_contrast(color);
+   color = agx_outset * color;
+
+   // The curve outputs display encoded values, decode them since the sRGB encoding
+   // is applied by the caller
+   return pow(clamp(color, 0.0, 1.0), vec3(2.2));
+}
+
+// Maps white_point and above to 1.0
+vec3 reinhard_extended(vec3 color, float white_point)
+{
+   vec3 numerator = color * (1.0 + color / (white_point * white_point));
+
+   return clamp(numerator / (1.0 + color), 0.0, 1.0);
+}
+
+// Khronos PBR Neutral, keeps the base colors of materials lit by a white light intact
+// https://github.com/KhronosGroup/ToneMapping/tree/main/PBR_Neutral
+vec3 pbr_neutral(vec3 color)
+{
+   const float start_compression = 0.8 - 0.04;
+   const float desaturation = 0.15;
+
+   float x = min(color.r, min(color.g, color.b));
+   float offset = x < 0.08 ? x - 6.25 * x * x : 0.04;
+   color -= offset;
+
+   float peak = max(color.r, max(color.g, color.b));
+   if (peak < start_compression)
+      return color;
+
+   const float d = 1.0 - start_compression;
+   float new_peak = 1.0 - d * d / (peak + d - start_compression);
+   color *= new_peak / peak;
+
+   float g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+
+   return mix(color, vec3(new_peak), g);
+}
+
+vec3 tonemap(vec3 color, uint tonemapper, float white_point)
+{
+   switch (tonemapper)
+   {
+      case TONEMAPPER_ACES_FITTED:
+         return aces_fitted(color);
+      case TONEMAPPER_AGX:
+         return agx(color);
+      case TONEMAPPER_REINHARD_EXTENDED:
+         return reinhard_extended(color, white_point);
+      case TONEMAPPER_PBR_NEUTRAL:
+         return pbr_neutral(color);
+      default:
+         return clamp(color, 0.0, 1.0);
+   }
+}
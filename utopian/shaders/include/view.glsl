@@ -10,22 +10,30 @@ layout (std140, set = 1, binding = 0) uniform UBO_view
     uint samples_per_frame;
     vec3 sun_dir;
     uint total_samples;
+    vec2 jitter; // Sub-pixel offset of the projection in NDC, 8 byte aligned
     uint num_bounces;
     uint viewport_width;
     uint viewport_height;
     float time;
     uint num_lights;
+    // Thin lens in world units, aperture_diameter is 0 when depth of field is disabled
+    float aperture_diameter;
+    float focal_length;
+    float sensor_height;
 
     // render settings
     uint shadows_enabled;
     uint ssao_enabled;
     uint fxaa_enabled;
+    uint taa_enabled;
+    uint dof_enabled;
     uint cubemap_enabled;
     uint ibl_enabled;
     uint sky_enabled;
     uint sun_shadow_enabled;
     uint lights_enabled;
     uint max_num_lights_used;
+    uint light_clusters_debug;
     uint marching_cubes_enabled;
     uint temporal_reuse_enabled;
     uint spatial_reuse_enabled;
@@ -65,6 +73,14 @@ vec3 linearToSrgb(vec3 linearColor)
    return vec3(linearToSrgb(linearColor.x), linearToSrgb(linearColor.y), linearToSrgb(linearColor.z));
 }
 
+// Distance along the view direction of a depth buffer value
+float view_depth(float depth)
+{
+   vec4 view_pos = view.inverse_projection * vec4(0.0, 0.0, depth, 1.0);
+
+   return -view_pos.z / view_pos.w;
+}
+
 vec3 extract_camera_position(mat4 viewMatrix) {
    mat4 inverseViewMatrix = inverse(viewMatrix);
    vec3 cameraPosition = vec3(inverseViewMatrix[3]);
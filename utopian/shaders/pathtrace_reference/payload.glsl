@@ -2,7 +2,8 @@
 struct Payload
 {
    vec4 colorDistance; // rgb + t
-   vec4 scatterDirection; // xyz + is scattered
+   vec4 scatterDirection; // xyz + is scattered (0 = absorbed, 1 = scattered, 2 = passed through)
    vec4 normal;
+   vec4 emission;
    uint randomSeed;
 };
@@ -6,6 +6,7 @@
 #include "include/bindless.glsl"
 #include "include/view.glsl"
 #include "include/pbr_lighting.glsl"
+#include "include/tonemapping.glsl"
 
 layout (location = 0) in vec2 in_uv;
 
@@ -13,10 +14,24 @@ layout (location = 0) out vec4 out_color;
 
 layout (set = 2, binding = 0) uniform sampler2D in_color_texture;
 
-layout(std140, set = 3, binding = 0) uniform FXAA_settings
+layout (std430, set = 2, binding = 1) readonly buffer ExposureSSBO
 {
+   float exposure;
+   float average_luminance;
+   float last_update_time;
+   float pad;
+} exposureSSBO;
+
+// Color grading lookup table, indexed with sRGB encoded colors
+layout (set = 2, binding = 2) uniform sampler3D in_color_grading_lut;
+
+layout(std140, set = 3, binding = 0) uniform UBO_settings_present
+{
+   // FXAA settings
    vec4 enabled_debug_threshold;
-} settings_fxaa;
+   // x = tonemapper, y = white point
+   vec4 tonemapping;
+} settings_present;
 
 #include "include/fxaa.glsl"
 
@@ -32,10 +47,16 @@ void main() {
         color = texture(in_color_texture, uv).rgb;
     }
 
-    /* Tonemapping */
-    // color = color / (color + vec3(1.0));
+    color = tonemap(color * exposureSSBO.exposure,
+                    uint(settings_present.tonemapping.x),
+                    settings_present.tonemapping.y);
     color = linearToSrgb(color);
 
+    // Sample at the texel centers so that the ends of the range map to the first and last texel
+    float lut_size = float(textureSize(in_color_grading_lut, 0).x);
+    vec3 lut_coord = clamp(color, 0.0, 1.0) * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+    color = texture(in_color_grading_lut, lut_coord).rgb;
+
     out_color = vec4(color, 1.0);
 }
 
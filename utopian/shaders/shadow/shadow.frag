@@ -6,6 +6,7 @@
 #include "include/bindless.glsl"
 #include "include/view.glsl"
 #include "include/pbr_lighting.glsl"
+#include "include/material.glsl"
 
 layout (location = 0) in vec3 in_pos;
 layout (location = 1) in vec2 in_uv;
@@ -13,10 +14,18 @@ layout (location = 2) in vec3 in_normal;
 layout (location = 3) in vec4 in_color;
 layout (location = 4) in vec4 in_tangent;
 layout (location = 5) in mat3 in_tbn;
+layout (location = 8) flat in uint in_mesh_index;
 
 //layout (location = 0) out vec4 out_color;
 
 void main() {
-    // Empty
+    // Alpha tested and blended surfaces only cast shadows where they are opaque
+    Mesh mesh = meshesSSBO.meshes[in_mesh_index];
+    Material material = materialsSSBO.materials[mesh.material];
+
+    float alpha = texture(samplerColor[material.diffuse_map], transform_uv(material, UV_TRANSFORM_DIFFUSE, in_uv)).a;
+
+    if (alpha_test_discard(material, alpha * material.base_color_factor.a, gl_FragCoord.xy))
+        discard;
 }
 
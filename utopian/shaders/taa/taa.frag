@@ -0,0 +1,150 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+#extension GL_GOOGLE_include_directive : enable
+
+#include "include/bindless.glsl"
+#include "include/view.glsl"
+
+layout (location = 0) in vec2 in_uv;
+
+// The alpha channel is the motion of the pixel in pixels, used for the disocclusion test
+// when it is the history of the next frame
+layout (location = 0) out vec4 out_color;
+
+layout (set = 2, binding = 0) uniform sampler2D in_color;
+layout (set = 2, binding = 1) uniform sampler2D in_depth;
+layout (set = 2, binding = 2) uniform sampler2D in_velocity;
+layout (set = 2, binding = 3) uniform sampler2D in_history;
+
+layout (std140, set = 3, binding = 0) uniform UBO_settings
+{
+   float current_weight;
+   uint history_valid;
+} settings_taa;
+
+// Reversible tonemapping so that bright pixels do not dominate the blending and clipping
+vec3 tonemap(vec3 color)
+{
+   return color / (1.0 + luminance(color));
+}
+
+vec3 inverse_tonemap(vec3 color)
+{
+   return color / max(1.0 - luminance(color), 1e-4);
+}
+
+vec3 rgb_to_ycocg(vec3 color)
+{
+   return vec3(dot(color, vec3(0.25, 0.5, 0.25)),
+               dot(color, vec3(0.5, 0.0, -0.5)),
+               dot(color, vec3(-0.25, 0.5, -0.25)));
+}
+
+vec3 ycocg_to_rgb(vec3 color)
+{
+   return vec3(color.x + color.y - color.z,
+               color.x + color.z,
+               color.x - color.y - color.z);
+}
+
+vec3 load_color(ivec2 pixel, ivec2 size)
+{
+   return rgb_to_ycocg(tonemap(texelFetch(in_color, clamp(pixel, ivec2(0), size - 1), 0).rgb));
+}
+
+// Moves the history towards the center of the range until it is inside of it
+vec3 clip_to_range(vec3 history, vec3 center, vec3 extents)
+{
+   vec3 offset = history - center;
+   vec3 units = abs(offset / max(extents, vec3(1e-4)));
+   float max_unit = max(units.x, max(units.y, units.z));
+
+   return max_unit > 1.0 ? center + offset / max_unit : history;
+}
+
+// The sky has no velocity in the G-buffer, only the camera rotation moves it
+vec2 background_velocity(vec2 uv)
+{
+   vec2 ndc = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+   vec4 view_pos = view.inverse_projection * vec4(ndc, 1.0, 1.0);
+   vec3 world_dir = mat3(view.inverse_view) * (view_pos.xyz / view_pos.w);
+   vec4 prev_clip_pos = view.prev_frame_projection_view * vec4(world_dir, 0.0);
+
+   return (ndc - view.jitter - prev_clip_pos.xy / prev_clip_pos.w) * vec2(0.5, -0.5);
+}
+
+// The motion of the closest surface in the 3x3 neighborhood, so that the edges of moving
+// objects are reprojected with them instead of leaving a trail
+vec2 dilated_velocity(ivec2 pixel, ivec2 size, vec2 uv)
+{
+   float closest_depth = 1.0;
+   ivec2 closest_pixel = pixel;
+
+   for (int y = -1; y <= 1; y++)
+   {
+      for (int x = -1; x <= 1; x++)
+      {
+         ivec2 neighbor = clamp(pixel + ivec2(x, y), ivec2(0), size - 1);
+         float depth = texelFetch(in_depth, neighbor, 0).r;
+
+         if (depth < closest_depth)
+         {
+            closest_depth = depth;
+            closest_pixel = neighbor;
+         }
+      }
+   }
+
+   if (closest_depth >= 1.0)
+      return background_velocity(uv);
+
+   return texelFetch(in_velocity, closest_pixel, 0).rg;
+}
+
+void main()
+{
+   vec2 uv = FLIP_UV_Y(in_uv);
+   ivec2 size = textureSize(in_color, 0);
+   ivec2 pixel = ivec2(gl_FragCoord.xy);
+
+   vec3 current = texelFetch(in_color, pixel, 0).rgb;
+   vec2 velocity = dilated_velocity(pixel, size, uv);
+   float velocity_pixels = length(velocity * vec2(size));
+   vec2 history_uv = uv - velocity;
+
+   if (settings_taa.history_valid == 0 ||
+       any(lessThan(history_uv, vec2(0.0))) || any(greaterThan(history_uv, vec2(1.0))))
+   {
+      out_color = vec4(current, velocity_pixels);
+      return;
+   }
+
+   // Mean and standard deviation of the neighborhood colors
+   vec3 moment1 = vec3(0.0);
+   vec3 moment2 = vec3(0.0);
+   for (int y = -1; y <= 1; y++)
+   {
+      for (int x = -1; x <= 1; x++)
+      {
+         vec3 color = load_color(pixel + ivec2(x, y), size);
+         moment1 += color;
+         moment2 += color * color;
+      }
+   }
+
+   vec3 mean = moment1 / 9.0;
+   vec3 sigma = sqrt(max(moment2 / 9.0 - mean * mean, vec3(0.0)));
+
+   vec4 history = texture(in_history, history_uv);
+   vec3 history_color = clip_to_range(rgb_to_ycocg(tonemap(history.rgb)), mean, sigma);
+
+   // A history pixel that moved differently most likely belongs to another surface
+   // that was disoccluded, its clipped color is still blended in a bit
+   float motion_difference = abs(history.a - velocity_pixels);
+   float weight = mix(settings_taa.current_weight, 1.0, clamp((motion_difference - 1.0) * 0.5, 0.0, 0.9));
+
+   vec3 color = mix(history_color, rgb_to_ycocg(tonemap(current)), weight);
+
+   out_color = vec4(inverse_tonemap(ycocg_to_rgb(color)), velocity_pixels);
+}
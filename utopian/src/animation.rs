@@ -0,0 +1,544 @@
+use glam::{Mat4, Quat, Vec3};
+use std::collections::HashMap;
+use std::ops::{Add, Mul};
+
+use crate::gltf_loader::Mesh;
+use crate::scene_graph::*;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+// For cubic spline interpolation every keyframe stores an in-tangent,
+// the value and an out-tangent, in that order.
+pub enum ChannelValues {
+    Translations(Vec<Vec3>),
+    Rotations(Vec<Quat>),
+    Scales(Vec<Vec3>),
+    // `num_targets` weights per keyframe
+    MorphWeights {
+        weights: Vec<f32>,
+        num_targets: usize,
+    },
+}
+
+pub struct Channel {
+    pub node: NodeId,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    pub duration: f32,
+}
+
+// A clip that is being played on a model instance
+#[derive(Copy, Clone)]
+pub struct AnimationState {
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    // Clips that are played at the same time are blended by their weights
+    pub weight: f32,
+    pub looping: bool,
+}
+
+#[derive(Copy, Clone)]
+struct NodePose {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+// Weighted sums of the sampled channels for one node
+#[derive(Default)]
+struct BlendedPose {
+    translation: Vec3,
+    translation_weight: f32,
+    rotation: Quat,
+    rotation_weight: f32,
+    scale: Vec3,
+    scale_weight: f32,
+    morph_weights: Vec<f32>,
+    morph_weights_weight: f32,
+}
+
+/// Plays and blends the animation clips of a model instance. The sampled poses are
+/// written as local transforms to the scene graph and as morph target weights to
+/// the meshes. Properties that are not animated by the playing clips, or clips with
+/// a total weight below 1.0, fall back to the rest pose of the model.
+pub struct AnimationPlayer {
+    pub states: Vec<AnimationState>,
+    rest_poses: HashMap<NodeId, NodePose>,
+    rest_morph_weights: HashMap<NodeId, Vec<f32>>,
+    // Set when the rest pose needs to be restored after all clips have stopped
+    needs_reset: bool,
+}
+
+impl Interpolation {
+    fn sample<T>(
+        &self,
+        times: &[f32],
+        values: &[T],
+        stride: usize,
+        component: usize,
+        time: f32,
+    ) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+    {
+        let value = |keyframe: usize, offset: usize| match self {
+            Interpolation::CubicSpline => values[(keyframe * 3 + offset) * stride + component],
+            _ => values[keyframe * stride + component],
+        };
+
+        let last = times.len() - 1;
+        if time <= times[0] {
+            return value(0, 1);
+        }
+        if time >= times[last] {
+            return value(last, 1);
+        }
+
+        let next = times.partition_point(|&t| t <= time);
+        let prev = next - 1;
+        let delta_time = times[next] - times[prev];
+        let t = (time - times[prev]) / delta_time;
+
+        match self {
+            Interpolation::Step => value(prev, 0),
+            Interpolation::Linear => value(prev, 0) * (1.0 - t) + value(next, 0) * t,
+            Interpolation::CubicSpline => {
+                // Hermite spline, see appendix C of the glTF specification
+                let t2 = t * t;
+                let t3 = t2 * t;
+                value(prev, 1) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + value(prev, 2) * (delta_time * (t3 - 2.0 * t2 + t))
+                    + value(next, 1) * (-2.0 * t3 + 3.0 * t2)
+                    + value(next, 0) * (delta_time * (t3 - t2))
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn sample_into(&self, time: f32, weight: f32, pose: &mut BlendedPose) {
+        let times = &self.times;
+
+        match &self.values {
+            ChannelValues::Translations(values) => {
+                let value = self.interpolation.sample(times, values, 1, 0, time);
+                pose.translation += value * weight;
+                pose.translation_weight += weight;
+            }
+            ChannelValues::Rotations(values) => {
+                let value = match self.interpolation {
+                    Interpolation::Linear => {
+                        // Quaternions are spherically interpolated instead of using `sample`
+                        let next = times
+                            .partition_point(|&t| t <= time)
+                            .clamp(1, times.len() - 1);
+                        let t = ((time - times[next - 1]) / (times[next] - times[next - 1]))
+                            .clamp(0.0, 1.0);
+                        values[next - 1].slerp(values[next], t)
+                    }
+                    _ => self.interpolation.sample(times, values, 1, 0, time),
+                }
+                .normalize();
+
+                // Keep the accumulated quaternions in the same hemisphere
+                let sign = if pose.rotation.dot(value) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                pose.rotation = pose.rotation + value * (sign * weight);
+                pose.rotation_weight += weight;
+            }
+            ChannelValues::Scales(values) => {
+                let value = self.interpolation.sample(times, values, 1, 0, time);
+                pose.scale += value * weight;
+                pose.scale_weight += weight;
+            }
+            ChannelValues::MorphWeights {
+                weights,
+                num_targets,
+            } => {
+                pose.morph_weights.resize(*num_targets, 0.0);
+                for (i, morph_weight) in pose.morph_weights.iter_mut().enumerate() {
+                    *morph_weight +=
+                        self.interpolation
+                            .sample(times, weights, *num_targets, i, time)
+                            * weight;
+                }
+                pose.morph_weights_weight += weight;
+            }
+        }
+    }
+}
+
+impl AnimationPlayer {
+    /// Captures the rest pose of all nodes and meshes that are animated by `clips`
+    pub fn new(clips: &[AnimationClip], scene_graph: &SceneGraph, meshes: &[Mesh]) -> Self {
+        let mut rest_poses = HashMap::new();
+        let mut rest_morph_weights = HashMap::new();
+
+        for channel in clips.iter().flat_map(|clip| clip.channels.iter()) {
+            match channel.values {
+                ChannelValues::MorphWeights { .. } => {
+                    let weights = meshes
+                        .iter()
+                        .find(|mesh| mesh.node == channel.node)
+                        .and_then(|mesh| mesh.deformation.as_ref())
+                        .map(|deformation| deformation.morph_weights.clone())
+                        .unwrap_or_default();
+                    rest_morph_weights.insert(channel.node, weights);
+                }
+                _ => {
+                    let (scale, rotation, translation) = scene_graph
+                        .local_transform(channel.node)
+                        .to_scale_rotation_translation();
+                    rest_poses.insert(
+                        channel.node,
+                        NodePose {
+                            translation,
+                            rotation,
+                            scale,
+                        },
+                    );
+                }
+            }
+        }
+
+        AnimationPlayer {
+            states: vec![],
+            rest_poses,
+            rest_morph_weights,
+            needs_reset: false,
+        }
+    }
+
+    pub fn play(&mut self, clip: usize, weight: f32, looping: bool) {
+        self.states.push(AnimationState {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            weight,
+            looping,
+        });
+    }
+
+    pub fn stop_all(&mut self) {
+        self.needs_reset = !self.states.is_empty();
+        self.states.clear();
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.states.is_empty()
+    }
+
+    /// Advances the playing clips by `delta_time` seconds and applies the blended pose
+    pub fn update(
+        &mut self,
+        clips: &[AnimationClip],
+        delta_time: f32,
+        scene_graph: &mut SceneGraph,
+        meshes: &mut [Mesh],
+    ) {
+        puffin::profile_function!();
+
+        if self.states.is_empty() && !self.needs_reset {
+            return;
+        }
+        self.needs_reset = false;
+
+        let mut poses: HashMap<NodeId, BlendedPose> = HashMap::new();
+
+        for state in &mut self.states {
+            let clip = &clips[state.clip];
+
+            state.time += delta_time * state.speed;
+            state.time = if state.looping && clip.duration > 0.0 {
+                state.time.rem_euclid(clip.duration)
+            } else {
+                state.time.clamp(0.0, clip.duration)
+            };
+
+            if state.weight <= 0.0 {
+                continue;
+            }
+
+            for channel in &clip.channels {
+                let pose = poses.entry(channel.node).or_insert_with(|| BlendedPose {
+                    rotation: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                    ..Default::default()
+                });
+                channel.sample_into(state.time, state.weight, pose);
+            }
+        }
+
+        // Fills up the remaining weight with the rest pose and normalizes the result
+        let blend = |weight: f32| (1.0 - weight).max(0.0);
+        let normalize = |weight: f32| 1.0 / weight.max(1.0);
+
+        for (&node, rest) in &self.rest_poses {
+            let pose = match poses.get(&node) {
+                Some(pose) => pose,
+                None => {
+                    scene_graph.set_local_transform(
+                        node,
+                        Mat4::from_scale_rotation_translation(
+                            rest.scale,
+                            rest.rotation,
+                            rest.translation,
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            let translation = (pose.translation
+                + rest.translation * blend(pose.translation_weight))
+                * normalize(pose.translation_weight);
+            let scale =
+                (pose.scale + rest.scale * blend(pose.scale_weight)) * normalize(pose.scale_weight);
+
+            let rest_rotation = if pose.rotation.dot(rest.rotation) < 0.0 {
+                -rest.rotation
+            } else {
+                rest.rotation
+            };
+            let rotation =
+                (pose.rotation + rest_rotation * blend(pose.rotation_weight)).normalize();
+
+            scene_graph.set_local_transform(
+                node,
+                Mat4::from_scale_rotation_translation(scale, rotation, translation),
+            );
+        }
+
+        for (&node, rest_weights) in &self.rest_morph_weights {
+            let weights: Vec<f32> = match poses.get(&node) {
+                Some(pose) => rest_weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &rest_weight)| {
+                        let weight = pose.morph_weights.get(i).copied().unwrap_or(0.0);
+                        (weight + rest_weight * blend(pose.morph_weights_weight))
+                            * normalize(pose.morph_weights_weight)
+                    })
+                    .collect(),
+                None => rest_weights.clone(),
+            };
+
+            for mesh in meshes.iter_mut().filter(|mesh| mesh.node == node) {
+                if let Some(deformation) = &mut mesh.deformation {
+                    for (morph_weight, weight) in deformation.morph_weights.iter_mut().zip(&weights)
+                    {
+                        *morph_weight = *weight;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation_clip(
+        node: NodeId,
+        interpolation: Interpolation,
+        values: Vec<Vec3>,
+    ) -> AnimationClip {
+        let times: Vec<f32> = match interpolation {
+            Interpolation::CubicSpline => (0..values.len() / 3).map(|i| i as f32).collect(),
+            _ => (0..values.len()).map(|i| i as f32).collect(),
+        };
+
+        AnimationClip {
+            name: String::from("clip"),
+            duration: *times.last().unwrap(),
+            channels: vec![Channel {
+                node,
+                interpolation,
+                times,
+                values: ChannelValues::Translations(values),
+            }],
+        }
+    }
+
+    fn translation(scene_graph: &SceneGraph, node: NodeId) -> Vec3 {
+        scene_graph.local_transform(node).w_axis.truncate()
+    }
+
+    #[test]
+    fn step_holds_the_previous_keyframe() {
+        let times = [0.0, 1.0, 2.0];
+        let values = [0.0, 10.0, 20.0];
+
+        assert_eq!(Interpolation::Step.sample(&times, &values, 1, 0, 0.5), 0.0);
+        assert_eq!(Interpolation::Step.sample(&times, &values, 1, 0, 1.0), 10.0);
+        assert_eq!(
+            Interpolation::Step.sample(&times, &values, 1, 0, 1.99),
+            10.0
+        );
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let times = [0.0, 2.0, 4.0];
+        let values = [0.0, 10.0, 30.0];
+
+        assert_eq!(
+            Interpolation::Linear.sample(&times, &values, 1, 0, 1.0),
+            5.0
+        );
+        assert_eq!(
+            Interpolation::Linear.sample(&times, &values, 1, 0, 3.0),
+            20.0
+        );
+    }
+
+    #[test]
+    fn sampling_is_clamped_to_the_first_and_last_keyframes() {
+        let times = [1.0, 2.0];
+        let values = [5.0, 10.0];
+
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            assert_eq!(interpolation.sample(&times, &values, 1, 0, 0.0), 5.0);
+            assert_eq!(interpolation.sample(&times, &values, 1, 0, 3.0), 10.0);
+        }
+
+        // In-tangent, value and out-tangent per keyframe
+        let spline = [100.0, 5.0, 100.0, 100.0, 10.0, 100.0];
+        assert_eq!(
+            Interpolation::CubicSpline.sample(&times, &spline, 1, 0, 0.0),
+            5.0
+        );
+        assert_eq!(
+            Interpolation::CubicSpline.sample(&times, &spline, 1, 0, 3.0),
+            10.0
+        );
+    }
+
+    #[test]
+    fn cubic_spline_uses_the_tangents() {
+        let times = [0.0, 1.0];
+        let sample =
+            |spline: &[f32], time| Interpolation::CubicSpline.sample(&times, spline, 1, 0, time);
+
+        // Tangents along the line between the values reproduce the line
+        let line = [1.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        assert!((sample(&line, 0.25) - 0.25).abs() < 1e-6);
+
+        // Flat tangents ease in and out
+        let flat = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        assert!((sample(&flat, 0.25) - 0.15625).abs() < 1e-6);
+        assert!((sample(&flat, 0.5) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn strided_values_sample_one_component() {
+        let times = [0.0, 1.0];
+        // Two morph target weights per keyframe
+        let weights = [0.0, 1.0, 1.0, 0.0];
+
+        assert_eq!(
+            Interpolation::Linear.sample(&times, &weights, 2, 0, 0.25),
+            0.25
+        );
+        assert_eq!(
+            Interpolation::Linear.sample(&times, &weights, 2, 1, 0.25),
+            0.75
+        );
+    }
+
+    #[test]
+    fn linear_rotations_are_slerped() {
+        let channel = Channel {
+            node: 0,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 1.0],
+            values: ChannelValues::Rotations(vec![
+                Quat::IDENTITY,
+                Quat::from_rotation_y(std::f32::consts::PI * 0.5),
+            ]),
+        };
+        let mut pose = BlendedPose {
+            rotation: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+            ..Default::default()
+        };
+
+        channel.sample_into(0.5, 1.0, &mut pose);
+
+        let expected = Quat::from_rotation_y(std::f32::consts::PI * 0.25);
+        assert!(pose.rotation.normalize().abs_diff_eq(expected, 1e-5));
+        assert_eq!(pose.rotation_weight, 1.0);
+    }
+
+    #[test]
+    fn clips_are_blended_by_their_weights() {
+        let mut scene_graph = SceneGraph::new();
+        let node = scene_graph.add_node("node", Mat4::IDENTITY, None);
+        let clips = [
+            translation_clip(node, Interpolation::Step, vec![Vec3::X * 10.0; 2]),
+            translation_clip(node, Interpolation::Step, vec![Vec3::Y * 10.0; 2]),
+        ];
+        let mut player = AnimationPlayer::new(&clips, &scene_graph, &[]);
+
+        player.play(0, 0.5, false);
+        player.play(1, 0.5, false);
+        player.update(&clips, 0.0, &mut scene_graph, &mut []);
+
+        assert!(translation(&scene_graph, node).abs_diff_eq(Vec3::new(5.0, 5.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn partial_weights_are_filled_with_the_rest_pose() {
+        let mut scene_graph = SceneGraph::new();
+        let node = scene_graph.add_node("node", Mat4::from_translation(Vec3::Z * 4.0), None);
+        let clips = [translation_clip(
+            node,
+            Interpolation::Linear,
+            vec![Vec3::X * 4.0; 2],
+        )];
+        let mut player = AnimationPlayer::new(&clips, &scene_graph, &[]);
+
+        player.play(0, 0.25, false);
+        player.update(&clips, 0.0, &mut scene_graph, &mut []);
+        assert!(translation(&scene_graph, node).abs_diff_eq(Vec3::new(1.0, 0.0, 3.0), 1e-5));
+
+        player.stop_all();
+        player.update(&clips, 0.0, &mut scene_graph, &mut []);
+        assert_eq!(translation(&scene_graph, node), Vec3::Z * 4.0);
+    }
+
+    #[test]
+    fn clips_loop_or_stop_at_the_end() {
+        let mut scene_graph = SceneGraph::new();
+        let node = scene_graph.add_node("node", Mat4::IDENTITY, None);
+        let clips = [translation_clip(
+            node,
+            Interpolation::Linear,
+            vec![Vec3::ZERO, Vec3::X * 2.0],
+        )];
+        let mut player = AnimationPlayer::new(&clips, &scene_graph, &[]);
+
+        player.play(0, 1.0, true);
+        player.update(&clips, 1.5, &mut scene_graph, &mut []);
+        assert!(translation(&scene_graph, node).abs_diff_eq(Vec3::X, 1e-5));
+
+        player.stop_all();
+        player.play(0, 1.0, false);
+        player.update(&clips, 1.5, &mut scene_graph, &mut []);
+        assert!(translation(&scene_graph, node).abs_diff_eq(Vec3::X * 2.0, 1e-5));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::asset_manager::*;
+use crate::device::*;
+use crate::gltf_loader::*;
+use crate::obj_loader::*;
+use crate::scene_graph::NodeId;
+use crate::texture::*;
+use crate::upload_batcher::UploadTicket;
+use crate::Renderer;
+
+// Limits how much texture data is uploaded each frame to keep the frame time stable
+const TEXTURE_UPLOAD_BUDGET_PER_FRAME: usize = 32 * 1024 * 1024;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AssetId(u64);
+
+#[derive(Copy, Clone)]
+enum AssetKind {
+    Gltf,
+    Obj,
+}
+
+struct DecodeJob {
+    id: AssetId,
+    kind: AssetKind,
+    path: String,
+    optimize_meshes: bool,
+}
+
+pub enum AssetData {
+    Gltf(Box<GltfData>),
+    Obj(ObjData),
+}
+
+pub struct DecodedAsset {
+    pub id: AssetId,
+    pub path: String,
+    // The panic message if the file could not be decoded
+    pub data: Result<AssetData, String>,
+}
+
+struct TextureUpload {
+    roots: Vec<NodeId>,
+    texture_index: usize,
+    key: AssetKey,
+    data: TextureData,
+    debug_name: String,
+}
+
+// Textures of a frame whose upload has been submitted, they are added when it completes
+struct UploadBatch {
+    // None if no new texture was uploaded
+    ticket: Option<UploadTicket>,
+    textures: Vec<(AssetKey, Texture)>,
+    uploads: Vec<TextureUpload>,
+}
+
+/// Decodes glTF and OBJ files on worker threads so that the main thread stays interactive
+/// while large scenes are loading. The meshes of a decoded file are created on the
+/// main thread and the textures of glTF files are streamed in over the following frames,
+/// the renderer uses placeholder textures until then.
+pub struct AssetLoader {
+    job_sender: Sender<DecodeJob>,
+    result_receiver: Receiver<DecodedAsset>,
+    _workers: Vec<std::thread::JoinHandle<()>>,
+    next_id: u64,
+    texture_uploads: VecDeque<TextureUpload>,
+    // Completes in order since the uploads are submitted to the same queue
+    upload_batches: VecDeque<UploadBatch>,
+}
+
+impl AssetLoader {
+    pub fn new() -> AssetLoader {
+        let (job_sender, job_receiver) = channel::<DecodeJob>();
+        let (result_sender, result_receiver) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let num_workers = std::thread::available_parallelism()
+            .map_or(2, |parallelism| parallelism.get())
+            .clamp(1, 4);
+
+        let workers = (0..num_workers)
+            .map(|i| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+
+                std::thread::Builder::new()
+                    .name(format!("asset_loader_{}", i))
+                    .spawn(move || loop {
+                        let job = match job_receiver.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+
+                        // The loaders panic on invalid files, which should not take the
+                        // worker down with it
+                        let data = std::panic::catch_unwind(|| match job.kind {
+                            AssetKind::Gltf => AssetData::Gltf(Box::new(decode_gltf(
+                                &job.path,
+                                job.optimize_meshes,
+                            ))),
+                            AssetKind::Obj => {
+                                AssetData::Obj(decode_obj(&job.path, job.optimize_meshes))
+                            }
+                        })
+                        .map_err(|payload| panic_message(payload.as_ref()));
+
+                        let result = DecodedAsset {
+                            id: job.id,
+                            path: job.path,
+                            data,
+                        };
+
+                        if result_sender.send(result).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Failed to spawn asset loader thread")
+            })
+            .collect();
+
+        AssetLoader {
+            job_sender,
+            result_receiver,
+            _workers: workers,
+            next_id: 0,
+            texture_uploads: VecDeque::new(),
+            upload_batches: VecDeque::new(),
+        }
+    }
+
+    /// Queues a glTF file for decoding, the result is returned by `poll_decoded`.
+    /// The meshes are optimized on the worker if `optimize_meshes` is set, which is
+    /// expected to be `AssetManager::optimize_meshes`.
+    pub fn load_gltf(&mut self, path: &str, optimize_meshes: bool) -> AssetId {
+        self.queue_decode(AssetKind::Gltf, path, optimize_meshes)
+    }
+
+    /// Queues an OBJ file and its material libraries for decoding, the result is
+    /// returned by `poll_decoded`
+    pub fn load_obj(&mut self, path: &str, optimize_meshes: bool) -> AssetId {
+        self.queue_decode(AssetKind::Obj, path, optimize_meshes)
+    }
+
+    fn queue_decode(&mut self, kind: AssetKind, path: &str, optimize_meshes: bool) -> AssetId {
+        let id = AssetId(self.next_id);
+        self.next_id += 1;
+
+        self.job_sender
+            .send(DecodeJob {
+                id,
+                kind,
+                path: path.to_string(),
+                optimize_meshes,
+            })
+            .expect("Asset loader threads have stopped");
+
+        id
+    }
+
+    /// Returns the files that have been decoded since the last call
+    pub fn poll_decoded(&mut self) -> Vec<DecodedAsset> {
+        self.result_receiver.try_iter().collect()
+    }
+
+    /// Queues the textures of a decoded model for upload to the model instances `roots`
+    pub fn stream_textures(
+        &mut self,
+        textures: Vec<(AssetKey, TextureData)>,
+        roots: Vec<NodeId>,
+        path: &str,
+    ) {
+        for (texture_index, (key, data)) in textures.into_iter().enumerate() {
+            self.texture_uploads.push_back(TextureUpload {
+                roots: roots.clone(),
+                texture_index,
+                key,
+                data,
+                debug_name: path.to_string(),
+            });
+        }
+    }
+
+    /// Uploads queued textures within the per frame budget and replaces the placeholders
+    /// of the textures whose upload has completed. Textures that are already loaded are
+    /// shared instead of uploaded again. Returns true if any placeholder was replaced.
+    pub fn update(&mut self, device: &Device, renderer: &mut Renderer) -> bool {
+        puffin::profile_function!();
+
+        let replaced_textures = self.finish_uploads(device, renderer);
+
+        let mut uploaded_bytes = 0;
+        let mut uploads = vec![];
+        let mut new_textures = HashMap::new();
+
+        while uploaded_bytes < TEXTURE_UPLOAD_BUDGET_PER_FRAME {
+            let upload = match self.texture_uploads.pop_front() {
+                Some(upload) => upload,
+                None => break,
+            };
+
+            let is_uploading = |key: &AssetKey| {
+                new_textures.contains_key(key)
+                    || self
+                        .upload_batches
+                        .iter()
+                        .any(|batch| batch.textures.iter().any(|(other, _)| other == key))
+            };
+
+            if renderer.asset_manager.find_texture(&upload.key).is_none()
+                && !is_uploading(&upload.key)
+            {
+                // Todo: upload on a transfer queue so that the frame does not wait for it
+                // Textures that can not be created keep their placeholder
+                match Texture::from_data(device, &upload.data, &upload.debug_name) {
+                    Ok(texture) => {
+                        new_textures.insert(upload.key.clone(), texture);
+                        uploaded_bytes +=
+                            upload.data.mips.iter().map(|mip| mip.len()).sum::<usize>();
+                    }
+                    Err(err) => log::error!("{}", err),
+                }
+            }
+
+            uploads.push(upload);
+        }
+
+        if !uploads.is_empty() {
+            // All textures of this frame are uploaded in one batch
+            self.upload_batches.push_back(UploadBatch {
+                ticket: device.flush_uploads(),
+                textures: new_textures.into_iter().collect(),
+                uploads,
+            });
+        }
+
+        replaced_textures
+    }
+
+    // Writes the descriptors of the textures whose upload fence has signaled and
+    // replaces their placeholders, the frames in flight keep using the placeholders
+    fn finish_uploads(&mut self, device: &Device, renderer: &mut Renderer) -> bool {
+        let mut replaced_textures = false;
+
+        while let Some(batch) = self.upload_batches.front() {
+            if !batch
+                .ticket
+                .is_none_or(|ticket| device.is_upload_complete(ticket))
+            {
+                break;
+            }
+
+            let batch = self.upload_batches.pop_front().unwrap();
+
+            for (key, texture) in batch.textures {
+                renderer.asset_manager.add_texture(device, key, texture);
+            }
+
+            for upload in batch.uploads {
+                if let Some(texture) = renderer.asset_manager.find_texture(&upload.key) {
+                    renderer.set_model_texture(
+                        device,
+                        &upload.roots,
+                        upload.texture_index,
+                        texture,
+                    );
+                    replaced_textures = true;
+                }
+            }
+        }
+
+        replaced_textures
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        !self.texture_uploads.is_empty() || !self.upload_batches.is_empty()
+    }
+
+    /// Drops all queued texture uploads, files that are being decoded are still
+    /// returned by `poll_decoded`. Textures that are being uploaded are still added
+    /// to the asset manager but do not replace any placeholders.
+    pub fn clear(&mut self) {
+        self.texture_uploads.clear();
+
+        for batch in &mut self.upload_batches {
+            batch.uploads.clear();
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => payload
+            .downcast_ref::<&str>()
+            .map_or(String::from("unknown error"), |message| message.to_string()),
+    }
+}
+
+impl Default for AssetLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_messages_are_returned_as_errors() {
+        let formatted = std::panic::catch_unwind(|| panic!("Unable to load \"{}\"", "a.gltf"));
+        let literal = std::panic::catch_unwind(|| panic!("invalid file"));
+
+        assert_eq!(
+            panic_message(formatted.unwrap_err().as_ref()),
+            "Unable to load \"a.gltf\""
+        );
+        assert_eq!(panic_message(literal.unwrap_err().as_ref()), "invalid file");
+    }
+}
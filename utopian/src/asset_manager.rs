@@ -0,0 +1,418 @@
+use ash::vk;
+use glam::Vec4;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::buffer::*;
+use crate::device::*;
+use crate::lod::*;
+use crate::primitive::*;
+use crate::tangent_space::generate_tangents;
+use crate::texture::*;
+
+/// Identifies an asset by where it was loaded from and a hash of its content. Loading
+/// the same data twice returns the existing asset while a file that has changed on
+/// disk is loaded again.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AssetKey {
+    pub path: String,
+    pub content_hash: u64,
+}
+
+impl AssetKey {
+    pub fn from_texture_data(path: &str, data: &TextureData) -> AssetKey {
+        let mut hasher = DefaultHasher::new();
+        data.desc.format.as_raw().hash(&mut hasher);
+        data.desc.width.hash(&mut hasher);
+        data.desc.height.hash(&mut hasher);
+        data.desc.mip_levels.hash(&mut hasher);
+        data.mips.hash(&mut hasher);
+
+        AssetKey {
+            path: path.to_string(),
+            content_hash: hasher.finish(),
+        }
+    }
+
+    pub fn from_mesh(path: &str, indices: &[u32], vertices: &[Vertex]) -> AssetKey {
+        let mut hasher = DefaultHasher::new();
+        indices.hash(&mut hasher);
+        for vertex in vertices {
+            let attributes = [
+                vertex.pos.to_array(),
+                vertex.normal.to_array(),
+                vertex.uv.extend(0.0).extend(0.0).to_array(),
+                vertex.color.to_array(),
+                vertex.tangent.to_array(),
+            ];
+            for value in attributes.iter().flatten() {
+                value.to_bits().hash(&mut hasher);
+            }
+        }
+
+        AssetKey {
+            path: path.to_string(),
+            content_hash: hasher.finish(),
+        }
+    }
+}
+
+// GPU resources whose last handle has been dropped
+enum ReleasedAsset {
+    Texture {
+        texture: Texture,
+        bindless_index: u32,
+    },
+    Mesh {
+        primitive: Box<Primitive>,
+        vertex_buffer_index: u32,
+        index_buffer_index: u32,
+    },
+}
+
+type ReleaseQueue = Arc<Mutex<Vec<ReleasedAsset>>>;
+
+pub struct TextureAsset {
+    texture: Option<Texture>,
+    pub bindless_index: u32,
+    release_queue: ReleaseQueue,
+}
+
+pub struct MeshAsset {
+    primitive: Option<Primitive>,
+    pub vertex_buffer_index: u32,
+    pub index_buffer_index: u32,
+    release_queue: ReleaseQueue,
+}
+
+/// Shared handles to assets owned by the `AssetManager`, the GPU resources are
+/// released when the last handle is dropped
+pub type TextureHandle = Arc<TextureAsset>;
+pub type MeshHandle = Arc<MeshAsset>;
+
+impl std::ops::Deref for TextureAsset {
+    type Target = Texture;
+
+    fn deref(&self) -> &Texture {
+        self.texture.as_ref().unwrap()
+    }
+}
+
+impl std::ops::Deref for MeshAsset {
+    type Target = Primitive;
+
+    fn deref(&self) -> &Primitive {
+        self.primitive.as_ref().unwrap()
+    }
+}
+
+impl Drop for TextureAsset {
+    fn drop(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            self.release_queue
+                .lock()
+                .unwrap()
+                .push(ReleasedAsset::Texture {
+                    texture,
+                    bindless_index: self.bindless_index,
+                });
+        }
+    }
+}
+
+impl Drop for MeshAsset {
+    fn drop(&mut self) {
+        if let Some(primitive) = self.primitive.take() {
+            self.release_queue
+                .lock()
+                .unwrap()
+                .push(ReleasedAsset::Mesh {
+                    primitive: Box::new(primitive),
+                    vertex_buffer_index: self.vertex_buffer_index,
+                    index_buffer_index: self.index_buffer_index,
+                });
+        }
+    }
+}
+
+// Hands out indices into one of the bindless descriptor arrays
+#[derive(Default)]
+struct BindlessSlots {
+    next: u32,
+    free: Vec<u32>,
+}
+
+impl BindlessSlots {
+    fn allocate(&mut self) -> u32 {
+        self.free.pop().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+
+    fn release(&mut self, index: u32) {
+        self.free.push(index);
+    }
+}
+
+/// Deduplicates textures and meshes across models and owns their slots in the bindless
+/// descriptor set. Assets are looked up by `AssetKey` and shared through reference
+/// counted handles, so two models that use the same texture share one image and one
+/// bindless index.
+pub struct AssetManager {
+    // Whether the loaders run `optimize_mesh` on the meshes they load
+    pub optimize_meshes: bool,
+    // Used for the meshes that are created after it is changed
+    pub lod_settings: LodSettings,
+    bindless_descriptor_set: vk::DescriptorSet,
+    textures: HashMap<AssetKey, Weak<TextureAsset>>,
+    meshes: HashMap<AssetKey, Weak<MeshAsset>>,
+    release_queue: ReleaseQueue,
+    texture_slots: BindlessSlots,
+    vertex_buffer_slots: BindlessSlots,
+    index_buffer_slots: BindlessSlots,
+}
+
+impl AssetManager {
+    pub fn new(bindless_descriptor_set: vk::DescriptorSet) -> AssetManager {
+        AssetManager {
+            optimize_meshes: true,
+            lod_settings: LodSettings::default(),
+            bindless_descriptor_set,
+            textures: HashMap::new(),
+            meshes: HashMap::new(),
+            release_queue: Arc::new(Mutex::new(vec![])),
+            texture_slots: BindlessSlots::default(),
+            vertex_buffer_slots: BindlessSlots::default(),
+            index_buffer_slots: BindlessSlots::default(),
+        }
+    }
+
+    pub fn load_texture(
+        &mut self,
+        device: &Device,
+        path: &str,
+        color_space: ColorSpace,
+    ) -> Result<TextureHandle, String> {
+        let content =
+            std::fs::read(path).map_err(|err| format!("Unable to load \"{}\": {}", path, err))?;
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        (color_space as u32).hash(&mut hasher);
+
+        let key = AssetKey {
+            path: path.to_string(),
+            content_hash: hasher.finish(),
+        };
+
+        if let Some(texture) = self.find_texture(&key) {
+            return Ok(texture);
+        }
+
+        let texture = Texture::load(device, path, color_space)?;
+        Ok(self.add_texture(device, key, texture))
+    }
+
+    pub fn find_texture(&self, key: &AssetKey) -> Option<TextureHandle> {
+        self.textures.get(key).and_then(|texture| texture.upgrade())
+    }
+
+    /// Returns the texture of `key` and only uploads `data` if it is not loaded yet
+    pub fn texture_from_data(
+        &mut self,
+        device: &Device,
+        key: &AssetKey,
+        data: &TextureData,
+        debug_name: &str,
+    ) -> Result<TextureHandle, String> {
+        match self.find_texture(key) {
+            Some(texture) => Ok(texture),
+            None => {
+                let texture = Texture::from_data(device, data, debug_name)?;
+                Ok(self.add_texture(device, key.clone(), texture))
+            }
+        }
+    }
+
+    /// Takes ownership of an already created texture and gives it a bindless index.
+    /// Note: this writes to the bindless descriptor set so there must not be any frames
+    /// in flight that use it.
+    pub fn add_texture(
+        &mut self,
+        device: &Device,
+        key: AssetKey,
+        texture: Texture,
+    ) -> TextureHandle {
+        let bindless_index = self.texture_slots.allocate();
+
+        let descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.bindless_descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(bindless_index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(std::slice::from_ref(&texture.descriptor_info))
+            .build();
+
+        unsafe {
+            device
+                .handle
+                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[])
+        };
+
+        let texture = Arc::new(TextureAsset {
+            texture: Some(texture),
+            bindless_index,
+            release_queue: self.release_queue.clone(),
+        });
+
+        self.textures.insert(key, Arc::downgrade(&texture));
+
+        texture
+    }
+
+    /// Returns the mesh with the same content that has been loaded from `path` before,
+    /// or creates a new one
+    pub fn mesh(
+        &mut self,
+        device: &Device,
+        path: &str,
+        indices: Vec<u32>,
+        vertices: Vec<Vertex>,
+    ) -> MeshHandle {
+        let key = AssetKey::from_mesh(path, &indices, &vertices);
+
+        if let Some(mesh) = self.meshes.get(&key).and_then(|mesh| mesh.upgrade()) {
+            return mesh;
+        }
+
+        let vertex_layout = VertexLayout::compact_for(&vertices);
+        let mesh = self.create_mesh(device, path, indices, vertices, vertex_layout);
+        self.meshes.insert(key, Arc::downgrade(&mesh));
+
+        mesh
+    }
+
+    /// Creates a mesh that is never shared, e.g because its vertices are deformed on the GPU.
+    /// It uses the full vertex layout since that is what the skinning pass writes.
+    pub fn unique_mesh(
+        &mut self,
+        device: &Device,
+        path: &str,
+        indices: Vec<u32>,
+        vertices: Vec<Vertex>,
+    ) -> MeshHandle {
+        self.create_mesh(device, path, indices, vertices, VertexLayout::Full)
+    }
+
+    fn create_mesh(
+        &mut self,
+        device: &Device,
+        path: &str,
+        mut indices: Vec<u32>,
+        mut vertices: Vec<Vertex>,
+        vertex_layout: VertexLayout,
+    ) -> MeshHandle {
+        // Normal mapping needs tangents, generate them for meshes that do not have any.
+        // This can split vertices so it has to happen before the LODs are generated.
+        if vertices.iter().all(|vertex| vertex.tangent == Vec4::ZERO) {
+            generate_tangents(&mut indices, &mut vertices);
+        }
+
+        let lod_indices = generate_lods(&indices, &vertices, &self.lod_settings);
+
+        let mut primitive =
+            Primitive::with_lods(device, indices, lod_indices, vertices, vertex_layout);
+        primitive.blas_lod = self.lod_settings.blas_lod.min(primitive.lods.len() - 1);
+
+        primitive
+            .vertex_buffer
+            .set_debug_name(device, format!("vertex_buffer: {}", path).as_str());
+        primitive
+            .index_buffer
+            .set_debug_name(device, format!("index_buffer: {}", path).as_str());
+
+        let vertex_buffer_index = self.add_bindless_vertex_buffer(device, &primitive.vertex_buffer);
+        let index_buffer_index = self.index_buffer_slots.allocate();
+        self.write_bindless_buffer(device, 2, index_buffer_index, &primitive.index_buffer);
+
+        Arc::new(MeshAsset {
+            primitive: Some(primitive),
+            vertex_buffer_index,
+            index_buffer_index,
+            release_queue: self.release_queue.clone(),
+        })
+    }
+
+    /// For storage buffers that are accessed through the vertex buffer array but are
+    /// not owned by a mesh, they are released with `release_bindless_vertex_buffer`
+    pub fn add_bindless_vertex_buffer(&mut self, device: &Device, buffer: &Buffer) -> u32 {
+        let index = self.vertex_buffer_slots.allocate();
+        self.write_bindless_buffer(device, 1, index, buffer);
+
+        index
+    }
+
+    pub fn release_bindless_vertex_buffer(&mut self, index: u32) {
+        self.vertex_buffer_slots.release(index);
+    }
+
+    /// Destroys the assets whose last handle has been dropped and reuses their bindless
+    /// indices. Note: there must not be any frames in flight that use them.
+    pub fn free_released_assets(&mut self, device: &Device) {
+        let released: Vec<ReleasedAsset> = self.release_queue.lock().unwrap().drain(..).collect();
+
+        if released.is_empty() {
+            return;
+        }
+
+        for asset in released {
+            match asset {
+                ReleasedAsset::Texture {
+                    texture,
+                    bindless_index,
+                } => {
+                    texture.destroy(device);
+                    self.texture_slots.release(bindless_index);
+                }
+                ReleasedAsset::Mesh {
+                    primitive,
+                    vertex_buffer_index,
+                    index_buffer_index,
+                } => {
+                    primitive.vertex_buffer.destroy(device);
+                    primitive.index_buffer.destroy(device);
+                    self.vertex_buffer_slots.release(vertex_buffer_index);
+                    self.index_buffer_slots.release(index_buffer_index);
+                }
+            }
+        }
+
+        self.textures
+            .retain(|_, texture| texture.strong_count() > 0);
+        self.meshes.retain(|_, mesh| mesh.strong_count() > 0);
+    }
+
+    fn write_bindless_buffer(&self, device: &Device, binding: u32, index: u32, buffer: &Buffer) {
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .range(buffer.size)
+            .build();
+
+        let descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.bindless_descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(std::slice::from_ref(&buffer_info))
+            .build();
+
+        unsafe {
+            device
+                .handle
+                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[])
+        };
+    }
+}
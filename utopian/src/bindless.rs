@@ -49,11 +49,24 @@ pub fn create_bindless_descriptor_set_layout(device: &Device) -> vk::DescriptorS
             .descriptor_count(MAX_BINDLESS_DESCRIPTOR_COUNT as u32) // Hack: actually 1
             .stage_flags(vk::ShaderStageFlags::ALL)
             .build(),
+        // Mesh instances of the GPU-driven draws (not bindless)
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(6)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(MAX_BINDLESS_DESCRIPTOR_COUNT as u32) // Hack: actually 1
+            .stage_flags(vk::ShaderStageFlags::ALL)
+            .build(),
     ];
 
+    // Textures and vertex and index buffers are added while frames that use other
+    // descriptors of the same arrays are in flight
     let binding_flags: Vec<vk::DescriptorBindingFlags> = vec![
-        vk::DescriptorBindingFlags::PARTIALLY_BOUND,
-        vk::DescriptorBindingFlags::PARTIALLY_BOUND,
+        vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+        vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+        vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
         vk::DescriptorBindingFlags::PARTIALLY_BOUND,
         vk::DescriptorBindingFlags::PARTIALLY_BOUND,
         vk::DescriptorBindingFlags::PARTIALLY_BOUND,
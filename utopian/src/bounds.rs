@@ -0,0 +1,366 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::primitive::Vertex;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+/// Bounds of a mesh in its local space. For skinned and morphed meshes they only
+/// cover the rest pose.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshBounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+// The planes of a view frustum with their normals pointing inwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Aabb {
+    pub fn from_points(points: impl Iterator<Item = Vec3>) -> Aabb {
+        let mut aabb = Aabb {
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(f32::MIN),
+        };
+
+        for point in points {
+            aabb.min = aabb.min.min(point);
+            aabb.max = aabb.max.max(point);
+        }
+
+        // Empty meshes get an empty box at the origin
+        if aabb.min.x > aabb.max.x {
+            aabb.min = Vec3::ZERO;
+            aabb.max = Vec3::ZERO;
+        }
+
+        aabb
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box that encloses this one after it has been transformed by `matrix`
+    pub fn transform(&self, matrix: Mat4) -> Aabb {
+        let center = matrix.transform_point3(self.center());
+        let extents = self.extents();
+        let extents = matrix.x_axis.truncate().abs() * extents.x
+            + matrix.y_axis.truncate().abs() * extents.y
+            + matrix.z_axis.truncate().abs() * extents.z;
+
+        Aabb {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+impl BoundingSphere {
+    /// Ritter's bounding sphere, it is at most a few percent larger than the minimal one
+    pub fn from_points(points: &[Vec3]) -> BoundingSphere {
+        let first = match points.first() {
+            Some(first) => *first,
+            None => {
+                return BoundingSphere {
+                    center: Vec3::ZERO,
+                    radius: 0.0,
+                }
+            }
+        };
+
+        let farthest_from = |from: Vec3| {
+            points.iter().copied().fold(from, |farthest, point| {
+                match point.distance_squared(from) > farthest.distance_squared(from) {
+                    true => point,
+                    false => farthest,
+                }
+            })
+        };
+
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+
+        let mut center = (a + b) * 0.5;
+        let mut radius = a.distance(b) * 0.5;
+
+        for &point in points {
+            let distance = point.distance(center);
+            if distance > radius {
+                let new_radius = (radius + distance) * 0.5;
+                center += (point - center) * ((new_radius - radius) / distance);
+                radius = new_radius;
+            }
+        }
+
+        BoundingSphere { center, radius }
+    }
+
+    pub fn transform(&self, matrix: Mat4) -> BoundingSphere {
+        let scale = matrix
+            .x_axis
+            .truncate()
+            .length()
+            .max(matrix.y_axis.truncate().length())
+            .max(matrix.z_axis.truncate().length());
+
+        BoundingSphere {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl MeshBounds {
+    pub fn from_vertices(vertices: &[Vertex]) -> MeshBounds {
+        let positions: Vec<Vec3> = vertices
+            .iter()
+            .map(|vertex| vertex.pos.truncate())
+            .collect();
+
+        MeshBounds {
+            aabb: Aabb::from_points(positions.iter().copied()),
+            sphere: BoundingSphere::from_points(&positions),
+        }
+    }
+
+    pub fn transform(&self, matrix: Mat4) -> MeshBounds {
+        MeshBounds {
+            aabb: self.aabb.transform(matrix),
+            sphere: self.sphere.transform(matrix),
+        }
+    }
+}
+
+impl Frustum {
+    /// Extracts the planes from a perspective or orthographic projection with a
+    /// 0 to 1 depth range, like the ones in `Camera` and the shadow cascades
+    pub fn from_view_projection(view_projection: Mat4) -> Frustum {
+        let row = |i: usize| view_projection.row(i);
+
+        let planes = [
+            row(3) + row(0), // Left
+            row(3) - row(0), // Right
+            row(3) + row(1), // Bottom
+            row(3) - row(1), // Top
+            row(2),          // Near
+            row(3) - row(2), // Far
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Frustum { planes }
+    }
+
+    /// Conservative, boxes close to the corners of the frustum can intersect it without
+    /// being inside. Note: `intersects_frustum` in cull_meshes.comp must match it.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // The corner that is the furthest along the plane normal
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aabb_encloses_the_points() {
+        let points = [Vec3::new(1.0, -2.0, 3.0), Vec3::new(-1.0, 4.0, 0.0)];
+        let aabb = Aabb::from_points(points.into_iter());
+
+        assert_eq!(aabb.min, Vec3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(1.0, 4.0, 3.0));
+        assert_eq!(aabb.center(), Vec3::new(0.0, 1.0, 1.5));
+        assert_eq!(aabb.extents(), Vec3::new(1.0, 3.0, 1.5));
+    }
+
+    #[test]
+    fn aabb_of_no_points_is_empty() {
+        let aabb = Aabb::from_points(std::iter::empty());
+
+        assert_eq!(aabb, Aabb::default());
+    }
+
+    #[test]
+    fn transformed_aabb_encloses_the_rotated_box() {
+        let aabb = Aabb {
+            min: Vec3::splat(-1.0),
+            max: Vec3::splat(1.0),
+        };
+        let matrix = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0))
+            * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_4);
+
+        let transformed = aabb.transform(matrix);
+        let half_diagonal = 2.0_f32.sqrt();
+
+        assert!(transformed
+            .min
+            .abs_diff_eq(Vec3::new(10.0 - half_diagonal, -1.0, -half_diagonal), 1e-5));
+        assert!(transformed
+            .max
+            .abs_diff_eq(Vec3::new(10.0 + half_diagonal, 1.0, half_diagonal), 1e-5));
+    }
+
+    #[test]
+    fn bounding_sphere_encloses_the_points() {
+        let points = [
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.5, 0.5, 0.5),
+        ];
+        let sphere = BoundingSphere::from_points(&points);
+
+        for point in points {
+            assert!(point.distance(sphere.center) <= sphere.radius + 1e-5);
+        }
+        // Ritter's sphere is close to the minimal one, which has radius 1
+        assert!(sphere.radius < 1.1);
+    }
+
+    #[test]
+    fn bounding_sphere_of_no_points_is_empty() {
+        assert_eq!(BoundingSphere::from_points(&[]), BoundingSphere::default());
+    }
+
+    #[test]
+    fn transformed_bounding_sphere_uses_the_largest_scale() {
+        let sphere = BoundingSphere {
+            center: Vec3::new(1.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+        let matrix = Mat4::from_scale_rotation_translation(
+            Vec3::new(1.0, 3.0, 2.0),
+            glam::Quat::IDENTITY,
+            Vec3::new(0.0, 5.0, 0.0),
+        );
+
+        let transformed = sphere.transform(matrix);
+
+        assert_eq!(transformed.center, Vec3::new(1.0, 5.0, 0.0));
+        assert_eq!(transformed.radius, 6.0);
+    }
+
+    fn camera_frustum() -> Frustum {
+        // Looks down -z from the origin with a 90 degree field of view, like `Camera`
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::new(0.0, 0.0, -1.0), Vec3::Y);
+
+        Frustum::from_view_projection(projection * view)
+    }
+
+    fn aabb(center: Vec3, extents: f32) -> Aabb {
+        Aabb {
+            min: center - Vec3::splat(extents),
+            max: center + Vec3::splat(extents),
+        }
+    }
+
+    #[test]
+    fn frustum_planes_are_normalized_and_point_inwards() {
+        let frustum = camera_frustum();
+        let distance = |plane: &Vec4, point: Vec3| plane.truncate().dot(point) + plane.w;
+
+        for plane in &frustum.planes {
+            assert!((plane.truncate().length() - 1.0).abs() < 1e-5);
+            assert!(distance(plane, Vec3::new(0.0, 0.0, -10.0)) > 0.0);
+        }
+
+        let [left, right, bottom, top, near, far] = frustum.planes;
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(left
+            .truncate()
+            .abs_diff_eq(Vec3::new(diagonal, 0.0, -diagonal), 1e-5));
+        assert!(right
+            .truncate()
+            .abs_diff_eq(Vec3::new(-diagonal, 0.0, -diagonal), 1e-5));
+        assert!(bottom
+            .truncate()
+            .abs_diff_eq(Vec3::new(0.0, diagonal, -diagonal), 1e-5));
+        assert!(top
+            .truncate()
+            .abs_diff_eq(Vec3::new(0.0, -diagonal, -diagonal), 1e-5));
+        assert!((distance(&near, Vec3::new(0.0, 0.0, -1.1)) - 1.0).abs() < 1e-4);
+        assert!((distance(&far, Vec3::new(0.0, 0.0, -90.0)) - 10.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn aabbs_are_culled_against_the_camera_frustum() {
+        let frustum = camera_frustum();
+
+        // Inside
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        // Around the camera, straddling the near plane
+        assert!(frustum.intersects_aabb(&aabb(Vec3::ZERO, 1.0)));
+        // Straddling the left and the far plane
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(-10.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -100.0), 1.0)));
+
+        // Behind the camera, beyond the far plane and outside of every side
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -102.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(-20.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(20.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, -20.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 20.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn spheres_are_culled_against_the_camera_frustum() {
+        let frustum = camera_frustum();
+        let sphere = |center: Vec3, radius: f32| BoundingSphere { center, radius };
+
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        // The center is outside but the sphere reaches over the left plane
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(-11.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(0.0, 0.0, 0.5), 1.0)));
+
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(-20.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(0.0, 0.0, 5.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(0.0, 0.0, -105.0), 1.0)));
+    }
+
+    #[test]
+    fn aabbs_are_culled_against_an_orthographic_frustum() {
+        // Like a shadow cascade, looking down at a 20 x 20 area
+        let projection = Mat4::orthographic_rh(-10.0, 10.0, -10.0, 10.0, 0.0, 50.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 25.0, 0.0), Vec3::ZERO, Vec3::Z);
+        let frustum = Frustum::from_view_projection(projection * view);
+
+        assert!(frustum.intersects_aabb(&aabb(Vec3::ZERO, 1.0)));
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(10.5, 0.0, 0.0), 1.0)));
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(0.0, 25.0, 0.0), 1.0)));
+
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(12.0, 0.0, 0.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -12.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, -27.0, 0.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 27.0, 0.0), 1.0)));
+    }
+}
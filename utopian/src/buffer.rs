@@ -87,53 +87,61 @@ impl Buffer {
     }
 
     pub fn update_memory<T: Copy>(&mut self, device: &Device, data: &[T]) {
+        self.update_memory_at(device, 0, data);
+    }
+
+    /// Writes `data` at `offset` bytes into the buffer, through a staging buffer if it
+    /// is not mapped on the CPU
+    pub fn update_memory_at<T: Copy>(&mut self, device: &Device, offset: u64, data: &[T]) {
         unsafe {
             let src = data.as_ptr() as *const u8;
             let src_bytes = std::mem::size_of_val(data);
 
             if self.memory_location != gpu_allocator::MemoryLocation::GpuOnly {
-                let dst = self.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
-                let dst_bytes = self.allocation.size() as usize;
-                std::ptr::copy_nonoverlapping(src, dst, std::cmp::min(src_bytes, dst_bytes));
-            } else {
-                // This is expensive and should not be done in a hot loop
-                let staging_buffer = Buffer::create_buffer(
-                    device,
-                    self.size,
-                    vk::BufferUsageFlags::TRANSFER_SRC,
-                    gpu_allocator::MemoryLocation::CpuToGpu,
-                );
-
-                let dst = staging_buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
-                let dst_bytes = staging_buffer.allocation.size() as usize;
-                std::ptr::copy_nonoverlapping(src, dst, std::cmp::min(src_bytes, dst_bytes));
-
-                device.execute_and_submit(|device, cb| {
+                self.update_mapped_memory_at(offset, data);
+            } else if src_bytes > 0 {
+                // The copy is batched with other uploads and executes before the next
+                // submission that could use the buffer
+                let dst_bytes = self.size.saturating_sub(offset) as usize;
+                let data = std::slice::from_raw_parts(src, std::cmp::min(src_bytes, dst_bytes));
+                let dst_buffer = self.buffer;
+
+                device.upload(data, |device, cb, staging_buffer, staging_offset| {
                     let regions = vk::BufferCopy::builder()
-                        .size(self.size)
-                        .src_offset(0)
-                        .dst_offset(0)
+                        .size(data.len() as u64)
+                        .src_offset(staging_offset)
+                        .dst_offset(offset)
                         .build();
 
                     device.handle.cmd_copy_buffer(
                         cb,
                         staging_buffer.buffer,
-                        self.buffer,
+                        dst_buffer,
                         &[regions],
                     );
                 });
-
-                device
-                    .gpu_allocator
-                    .lock()
-                    .unwrap()
-                    .free(staging_buffer.allocation)
-                    .unwrap();
-                device.handle.destroy_buffer(staging_buffer.buffer, None);
             }
         }
     }
 
+    /// Writes `data` at `offset` bytes into a buffer that is mapped on the CPU
+    pub fn update_mapped_memory_at<T: Copy>(&mut self, offset: u64, data: &[T]) {
+        assert!(self.memory_location != gpu_allocator::MemoryLocation::GpuOnly);
+
+        unsafe {
+            let src = data.as_ptr() as *const u8;
+            let src_bytes = std::mem::size_of_val(data);
+            let dst_bytes = (self.allocation.size() as usize).saturating_sub(offset as usize);
+            let dst = self.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+
+            std::ptr::copy_nonoverlapping(
+                src,
+                dst.add(offset as usize),
+                std::cmp::min(src_bytes, dst_bytes),
+            );
+        }
+    }
+
     pub fn copy_to_buffer(&self, device: &Device, cb: vk::CommandBuffer, dst: &Buffer) {
         let buffer_copy_regions = vk::BufferCopy::builder()
             .size(self.size)
@@ -149,17 +157,31 @@ impl Buffer {
     }
 
     pub fn copy_to_image(&self, device: &Device, cb: vk::CommandBuffer, image: &Image) {
+        self.copy_to_image_mip(device, cb, image, 0, 0);
+    }
+
+    /// Copies tightly packed data starting at `buffer_offset` to a single mip level
+    pub fn copy_to_image_mip(
+        &self,
+        device: &Device,
+        cb: vk::CommandBuffer,
+        image: &Image,
+        mip_level: u32,
+        buffer_offset: u64,
+    ) {
         let buffer_copy_regions = vk::BufferImageCopy::builder()
+            .buffer_offset(buffer_offset)
             .image_subresource(
                 vk::ImageSubresourceLayers::builder()
                     .aspect_mask(image.desc.aspect_flags)
+                    .mip_level(mip_level)
                     .layer_count(1)
                     .build(),
             )
             .image_extent(vk::Extent3D {
-                width: image.width(),
-                height: image.height(),
-                depth: 1,
+                width: (image.width() >> mip_level).max(1),
+                height: (image.height() >> mip_level).max(1),
+                depth: (image.desc.depth >> mip_level).max(1),
             });
 
         unsafe {
@@ -181,6 +203,17 @@ impl Buffer {
         unsafe { device.handle.get_buffer_device_address(&info) }
     }
 
+    /// Note: the buffer must not be used by any frames in flight
+    pub fn destroy(self, device: &Device) {
+        device
+            .gpu_allocator
+            .lock()
+            .unwrap()
+            .free(self.allocation)
+            .unwrap();
+        unsafe { device.handle.destroy_buffer(self.buffer, None) };
+    }
+
     pub fn set_debug_name(&mut self, device: &Device, name: &str) {
         self.debug_name = String::from(name);
         device.set_debug_name(
@@ -1,6 +1,7 @@
 use dolly::prelude::*;
-use glam::{Mat3, Mat4, Quat, Vec3};
+use glam::{Mat3, Mat4, Quat, UVec2, Vec2, Vec3};
 
+use crate::gltf_loader::ModelCamera;
 use crate::Input;
 
 pub struct Camera {
@@ -10,6 +11,106 @@ pub struct Camera {
     z_near: f32,
     z_far: f32,
     speed: f32,
+    // Sub-pixel offset of the projection in NDC, for temporal anti-aliasing
+    jitter: Vec2,
+    jitter_index: u32,
+    exposure: Exposure,
+    lens: Lens,
+    // The pixel under the cursor, for the autofocus
+    cursor_pixel: UVec2,
+}
+
+/// Thin lens used for depth of field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lens {
+    // Distance to the plane in focus in world units
+    pub focal_distance: f32,
+    // The focal length divided by the diameter of the aperture
+    pub f_stop: f32,
+    // Height of the sensor in millimeters, 24 for a full frame sensor
+    pub sensor_height: f32,
+    // Focus on the surface under the cursor instead of `focal_distance`
+    pub autofocus: bool,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Lens {
+            focal_distance: 5.0,
+            f_stop: 2.8,
+            sensor_height: 24.0,
+            autofocus: false,
+        }
+    }
+}
+
+/// How the scene luminance is scaled before tonemapping, see `renderers::exposure`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposure {
+    /// Exposure value at ISO 100
+    Manual { ev100: f32 },
+    /// Aperture in f-stops, shutter speed in seconds
+    Physical {
+        aperture: f32,
+        shutter_speed: f32,
+        iso: f32,
+    },
+    /// Adapts to the average luminance of the scene from a histogram. The compensation
+    /// is in EV and positive values brighten the image. The speed is in 1/seconds.
+    Automatic {
+        compensation: f32,
+        min_ev100: f32,
+        max_ev100: f32,
+        adaptation_speed: f32,
+    },
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure::Automatic {
+            compensation: 0.0,
+            min_ev100: -6.0,
+            max_ev100: 16.0,
+            adaptation_speed: 1.5,
+        }
+    }
+}
+
+impl Exposure {
+    /// None when the exposure is automatic
+    pub fn ev100(&self) -> Option<f32> {
+        match *self {
+            Exposure::Manual { ev100 } => Some(ev100),
+            Exposure::Physical {
+                aperture,
+                shutter_speed,
+                iso,
+            } => Some((aperture * aperture / shutter_speed * 100.0 / iso).log2()),
+            Exposure::Automatic { .. } => None,
+        }
+    }
+}
+
+/// The scale applied to the luminance so that the maximum luminance without clipping,
+/// for a sensor with a saturation based speed, maps to 1.0
+pub fn exposure_from_ev100(ev100: f32) -> f32 {
+    1.0 / (1.2 * 2.0_f32.powf(ev100))
+}
+
+// Length of the Halton sequence that the jitter cycles through
+const NUM_JITTER_SAMPLES: u32 = 16;
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
 }
 
 impl Camera {
@@ -37,6 +138,11 @@ impl Camera {
             z_near,
             z_far,
             speed,
+            jitter: Vec2::ZERO,
+            jitter_index: 0,
+            exposure: Exposure::default(),
+            lens: Lens::default(),
+            cursor_pixel: UVec2::ZERO,
         }
     }
 
@@ -84,7 +190,12 @@ impl Camera {
         // Todo: proper frame delta time
         self.camera_rig.update(1.0);
 
-        movement != Vec3::new(0.0, 0.0, 0.0) || view_changed
+        // The focus changes when the cursor moves
+        let cursor_pixel = UVec2::new(input.mouse_pos.x as u32, input.mouse_pos.y as u32);
+        let focus_changed = self.lens.autofocus && cursor_pixel != self.cursor_pixel;
+        self.cursor_pixel = cursor_pixel;
+
+        movement != Vec3::new(0.0, 0.0, 0.0) || view_changed || focus_changed
     }
 
     pub fn get_view(&self) -> Mat4 {
@@ -97,7 +208,12 @@ impl Camera {
         )
     }
 
+    /// Includes the jitter, see `next_jitter`
     pub fn get_projection(&self) -> Mat4 {
+        Mat4::from_translation(self.jitter.extend(0.0)) * self.get_unjittered_projection()
+    }
+
+    pub fn get_unjittered_projection(&self) -> Mat4 {
         glam::Mat4::perspective_rh(
             f32::to_radians(self.fov_degrees),
             self.aspect_ratio,
@@ -106,6 +222,58 @@ impl Camera {
         )
     }
 
+    /// Moves the projection to the next sub-pixel offset of a Halton (2, 3) sequence,
+    /// so that accumulating the frames over time covers the whole pixel
+    pub fn next_jitter(&mut self, width: u32, height: u32) {
+        self.jitter_index = self.jitter_index % NUM_JITTER_SAMPLES + 1;
+
+        let offset = Vec2::new(halton(self.jitter_index, 2), halton(self.jitter_index, 3)) - 0.5;
+        self.jitter = offset * 2.0 / Vec2::new(width as f32, height as f32);
+    }
+
+    pub fn clear_jitter(&mut self) {
+        self.jitter = Vec2::ZERO;
+    }
+
+    pub fn get_jitter(&self) -> Vec2 {
+        self.jitter
+    }
+
+    pub fn get_exposure(&self) -> Exposure {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        self.exposure = exposure;
+    }
+
+    pub fn get_lens(&self) -> Lens {
+        self.lens
+    }
+
+    pub fn set_lens(&mut self, lens: Lens) {
+        self.lens = lens;
+    }
+
+    /// The focal length in world units of a lens that gives the field of view on the sensor
+    pub fn get_focal_length(&self) -> f32 {
+        0.5 * self.get_sensor_height() / (0.5 * f32::to_radians(self.fov_degrees)).tan()
+    }
+
+    /// The sensor height in world units, assuming that they are meters
+    pub fn get_sensor_height(&self) -> f32 {
+        self.lens.sensor_height * 0.001
+    }
+
+    pub fn get_aperture_diameter(&self) -> f32 {
+        self.get_focal_length() / self.lens.f_stop
+    }
+
+    /// The pixel that the autofocus measures the focal distance at, if enabled
+    pub fn get_autofocus_pixel(&self) -> Option<UVec2> {
+        self.lens.autofocus.then_some(self.cursor_pixel)
+    }
+
     pub fn get_position(&self) -> Vec3 {
         self.camera_rig.final_transform.position
     }
@@ -123,6 +291,24 @@ impl Camera {
             .set_rotation_quat(rotation);
     }
 
+    /// Moves the camera to the world transform of a camera loaded from glTF and uses its
+    /// field of view and clip planes. The aspect ratio of the window is kept.
+    /// Note: roll is lost since the camera rig only has yaw and pitch
+    pub fn set_from_model_camera(&mut self, camera: &ModelCamera, world_transform: Mat4) {
+        let (_, rotation, position) = world_transform.to_scale_rotation_translation();
+
+        self.camera_rig.driver_mut::<Position>().position = position;
+        self.camera_rig
+            .driver_mut::<YawPitch>()
+            .set_rotation_quat(rotation);
+
+        self.fov_degrees = camera.yfov.to_degrees();
+        self.z_near = camera.z_near;
+        if let Some(z_far) = camera.z_far {
+            self.z_far = z_far;
+        }
+    }
+
     pub(crate) fn get_near_plane(&self) -> f32 {
         self.z_near
     }
@@ -185,6 +185,15 @@ impl DescriptorSet {
     }
 
     pub fn write_storage_image(&self, device: &Device, name: DescriptorIdentifier, image: &Image) {
+        self.write_storage_image_view(device, name, image.image_view);
+    }
+
+    pub fn write_storage_image_view(
+        &self,
+        device: &Device,
+        name: DescriptorIdentifier,
+        image_view: vk::ImageView,
+    ) {
         let binding = match name {
             DescriptorIdentifier::Name(name) => match self.binding_map.get(&name) {
                 Some(binding) => binding.binding,
@@ -195,7 +204,7 @@ impl DescriptorSet {
 
         let descriptor_info = vk::DescriptorImageInfo {
             image_layout: vk::ImageLayout::GENERAL,
-            image_view: image.image_view,
+            image_view,
             sampler: vk::Sampler::null(),
         };
 
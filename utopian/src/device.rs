@@ -6,8 +6,12 @@ use gpu_allocator::vulkan::*;
 use gpu_allocator::AllocatorDebugSettings;
 use std::sync::{Arc, Mutex};
 
+use crate::buffer::Buffer;
+use crate::upload_batcher::*;
+
 pub struct Device {
     pub handle: ash::Device,
+    pub instance: ash::Instance,
     pub physical_device: vk::PhysicalDevice,
     pub queue: vk::Queue,
     pub cmd_pool: vk::CommandPool,
@@ -17,10 +21,12 @@ pub struct Device {
     pub rt_pipeline_properties: vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
     pub acceleration_structure_ext: khr::AccelerationStructure,
     pub raytracing_pipeline_ext: khr::RayTracingPipeline,
+    pub draw_indirect_count_ext: khr::DrawIndirectCount,
     pub gpu_allocator: Arc<Mutex<gpu_allocator::vulkan::Allocator>>,
     pub raytracing_supported: bool,
     pub debug_utils: ash::extensions::ext::DebugUtils,
     pub frame_profiler: crate::profiler_backend::VkProfilerData,
+    pub upload_batcher: Mutex<UploadBatcher>,
 }
 
 impl Drop for Device {
@@ -78,6 +84,8 @@ impl Device {
                 vk::KhrMaintenance2Fn::name().as_ptr(),
                 vk::KhrMaintenance3Fn::name().as_ptr(),
                 vk::KhrShaderNonSemanticInfoFn::name().as_ptr(),
+                // For the GPU-driven draws, see `Renderer::draw_meshes_indirect`
+                vk::KhrDrawIndirectCountFn::name().as_ptr(),
             ];
 
             let rt_extension_names_raw = vec![
@@ -154,11 +162,15 @@ impl Device {
             let (cmd_pool, setup_cmd_buf) =
                 Device::create_setup_command_buffer(&device, queue_family_index);
 
+            let upload_batcher = UploadBatcher::new(&device, queue_family_index);
+
             let (rt_pipeline_properties, _as_features) =
                 Device::retrieve_rt_properties(instance, physical_device);
 
             let acceleration_structure_ext = khr::AccelerationStructure::new(instance, &device);
             let raytracing_pipeline_ext = khr::RayTracingPipeline::new(instance, &device);
+            // The core 1.2 entry points would need the drawIndirectCount feature instead
+            let draw_indirect_count_ext = khr::DrawIndirectCount::new(instance, &device);
 
             // println!("{:#?}", rt_pipeline_properties);
             // println!("{:#?}", as_features);
@@ -196,6 +208,7 @@ impl Device {
 
             Device {
                 handle: device,
+                instance: instance.clone(),
                 physical_device,
                 queue: present_queue,
                 queue_family_index,
@@ -205,14 +218,38 @@ impl Device {
                 rt_pipeline_properties,
                 acceleration_structure_ext,
                 raytracing_pipeline_ext,
+                draw_indirect_count_ext,
                 gpu_allocator: Arc::new(Mutex::new(gpu_allocator)),
                 raytracing_supported,
                 debug_utils,
                 frame_profiler,
+                upload_batcher: Mutex::new(upload_batcher),
             }
         }
     }
 
+    /// True if mips of `format` can be generated with linear filtered blits
+    pub fn supports_linear_blit(&self, format: vk::Format) -> bool {
+        let required_features = vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+
+        self.format_features(format).contains(required_features)
+    }
+
+    pub fn supports_sampled_format(&self, format: vk::Format) -> bool {
+        self.format_features(format)
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    }
+
+    fn format_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+                .optimal_tiling_features
+        }
+    }
+
     fn retrieve_rt_properties(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
@@ -267,7 +304,45 @@ impl Device {
         (pool, command_buffers[0])
     }
 
+    /// Copies `data` to staging memory and records the copy from it with `record_copy`
+    /// into the current upload batch. The copy executes when the batch is flushed, which
+    /// happens at the latest before the next frame is submitted.
+    /// Note: `record_copy` must not queue other uploads, the batcher is locked while it runs
+    pub fn upload<F>(&self, data: &[u8], record_copy: F)
+    where
+        F: FnOnce(&Device, vk::CommandBuffer, &Buffer, u64),
+    {
+        self.upload_batcher
+            .lock()
+            .unwrap()
+            .upload(self, data, record_copy);
+    }
+
+    /// Records commands without staging data into the current upload batch
+    pub fn record_upload_commands<F: FnOnce(&Device, vk::CommandBuffer)>(&self, record: F) {
+        self.upload_batcher.lock().unwrap().record(self, record);
+    }
+
+    /// Submits all pending uploads in a single batch
+    pub fn flush_uploads(&self) -> Option<UploadTicket> {
+        self.upload_batcher.lock().unwrap().flush(self)
+    }
+
+    pub fn is_upload_complete(&self, ticket: UploadTicket) -> bool {
+        self.upload_batcher
+            .lock()
+            .unwrap()
+            .is_complete(self, ticket)
+    }
+
+    pub fn wait_for_upload(&self, ticket: UploadTicket) {
+        self.upload_batcher.lock().unwrap().wait(self, ticket);
+    }
+
     pub fn execute_and_submit<F: FnOnce(&Device, vk::CommandBuffer)>(&self, recording_function: F) {
+        // Pending uploads may be used by the recorded commands
+        self.flush_uploads();
+
         let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
         unsafe {
@@ -1,14 +1,24 @@
 use ash::vk;
-use glam::{Mat4, Vec2, Vec3, Vec4};
-
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::animation::*;
+use crate::asset_manager::*;
+use crate::bounds::*;
+use crate::buffer::*;
 use crate::device::*;
-use crate::image::ImageDesc;
+use crate::light::Light;
+use crate::mesh_optimizer::*;
 use crate::primitive::*;
+use crate::renderer::MAX_NUM_FRAMES_IN_FLIGHT;
+use crate::scene_graph::*;
+use crate::tangent_space::*;
 use crate::texture::*;
 
 pub const DEFAULT_TEXTURE_MAP: u32 = u32::MAX;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Deserialize)]
 pub enum MaterialType {
     Lambertian = 0,
     Metal = 1,
@@ -16,203 +26,973 @@ pub enum MaterialType {
     DiffuseLight = 3,
 }
 
+// Must match the ALPHA_MODE_* defines in material.glsl
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque = 0,
+    Mask = 1,
+    Blend = 2,
+}
+
+// KHR_texture_transform
+#[derive(Copy, Clone)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub rotation: f32, // radians
+    pub scale: Vec2,
+}
+
 // Note: indexes into the Model specific texture array,
 // not bindless indexes.
+#[derive(Clone)]
 pub struct Material {
     pub diffuse_map: u32,
     pub normal_map: u32,
     pub metallic_roughness_map: u32,
     pub occlusion_map: u32,
+    pub emissive_map: u32,
     pub base_color_factor: Vec4,
     pub metallic_factor: f32,
     pub roughness_factor: f32,
+    pub emissive_factor: Vec3, // includes KHR_materials_emissive_strength
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+    pub transmission_factor: f32,
+    pub ior: f32,
+    pub clearcoat_factor: f32,
+    pub clearcoat_roughness_factor: f32,
+    // Indexed by the UV_TRANSFORM_* defines in material.glsl
+    pub uv_transforms: [UvTransform; 5],
 
     // Ray tracing properties
     pub material_type: MaterialType, // 0 = lambertian, 1 = metal, 2 = dielectric, 3 = diffuse light
     pub material_property: f32,      // metal = fuzz, dielectric = index of refraction
 }
 
+impl Default for UvTransform {
+    fn default() -> Self {
+        UvTransform {
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl UvTransform {
+    // The rotation is counter-clockwise in UV space which has the y-axis pointing down
+    pub fn matrix(&self) -> Mat3 {
+        Mat3::from_scale_angle_translation(self.scale, -self.rotation, self.offset)
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            diffuse_map: DEFAULT_TEXTURE_MAP,
+            normal_map: DEFAULT_TEXTURE_MAP,
+            metallic_roughness_map: DEFAULT_TEXTURE_MAP,
+            occlusion_map: DEFAULT_TEXTURE_MAP,
+            emissive_map: DEFAULT_TEXTURE_MAP,
+            base_color_factor: Vec4::ONE,
+            metallic_factor: 0.0,
+            roughness_factor: 0.5,
+            emissive_factor: Vec3::ZERO,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            transmission_factor: 0.0,
+            ior: 1.5,
+            clearcoat_factor: 0.0,
+            clearcoat_roughness_factor: 0.0,
+            uv_transforms: [UvTransform::default(); 5],
+            material_type: MaterialType::Lambertian,
+            material_property: 0.0,
+        }
+    }
+}
+
+// Material extensions that are not supported by the gltf crate are read
+// directly from the json, indexed by material index.
+#[derive(Deserialize, Default)]
+struct JsonRoot {
+    #[serde(default)]
+    materials: Vec<JsonMaterial>,
+}
+
+#[derive(Deserialize, Default)]
+struct JsonMaterial {
+    #[serde(default)]
+    extensions: JsonMaterialExtensions,
+}
+
+#[derive(Deserialize, Default)]
+struct JsonMaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<JsonEmissiveStrength>,
+    #[serde(rename = "KHR_materials_clearcoat")]
+    clearcoat: Option<JsonClearcoat>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonEmissiveStrength {
+    emissive_strength: f32,
+}
+
+// Todo: clearcoat textures
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct JsonClearcoat {
+    clearcoat_factor: f32,
+    clearcoat_roughness_factor: f32,
+}
+
+fn read_material_extensions(path: &str) -> Vec<JsonMaterial> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => panic!("Reading model {} failed with error: {}", path, err),
+    };
+
+    let root: Result<JsonRoot, _> = if bytes.starts_with(b"glTF") {
+        match gltf::Glb::from_slice(&bytes) {
+            Ok(glb) => serde_json::from_slice(&glb.json),
+            Err(err) => panic!("Reading glb {} failed with error: {}", path, err),
+        }
+    } else {
+        serde_json::from_slice(&bytes)
+    };
+
+    match root {
+        Ok(root) => root.materials,
+        Err(err) => {
+            log::warn!("Reading material extensions from {} failed: {}", path, err);
+            vec![]
+        }
+    }
+}
+
+fn load_uv_transform(texture: &gltf::texture::Info) -> UvTransform {
+    match texture.texture_transform() {
+        Some(transform) => UvTransform {
+            offset: Vec2::from(transform.offset()),
+            rotation: transform.rotation(),
+            scale: Vec2::from(transform.scale()),
+        },
+        None => UvTransform::default(),
+    }
+}
+
+fn load_material(
+    material: &gltf::Material,
+    extensions: Option<&JsonMaterialExtensions>,
+) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+
+    let texture_index = |texture: Option<gltf::texture::Info>| {
+        texture.map_or(DEFAULT_TEXTURE_MAP, |texture| {
+            texture.texture().index() as u32
+        })
+    };
+
+    // Note: KHR_texture_transform is not exposed for the normal and occlusion
+    // textures by the gltf crate so they always use the identity transform.
+    // Todo: multiple texture coordinate sets (texCoord) are not supported
+    let mut uv_transforms = [UvTransform::default(); 5];
+    if let Some(texture) = pbr.base_color_texture() {
+        uv_transforms[0] = load_uv_transform(&texture);
+    }
+    if let Some(texture) = pbr.metallic_roughness_texture() {
+        uv_transforms[2] = load_uv_transform(&texture);
+    }
+    if let Some(texture) = material.emissive_texture() {
+        uv_transforms[4] = load_uv_transform(&texture);
+    }
+
+    let emissive_strength = extensions
+        .and_then(|extensions| extensions.emissive_strength.as_ref())
+        .map_or(1.0, |extension| extension.emissive_strength);
+
+    let (clearcoat_factor, clearcoat_roughness_factor) = extensions
+        .and_then(|extensions| extensions.clearcoat.as_ref())
+        .map_or((0.0, 0.0), |extension| {
+            (
+                extension.clearcoat_factor,
+                extension.clearcoat_roughness_factor,
+            )
+        });
+
+    let transmission_factor = material
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+    let ior = material.ior().unwrap_or(1.5);
+
+    // Pick the closest matching path tracing material
+    let (material_type, material_property) = if transmission_factor > 0.0 {
+        (MaterialType::Dielectric, ior)
+    } else if pbr.metallic_factor() >= 1.0 && pbr.metallic_roughness_texture().is_none() {
+        (MaterialType::Metal, pbr.roughness_factor())
+    } else {
+        (MaterialType::Lambertian, 0.0)
+    };
+
+    Material {
+        diffuse_map: texture_index(pbr.base_color_texture()),
+        normal_map: material
+            .normal_texture()
+            .map_or(DEFAULT_TEXTURE_MAP, |texture| {
+                texture.texture().index() as u32
+            }),
+        metallic_roughness_map: texture_index(pbr.metallic_roughness_texture()),
+        occlusion_map: material
+            .occlusion_texture()
+            .map_or(DEFAULT_TEXTURE_MAP, |texture| {
+                texture.texture().index() as u32
+            }),
+        emissive_map: texture_index(material.emissive_texture()),
+        base_color_factor: Vec4::from(pbr.base_color_factor()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        emissive_factor: Vec3::from(material.emissive_factor()) * emissive_strength,
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+        transmission_factor,
+        ior,
+        clearcoat_factor,
+        clearcoat_roughness_factor,
+        uv_transforms,
+        material_type,
+        material_property,
+    }
+}
+
 pub struct Mesh {
-    pub primitive: Primitive,
+    pub primitive: MeshHandle,
+    pub bounds: MeshBounds,
+    // `bounds` transformed by the world transform of `node`, see `Renderer::update_world_bounds`
+    pub world_bounds: MeshBounds,
+    // World transform of the previously rendered frame, for motion vectors
+    pub prev_world_transform: Option<Mat4>,
     pub material: Material,
     pub gpu_mesh: u32,
+    pub node: NodeId,
+    pub deformation: Option<MeshDeformation>,
+    // Level of detail in `primitive.lods`, selected by `Renderer::update_lods`
+    pub lod: usize,
+}
+
+// Must match SkinVertex in skinning.comp
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SkinVertex {
+    joints: [u32; 4],
+    weights: Vec4,
+}
+
+// Skinning and morph target data for meshes that are deformed by the skinning pass.
+// The deformed vertices are written to `Mesh::primitive` so that both rasterization
+// and the BLAS refits see the animated mesh, `rest_vertex_buffer` keeps the original ones.
+pub struct MeshDeformation {
+    pub skin: Option<usize>, // Index into `Model::skins`
+    pub num_vertices: u32,
+    pub rest_vertex_buffer: Buffer,
+    pub skin_vertex_buffer: Option<Buffer>, // JOINTS_0 and WEIGHTS_0
+    // Position, normal and tangent displacements for every target and vertex
+    pub morph_target_buffer: Option<Buffer>,
+    pub num_morph_targets: u32,
+    pub morph_weights: Vec<f32>,
+    // Updated every frame by `Renderer::update_deformations`, with one copy of the
+    // joint matrices and morph weights per frame in flight
+    pub joint_matrices_buffer: Option<Buffer>,
+    pub morph_weights_buffer: Option<Buffer>,
+    // Index in the deformations of the skinning pass, set by `Renderer::add_model`
+    pub gpu_deformation: usize,
+}
+
+impl MeshDeformation {
+    /// Note: the buffers must not be used by any frames in flight
+    pub fn destroy(self, device: &Device) {
+        self.rest_vertex_buffer.destroy(device);
+
+        let optional_buffers = [
+            self.skin_vertex_buffer,
+            self.morph_target_buffer,
+            self.joint_matrices_buffer,
+            self.morph_weights_buffer,
+        ];
+
+        for buffer in optional_buffers.into_iter().flatten() {
+            buffer.destroy(device);
+        }
+    }
+}
+
+pub struct Skin {
+    pub joints: Vec<NodeId>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+// Light from KHR_lights_punctual, `light` is in the local space of `node`
+pub struct ModelLight {
+    pub light: Light,
+    pub node: NodeId,
+    // Index in the light buffer, set by `Renderer::add_model`
+    pub gpu_light: u32,
+}
+
+// Perspective camera, looks along -Z in the local space of `node`
+pub struct ModelCamera {
+    pub name: String,
+    pub node: NodeId,
+    pub yfov: f32, // radians
+    pub aspect_ratio: Option<f32>,
+    pub z_near: f32,
+    pub z_far: Option<f32>,
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub textures: Vec<Texture>,
-    pub transforms: Vec<Mat4>,
+    // Textures that failed to load are None and use the default texture for their usage
+    pub textures: Vec<Option<TextureHandle>>,
+    pub lights: Vec<ModelLight>,
+    pub cameras: Vec<ModelCamera>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<AnimationClip>,
+    // The node hierarchy of the model. The node ids referenced by `Mesh::node` are
+    // moved into the renderer's scene graph when the model is added to the renderer.
+    pub nodes: SceneGraph,
 }
 
-pub fn load_node(
+// `node_ids` maps glTF node indices to the node ids in `Model::nodes`
+#[allow(clippy::too_many_arguments)]
+fn load_node(
     device: &Device,
+    asset_manager: &mut AssetManager,
     node: &gltf::Node,
     model: &mut Model,
-    buffers: &[gltf::buffer::Data],
-    parent_transform: Mat4,
+    data: &GltfData,
+    node_ids: &mut [Option<NodeId>],
+    parent: Option<NodeId>,
     path: &str,
 ) {
-    let node_transform =
-        parent_transform * glam::Mat4::from_cols_array_2d(&node.transform().matrix());
+    let node_id = model.nodes.add_node(
+        node.name().unwrap_or_default(),
+        Mat4::from_cols_array_2d(&node.transform().matrix()),
+        parent,
+    );
+    node_ids[node.index()] = Some(node_id);
 
     for child in node.children() {
-        load_node(device, &child, model, buffers, node_transform, path);
+        load_node(
+            device,
+            asset_manager,
+            &child,
+            model,
+            data,
+            node_ids,
+            Some(node_id),
+            path,
+        );
+    }
+
+    if let Some(light) = node.light() {
+        model.lights.push(ModelLight {
+            light: load_light(&light),
+            node: node_id,
+            gpu_light: 0,
+        });
+    }
+
+    if let Some(camera) = node.camera() {
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => {
+                model.cameras.push(ModelCamera {
+                    name: camera.name().unwrap_or_default().to_string(),
+                    node: node_id,
+                    yfov: perspective.yfov(),
+                    aspect_ratio: perspective.aspect_ratio(),
+                    z_near: perspective.znear(),
+                    z_far: perspective.zfar(),
+                });
+            }
+            gltf::camera::Projection::Orthographic(_) => {
+                log::warn!("Orthographic camera in {} is not supported", path);
+            }
+        }
     }
 
     if let Some(mesh) = node.mesh() {
-        let primitives = mesh.primitives();
-
-        for primitive in primitives {
-            let reader = primitive.reader(|i| Some(&buffers[i.index()]));
-
-            let indices: Vec<_> = reader.read_indices().unwrap().into_u32().collect();
-            let positions: Vec<_> = reader.read_positions().unwrap().map(Vec3::from).collect();
-            let normals: Vec<_> = reader.read_normals().unwrap().map(Vec3::from).collect();
-            let tex_coords = if let Some(tex_coords) = reader.read_tex_coords(0) {
-                tex_coords.into_f32().map(Vec2::from).collect()
-            } else {
-                vec![Vec2::new(0.0, 0.0); positions.len()]
-            };
+        for (primitive, geometry) in mesh.primitives().zip(&data.primitives[mesh.index()]) {
+            let indices = geometry.indices.clone();
+            let vertices = geometry.vertices.clone();
 
-            let tangents = if let Some(tangents) = reader.read_tangents() {
-                tangents.map(Vec4::from).collect()
-            } else {
-                vec![Vec4::new(0.0, 0.0, 0.0, 0.0); positions.len()]
-            };
+            let material = primitive.material();
+            let extensions = material
+                .index()
+                .and_then(|index| data.material_extensions.get(index))
+                .map(|material| &material.extensions);
+
+            let deformation = load_mesh_deformation(
+                device,
+                node,
+                &primitive,
+                &data.buffers,
+                &vertices,
+                geometry.source_vertices.as_deref(),
+            );
 
-            let colors: Vec<_> = if let Some(colors) = reader.read_colors(0) {
-                colors.into_rgba_f32().map(Vec4::from).collect()
-            } else {
-                vec![Vec4::new(1.0, 1.0, 1.0, 1.0); positions.len()]
+            // Deformed meshes get their own vertex buffer that the skinning pass writes to
+            let primitive = match deformation {
+                Some(_) => asset_manager.unique_mesh(device, path, indices, vertices),
+                None => asset_manager.mesh(device, path, indices, vertices),
             };
 
-            let mut vertices: Vec<Vertex> = vec![];
-
-            for (i, _) in positions.iter().enumerate() {
-                vertices.push(Vertex {
-                    pos: positions[i].extend(0.0),
-                    normal: normals[i].extend(0.0),
-                    uv: tex_coords[i],
-                    tangent: tangents[i],
-                    color: colors[i],
-                });
-            }
+            model.meshes.push(Mesh {
+                bounds: MeshBounds::from_vertices(&primitive.vertices),
+                world_bounds: MeshBounds::default(),
+                prev_world_transform: None,
+                primitive,
+                material: load_material(&material, extensions),
+                gpu_mesh: 0,
+                node: node_id,
+                deformation,
+                lod: 0,
+            });
+        }
+    }
+}
 
-            let material = primitive.material();
-            let pbr = material.pbr_metallic_roughness();
+fn create_storage_buffer<T: Copy>(device: &Device, data: &[T], name: &str) -> Buffer {
+    let mut buffer = Buffer::new(
+        device,
+        Some(data),
+        std::mem::size_of_val(data) as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+    buffer.set_debug_name(device, name);
+    buffer
+}
 
-            let diffuse_index = pbr
-                .base_color_texture()
-                .map_or(DEFAULT_TEXTURE_MAP, |texture| {
-                    texture.texture().index() as u32
-                });
+// Returns None for meshes that are neither skinned nor have morph targets
+fn load_mesh_deformation(
+    device: &Device,
+    node: &gltf::Node,
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    vertices: &[Vertex],
+    source_vertices: Option<&[u32]>,
+) -> Option<MeshDeformation> {
+    let reader = primitive.reader(|i| Some(&buffers[i.index()]));
+    let num_vertices = vertices.len();
+    let num_source_vertices = reader.read_positions().unwrap().len();
+
+    // Todo: JOINTS_1 and WEIGHTS_1 for meshes with more than four influences per vertex
+    let skin_vertices: Option<Vec<SkinVertex>> =
+        match (node.skin(), reader.read_joints(0), reader.read_weights(0)) {
+            (Some(_), Some(joints), Some(weights)) => Some(remap_vertex_data(
+                joints
+                    .into_u16()
+                    .zip(weights.into_f32())
+                    .map(|(joints, weights)| SkinVertex {
+                        joints: joints.map(u32::from),
+                        weights: Vec4::from(weights),
+                    })
+                    .collect(),
+                source_vertices,
+            )),
+            _ => None,
+        };
+
+    let mut morph_targets: Vec<Vec4> = vec![];
+    let mut num_morph_targets = 0;
+
+    for (positions, normals, tangents) in reader.read_morph_targets() {
+        let positions: Vec<Vec3> = positions
+            .map(|positions| positions.map(Vec3::from).collect())
+            .unwrap_or_else(|| vec![Vec3::ZERO; num_source_vertices]);
+        let normals: Vec<Vec3> = normals
+            .map(|normals| normals.map(Vec3::from).collect())
+            .unwrap_or_else(|| vec![Vec3::ZERO; num_source_vertices]);
+        let tangents: Vec<Vec3> = tangents
+            .map(|tangents| tangents.map(Vec3::from).collect())
+            .unwrap_or_else(|| vec![Vec3::ZERO; num_source_vertices]);
+
+        let positions = remap_vertex_data(positions, source_vertices);
+        let normals = remap_vertex_data(normals, source_vertices);
+        let tangents = remap_vertex_data(tangents, source_vertices);
+
+        for i in 0..num_vertices {
+            morph_targets.push(positions[i].extend(0.0));
+            morph_targets.push(normals[i].extend(0.0));
+            morph_targets.push(tangents[i].extend(0.0));
+        }
 
-            let normal_index = material
-                .normal_texture()
-                .map_or(DEFAULT_TEXTURE_MAP, |texture| {
-                    texture.texture().index() as u32
-                });
+        num_morph_targets += 1;
+    }
 
-            let metallic_roughness_index = pbr
-                .metallic_roughness_texture()
-                .map_or(DEFAULT_TEXTURE_MAP, |texture| {
-                    texture.texture().index() as u32
-                });
+    if skin_vertices.is_none() && num_morph_targets == 0 {
+        return None;
+    }
 
-            let occlusion_index = material
-                .occlusion_texture()
-                .map_or(DEFAULT_TEXTURE_MAP, |texture| {
-                    texture.texture().index() as u32
-                });
+    // The weights of the node override the default weights of the mesh
+    let mut morph_weights = node
+        .weights()
+        .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+        .map(|weights| weights.to_vec())
+        .unwrap_or_default();
+    morph_weights.resize(num_morph_targets, 0.0);
+
+    let skin = skin_vertices.as_ref().and(node.skin());
+    let joint_matrices = skin
+        .as_ref()
+        .map(|skin| vec![Mat4::IDENTITY; skin.joints().count() * MAX_NUM_FRAMES_IN_FLIGHT]);
+
+    Some(MeshDeformation {
+        skin: skin.map(|skin| skin.index()),
+        num_vertices: num_vertices as u32,
+        rest_vertex_buffer: create_storage_buffer(device, vertices, "rest_vertex_buffer"),
+        skin_vertex_buffer: skin_vertices.map(|skin_vertices| {
+            create_storage_buffer(device, &skin_vertices, "skin_vertex_buffer")
+        }),
+        morph_target_buffer: (num_morph_targets > 0)
+            .then(|| create_storage_buffer(device, &morph_targets, "morph_target_buffer")),
+        num_morph_targets: num_morph_targets as u32,
+        joint_matrices_buffer: joint_matrices.map(|joint_matrices| {
+            Buffer::new(
+                device,
+                Some(joint_matrices.as_slice()),
+                std::mem::size_of_val(joint_matrices.as_slice()) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_allocator::MemoryLocation::CpuToGpu,
+            )
+        }),
+        morph_weights_buffer: (num_morph_targets > 0).then(|| {
+            let morph_weights = morph_weights.repeat(MAX_NUM_FRAMES_IN_FLIGHT);
+            Buffer::new(
+                device,
+                Some(morph_weights.as_slice()),
+                std::mem::size_of_val(morph_weights.as_slice()) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_allocator::MemoryLocation::CpuToGpu,
+            )
+        }),
+        morph_weights,
+        gpu_deformation: 0,
+    })
+}
 
-            let base_color_factor = pbr.base_color_factor();
-            let metallic_factor = pbr.metallic_factor();
-            let roughness_factor = pbr.roughness_factor();
+fn load_skin(
+    skin: &gltf::Skin,
+    buffers: &[gltf::buffer::Data],
+    node_ids: &[Option<NodeId>],
+) -> Skin {
+    let reader = skin.reader(|i| Some(&buffers[i.index()]));
+
+    let joints: Vec<NodeId> = skin
+        .joints()
+        .map(|joint| node_ids[joint.index()].expect("Skin joint is not part of the scene"))
+        .collect();
+
+    let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+        Some(matrices) => matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+        None => vec![Mat4::IDENTITY; joints.len()],
+    };
 
-            model.meshes.push(Mesh {
-                primitive: Primitive::new(device, indices, vertices),
-                material: Material {
-                    diffuse_map: diffuse_index,
-                    normal_map: normal_index,
-                    metallic_roughness_map: metallic_roughness_index,
-                    occlusion_map: occlusion_index,
-                    base_color_factor: Vec4::from(base_color_factor),
-                    metallic_factor,
-                    roughness_factor,
-                    material_type: MaterialType::Lambertian,
-                    material_property: 0.0,
-                },
-                gpu_mesh: 0,
-            });
+    Skin {
+        joints,
+        inverse_bind_matrices,
+    }
+}
 
-            model
-                .meshes
-                .last_mut()
-                .unwrap()
-                .primitive
-                .vertex_buffer
-                .set_debug_name(device, format!("vertex_buffer: {}", path).as_str());
-            model
-                .meshes
-                .last_mut()
-                .unwrap()
-                .primitive
-                .index_buffer
-                .set_debug_name(device, format!("index_buffer: {}", path).as_str());
+fn load_animation(
+    animation: &gltf::Animation,
+    buffers: &[gltf::buffer::Data],
+    node_ids: &[Option<NodeId>],
+) -> AnimationClip {
+    let mut channels = vec![];
+
+    for channel in animation.channels() {
+        let node = match node_ids[channel.target().node().index()] {
+            Some(node) => node,
+            None => continue, // The target node is not part of the loaded scene
+        };
+
+        let reader = channel.reader(|i| Some(&buffers[i.index()]));
+
+        let times: Vec<f32> = match reader.read_inputs() {
+            Some(inputs) => inputs.collect(),
+            None => continue,
+        };
+
+        let values = match reader.read_outputs() {
+            Some(gltf::animation::util::ReadOutputs::Translations(translations)) => {
+                ChannelValues::Translations(translations.map(Vec3::from).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::Rotations(rotations)) => {
+                ChannelValues::Rotations(rotations.into_f32().map(Quat::from_array).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::Scales(scales)) => {
+                ChannelValues::Scales(scales.map(Vec3::from).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::MorphTargetWeights(weights)) => {
+                let weights: Vec<f32> = weights.into_f32().collect();
+                let interpolation = channel.sampler().interpolation();
+                let values_per_keyframe = match interpolation {
+                    gltf::animation::Interpolation::CubicSpline => times.len() * 3,
+                    _ => times.len(),
+                };
+                ChannelValues::MorphWeights {
+                    num_targets: weights.len() / values_per_keyframe.max(1),
+                    weights,
+                }
+            }
+            None => continue,
+        };
 
-            model.transforms.push(node_transform);
+        if times.is_empty() {
+            continue;
         }
+
+        channels.push(Channel {
+            node,
+            interpolation: match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            },
+            times,
+            values,
+        });
+    }
+
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last().copied())
+        .fold(0.0, f32::max);
+
+    AnimationClip {
+        name: animation.name().unwrap_or_default().to_string(),
+        channels,
+        duration,
     }
 }
 
-pub fn load_gltf(device: &Device, path: &str) -> Model {
-    let (gltf, buffers, mut images) = match gltf::import(path) {
-        Ok(result) => result,
-        Err(err) => panic!("Loading model {} failed with error: {}", path, err),
+// KHR_lights_punctual uses the same units as `Light`, candela for point and spot
+// lights and lux for directional lights. Lights point along -Z in node space.
+fn load_light(light: &gltf::khr_lights_punctual::Light) -> Light {
+    let color = Vec3::from(light.color());
+    let intensity = light.intensity();
+    let range = light.range().unwrap_or(0.0);
+
+    match light.kind() {
+        gltf::khr_lights_punctual::Kind::Directional => Light::Directional {
+            direction: -Vec3::Z,
+            color,
+            illuminance: intensity,
+        },
+        gltf::khr_lights_punctual::Kind::Point => Light::Point {
+            position: Vec3::ZERO,
+            color,
+            intensity,
+            range,
+        },
+        gltf::khr_lights_punctual::Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => Light::Spot {
+            position: Vec3::ZERO,
+            direction: -Vec3::Z,
+            color,
+            intensity,
+            range,
+            inner_cone_angle,
+            outer_cone_angle,
+        },
+    }
+}
+
+fn gltf_image_to_dynamic_image(image: gltf::image::Data) -> image::DynamicImage {
+    use gltf::image::Format;
+    use image::{DynamicImage, ImageBuffer};
+
+    let (width, height) = (image.width, image.height);
+    let pixels = image.pixels;
+    // 16-bit pixels are stored as bytes in native endianness
+    let pixels16 = |pixels: &[u8]| -> Vec<u16> {
+        pixels
+            .chunks_exact(2)
+            .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+            .collect()
     };
 
-    let mut model = Model {
-        meshes: vec![],
-        transforms: vec![],
-        textures: vec![],
+    let dynamic_image = match image.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        Format::B8G8R8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageBgr8),
+        Format::B8G8R8A8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageBgra8)
+        }
+        Format::R16 => {
+            ImageBuffer::from_raw(width, height, pixels16(&pixels)).map(DynamicImage::ImageLuma16)
+        }
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, pixels16(&pixels)).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, pixels16(&pixels)).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, pixels16(&pixels)).map(DynamicImage::ImageRgba16)
+        }
     };
 
-    for image in &mut images {
-        // Convert images from rgb8 to rgba8
-        if image.format == gltf::image::Format::R8G8B8 {
-            let dynamic_image = image::DynamicImage::ImageRgb8(
-                image::RgbImage::from_raw(
-                    image.width,
-                    image.height,
-                    std::mem::take(&mut image.pixels),
-                )
-                .unwrap(),
-            );
+    dynamic_image.expect("Image data does not match its size")
+}
 
-            let rgba8_image = dynamic_image.to_rgba8();
-            image.format = gltf::image::Format::R8G8B8A8;
-            image.pixels = rgba8_image.into_raw();
-        }
+// Reads the vertices of a primitive and generates the normals and tangents that are
+// missing. `source_vertices` is set if the vertices were split or reordered.
+fn decode_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    optimize_meshes: bool,
+) -> PrimitiveGeometry {
+    let reader = primitive.reader(|i| Some(&buffers[i.index()]));
+
+    let mut indices: Vec<_> = reader.read_indices().unwrap().into_u32().collect();
+    let positions: Vec<_> = reader.read_positions().unwrap().map(Vec3::from).collect();
+    let normals: Vec<_> = if let Some(normals) = reader.read_normals() {
+        normals.map(Vec3::from).collect()
+    } else {
+        vec![Vec3::ZERO; positions.len()]
+    };
+    let tex_coords = if let Some(tex_coords) = reader.read_tex_coords(0) {
+        tex_coords.into_f32().map(Vec2::from).collect()
+    } else {
+        vec![Vec2::new(0.0, 0.0); positions.len()]
+    };
+
+    let tangents: Option<Vec<Vec4>> = reader
+        .read_tangents()
+        .map(|tangents| tangents.map(Vec4::from).collect());
+
+    let colors: Vec<_> = if let Some(colors) = reader.read_colors(0) {
+        colors.into_rgba_f32().map(Vec4::from).collect()
+    } else {
+        vec![Vec4::new(1.0, 1.0, 1.0, 1.0); positions.len()]
+    };
+
+    let mut vertices: Vec<Vertex> = vec![];
+
+    for (i, _) in positions.iter().enumerate() {
+        vertices.push(Vertex {
+            pos: positions[i].extend(0.0),
+            normal: normals[i].extend(0.0),
+            uv: tex_coords[i],
+            tangent: tangents.as_ref().map_or(Vec4::ZERO, |tangents| tangents[i]),
+            color: colors[i],
+        });
+    }
+
+    // The glTF spec requires flat normals when they are missing, which means that
+    // vertices can no longer be shared between triangles
+    let mut source_vertices = reader
+        .read_normals()
+        .is_none()
+        .then(|| generate_flat_normals(&mut indices, &mut vertices));
+
+    if tangents.is_none() {
+        let split = generate_tangents(&mut indices, &mut vertices);
+        source_vertices = Some(match source_vertices {
+            Some(source_vertices) => remap_vertex_data(source_vertices, Some(&split)),
+            None => split,
+        });
+    }
+
+    if optimize_meshes {
+        let deformed = reader.read_joints(0).is_some() || reader.read_morph_targets().len() > 0;
+        let optimized = optimize_mesh(&mut indices, &mut vertices, !deformed);
+        source_vertices = Some(match source_vertices {
+            Some(source_vertices) => remap_vertex_data(source_vertices, Some(&optimized)),
+            None => optimized,
+        });
+    }
+
+    PrimitiveGeometry {
+        indices,
+        vertices,
+        source_vertices,
+    }
+}
+
+// The geometry of a primitive that is ready to be uploaded
+struct PrimitiveGeometry {
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+    // The original index of every vertex, used to remap the skinning and morph target data
+    source_vertices: Option<Vec<u32>>,
+}
+
+/// The CPU side of a glTF file with decoded textures. Decoding does not need a
+/// device so it can be done on a worker thread, see `AssetLoader`.
+pub struct GltfData {
+    document: gltf::Document,
+    buffers: Vec<gltf::buffer::Data>,
+    material_extensions: Vec<JsonMaterial>,
+    // Indexed by glTF mesh and then primitive, done here since it is the slow part
+    primitives: Vec<Vec<PrimitiveGeometry>>,
+    // Materials reference textures and not images, so there is one entry per glTF texture.
+    // The keys let textures that are shared with other files be uploaded only once.
+    pub textures: Vec<(AssetKey, TextureData)>,
+}
 
-        if image.format != gltf::image::Format::R8G8B8A8 {
-            panic!("Unsupported image format!");
+pub fn decode_gltf(path: &str, optimize_meshes: bool) -> GltfData {
+    puffin::profile_function!();
+
+    let (document, buffers, images) = match gltf::import(path) {
+        Ok(result) => result,
+        Err(err) => panic!("Loading model {} failed with error: {}", path, err),
+    };
+
+    // Base color and emissive textures hold sRGB encoded colors, the rest is linear data
+    let mut srgb_textures = HashSet::new();
+    for material in document.materials() {
+        let color_textures = [
+            material.pbr_metallic_roughness().base_color_texture(),
+            material.emissive_texture(),
+        ];
+        for info in color_textures.into_iter().flatten() {
+            srgb_textures.insert(info.texture().index());
         }
+    }
 
-        let texture = Texture::create(
-            device,
-            Some(&image.pixels),
-            ImageDesc::new_2d(image.width, image.height, vk::Format::R8G8B8A8_UNORM),
-            path,
-        );
+    // Todo: KHR_texture_basisu, gltf::import fails on KTX2 images
+    let images: Vec<image::DynamicImage> = images
+        .into_iter()
+        .map(gltf_image_to_dynamic_image)
+        .collect();
+
+    let textures = document
+        .textures()
+        .map(|texture| {
+            let color_space = match srgb_textures.contains(&texture.index()) {
+                true => ColorSpace::Srgb,
+                false => ColorSpace::Linear,
+            };
+
+            let image = texture.source();
+            let data = TextureData::from_image(&images[image.index()], color_space);
+
+            // External images are keyed by their own path so that other files that
+            // reference them can share the texture
+            let image_path = match image.source() {
+                gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                    std::path::Path::new(path)
+                        .with_file_name(uri)
+                        .to_string_lossy()
+                        .into_owned()
+                }
+                _ => format!("{}#image{}", path, image.index()),
+            };
 
-        model.textures.push(texture);
+            (AssetKey::from_texture_data(&image_path, &data), data)
+        })
+        .collect();
+
+    let primitives = document
+        .meshes()
+        .map(|mesh| {
+            mesh.primitives()
+                .map(|primitive| decode_primitive(&primitive, &buffers, optimize_meshes))
+                .collect()
+        })
+        .collect();
+
+    GltfData {
+        document,
+        buffers,
+        material_extensions: read_material_extensions(path),
+        primitives,
+        textures,
     }
+}
+
+pub fn load_gltf(device: &Device, asset_manager: &mut AssetManager, path: &str) -> Model {
+    let data = decode_gltf(path, asset_manager.optimize_meshes);
+
+    let mut model = create_gltf_model(device, asset_manager, &data, path);
+    model.textures = data
+        .textures
+        .iter()
+        .map(|(key, texture)| {
+            asset_manager
+                .texture_from_data(device, key, texture, path)
+                .map_err(|err| log::error!("{}", err))
+                .ok()
+        })
+        .collect();
+
+    model
+}
+
+/// Creates the meshes of the model but not its textures, they are expected to be
+/// added to the renderer separately.
+pub fn create_gltf_model(
+    device: &Device,
+    asset_manager: &mut AssetManager,
+    data: &GltfData,
+    path: &str,
+) -> Model {
+    puffin::profile_function!();
+
+    let mut model = Model {
+        meshes: vec![],
+        textures: vec![],
+        lights: vec![],
+        cameras: vec![],
+        skins: vec![],
+        animations: vec![],
+        nodes: SceneGraph::new(),
+    };
+
+    let gltf = &data.document;
+    let buffers = &data.buffers;
+    let mut node_ids = vec![None; gltf.nodes().count()];
 
     for scene in gltf.scenes() {
         for node in scene.nodes() {
-            load_node(device, &node, &mut model, &buffers, Mat4::IDENTITY, path);
+            load_node(
+                device,
+                asset_manager,
+                &node,
+                &mut model,
+                data,
+                &mut node_ids,
+                None,
+                path,
+            );
         }
     }
 
+    model.skins = gltf
+        .skins()
+        .map(|skin| load_skin(&skin, buffers, &node_ids))
+        .collect();
+
+    model.animations = gltf
+        .animations()
+        .map(|animation| load_animation(&animation, buffers, &node_ids))
+        .collect();
+
     model
 }
@@ -53,6 +53,7 @@ pub enum DepthAttachment {
 pub enum ViewType {
     Full(),
     Layer(u32),
+    Mip(u32),
 }
 
 #[derive(Copy, Clone)]
@@ -71,6 +72,7 @@ pub enum TextureResourceType {
 #[derive(Copy, Clone)]
 pub struct TextureResource {
     pub texture: TextureId,
+    pub view: ViewType,
     pub input_type: TextureResourceType,
     pub access_type: vk_sync::AccessType,
 }
@@ -137,6 +139,7 @@ impl PassBuilder {
     pub fn read(mut self, resource_id: TextureId) -> Self {
         self.reads.push(Resource::Texture(TextureResource {
             texture: resource_id,
+            view: ViewType::Full(),
             input_type: TextureResourceType::CombinedImageSampler,
             access_type: vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
         }));
@@ -146,6 +149,18 @@ impl PassBuilder {
     pub fn image_write(mut self, resource_id: TextureId) -> Self {
         self.reads.push(Resource::Texture(TextureResource {
             texture: resource_id,
+            view: ViewType::Full(),
+            input_type: TextureResourceType::StorageImage,
+            access_type: vk_sync::AccessType::AnyShaderWrite,
+        }));
+        self
+    }
+
+    /// Writes a single mip level as storage image, the barrier still covers all mips.
+    pub fn image_write_mip(mut self, resource_id: TextureId, mip: u32) -> Self {
+        self.reads.push(Resource::Texture(TextureResource {
+            texture: resource_id,
+            view: ViewType::Mip(mip),
             input_type: TextureResourceType::StorageImage,
             access_type: vk_sync::AccessType::AnyShaderWrite,
         }));
@@ -186,6 +201,15 @@ impl PassBuilder {
         self
     }
 
+    pub fn write_mip(mut self, resource_id: TextureId, mip: u32) -> Self {
+        self.writes.push(Attachment {
+            texture: resource_id,
+            view: ViewType::Mip(mip),
+            load_op: vk::AttachmentLoadOp::CLEAR,
+        });
+        self
+    }
+
     pub fn load_write(mut self, resource_id: TextureId) -> Self {
         self.writes.push(Attachment {
             texture: resource_id,
@@ -283,6 +307,16 @@ impl PassBuilder {
         self
     }
 
+    /// Use image as depth attachment without clearing it first.
+    pub fn load_depth_attachment(mut self, depth_attachment: TextureId) -> Self {
+        self.depth_attachment = Some(DepthAttachment::GraphHandle(Attachment {
+            texture: depth_attachment,
+            view: ViewType::Full(),
+            load_op: vk::AttachmentLoadOp::LOAD,
+        }));
+        self
+    }
+
     /// Use a specific layer of an image as depth attachment.
     pub fn depth_attachment_layer(mut self, depth_attachment: TextureId, layer: u32) -> Self {
         self.depth_attachment = Some(DepthAttachment::GraphHandle(Attachment {
@@ -586,9 +620,40 @@ impl Graph {
             })
     }
 
+    /// Creates a texture with the data returned by `load` and returns its handle.
+    ///
+    /// If a texture with the same name already exists, it will be returned instead and
+    /// `load` is not called, so a file path can be used as the name.
+    pub fn create_texture_from_data(
+        &mut self,
+        debug_name: &str,
+        device: &crate::Device,
+        load: impl FnOnce() -> crate::texture::TextureData,
+    ) -> TextureId {
+        puffin::profile_function!();
+
+        self.resources
+            .textures
+            .iter()
+            .position(|iter| iter.texture.image.debug_name == debug_name)
+            .unwrap_or_else(|| {
+                let texture = crate::Texture::from_data(device, &load(), debug_name)
+                    .unwrap_or_else(|err| panic!("{}", err));
+
+                // The upload leaves the texture ready to be sampled
+                self.resources.textures.push(GraphTexture {
+                    texture,
+                    prev_access: vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                });
+
+                self.resources.textures.len() - 1
+            })
+    }
+
     /// Creates a buffer and returns its handle.
     ///
     /// If a buffer with the same name already exists, it will be returned instead.
+    /// It is recreated if it is smaller than `size`, e.g for the draws of a scene that grew.
     /// Compared to `graph::create_pipeline`, this function does not defer the creation of the buffer.
     pub fn create_buffer(
         &mut self,
@@ -600,22 +665,38 @@ impl Graph {
     ) -> BufferId {
         puffin::profile_function!();
 
-        self.resources
+        let create = || {
+            let mut buffer = Buffer::new::<u8>(device, None, size, usage, memory_location);
+            buffer.set_debug_name(device, debug_name);
+
+            GraphBuffer {
+                buffer,
+                prev_access: vk_sync::AccessType::Nothing,
+            }
+        };
+
+        match self
+            .resources
             .buffers
             .iter()
             .position(|iter| iter.buffer.debug_name == debug_name)
-            .unwrap_or_else(|| {
-                let mut buffer = Buffer::new::<u8>(device, None, size, usage, memory_location);
+        {
+            Some(index) if self.resources.buffers[index].buffer.size >= size => index,
+            Some(index) => {
+                // The old buffer can be used by frames in flight
+                unsafe { device.handle.device_wait_idle().unwrap() };
 
-                buffer.set_debug_name(device, debug_name);
-
-                self.resources.buffers.push(GraphBuffer {
-                    buffer,
-                    prev_access: vk_sync::AccessType::Nothing,
-                });
+                std::mem::replace(&mut self.resources.buffers[index], create())
+                    .buffer
+                    .destroy(device);
 
+                index
+            }
+            None => {
+                self.resources.buffers.push(create());
                 self.resources.buffers.len() - 1
-            })
+            }
+        }
     }
 
     /// Creates a pipeline and returns its handle.
@@ -694,6 +775,7 @@ impl Graph {
                 || desc.raygen_path.is_some_and(|p| path.ends_with(p))
                 || desc.miss_path.is_some_and(|p| path.ends_with(p))
                 || desc.hit_path.is_some_and(|p| path.ends_with(p))
+                || desc.any_hit_path.is_some_and(|p| path.ends_with(p))
             {
                 pipeline.recreate_pipeline(device, bindless_descriptor_set_layout);
             }
@@ -712,35 +794,30 @@ impl Graph {
 
         self.begin_gpu_profiler_frame(device, command_buffer);
 
-        if let Some(raytracing) = &mut renderer.raytracing {
-            if rebuild_tlas {
-                let rebuild_tlas_scope = self.begin_gpu_scope(
-                    device,
-                    command_buffer,
-                    &String::from("rebuild_tlas_pass"),
-                );
+        renderer.scene_graph.update_world_transforms();
+        renderer.update_world_bounds();
+        renderer.update_model_lights(device);
+        renderer.update_gpu_instances(self.current_frame);
+        renderer.update_deformations(self.current_frame);
+        renderer.reset_culling_stats();
 
-                crate::synch::global_pipeline_barrier(
-                    device,
-                    command_buffer,
-                    vk_sync::AccessType::AnyShaderReadOther,
-                    vk_sync::AccessType::AccelerationStructureBuildWrite,
-                );
-
-                raytracing.rebuild_tlas(device, command_buffer, &renderer.instances);
-
-                crate::synch::global_pipeline_barrier(
-                    device,
-                    command_buffer,
-                    vk_sync::AccessType::AccelerationStructureBuildWrite,
-                    vk_sync::AccessType::AnyShaderReadOther,
-                );
+        // The acceleration structures are updated right before the first pass that uses
+        // the TLAS so that the BLAS refits see the vertices written by the skinning pass
+        let mut update_acceleration_structures = rebuild_tlas
+            && renderer.raytracing.is_some()
+            && (renderer.scene_graph.has_changes() || renderer.has_deformed_meshes());
 
-                self.end_gpu_scope(device, command_buffer, rebuild_tlas_scope);
+        for pass in &self.passes[self.current_frame] {
+            if update_acceleration_structures
+                && pass
+                    .reads
+                    .iter()
+                    .any(|read| matches!(read, Resource::Tlas(_)))
+            {
+                self.update_acceleration_structures(device, command_buffer, renderer);
+                update_acceleration_structures = false;
             }
-        }
 
-        for pass in &self.passes[self.current_frame] {
             let active_gpu_scope = self.begin_gpu_scope(device, command_buffer, &pass.name);
 
             let pass_pipeline = &self.resources.pipelines[pass.pipeline_handle];
@@ -869,15 +946,24 @@ impl Graph {
 
             // Todo: very ugly just to get the extents...
             let extent = if !pass.writes.is_empty() {
+                let mip = match pass.writes[0].view {
+                    ViewType::Mip(mip) => mip,
+                    _ => 0,
+                };
+
                 vk::Extent2D {
-                    width: self.resources.textures[pass.writes[0].texture]
+                    width: (self.resources.textures[pass.writes[0].texture]
                         .texture
                         .image
-                        .width(),
-                    height: self.resources.textures[pass.writes[0].texture]
+                        .width()
+                        >> mip)
+                        .max(1),
+                    height: (self.resources.textures[pass.writes[0].texture]
                         .texture
                         .image
-                        .height(),
+                        .height()
+                        >> mip)
+                        .max(1),
                 }
             } else if pass.depth_attachment.is_some() {
                 match pass.depth_attachment.as_ref().unwrap() {
@@ -1064,6 +1150,52 @@ impl Graph {
         self.end_gpu_profiler_frame(device, command_buffer);
     }
 
+    fn update_acceleration_structures(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        renderer: &mut Renderer,
+    ) {
+        let raytracing = renderer.raytracing.as_mut().unwrap();
+
+        let rebuild_tlas_scope =
+            self.begin_gpu_scope(device, command_buffer, &String::from("rebuild_tlas_pass"));
+
+        crate::synch::global_pipeline_barrier(
+            device,
+            command_buffer,
+            vk_sync::AccessType::AnyShaderReadOther,
+            vk_sync::AccessType::AccelerationStructureBuildWrite,
+        );
+
+        raytracing.refit_deformed_blas(device, command_buffer, &renderer.instances);
+
+        crate::synch::global_pipeline_barrier(
+            device,
+            command_buffer,
+            vk_sync::AccessType::AccelerationStructureBuildWrite,
+            vk_sync::AccessType::AccelerationStructureBuildRead,
+        );
+
+        raytracing.rebuild_tlas(
+            device,
+            command_buffer,
+            &renderer.instances,
+            &renderer.scene_graph,
+        );
+
+        crate::synch::global_pipeline_barrier(
+            device,
+            command_buffer,
+            vk_sync::AccessType::AccelerationStructureBuildWrite,
+            vk_sync::AccessType::AnyShaderReadOther,
+        );
+
+        self.end_gpu_scope(device, command_buffer, rebuild_tlas_scope);
+
+        renderer.scene_graph.clear_changes();
+    }
+
     pub fn begin_gpu_profiler_frame(&self, device: &Device, command_buffer: vk::CommandBuffer) {
         if self.profiling_enabled {
             device
@@ -64,6 +64,20 @@ impl ImageDesc {
         }
     }
 
+    pub fn new_3d(width: u32, height: u32, depth: u32, format: vk::Format) -> Self {
+        ImageDesc {
+            width,
+            height,
+            depth,
+            array_layers: 1,
+            format,
+            image_type: ImageType::Tex3d,
+            aspect_flags: vk::ImageAspectFlags::COLOR,
+            usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            mip_levels: 1,
+        }
+    }
+
     pub fn new_cubemap(width: u32, height: u32, format: vk::Format) -> Self {
         ImageDesc {
             width,
@@ -95,6 +109,12 @@ impl ImageDesc {
         self.mip_levels = mip_levels;
         self
     }
+
+    /// Sets the number of mips needed to go down to 1x1
+    pub fn full_mip_chain(mut self) -> Self {
+        self.mip_levels = 32 - self.width.max(self.height).max(1).leading_zeros();
+        self
+    }
 }
 
 // Todo: Hack
@@ -103,6 +123,8 @@ pub struct Image {
     pub image: vk::Image,
     pub image_view: vk::ImageView,
     pub layer_views: Vec<vk::ImageView>,
+    // One view per mip level of mipmapped storage images, for writing the mips separately
+    pub mip_views: Vec<vk::ImageView>,
     pub device_memory: vk::DeviceMemory,
     pub current_layout: vk::ImageLayout,
     pub desc: ImageDesc,
@@ -117,12 +139,16 @@ impl Image {
             // Create image
             let initial_layout = vk::ImageLayout::UNDEFINED;
             let image_create_info = vk::ImageCreateInfo {
-                image_type: vk::ImageType::TYPE_2D,
+                image_type: if desc.image_type == ImageType::Tex3d {
+                    vk::ImageType::TYPE_3D
+                } else {
+                    vk::ImageType::TYPE_2D
+                },
                 format: desc.format,
                 extent: vk::Extent3D {
                     width: desc.width,
                     height: desc.height,
-                    depth: 1,
+                    depth: desc.depth,
                 },
                 mip_levels: desc.mip_levels,
                 array_layers: desc.array_layers,
@@ -171,6 +197,8 @@ impl Image {
                 vk::ImageViewType::TYPE_2D_ARRAY
             } else if desc.image_type == ImageType::Cube {
                 vk::ImageViewType::CUBE
+            } else if desc.image_type == ImageType::Tex3d {
+                vk::ImageViewType::TYPE_3D
             } else {
                 unimplemented!()
             };
@@ -183,6 +211,7 @@ impl Image {
                 view_type,
                 0,
                 desc.array_layers,
+                0,
                 desc.mip_levels,
             );
 
@@ -204,16 +233,37 @@ impl Image {
                         },
                         layer,
                         1,
+                        0,
                         desc.mip_levels,
                     );
                     layer_views.push(view);
                 }
             }
 
+            let mut mip_views = vec![];
+
+            if desc.mip_levels > 1 && desc.usage.contains(vk::ImageUsageFlags::STORAGE) {
+                for mip in 0..desc.mip_levels {
+                    let view = Image::create_image_view(
+                        device,
+                        image,
+                        desc.format,
+                        desc.aspect_flags,
+                        view_type,
+                        0,
+                        desc.array_layers,
+                        mip,
+                        1,
+                    );
+                    mip_views.push(view);
+                }
+            }
+
             Image {
                 image,
                 image_view,
                 layer_views,
+                mip_views,
                 device_memory,
                 current_layout: initial_layout,
                 desc,
@@ -241,6 +291,7 @@ impl Image {
             view_type,
             0,
             1,
+            0,
             desc.mip_levels,
         );
 
@@ -248,6 +299,7 @@ impl Image {
             image,
             image_view,
             layer_views: vec![],
+            mip_views: vec![],
             device_memory: vk::DeviceMemory::null(),
             current_layout: vk::ImageLayout::UNDEFINED,
             desc,
@@ -264,6 +316,7 @@ impl Image {
         view_type: vk::ImageViewType,
         base_array_layer: u32,
         layer_count: u32,
+        base_mip_level: u32,
         mip_levels: u32,
     ) -> vk::ImageView {
         // Create image view
@@ -286,7 +339,7 @@ impl Image {
                 aspect_mask: aspect_flags,
                 base_array_layer,
                 layer_count,
-                base_mip_level: 0,
+                base_mip_level,
                 level_count: mip_levels,
             },
             image,
@@ -301,6 +354,18 @@ impl Image {
         }
     }
 
+    /// Note: the image must not be used by any frames in flight
+    pub fn destroy(self, device: &Device) {
+        unsafe {
+            for view in self.layer_views.iter().chain(&self.mip_views) {
+                device.handle.destroy_image_view(*view, None);
+            }
+            device.handle.destroy_image_view(self.image_view, None);
+            device.handle.destroy_image(self.image, None);
+            device.handle.free_memory(self.device_memory, None);
+        }
+    }
+
     pub fn set_debug_name(&mut self, device: &Device, name: &str) {
         self.debug_name = String::from(name);
         device.set_debug_name(vk::Handle::as_raw(self.image), vk::ObjectType::IMAGE, name);
@@ -310,7 +375,7 @@ impl Image {
             name,
         );
 
-        for view in &self.layer_views {
+        for view in self.layer_views.iter().chain(&self.mip_views) {
             device.set_debug_name(vk::Handle::as_raw(*view), vk::ObjectType::IMAGE_VIEW, name);
         }
     }
@@ -440,6 +505,11 @@ impl Image {
         self.layer_views[layer as usize]
     }
 
+    pub fn mip_view(&self, mip: u32) -> vk::ImageView {
+        assert!(mip < self.mip_views.len() as u32);
+        self.mip_views[mip as usize]
+    }
+
     pub fn width(&self) -> u32 {
         self.desc.width
     }
@@ -452,7 +522,7 @@ impl Image {
         vk::Extent3D {
             width: self.desc.width,
             height: self.desc.height,
-            depth: 1,
+            depth: self.desc.depth,
         }
     }
 
@@ -0,0 +1,463 @@
+use ash::vk;
+use basis_universal::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscodeParameters, Transcoder,
+    TranscoderBlockFormat, TranscoderTextureFormat,
+};
+
+// «KTX 20»\r\n\x1A\n
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+
+// Color model of the data format descriptor used by UASTC encoded Basis Universal files
+const KHR_DF_MODEL_UASTC: u8 = 166;
+
+// Channel ids of the data format descriptor samples
+const KHR_DF_CHANNEL_ETC1S_GGG: u8 = 4;
+const KHR_DF_CHANNEL_ETC1S_AAA: u8 = 15;
+const KHR_DF_CHANNEL_UASTC_RGBA: u8 = 3;
+const KHR_DF_CHANNEL_UASTC_RRRG: u8 = 5;
+
+// Layout of a .basis file, see basisu_file_headers.h
+const BASIS_SIGNATURE: u32 = 0x4273;
+const BASIS_VERSION: u32 = 0x13;
+const BASIS_HEADER_SIZE: usize = 77;
+const BASIS_SLICE_DESC_SIZE: usize = 23;
+const BASIS_HEADER_FLAG_ETC1S: u32 = 1;
+const BASIS_HEADER_FLAG_HAS_ALPHA_SLICES: u32 = 4;
+const BASIS_SLICE_FLAG_IS_ALPHA: u32 = 1;
+
+static TRANSCODER_INIT: std::sync::Once = std::sync::Once::new();
+
+pub struct Ktx2Image {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    // Tightly packed data of every mip level, starting with the largest one
+    pub levels: Vec<Vec<u8>>,
+    // A level count of 0 in the header means that the mips should be generated on load
+    pub generate_mips: bool,
+}
+
+impl Ktx2Image {
+    // Unsupported files are errors instead of panics so that the caller can fall back
+    // to a default texture
+    pub fn load(path: &str) -> Result<Ktx2Image, String> {
+        let data =
+            std::fs::read(path).map_err(|err| format!("Unable to load \"{}\": {}", path, err))?;
+
+        Ktx2Image::parse(&data, path)
+    }
+
+    pub fn parse(data: &[u8], path: &str) -> Result<Ktx2Image, String> {
+        if data.len() < HEADER_SIZE || data[0..12] != KTX2_IDENTIFIER {
+            return Err(format!("\"{}\" is not a KTX2 file", path));
+        }
+
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let read_u64 = |offset: usize| {
+            u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap()) as usize
+        };
+
+        let vk_format = read_u32(12);
+        let width = read_u32(20);
+        let height = read_u32(24);
+        let depth = read_u32(28);
+        let layer_count = read_u32(32);
+        let face_count = read_u32(36);
+        let level_count = read_u32(40);
+        let supercompression_scheme = read_u32(44);
+        let dfd_offset = read_u32(48) as usize;
+
+        let color_model = data.get(dfd_offset + 12).copied().unwrap_or(0);
+
+        if supercompression_scheme != SUPERCOMPRESSION_NONE
+            && supercompression_scheme != SUPERCOMPRESSION_BASIS_LZ
+        {
+            return Err(format!(
+                "Unsupported KTX2 supercompression scheme {} in \"{}\"",
+                supercompression_scheme, path
+            ));
+        }
+
+        if depth > 1 || layer_count > 1 || face_count > 1 {
+            return Err(format!("Only 2D KTX2 textures are supported, \"{}\"", path));
+        }
+
+        let levels = (0..level_count.max(1) as usize)
+            .map(|level| {
+                let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+                let byte_offset = read_u64(entry);
+                let byte_length = read_u64(entry + 8);
+
+                match data.get(byte_offset..byte_offset + byte_length) {
+                    Some(level_data) => Ok(level_data.to_vec()),
+                    None => Err(format!(
+                        "Mip level {} is out of bounds in \"{}\"",
+                        level, path
+                    )),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // Basis Universal files are transcoded to BC7, or to BC5 for two channel normal maps
+        let channels = dfd_channels(data, dfd_offset);
+        let (format, levels) = if supercompression_scheme == SUPERCOMPRESSION_BASIS_LZ {
+            let sgd_offset = read_u64(64);
+            let sgd_length = read_u64(72);
+            let global_data = data
+                .get(sgd_offset..sgd_offset + sgd_length)
+                .ok_or(format!(
+                    "Supercompression data is out of bounds in \"{}\"",
+                    path
+                ))?;
+
+            transcode_etc1s(global_data, &levels, width, height, &channels)
+                .map_err(|err| format!("Unable to transcode \"{}\": {}", path, err))?
+        } else if color_model == KHR_DF_MODEL_UASTC {
+            transcode_uastc(&levels, width, height, &channels)
+                .map_err(|err| format!("Unable to transcode \"{}\": {}", path, err))?
+        } else if vk_format == 0 {
+            return Err(format!("Missing vkFormat in \"{}\"", path));
+        } else {
+            (vk::Format::from_raw(vk_format as i32), levels)
+        };
+
+        Ok(Ktx2Image {
+            format,
+            width,
+            height,
+            levels,
+            generate_mips: level_count == 0,
+        })
+    }
+}
+
+// Channel ids of the samples in the basic data format descriptor block
+fn dfd_channels(data: &[u8], dfd_offset: usize) -> Vec<u8> {
+    let block_size = data
+        .get(dfd_offset + 10..dfd_offset + 12)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+        .unwrap_or(0);
+    let num_samples = block_size.saturating_sub(24) / 16;
+
+    (0..num_samples)
+        .filter_map(|sample| data.get(dfd_offset + 4 + 24 + sample * 16 + 3))
+        .map(|channel_type| channel_type & 0xf)
+        .collect()
+}
+
+fn level_size(size: u32, level: usize) -> u32 {
+    (size >> level).max(1)
+}
+
+fn num_blocks(size: u32) -> u32 {
+    size.div_ceil(4)
+}
+
+fn transcode_uastc(
+    levels: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    channels: &[u8],
+) -> Result<(vk::Format, Vec<Vec<u8>>), String> {
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+
+    let channel = channels.first().copied().unwrap_or(0);
+    // Note: BC5 is transcoded from the red and alpha channels
+    let (format, block_format) = match channel {
+        KHR_DF_CHANNEL_UASTC_RRRG => (vk::Format::BC5_UNORM_BLOCK, TranscoderBlockFormat::BC5),
+        _ => (vk::Format::BC7_UNORM_BLOCK, TranscoderBlockFormat::BC7),
+    };
+    let has_alpha = channel == KHR_DF_CHANNEL_UASTC_RGBA || channel == KHR_DF_CHANNEL_UASTC_RRRG;
+
+    let transcoder = LowLevelUastcTranscoder::new();
+    let levels = levels
+        .iter()
+        .enumerate()
+        .map(|(level, level_data)| {
+            let level_width = level_size(width, level);
+            let level_height = level_size(height, level);
+            let slice_parameters = SliceParametersUastc {
+                num_blocks_x: num_blocks(level_width),
+                num_blocks_y: num_blocks(level_height),
+                has_alpha,
+                original_width: level_width,
+                original_height: level_height,
+            };
+
+            transcoder
+                .transcode_slice(
+                    level_data,
+                    slice_parameters,
+                    DecodeFlags::HIGH_QUALITY,
+                    block_format,
+                )
+                .map_err(|err| format!("{:?} in mip level {}", err, level))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok((format, levels))
+}
+
+fn transcode_etc1s(
+    global_data: &[u8],
+    levels: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    channels: &[u8],
+) -> Result<(vk::Format, Vec<Vec<u8>>), String> {
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+
+    // Normal maps store X in the color slices and Y in the alpha slices
+    let (format, texture_format) = match channels.get(1) {
+        Some(&KHR_DF_CHANNEL_ETC1S_GGG) => {
+            (vk::Format::BC5_UNORM_BLOCK, TranscoderTextureFormat::BC5_RG)
+        }
+        _ => (
+            vk::Format::BC7_UNORM_BLOCK,
+            TranscoderTextureFormat::BC7_RGBA,
+        ),
+    };
+    let has_alpha = matches!(
+        channels.get(1),
+        Some(&KHR_DF_CHANNEL_ETC1S_AAA) | Some(&KHR_DF_CHANNEL_ETC1S_GGG)
+    );
+
+    let basis_file = basis_file_from_etc1s(global_data, levels, width, height, has_alpha)?;
+
+    let mut transcoder = Transcoder::new();
+    transcoder
+        .prepare_transcoding(&basis_file)
+        .map_err(|_| String::from("invalid ETC1S codebooks"))?;
+
+    let levels = (0..levels.len())
+        .map(|level| {
+            transcoder
+                .transcode_image_level(
+                    &basis_file,
+                    texture_format,
+                    TranscodeParameters {
+                        image_index: 0,
+                        level_index: level as u32,
+                        decode_flags: Some(DecodeFlags::HIGH_QUALITY),
+                        output_row_pitch_in_blocks_or_pixels: None,
+                        output_rows_in_pixels: None,
+                    },
+                )
+                .map_err(|err| format!("{:?} in mip level {}", err, level))
+        })
+        .collect::<Result<Vec<_>, String>>();
+
+    transcoder.end_transcoding();
+
+    Ok((format, levels?))
+}
+
+/// The transcoder only reads ETC1S from .basis files, so the codebooks from the
+/// supercompression global data and the slices of every mip level are repacked into one
+fn basis_file_from_etc1s(
+    global_data: &[u8],
+    levels: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+) -> Result<Vec<u8>, String> {
+    let read_u16 = |offset: usize| {
+        global_data
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+            .ok_or(String::from("truncated supercompression data"))
+    };
+    let read_u32 = |offset: usize| {
+        global_data
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or(String::from("truncated supercompression data"))
+    };
+
+    let endpoint_count = read_u16(0)?;
+    let selector_count = read_u16(2)?;
+    let endpoints_length = read_u32(4)?;
+    let selectors_length = read_u32(8)?;
+    let tables_length = read_u32(12)?;
+
+    // One image descriptor of 5 u32 per mip level, followed by the codebooks
+    let image_descs_offset = 20;
+    let endpoints_offset = image_descs_offset + levels.len() * 20;
+    let selectors_offset = endpoints_offset + endpoints_length;
+    let tables_offset = selectors_offset + selectors_length;
+    let codebooks = global_data
+        .get(endpoints_offset..tables_offset + tables_length)
+        .ok_or(String::from("truncated supercompression data"))?;
+
+    let mut slices: Vec<(usize, bool, &[u8])> = vec![];
+    for (level, level_data) in levels.iter().enumerate() {
+        let desc = image_descs_offset + level * 20;
+        let mut slice = |offset: usize, length: usize, is_alpha: bool| {
+            level_data
+                .get(offset..offset + length)
+                .map(|slice_data| slices.push((level, is_alpha, slice_data)))
+                .ok_or(format!("slice of mip level {} is out of bounds", level))
+        };
+
+        slice(read_u32(desc + 4)?, read_u32(desc + 8)?, false)?;
+        if has_alpha {
+            slice(read_u32(desc + 12)?, read_u32(desc + 16)?, true)?;
+        }
+    }
+
+    let slice_descs_offset = BASIS_HEADER_SIZE;
+    let codebooks_offset = slice_descs_offset + slices.len() * BASIS_SLICE_DESC_SIZE;
+    let mut slice_data_offset = codebooks_offset + codebooks.len();
+
+    let mut file = vec![0; BASIS_HEADER_SIZE];
+    for &(level, is_alpha, slice_data) in &slices {
+        let level_width = level_size(width, level);
+        let level_height = level_size(height, level);
+
+        put_packed(&mut file, 0, 3);
+        put_packed(&mut file, level as u32, 1);
+        put_packed(&mut file, is_alpha as u32 * BASIS_SLICE_FLAG_IS_ALPHA, 1);
+        put_packed(&mut file, level_width, 2);
+        put_packed(&mut file, level_height, 2);
+        put_packed(&mut file, num_blocks(level_width), 2);
+        put_packed(&mut file, num_blocks(level_height), 2);
+        put_packed(&mut file, slice_data_offset as u32, 4);
+        put_packed(&mut file, slice_data.len() as u32, 4);
+        put_packed(&mut file, crc16(slice_data) as u32, 2);
+
+        slice_data_offset += slice_data.len();
+    }
+
+    file.extend_from_slice(codebooks);
+    for &(_, _, slice_data) in &slices {
+        file.extend_from_slice(slice_data);
+    }
+
+    let flags = match has_alpha {
+        true => BASIS_HEADER_FLAG_ETC1S | BASIS_HEADER_FLAG_HAS_ALPHA_SLICES,
+        false => BASIS_HEADER_FLAG_ETC1S,
+    };
+
+    let mut header = vec![];
+    put_packed(&mut header, BASIS_SIGNATURE, 2);
+    put_packed(&mut header, BASIS_VERSION, 2);
+    put_packed(&mut header, BASIS_HEADER_SIZE as u32, 2);
+    put_packed(&mut header, 0, 2); // Header CRC, written below
+    put_packed(&mut header, (file.len() - BASIS_HEADER_SIZE) as u32, 4);
+    put_packed(&mut header, crc16(&file[BASIS_HEADER_SIZE..]) as u32, 2);
+    put_packed(&mut header, slices.len() as u32, 3);
+    put_packed(&mut header, 1, 3); // Images
+    put_packed(&mut header, 0, 1); // ETC1S texture format
+    put_packed(&mut header, flags, 2);
+    put_packed(&mut header, 0, 1); // 2D texture type
+    put_packed(&mut header, 0, 3); // Microseconds per frame
+    put_packed(&mut header, 0, 4); // Reserved
+    put_packed(&mut header, 0, 4); // User data
+    put_packed(&mut header, 0, 4);
+    put_packed(&mut header, endpoint_count, 2);
+    put_packed(&mut header, codebooks_offset as u32, 4);
+    put_packed(&mut header, endpoints_length as u32, 3);
+    put_packed(&mut header, selector_count, 2);
+    put_packed(&mut header, (codebooks_offset + endpoints_length) as u32, 4);
+    put_packed(&mut header, selectors_length as u32, 3);
+    put_packed(
+        &mut header,
+        (codebooks_offset + endpoints_length + selectors_length) as u32,
+        4,
+    );
+    put_packed(&mut header, tables_length as u32, 4);
+    put_packed(&mut header, slice_descs_offset as u32, 4);
+    put_packed(&mut header, 0, 4); // Extended data
+    put_packed(&mut header, 0, 4);
+
+    // The header CRC covers everything after itself
+    let header_crc = crc16(&header[8..]);
+    header[6..8].copy_from_slice(&header_crc.to_le_bytes());
+
+    file[0..BASIS_HEADER_SIZE].copy_from_slice(&header);
+
+    Ok(file)
+}
+
+// Little endian integer with a size in bytes, like basisu::packed_uint
+fn put_packed(buffer: &mut Vec<u8>, value: u32, size: usize) {
+    buffer.extend_from_slice(&value.to_le_bytes()[0..size]);
+}
+
+// CRC-16 with the 0x1021 polynomial used by the .basis file checksums
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = !0u16;
+    for &byte in data {
+        let q = (byte as u16) ^ (crc >> 8);
+        let k = (q >> 4) ^ q;
+        crc = (((crc << 8) ^ k) ^ (k << 5)) ^ (k << 12);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_matches_the_basis_checksum() {
+        assert_eq!(crc16(b"123456789"), 0xd64e);
+    }
+
+    #[test]
+    fn etc1s_slices_are_repacked_into_a_basis_file() {
+        let rgb_slice = [1, 2, 3];
+        let alpha_slice = [4, 5];
+        let level = [&rgb_slice[..], &alpha_slice[..]].concat();
+
+        let mut global_data = vec![];
+        put_packed(&mut global_data, 10, 2); // Endpoint count
+        put_packed(&mut global_data, 20, 2); // Selector count
+        put_packed(&mut global_data, 2, 4); // Endpoints length
+        put_packed(&mut global_data, 1, 4); // Selectors length
+        put_packed(&mut global_data, 1, 4); // Tables length
+        put_packed(&mut global_data, 0, 4); // Extended length
+        for value in [0, 0, 3, 3, 2] {
+            put_packed(&mut global_data, value, 4);
+        }
+        global_data.extend_from_slice(&[0xe0, 0xe1, 0x50, 0x7a]);
+
+        let file = basis_file_from_etc1s(&global_data, &[level], 6, 5, true).unwrap();
+        let read = |offset: usize, size: usize| {
+            let mut bytes = [0; 4];
+            bytes[0..size].copy_from_slice(&file[offset..offset + size]);
+            u32::from_le_bytes(bytes) as usize
+        };
+
+        assert_eq!(&file[0..2], b"sB");
+        assert_eq!(read(4, 2), BASIS_HEADER_SIZE);
+        assert_eq!(read(6, 2), crc16(&file[8..BASIS_HEADER_SIZE]) as usize);
+        assert_eq!(read(8, 4), file.len() - BASIS_HEADER_SIZE);
+        // Two slices
+        assert_eq!(read(14, 3), 2);
+
+        // The codebooks follow the slice descriptors
+        let codebooks_offset = BASIS_HEADER_SIZE + 2 * BASIS_SLICE_DESC_SIZE;
+        assert_eq!(read(39, 2), 10);
+        assert_eq!(read(41, 4), codebooks_offset);
+        assert_eq!(
+            &file[codebooks_offset..codebooks_offset + 4],
+            &[0xe0, 0xe1, 0x50, 0x7a]
+        );
+
+        let alpha_desc = BASIS_HEADER_SIZE + BASIS_SLICE_DESC_SIZE;
+        assert_eq!(read(alpha_desc + 4, 1), BASIS_SLICE_FLAG_IS_ALPHA as usize);
+        assert_eq!(read(alpha_desc + 9, 2), 2); // Blocks in x
+        let alpha_offset = read(alpha_desc + 13, 4);
+        assert_eq!(&file[alpha_offset..alpha_offset + 2], &alpha_slice);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod animation;
+pub mod asset_loader;
+pub mod asset_manager;
 pub mod bindless;
+pub mod bounds;
 pub mod buffer;
 pub mod camera;
 pub mod descriptor_set;
@@ -9,7 +13,12 @@ pub mod gltf_loader;
 pub mod graph;
 pub mod image;
 pub mod input;
+pub mod ktx2;
+pub mod light;
+pub mod lod;
+pub mod mesh_optimizer;
 pub mod model_loader;
+pub mod obj_loader;
 pub mod pass;
 pub mod pipeline;
 pub mod primitive;
@@ -18,25 +27,37 @@ pub mod raytracing;
 pub mod render_utils;
 pub mod renderer;
 pub mod renderers;
+pub mod scene_graph;
+pub mod scene_loader;
 pub mod shader;
 pub mod synch;
+pub mod tangent_space;
 pub mod texture;
+pub mod upload_batcher;
 pub mod vulkan_base;
 
 pub use crate::image::Image;
 pub use crate::image::ImageCopyDescBuilder;
+pub use asset_loader::AssetLoader;
+pub use asset_manager::AssetManager;
+pub use asset_manager::MeshHandle;
+pub use asset_manager::TextureHandle;
 pub use bindless::*;
 pub use buffer::Buffer;
 pub use camera::Camera;
+pub use camera::Exposure;
+pub use camera::Lens;
 pub use descriptor_set::DescriptorSet;
 pub use device::Device;
 pub use directory_watcher::DirectoryWatcher;
 pub use fps_timer::FpsTimer;
 pub use gltf_loader::Model;
+pub use gltf_loader::ModelCamera;
 pub use gltf_loader::DEFAULT_TEXTURE_MAP;
 pub use graph::Graph;
 pub use graph::TextureId;
 pub use input::Input;
+pub use light::Light;
 pub use model_loader::ModelLoader;
 pub use pass::RenderPass;
 pub use pipeline::Pipeline;
@@ -44,8 +65,13 @@ pub use pipeline::PipelineDesc;
 pub use pipeline::PipelineDescBuilder;
 pub use primitive::Primitive;
 pub use primitive::Vertex;
+pub use primitive::VertexLayout;
 pub use raytracing::Raytracing;
 pub use renderer::Renderer;
 pub use renderer::*;
+pub use scene_graph::NodeId;
+pub use scene_graph::SceneGraph;
+pub use texture::ColorSpace;
 pub use texture::Texture;
+pub use upload_batcher::UploadBatcher;
 pub use vulkan_base::VulkanBase;
@@ -0,0 +1,235 @@
+use glam::{Mat4, Vec3};
+use serde::Deserialize;
+
+// Must match the LIGHT_TYPE_* defines in bindless.glsl
+pub const LIGHT_TYPE_DIRECTIONAL: u32 = 0;
+pub const LIGHT_TYPE_POINT: u32 = 1;
+pub const LIGHT_TYPE_SPOT: u32 = 2;
+pub const LIGHT_TYPE_RECT_AREA: u32 = 3;
+pub const LIGHT_TYPE_DISK_AREA: u32 = 4;
+
+// Illuminance in lux below which a light without a range is considered to have no
+// visible effect, see `Light::effective_range`
+const LIGHT_CUTOFF_ILLUMINANCE: f32 = 0.01;
+
+/// Light sources with intensities in physical units:
+/// - point and spot lights: luminous intensity in candela (lm/sr)
+/// - directional lights: illuminance in lux (lm/m^2)
+/// - area lights: luminance in nits (cd/m^2), emitted one-sided along `direction`
+///
+/// A `range` of 0.0 means that the cutoff distance is derived from the intensity.
+/// Spot cone angles are in radians.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Light {
+    Point {
+        position: Vec3,
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: f32,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+    Directional {
+        direction: Vec3,
+        color: Vec3,
+        illuminance: f32,
+    },
+    RectArea {
+        position: Vec3,
+        direction: Vec3,
+        // Direction of the width axis, needs to be perpendicular to `direction`
+        tangent: Vec3,
+        width: f32,
+        height: f32,
+        color: Vec3,
+        luminance: f32,
+    },
+    DiskArea {
+        position: Vec3,
+        direction: Vec3,
+        radius: f32,
+        color: Vec3,
+        luminance: f32,
+    },
+}
+
+impl Light {
+    /// Returns the light with its position and directions transformed by `transform`
+    pub fn transformed(&self, transform: Mat4) -> Light {
+        let mut light = *self;
+
+        match &mut light {
+            Light::Point { position, .. } => {
+                *position = transform.transform_point3(*position);
+            }
+            Light::Spot {
+                position,
+                direction,
+                ..
+            } => {
+                *position = transform.transform_point3(*position);
+                *direction = transform.transform_vector3(*direction).normalize();
+            }
+            Light::Directional { direction, .. } => {
+                *direction = transform.transform_vector3(*direction).normalize();
+            }
+            Light::RectArea {
+                position,
+                direction,
+                tangent,
+                width,
+                height,
+                ..
+            } => {
+                let bitangent = direction.cross(*tangent);
+                *width *= transform.transform_vector3(*tangent).length();
+                *height *= transform.transform_vector3(bitangent).length();
+                *position = transform.transform_point3(*position);
+                *direction = transform.transform_vector3(*direction).normalize();
+                *tangent = transform.transform_vector3(*tangent).normalize();
+            }
+            Light::DiskArea {
+                position,
+                direction,
+                radius,
+                ..
+            } => {
+                // Note: non-uniform scaling in the plane of the disk is not supported
+                *radius *= transform
+                    .transform_vector3(direction.any_orthonormal_vector())
+                    .length();
+                *position = transform.transform_point3(*position);
+                *direction = transform.transform_vector3(*direction).normalize();
+            }
+        }
+
+        light
+    }
+
+    pub fn light_type(&self) -> u32 {
+        match self {
+            Light::Directional { .. } => LIGHT_TYPE_DIRECTIONAL,
+            Light::Point { .. } => LIGHT_TYPE_POINT,
+            Light::Spot { .. } => LIGHT_TYPE_SPOT,
+            Light::RectArea { .. } => LIGHT_TYPE_RECT_AREA,
+            Light::DiskArea { .. } => LIGHT_TYPE_DISK_AREA,
+        }
+    }
+
+    pub fn color(&self) -> Vec3 {
+        match *self {
+            Light::Point { color, .. }
+            | Light::Spot { color, .. }
+            | Light::Directional { color, .. }
+            | Light::RectArea { color, .. }
+            | Light::DiskArea { color, .. } => color,
+        }
+    }
+
+    /// Candela, lux or nits depending on the light type
+    pub fn intensity(&self) -> f32 {
+        match *self {
+            Light::Point { intensity, .. } | Light::Spot { intensity, .. } => intensity,
+            Light::Directional { illuminance, .. } => illuminance,
+            Light::RectArea { luminance, .. } | Light::DiskArea { luminance, .. } => luminance,
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        match *self {
+            Light::Point { position, .. }
+            | Light::Spot { position, .. }
+            | Light::RectArea { position, .. }
+            | Light::DiskArea { position, .. } => position,
+            Light::Directional { .. } => Vec3::ZERO,
+        }
+    }
+
+    /// The distance at which the light is cut off. Point and spot lights without a range
+    /// reach until their illuminance falls below `LIGHT_CUTOFF_ILLUMINANCE`, so that
+    /// clustered light culling still works for them. Other lights have no cutoff and
+    /// return 0.0.
+    pub fn effective_range(&self) -> f32 {
+        match *self {
+            Light::Point {
+                color,
+                intensity,
+                range,
+                ..
+            }
+            | Light::Spot {
+                color,
+                intensity,
+                range,
+                ..
+            } => match range > 0.0 {
+                true => range,
+                false => (color.max_element() * intensity / LIGHT_CUTOFF_ILLUMINANCE).sqrt(),
+            },
+            _ => 0.0,
+        }
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        match *self {
+            Light::Spot { direction, .. }
+            | Light::Directional { direction, .. }
+            | Light::RectArea { direction, .. }
+            | Light::DiskArea { direction, .. } => direction.normalize(),
+            Light::Point { .. } => Vec3::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effective_range_keeps_an_explicit_range() {
+        let light = Light::Point {
+            position: Vec3::ZERO,
+            color: Vec3::ONE,
+            intensity: 100.0,
+            range: 5.0,
+        };
+
+        assert_eq!(light.effective_range(), 5.0);
+    }
+
+    #[test]
+    fn effective_range_is_where_the_illuminance_reaches_the_cutoff() {
+        let light = Light::Spot {
+            position: Vec3::ZERO,
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            color: Vec3::new(0.5, 1.0, 0.25),
+            intensity: 100.0,
+            range: 0.0,
+            inner_cone_angle: 0.2,
+            outer_cone_angle: 0.4,
+        };
+        let range = light.effective_range();
+
+        assert!((100.0 / (range * range) - LIGHT_CUTOFF_ILLUMINANCE).abs() < 1e-6);
+    }
+
+    #[test]
+    fn directional_lights_have_no_cutoff() {
+        let light = Light::Directional {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            color: Vec3::ONE,
+            illuminance: 100000.0,
+        };
+
+        assert_eq!(light.effective_range(), 0.0);
+    }
+}
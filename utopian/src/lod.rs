@@ -0,0 +1,269 @@
+use glam::{Mat4, Vec3};
+
+use crate::bounds::BoundingSphere;
+use crate::primitive::Vertex;
+
+// A range of a primitive's index buffer that draws it at a certain level of detail
+#[derive(Clone, Copy, Debug)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    // Upper bound of how far the simplified surface is from the full detail one,
+    // in the units of the mesh
+    pub error: f32,
+}
+
+/// How the level of detail chain of every primitive is generated at load time
+#[derive(Clone, Copy, Debug)]
+pub struct LodSettings {
+    // Including the full detail one, 1 disables the simplification
+    pub max_lod_count: usize,
+    // Target triangle count of a level relative to the previous one
+    pub triangle_ratio: f32,
+    // Allowed error of the first simplified level relative to the mesh extents,
+    // it is doubled for every following level
+    pub target_error: f32,
+    // Meshes with fewer triangles are not simplified further
+    pub min_triangles: usize,
+    // The level used for building ray tracing acceleration structures
+    pub blas_lod: usize,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            max_lod_count: 5,
+            triangle_ratio: 0.5,
+            target_error: 0.01,
+            min_triangles: 64,
+            blas_lod: 0,
+        }
+    }
+}
+
+/// Simplifies the mesh into progressively coarser index buffers that reference the same
+/// vertices. Every level is simplified from the full detail mesh so that its error does
+/// not accumulate. Levels that would not remove a meaningful amount of triangles, e.g
+/// because the error limit is reached, end the chain.
+/// Returns the indices of every level after the full detail one together with its error.
+pub fn generate_lods(
+    indices: &[u32],
+    vertices: &[Vertex],
+    settings: &LodSettings,
+) -> Vec<(Vec<u32>, f32)> {
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), vertex| {
+            (
+                min.min(vertex.pos.truncate()),
+                max.max(vertex.pos.truncate()),
+            )
+        },
+    );
+    // The simplifier error is relative to the largest side of the bounding box
+    let extent = (max - min).max_element().max(0.0);
+
+    let mut lods: Vec<(Vec<u32>, f32)> = vec![];
+    let mut previous_count = indices.len();
+    let mut target_error = settings.target_error;
+
+    while lods.len() + 1 < settings.max_lod_count && previous_count / 3 > settings.min_triangles {
+        let target_count = (previous_count as f32 * settings.triangle_ratio) as usize / 3 * 3;
+        let lod_indices = meshopt::simplify_decoder(indices, vertices, target_count, target_error);
+
+        if lod_indices.is_empty() || lod_indices.len() as f32 > previous_count as f32 * 0.9 {
+            break;
+        }
+
+        previous_count = lod_indices.len();
+        let lod_indices = meshopt::optimize_vertex_cache(&lod_indices, vertices.len());
+        lods.push((lod_indices, target_error * extent));
+        target_error *= 2.0;
+    }
+
+    lods
+}
+
+/// How the level of detail of every mesh instance is selected from its size on screen
+#[derive(Clone, Copy, Debug)]
+pub struct LodSelection {
+    pub enabled: bool,
+    // The coarsest level whose projected error is below this many pixels is used
+    pub error_threshold: f32,
+    // Relative margin around the threshold that keeps the current level, so that
+    // instances close to it do not switch back and forth every frame
+    pub hysteresis: f32,
+}
+
+impl Default for LodSelection {
+    fn default() -> Self {
+        LodSelection {
+            enabled: true,
+            error_threshold: 1.0,
+            hysteresis: 0.2,
+        }
+    }
+}
+
+impl LodSelection {
+    /// `pixels_per_unit` is how many pixels one unit of the mesh covers on screen, at the
+    /// distance of its bounding sphere and including the scale of the instance
+    pub fn select(&self, lods: &[MeshLod], pixels_per_unit: f32, current: usize) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+
+        let mut selected = 0;
+
+        for (i, lod) in lods.iter().enumerate() {
+            // Switching to a coarser level must be clearly below the threshold
+            // while staying at or going back to a finer one can be slightly above it
+            let threshold = match i > current {
+                true => self.error_threshold * (1.0 - self.hysteresis),
+                false => self.error_threshold * (1.0 + self.hysteresis),
+            };
+
+            if lod.error * pixels_per_unit <= threshold {
+                selected = i;
+            } else {
+                break;
+            }
+        }
+
+        selected
+    }
+
+    /// Selects the level of a mesh from the distance to its bounding sphere, with full
+    /// detail when the camera is inside it. `pixels_per_unit` is how many pixels one unit
+    /// covers on screen at a distance of one unit.
+    pub fn select_for_view(
+        &self,
+        lods: &[MeshLod],
+        sphere: &BoundingSphere,
+        world_transform: Mat4,
+        eye_pos: Vec3,
+        pixels_per_unit: f32,
+        current: usize,
+    ) -> usize {
+        let scale = world_transform
+            .x_axis
+            .truncate()
+            .length()
+            .max(world_transform.y_axis.truncate().length())
+            .max(world_transform.z_axis.truncate().length());
+
+        let center = world_transform.transform_point3(sphere.center);
+        let distance = center.distance(eye_pos) - sphere.radius * scale;
+
+        match distance > 0.0 {
+            true => self.select(lods, pixels_per_unit * scale / distance, current),
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every level has twice the error of the previous one
+    fn lods() -> Vec<MeshLod> {
+        [0.0, 0.01, 0.02, 0.04]
+            .iter()
+            .map(|&error| MeshLod {
+                first_index: 0,
+                index_count: 0,
+                error,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_coarsest_level_below_the_threshold_is_selected() {
+        let selection = LodSelection {
+            hysteresis: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(selection.select(&lods(), 1000.0, 0), 0);
+        // 0.01 units is 1 pixel
+        assert_eq!(selection.select(&lods(), 100.0, 0), 1);
+        assert_eq!(selection.select(&lods(), 30.0, 0), 2);
+        assert_eq!(selection.select(&lods(), 1.0, 0), 3);
+    }
+
+    #[test]
+    fn hysteresis_keeps_the_level_at_the_threshold() {
+        let selection = LodSelection::default();
+        let lods = lods();
+
+        // The error of level 1 is 1 pixel, right at the threshold
+        let at_threshold = 100.0;
+        assert_eq!(selection.select(&lods, at_threshold, 0), 0);
+        assert_eq!(selection.select(&lods, at_threshold, 1), 1);
+
+        // Small movements around the threshold keep the current level
+        for pixels_per_unit in [90.0, 100.0, 110.0] {
+            assert_eq!(selection.select(&lods, pixels_per_unit, 0), 0);
+            assert_eq!(selection.select(&lods, pixels_per_unit, 1), 1);
+        }
+
+        // Outside of the margin the level changes
+        assert_eq!(selection.select(&lods, 75.0, 0), 1);
+        assert_eq!(selection.select(&lods, 125.0, 1), 0);
+    }
+
+    #[test]
+    fn disabled_selection_uses_full_detail() {
+        let selection = LodSelection {
+            enabled: false,
+            ..Default::default()
+        };
+
+        assert_eq!(selection.select(&lods(), 1.0, 3), 0);
+    }
+
+    #[test]
+    fn full_detail_is_selected_up_close() {
+        let selection = LodSelection::default();
+        let sphere = BoundingSphere {
+            center: Vec3::ZERO,
+            radius: 1.0,
+        };
+        let transform = Mat4::from_translation(Vec3::new(0.0, 0.0, -10.0));
+        let select = |eye_pos: Vec3, current: usize| {
+            selection.select_for_view(&lods(), &sphere, transform, eye_pos, 1000.0, current)
+        };
+
+        // Inside the bounding sphere
+        assert_eq!(select(Vec3::new(0.0, 0.0, -10.5), 3), 0);
+        // 1 unit from the sphere
+        assert_eq!(select(Vec3::new(0.0, 0.0, -8.0), 3), 0);
+        // Far away
+        assert_eq!(select(Vec3::new(0.0, 0.0, 1000.0), 0), 3);
+    }
+
+    #[test]
+    fn scaled_instances_select_finer_levels() {
+        let selection = LodSelection::default();
+        let sphere = BoundingSphere {
+            center: Vec3::ZERO,
+            radius: 1.0,
+        };
+        let eye_pos = Vec3::new(0.0, 0.0, 31.0);
+
+        let unscaled =
+            selection.select_for_view(&lods(), &sphere, Mat4::IDENTITY, eye_pos, 1000.0, 0);
+        let scaled = selection.select_for_view(
+            &lods(),
+            &sphere,
+            Mat4::from_scale(Vec3::splat(2.0)),
+            eye_pos,
+            1000.0,
+            0,
+        );
+
+        assert_eq!(unscaled, 2);
+        assert_eq!(scaled, 1);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use crate::primitive::Vertex;
+
+impl meshopt::DecodePosition for Vertex {
+    fn decode_position(&self) -> [f32; 3] {
+        self.pos.truncate().to_array()
+    }
+}
+
+// How much the overdraw optimization may increase the vertex cache misses
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+fn vertex_bits(vertex: &Vertex) -> [u32; 18] {
+    let mut bits = [0; 18];
+    let attributes = vertex
+        .pos
+        .to_array()
+        .into_iter()
+        .chain(vertex.normal.to_array())
+        .chain(vertex.uv.to_array())
+        .chain(vertex.color.to_array())
+        .chain(vertex.tangent.to_array());
+
+    for (bits, value) in bits.iter_mut().zip(attributes) {
+        *bits = value.to_bits();
+    }
+
+    bits
+}
+
+/// Merges vertices whose attributes are bit for bit equal. Returns the original index of
+/// every remaining vertex.
+pub fn deduplicate_vertices(indices: &mut [u32], vertices: &mut Vec<Vertex>) -> Vec<u32> {
+    let mut unique_indices: HashMap<[u32; 18], u32> = HashMap::new();
+    let mut source_vertices = vec![];
+    let mut remap = vec![0; vertices.len()];
+
+    for (i, vertex) in vertices.iter().enumerate() {
+        remap[i] = *unique_indices
+            .entry(vertex_bits(vertex))
+            .or_insert_with(|| {
+                source_vertices.push(i as u32);
+                source_vertices.len() as u32 - 1
+            });
+    }
+
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+
+    *vertices = source_vertices
+        .iter()
+        .map(|&i| vertices[i as usize])
+        .collect();
+
+    source_vertices
+}
+
+/// Orders the vertices by their first use in `indices` and drops unused ones. Returns
+/// the original index of every remaining vertex.
+pub fn optimize_vertex_fetch(indices: &mut [u32], vertices: &mut Vec<Vertex>) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut source_vertices = vec![];
+
+    for index in indices.iter_mut() {
+        if remap[*index as usize] == u32::MAX {
+            remap[*index as usize] = source_vertices.len() as u32;
+            source_vertices.push(*index);
+        }
+        *index = remap[*index as usize];
+    }
+
+    *vertices = source_vertices
+        .iter()
+        .map(|&i| vertices[i as usize])
+        .collect();
+
+    source_vertices
+}
+
+/// Deduplicates the vertices and reorders the triangles for the post transform vertex
+/// cache and less overdraw, then the vertices for fetch locality. The result only depends
+/// on the input so it is the same every time a mesh is loaded. Returns the original index
+/// of every vertex so that other per vertex data can be remapped with `remap_vertex_data`.
+/// Note: meshes with skinning or morph targets should not be deduplicated since vertices
+/// with the same attributes can have different weights.
+pub fn optimize_mesh(
+    indices: &mut Vec<u32>,
+    vertices: &mut Vec<Vertex>,
+    deduplicate: bool,
+) -> Vec<u32> {
+    let deduplicated = match deduplicate {
+        true => Some(deduplicate_vertices(indices, vertices)),
+        false => None,
+    };
+
+    *indices = meshopt::optimize_vertex_cache(indices, vertices.len());
+    meshopt::optimize_overdraw_in_place_decoder(indices, vertices, OVERDRAW_THRESHOLD);
+
+    optimize_vertex_fetch(indices, vertices)
+        .into_iter()
+        .map(|i| deduplicated.as_ref().map_or(i, |source| source[i as usize]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Vec2, Vec4};
+
+    fn vertex(x: f32, y: f32) -> Vertex {
+        Vertex {
+            pos: Vec4::new(x, y, 0.0, 0.0),
+            normal: Vec4::new(0.0, 0.0, 1.0, 0.0),
+            uv: Vec2::new(x, y),
+            color: Vec4::ONE,
+            tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    // Two triangles of a quad that do not share their vertices
+    fn unwelded_quad() -> (Vec<u32>, Vec<Vertex>) {
+        let vertices = vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+        ];
+
+        (vec![0, 1, 2, 3, 4, 5], vertices)
+    }
+
+    // The triangles as positions so that they can be compared after reordering
+    fn triangles(indices: &[u32], vertices: &[Vertex]) -> Vec<[[f32; 4]; 3]> {
+        let mut triangles: Vec<[[f32; 4]; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| vertices[triangle[i] as usize].pos.to_array()))
+            .collect();
+        triangles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        triangles
+    }
+
+    #[test]
+    fn deduplicate_vertices_merges_equal_vertices() {
+        let (mut indices, mut vertices) = unwelded_quad();
+
+        let source_vertices = deduplicate_vertices(&mut indices, &mut vertices);
+
+        assert_eq!(source_vertices, [0, 1, 2, 5]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices.len(), 4);
+    }
+
+    #[test]
+    fn deduplicate_vertices_keeps_vertices_that_differ_in_any_attribute() {
+        let (mut indices, mut vertices) = unwelded_quad();
+        vertices[3].color = Vec4::ZERO;
+
+        let source_vertices = deduplicate_vertices(&mut indices, &mut vertices);
+
+        assert_eq!(source_vertices, [0, 1, 2, 3, 5]);
+        assert_eq!(indices, [0, 1, 2, 3, 2, 4]);
+    }
+
+    #[test]
+    fn optimize_vertex_fetch_orders_vertices_by_first_use() {
+        let mut indices = vec![3, 1, 2, 3, 2, 0];
+        let mut vertices = vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+            vertex(2.0, 2.0),
+        ];
+
+        let source_vertices = optimize_vertex_fetch(&mut indices, &mut vertices);
+
+        // The unused last vertex is dropped
+        assert_eq!(source_vertices, [3, 1, 2, 0]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices[0].pos, Vec4::new(0.0, 1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn optimize_mesh_keeps_the_triangles() {
+        let (mut indices, mut vertices) = unwelded_quad();
+        let expected = triangles(&indices, &vertices);
+
+        let source_vertices = optimize_mesh(&mut indices, &mut vertices, true);
+
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(triangles(&indices, &vertices), expected);
+        for (vertex, &source) in vertices.iter().zip(&source_vertices) {
+            assert_eq!(vertex.pos, unwelded_quad().1[source as usize].pos);
+        }
+    }
+
+    #[test]
+    fn optimize_mesh_without_deduplication_keeps_all_vertices() {
+        let (mut indices, mut vertices) = unwelded_quad();
+
+        let mut source_vertices = optimize_mesh(&mut indices, &mut vertices, false);
+
+        assert_eq!(vertices.len(), 6);
+        source_vertices.sort();
+        assert_eq!(source_vertices, [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -1,8 +1,15 @@
-use glam::{Mat4, Vec2, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::f32::consts::PI;
 
+use crate::asset_manager::*;
+use crate::bounds::*;
 use crate::device::*;
 use crate::gltf_loader::*;
+use crate::mesh_optimizer::*;
 use crate::primitive::*;
+use crate::scene_graph::*;
+use crate::tangent_space::*;
 use crate::Model;
 
 pub struct ModelLoader {}
@@ -34,8 +41,500 @@ pub fn add_vertex(
     });
 }
 
+fn surface_vertex(pos: Vec3, normal: Vec3, uv: Vec2) -> Vertex {
+    Vertex {
+        pos: pos.extend(0.0),
+        normal: normal.extend(0.0),
+        uv,
+        color: Vec4::ONE,
+        tangent: Vec4::ZERO,
+    }
+}
+
+// Adds a grid of `columns` x `rows` quads where `surface` returns the position and normal
+// of every grid point. u goes along the columns and v along the rows, and v must point
+// downwards when looking at the front of the surface for the triangles to be wound
+// counter clockwise. Triangles that collapse at poles and apexes are skipped.
+fn add_surface(
+    indices: &mut Vec<u32>,
+    vertices: &mut Vec<Vertex>,
+    columns: u32,
+    rows: u32,
+    surface: impl Fn(u32, u32) -> (Vec3, Vec3),
+) {
+    let first = vertices.len() as u32;
+
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (pos, normal) = surface(column, row);
+            let uv = Vec2::new(column as f32 / columns as f32, row as f32 / rows as f32);
+            vertices.push(surface_vertex(pos, normal, uv));
+        }
+    }
+
+    let mut add_non_degenerate = |v1: u32, v2: u32, v3: u32| {
+        let p1 = vertices[v1 as usize].pos.truncate();
+        let p2 = vertices[v2 as usize].pos.truncate();
+        let p3 = vertices[v3 as usize].pos.truncate();
+        // The height of the triangle is compared to its longest edge, so that the slivers
+        // left by rounding errors at the poles are skipped as well
+        let longest_edge = (p2 - p1)
+            .length_squared()
+            .max((p3 - p2).length_squared())
+            .max((p1 - p3).length_squared());
+        if (p2 - p1).cross(p3 - p1).length() > 1e-5 * longest_edge {
+            add_triangle(indices, v1, v2, v3);
+        }
+    };
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let top_left = first + row * (columns + 1) + column;
+            let bottom_left = top_left + columns + 1;
+            add_non_degenerate(top_left, bottom_left, top_left + 1);
+            add_non_degenerate(top_left + 1, bottom_left, bottom_left + 1);
+        }
+    }
+}
+
+// Adds a disk in the xz-plane facing up or down, with the texture projected from above
+fn add_disk(
+    indices: &mut Vec<u32>,
+    vertices: &mut Vec<Vertex>,
+    y: f32,
+    radius: f32,
+    segments: u32,
+    facing_up: bool,
+) {
+    let sign = if facing_up { 1.0 } else { -1.0 };
+    let normal = Vec3::new(0.0, sign, 0.0);
+    let center = vertices.len() as u32;
+
+    vertices.push(surface_vertex(
+        Vec3::new(0.0, y, 0.0),
+        normal,
+        Vec2::new(0.5, 0.5),
+    ));
+
+    for segment in 0..=segments {
+        let phi = 2.0 * PI * segment as f32 / segments as f32;
+        let (x, z) = (phi.sin(), phi.cos());
+        vertices.push(surface_vertex(
+            Vec3::new(x * radius, y, z * radius),
+            normal,
+            Vec2::new(0.5 + 0.5 * x, 0.5 + 0.5 * z * sign),
+        ));
+    }
+
+    for segment in 0..segments {
+        let ring = center + 1 + segment;
+        match facing_up {
+            true => add_triangle(indices, center, ring, ring + 1),
+            false => add_triangle(indices, center, ring + 1, ring),
+        }
+    }
+}
+
+// Unit vector around the y-axis, the u texture coordinate of all round shapes follows
+// the same angle
+fn around_y(phi: f32) -> Vec3 {
+    Vec3::new(phi.sin(), 0.0, phi.cos())
+}
+
+/// Like the other generators the segment counts are clamped to the fewest that still
+/// give a closed shape, here 3 sectors and 2 stacks
+pub fn generate_uv_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<u32>, Vec<Vertex>) {
+    let (sectors, stacks) = (sectors.max(3), stacks.max(2));
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    add_surface(
+        &mut indices,
+        &mut vertices,
+        sectors,
+        stacks,
+        |column, row| {
+            let phi = 2.0 * PI * column as f32 / sectors as f32;
+            let theta = PI * row as f32 / stacks as f32;
+            let normal = around_y(phi) * theta.sin() + Vec3::Y * theta.cos();
+            (normal * radius, normal)
+        },
+    );
+
+    (indices, vertices)
+}
+
+/// Sphere made from a subdivided icosahedron, which distributes the triangles more
+/// evenly than a UV sphere. It uses the same texture mapping as `generate_uv_sphere`.
+pub fn generate_icosphere(radius: f32, subdivisions: u32) -> (Vec<u32>, Vec<Vertex>) {
+    let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let pos = (positions[a as usize] + positions[b as usize]).normalize();
+                positions.push(pos);
+                positions.len() as u32 - 1
+            })
+        };
+
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // The texture coordinates are assigned per triangle since the vertices along the
+    // seam and at the poles need different ones depending on the triangle
+    let mut indices = vec![];
+    let mut vertices = vec![];
+    let mut vertex_indices: HashMap<(u32, u32, u32), u32> = HashMap::new();
+
+    for triangle in &triangles {
+        let corners = triangle.map(|i| positions[i as usize]);
+        let mut uvs = corners.map(|pos| {
+            let u = pos.x.atan2(pos.z) / (2.0 * PI);
+            Vec2::new(u.rem_euclid(1.0), pos.y.clamp(-1.0, 1.0).acos() / PI)
+        });
+
+        // The u coordinate is undefined at the poles so they are left out of the seam check
+        let is_pole = corners.map(|pos| pos.y.abs() > 0.999);
+
+        let (min_u, max_u) = (0..3)
+            .filter(|&i| !is_pole[i])
+            .fold((1.0_f32, 0.0_f32), |(min, max), i| {
+                (min.min(uvs[i].x), max.max(uvs[i].x))
+            });
+        if max_u - min_u > 0.5 {
+            for uv in uvs.iter_mut().filter(|uv| uv.x < 0.5) {
+                uv.x += 1.0;
+            }
+        }
+
+        for i in 0..3 {
+            if is_pole[i] {
+                uvs[i].x = (uvs[(i + 1) % 3].x + uvs[(i + 2) % 3].x) / 2.0;
+            }
+        }
+
+        for i in 0..3 {
+            let key = (triangle[i], uvs[i].x.to_bits(), uvs[i].y.to_bits());
+            let index = *vertex_indices.entry(key).or_insert_with(|| {
+                vertices.push(surface_vertex(corners[i] * radius, corners[i], uvs[i]));
+                vertices.len() as u32 - 1
+            });
+            indices.push(index);
+        }
+    }
+
+    (indices, vertices)
+}
+
+/// Plane in the xz-plane facing up, with `subdivisions` quads along each side
+pub fn generate_plane(width: f32, depth: f32, subdivisions: u32) -> (Vec<u32>, Vec<Vertex>) {
+    let subdivisions = subdivisions.max(1);
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    add_surface(
+        &mut indices,
+        &mut vertices,
+        subdivisions,
+        subdivisions,
+        |column, row| {
+            let x = (column as f32 / subdivisions as f32 - 0.5) * width;
+            let z = (row as f32 / subdivisions as f32 - 0.5) * depth;
+            (Vec3::new(x, 0.0, z), Vec3::Y)
+        },
+    );
+
+    (indices, vertices)
+}
+
+pub fn generate_cylinder(radius: f32, height: f32, segments: u32) -> (Vec<u32>, Vec<Vertex>) {
+    let segments = segments.max(3);
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    add_surface(&mut indices, &mut vertices, segments, 1, |column, row| {
+        let normal = around_y(2.0 * PI * column as f32 / segments as f32);
+        let y = (0.5 - row as f32) * height;
+        (normal * radius + Vec3::Y * y, normal)
+    });
+    add_disk(
+        &mut indices,
+        &mut vertices,
+        height / 2.0,
+        radius,
+        segments,
+        true,
+    );
+    add_disk(
+        &mut indices,
+        &mut vertices,
+        -height / 2.0,
+        radius,
+        segments,
+        false,
+    );
+
+    (indices, vertices)
+}
+
+/// Cone with its apex pointing up, centered between the apex and the base
+pub fn generate_cone(radius: f32, height: f32, segments: u32) -> (Vec<u32>, Vec<Vertex>) {
+    let segments = segments.max(3);
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    add_surface(&mut indices, &mut vertices, segments, 1, |column, row| {
+        let direction = around_y(2.0 * PI * column as f32 / segments as f32);
+        let normal = (direction * height + Vec3::Y * radius).normalize();
+        let y = (0.5 - row as f32) * height;
+        (direction * radius * row as f32 + Vec3::Y * y, normal)
+    });
+    add_disk(
+        &mut indices,
+        &mut vertices,
+        -height / 2.0,
+        radius,
+        segments,
+        false,
+    );
+
+    (indices, vertices)
+}
+
+/// Cylinder with hemispheres at the ends, `height` is the length of the cylinder part
+pub fn generate_capsule(
+    radius: f32,
+    height: f32,
+    segments: u32,
+    rings: u32,
+) -> (Vec<u32>, Vec<Vertex>) {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    // The rows go down over the top hemisphere, then along the cylinder, and then
+    // down over the bottom hemisphere
+    add_surface(
+        &mut indices,
+        &mut vertices,
+        segments,
+        2 * rings + 1,
+        |column, row| {
+            let (theta, center_y) = match row <= rings {
+                true => (PI / 2.0 * row as f32 / rings as f32, height / 2.0),
+                false => (
+                    PI / 2.0 * (1.0 + (row - rings - 1) as f32 / rings as f32),
+                    -height / 2.0,
+                ),
+            };
+            let phi = 2.0 * PI * column as f32 / segments as f32;
+            let normal = around_y(phi) * theta.sin() + Vec3::Y * theta.cos();
+            (normal * radius + Vec3::Y * center_y, normal)
+        },
+    );
+
+    (indices, vertices)
+}
+
+/// Torus around the y-axis, `major_radius` is the distance from the center to the
+/// middle of the tube
+pub fn generate_torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> (Vec<u32>, Vec<Vertex>) {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let (mut indices, mut vertices) = (vec![], vec![]);
+
+    // The tube is walked from its top, over the outside and back along the inside
+    add_surface(
+        &mut indices,
+        &mut vertices,
+        major_segments,
+        minor_segments,
+        |column, row| {
+            let direction = around_y(2.0 * PI * column as f32 / major_segments as f32);
+            let theta = 2.0 * PI * row as f32 / minor_segments as f32;
+            let normal = direction * theta.sin() + Vec3::Y * theta.cos();
+            (direction * major_radius + normal * minor_radius, normal)
+        },
+    );
+
+    (indices, vertices)
+}
+
 impl ModelLoader {
-    pub fn load_triangle(device: &Device) -> Model {
+    /// Creates a model with a single mesh from generated geometry, e.g `generate_uv_sphere`
+    pub fn load_generated(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        name: &str,
+        geometry: (Vec<u32>, Vec<Vertex>),
+        material: Material,
+    ) -> Model {
+        let (mut indices, mut vertices) = geometry;
+        generate_tangents(&mut indices, &mut vertices);
+
+        if asset_manager.optimize_meshes {
+            optimize_mesh(&mut indices, &mut vertices, true);
+        }
+
+        let primitive = asset_manager.mesh(device, name, indices, vertices);
+
+        let mut nodes = SceneGraph::new();
+        let node = nodes.add_node(name, Mat4::IDENTITY, None);
+
+        Model {
+            meshes: vec![Mesh {
+                bounds: MeshBounds::from_vertices(&primitive.vertices),
+                world_bounds: MeshBounds::default(),
+                prev_world_transform: None,
+                primitive,
+                material,
+                gpu_mesh: 0,
+                node,
+                deformation: None,
+                lod: 0,
+            }],
+            textures: vec![],
+            lights: vec![],
+            cameras: vec![],
+            skins: vec![],
+            animations: vec![],
+            nodes,
+        }
+    }
+
+    pub fn load_uv_sphere(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        radius: f32,
+        sectors: u32,
+        stacks: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_uv_sphere(radius, sectors, stacks);
+        Self::load_generated(device, asset_manager, "uv_sphere", geometry, material)
+    }
+
+    pub fn load_icosphere(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        radius: f32,
+        subdivisions: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_icosphere(radius, subdivisions);
+        Self::load_generated(device, asset_manager, "icosphere", geometry, material)
+    }
+
+    pub fn load_plane(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        width: f32,
+        depth: f32,
+        subdivisions: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_plane(width, depth, subdivisions);
+        Self::load_generated(device, asset_manager, "plane", geometry, material)
+    }
+
+    pub fn load_cylinder(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        radius: f32,
+        height: f32,
+        segments: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_cylinder(radius, height, segments);
+        Self::load_generated(device, asset_manager, "cylinder", geometry, material)
+    }
+
+    pub fn load_cone(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        radius: f32,
+        height: f32,
+        segments: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_cone(radius, height, segments);
+        Self::load_generated(device, asset_manager, "cone", geometry, material)
+    }
+
+    pub fn load_capsule(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        radius: f32,
+        height: f32,
+        segments: u32,
+        rings: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_capsule(radius, height, segments, rings);
+        Self::load_generated(device, asset_manager, "capsule", geometry, material)
+    }
+
+    pub fn load_torus(
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+        material: Material,
+    ) -> Model {
+        let geometry = generate_torus(major_radius, minor_radius, major_segments, minor_segments);
+        Self::load_generated(device, asset_manager, "torus", geometry, material)
+    }
+
+    pub fn load_triangle(device: &Device, asset_manager: &mut AssetManager) -> Model {
         let indices = vec![0, 1, 2];
 
         let mut vertices = vec![];
@@ -43,34 +542,43 @@ impl ModelLoader {
         add_vertex(&mut vertices, -1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0);
         add_vertex(&mut vertices, 1.0, -1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0);
 
+        let mut nodes = SceneGraph::new();
+        let node = nodes.add_node("triangle", Mat4::IDENTITY, None);
+
         Model {
             meshes: vec![Mesh {
-                primitive: Primitive::new(device, indices, vertices),
-                material: Material {
-                    diffuse_map: DEFAULT_TEXTURE_MAP,
-                    normal_map: DEFAULT_TEXTURE_MAP,
-                    metallic_roughness_map: DEFAULT_TEXTURE_MAP,
-                    occlusion_map: DEFAULT_TEXTURE_MAP,
-                    base_color_factor: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    metallic_factor: 0.0,
-                    roughness_factor: 0.5,
-                    material_type: MaterialType::Lambertian,
-                    material_property: 0.0,
-                },
+                bounds: MeshBounds::from_vertices(&vertices),
+                world_bounds: MeshBounds::default(),
+                prev_world_transform: None,
+                primitive: asset_manager.mesh(device, "triangle", indices, vertices),
+                material: Material::default(),
                 gpu_mesh: 0,
+                node,
+                deformation: None,
+                lod: 0,
             }],
-            transforms: vec![Mat4::IDENTITY],
             textures: vec![],
+            lights: vec![],
+            cameras: vec![],
+            skins: vec![],
+            animations: vec![],
+            nodes,
         }
     }
 
-    pub fn load_cube(device: &Device) -> Model {
+    pub fn load_cube(device: &Device, asset_manager: &mut AssetManager) -> Model {
         let mut model = Model {
             meshes: vec![],
-            transforms: vec![],
             textures: vec![],
+            lights: vec![],
+            cameras: vec![],
+            skins: vec![],
+            animations: vec![],
+            nodes: SceneGraph::new(),
         };
 
+        let node = model.nodes.add_node("cube", Mat4::IDENTITY, None);
+
         let mut indices = vec![];
         let mut vertices = vec![];
 
@@ -135,22 +643,177 @@ impl ModelLoader {
         add_vertex(&mut vertices, 0.5, -0.5, 0.5, 1.0, 0.0, 0.0, 0.0, 0.0);
 
         model.meshes.push(Mesh {
-            primitive: Primitive::new(device, indices, vertices),
-            material: Material {
-                diffuse_map: DEFAULT_TEXTURE_MAP,
-                normal_map: DEFAULT_TEXTURE_MAP,
-                metallic_roughness_map: DEFAULT_TEXTURE_MAP,
-                occlusion_map: DEFAULT_TEXTURE_MAP,
-                base_color_factor: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                metallic_factor: 0.0,
-                roughness_factor: 0.5,
-                material_type: MaterialType::Lambertian,
-                material_property: 0.0,
-            },
+            bounds: MeshBounds::from_vertices(&vertices),
+            world_bounds: MeshBounds::default(),
+            prev_world_transform: None,
+            primitive: asset_manager.mesh(device, "cube", indices, vertices),
+            material: Material::default(),
             gpu_mesh: 0,
+            node,
+            deformation: None,
+            lod: 0,
         });
-        model.transforms.push(Mat4::IDENTITY);
 
         model
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the indices are valid, that the normals have unit length and that
+    // every triangle is wound counter clockwise when seen from the side of its normals
+    fn check_mesh((indices, vertices): &(Vec<u32>, Vec<Vertex>)) {
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices
+            .iter()
+            .all(|&index| (index as usize) < vertices.len()));
+
+        for vertex in vertices {
+            assert!((vertex.normal.truncate().length() - 1.0).abs() < 1e-5);
+        }
+
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+            let face_normal = (b.pos - a.pos).truncate().cross((c.pos - a.pos).truncate());
+            let vertex_normal = (a.normal + b.normal + c.normal).truncate();
+
+            assert!(face_normal.length() > 0.0, "Degenerate triangle");
+            assert!(
+                face_normal.dot(vertex_normal) > 0.0,
+                "Triangle faces inwards"
+            );
+        }
+    }
+
+    // Every edge of a closed surface is shared by exactly two triangles going in opposite
+    // directions. Vertices at seams and poles are welded by their position.
+    fn check_closed((indices, vertices): &(Vec<u32>, Vec<Vertex>)) {
+        let position = |index: u32| {
+            let pos = vertices[index as usize].pos.truncate() * 1e4;
+            (
+                pos.x.round() as i32,
+                pos.y.round() as i32,
+                pos.z.round() as i32,
+            )
+        };
+
+        let mut edges: HashMap<_, u32> = HashMap::new();
+        for triangle in indices.chunks(3) {
+            for i in 0..3 {
+                let edge = (position(triangle[i]), position(triangle[(i + 1) % 3]));
+                *edges.entry(edge).or_default() += 1;
+            }
+        }
+
+        for (&(a, b), &count) in &edges {
+            assert_eq!(
+                count,
+                1,
+                "Edge {:?} is used by more than one triangle",
+                (a, b)
+            );
+            assert_eq!(edges.get(&(b, a)), Some(&1), "Edge {:?} is open", (a, b));
+        }
+    }
+
+    #[test]
+    fn uv_sphere() {
+        let sphere = generate_uv_sphere(2.0, 8, 4);
+
+        check_mesh(&sphere);
+        check_closed(&sphere);
+        assert_eq!(sphere.1.len(), 9 * 5);
+        // The triangles at the poles collapse
+        assert_eq!(sphere.0.len(), 8 * (4 - 1) * 6);
+        assert!(sphere
+            .1
+            .iter()
+            .all(|vertex| (vertex.pos.truncate().length() - 2.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn icosphere() {
+        let sphere = generate_icosphere(1.0, 2);
+
+        check_mesh(&sphere);
+        check_closed(&sphere);
+        assert_eq!(sphere.0.len(), 20 * 16 * 3);
+    }
+
+    #[test]
+    fn plane() {
+        let plane = generate_plane(4.0, 2.0, 3);
+
+        check_mesh(&plane);
+        assert_eq!(plane.1.len(), 4 * 4);
+        assert_eq!(plane.0.len(), 3 * 3 * 6);
+        assert!(plane
+            .1
+            .iter()
+            .all(|vertex| vertex.normal.truncate() == Vec3::Y));
+
+        let aabb = MeshBounds::from_vertices(&plane.1).aabb;
+        assert_eq!(aabb.min, Vec3::new(-2.0, 0.0, -1.0));
+        assert_eq!(aabb.max, Vec3::new(2.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn cylinder() {
+        let cylinder = generate_cylinder(1.0, 2.0, 6);
+
+        check_mesh(&cylinder);
+        check_closed(&cylinder);
+        // The side and the two caps with their center vertex
+        assert_eq!(cylinder.1.len(), 2 * 7 + 2 * (7 + 1));
+        assert_eq!(cylinder.0.len(), (6 * 2 + 2 * 6) * 3);
+    }
+
+    #[test]
+    fn cone() {
+        let cone = generate_cone(1.0, 2.0, 6);
+
+        check_mesh(&cone);
+        check_closed(&cone);
+        assert_eq!(cone.1.len(), 2 * 7 + 7 + 1);
+        // The triangles at the apex collapse
+        assert_eq!(cone.0.len(), (6 + 6) * 3);
+    }
+
+    #[test]
+    fn capsule() {
+        let capsule = generate_capsule(0.5, 1.0, 8, 3);
+
+        check_mesh(&capsule);
+        check_closed(&capsule);
+        assert_eq!(capsule.1.len(), 9 * (2 * 3 + 2));
+
+        let aabb = MeshBounds::from_vertices(&capsule.1).aabb;
+        assert!((aabb.max.y - 1.0).abs() < 1e-5);
+        assert!((aabb.min.y + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn torus() {
+        let torus = generate_torus(2.0, 0.5, 12, 6);
+
+        check_mesh(&torus);
+        check_closed(&torus);
+        assert_eq!(torus.1.len(), 13 * 7);
+        assert_eq!(torus.0.len(), 12 * 6 * 6);
+    }
+
+    #[test]
+    fn segment_counts_are_clamped() {
+        let sphere = generate_uv_sphere(1.0, 0, 0);
+        let plane = generate_plane(1.0, 1.0, 0);
+        let torus = generate_torus(1.0, 0.25, 1, 1);
+
+        check_closed(&sphere);
+        assert_eq!(sphere.1.len(), 4 * 3);
+        assert_eq!(plane.0.len(), 6);
+        check_mesh(&torus);
+        assert_eq!(torus.1.len(), 4 * 4);
+    }
+}
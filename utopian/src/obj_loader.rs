@@ -0,0 +1,651 @@
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+
+use crate::asset_manager::*;
+use crate::bounds::*;
+use crate::device::*;
+use crate::gltf_loader::*;
+use crate::mesh_optimizer::*;
+use crate::primitive::*;
+use crate::scene_graph::*;
+use crate::tangent_space::*;
+use crate::texture::*;
+
+// Illumination models from the MTL specification
+const ILLUM_REFLECTION: [u32; 3] = [3, 5, 8];
+const ILLUM_REFRACTION: [u32; 4] = [4, 6, 7, 9];
+
+struct ObjMaterial {
+    name: String,
+    diffuse: Vec3,                // Kd
+    emission: Vec3,               // Ke
+    shininess: Option<f32>,       // Ns
+    roughness: Option<f32>,       // Pr
+    metallic: Option<f32>,        // Pm
+    dissolve: f32,                // d, 1.0 - Tr
+    optical_density: Option<f32>, // Ni
+    illum: u32,
+    // Texture paths relative to the working directory
+    diffuse_map: Option<String>,
+    specular_map: Option<String>,
+    roughness_map: Option<String>,
+    metallic_map: Option<String>,
+    normal_map: Option<String>,
+    emissive_map: Option<String>,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            diffuse: Vec3::ONE,
+            emission: Vec3::ZERO,
+            shininess: None,
+            roughness: None,
+            metallic: None,
+            dissolve: 1.0,
+            optical_density: None,
+            illum: 2,
+            diffuse_map: None,
+            specular_map: None,
+            roughness_map: None,
+            metallic_map: None,
+            normal_map: None,
+            emissive_map: None,
+        }
+    }
+}
+
+// All faces that use the same material are merged into one mesh
+struct ObjMesh {
+    material: Option<usize>,
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+}
+
+/// The CPU side of an OBJ file and its MTL libraries
+pub struct ObjData {
+    meshes: Vec<ObjMesh>,
+    materials: Vec<ObjMaterial>,
+}
+
+fn parse_floats<const N: usize>(tokens: &[&str], path: &str, line_number: usize) -> [f32; N] {
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = match tokens.get(i).map(|token| token.parse::<f32>()) {
+            Some(Ok(parsed)) => parsed,
+            _ => panic!("Invalid number on line {} in {}", line_number + 1, path),
+        };
+    }
+
+    values
+}
+
+// OBJ indices start at 1 and negative indices are relative to the end of the list
+fn resolve_index(token: &str, count: usize, path: &str, line_number: usize) -> usize {
+    let index = token.parse::<i64>().unwrap_or(0);
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i => count as i64 + i,
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        panic!(
+            "Invalid index {} on line {} in {}",
+            token,
+            line_number + 1,
+            path
+        );
+    }
+
+    resolved as usize
+}
+
+// Texture statements can have options before the file name, e.g `bump -bm 0.5 normal.png`
+fn texture_path(tokens: &[&str], mtl_path: &str) -> Option<String> {
+    tokens.last().map(|file| {
+        std::path::Path::new(mtl_path)
+            .with_file_name(file.replace('\\', "/"))
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+fn read_mtl(path: &str) -> Vec<ObjMaterial> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_err) => {
+            log::warn!("Unable to load material library {}", path);
+            return vec![];
+        }
+    };
+
+    let mut materials: Vec<ObjMaterial> = vec![];
+
+    for (line_number, line) in content.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = match tokens.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial::new(args.first().copied().unwrap_or_default()));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = Vec3::from(parse_floats(args, path, line_number)),
+            "Ke" => material.emission = Vec3::from(parse_floats(args, path, line_number)),
+            "Ns" => material.shininess = Some(parse_floats::<1>(args, path, line_number)[0]),
+            "Pr" => material.roughness = Some(parse_floats::<1>(args, path, line_number)[0]),
+            "Pm" => material.metallic = Some(parse_floats::<1>(args, path, line_number)[0]),
+            "Ni" => material.optical_density = Some(parse_floats::<1>(args, path, line_number)[0]),
+            "d" => material.dissolve = parse_floats::<1>(args, path, line_number)[0],
+            "Tr" => material.dissolve = 1.0 - parse_floats::<1>(args, path, line_number)[0],
+            "illum" => material.illum = args.first().and_then(|arg| arg.parse().ok()).unwrap_or(2),
+            "map_Kd" => material.diffuse_map = texture_path(args, path),
+            "map_Ks" => material.specular_map = texture_path(args, path),
+            "map_Pr" => material.roughness_map = texture_path(args, path),
+            "map_Pm" => material.metallic_map = texture_path(args, path),
+            "map_Ke" => material.emissive_map = texture_path(args, path),
+            "norm" | "bump" | "map_Bump" | "map_bump" => {
+                material.normal_map = texture_path(args, path)
+            }
+            _ => {}
+        }
+    }
+
+    materials
+}
+
+/// Parses the file and generates the tangents of its meshes, and optimizes them if
+/// `optimize_meshes` is set, so that the main thread only has to upload them
+pub fn decode_obj(path: &str, optimize_meshes: bool) -> ObjData {
+    puffin::profile_function!();
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_err) => panic!("Unable to load \"{}\"", path),
+    };
+
+    let mut data = parse_obj(&content, path);
+
+    for mesh in &mut data.meshes {
+        generate_tangents(&mut mesh.indices, &mut mesh.vertices);
+
+        if optimize_meshes {
+            optimize_mesh(&mut mesh.indices, &mut mesh.vertices, true);
+        }
+    }
+
+    data
+}
+
+// Material libraries are loaded relative to `path`
+fn parse_obj(content: &str, path: &str) -> ObjData {
+    let mut positions: Vec<Vec3> = vec![];
+    let mut tex_coords: Vec<Vec2> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+
+    let mut materials: Vec<ObjMaterial> = vec![];
+    let mut current_material = None;
+
+    let mut meshes: Vec<ObjMesh> = vec![];
+    let mut mesh_indices: HashMap<Option<usize>, usize> = HashMap::new();
+    // Maps position, texture coordinate and normal indices to a vertex of a mesh
+    let mut vertex_indices: HashMap<(usize, usize, Option<usize>, Option<usize>), u32> =
+        HashMap::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = match tokens.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(Vec3::from(parse_floats(args, path, line_number))),
+            "vt" => {
+                // OBJ has the origin of the texture coordinates in the bottom left corner
+                let [u, v] = parse_floats(args, path, line_number);
+                tex_coords.push(Vec2::new(u, 1.0 - v));
+            }
+            "vn" => normals.push(Vec3::from(parse_floats(args, path, line_number))),
+            "mtllib" => {
+                for library in args {
+                    let library_path = std::path::Path::new(path)
+                        .with_file_name(library)
+                        .to_string_lossy()
+                        .into_owned();
+                    materials.extend(read_mtl(&library_path));
+                }
+            }
+            "usemtl" => {
+                let name = args.first().copied().unwrap_or_default();
+                current_material = materials.iter().position(|material| material.name == name);
+                if current_material.is_none() {
+                    log::warn!("Material {} not found in {}", name, path);
+                }
+            }
+            "f" => {
+                let mesh_index = *mesh_indices.entry(current_material).or_insert_with(|| {
+                    meshes.push(ObjMesh {
+                        material: current_material,
+                        indices: vec![],
+                        vertices: vec![],
+                    });
+                    meshes.len() - 1
+                });
+                let mesh = &mut meshes[mesh_index];
+
+                let corners: Vec<u32> = args
+                    .iter()
+                    .map(|corner| {
+                        let mut parts = corner.split('/');
+                        let position = resolve_index(
+                            parts.next().unwrap_or_default(),
+                            positions.len(),
+                            path,
+                            line_number,
+                        );
+                        let tex_coord = parts
+                            .next()
+                            .filter(|part| !part.is_empty())
+                            .map(|part| resolve_index(part, tex_coords.len(), path, line_number));
+                        let normal = parts
+                            .next()
+                            .filter(|part| !part.is_empty())
+                            .map(|part| resolve_index(part, normals.len(), path, line_number));
+
+                        *vertex_indices
+                            .entry((mesh_index, position, tex_coord, normal))
+                            .or_insert_with(|| {
+                                mesh.vertices.push(Vertex {
+                                    pos: positions[position].extend(0.0),
+                                    normal: normal.map_or(Vec4::ZERO, |n| normals[n].extend(0.0)),
+                                    uv: tex_coord.map_or(Vec2::ZERO, |t| tex_coords[t]),
+                                    color: Vec4::ONE,
+                                    tangent: Vec4::ZERO,
+                                });
+                                mesh.vertices.len() as u32 - 1
+                            })
+                    })
+                    .collect();
+
+                // Polygons are triangulated as fans
+                for i in 1..corners.len().saturating_sub(1) {
+                    mesh.indices
+                        .extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Faces without normals get flat ones
+    for mesh in &mut meshes {
+        if mesh
+            .vertices
+            .iter()
+            .any(|vertex| vertex.normal == Vec4::ZERO)
+        {
+            generate_missing_normals(&mut mesh.indices, &mut mesh.vertices);
+        }
+    }
+
+    ObjData { meshes, materials }
+}
+
+pub fn load_obj(device: &Device, asset_manager: &mut AssetManager, path: &str) -> Model {
+    let data = decode_obj(path, asset_manager.optimize_meshes);
+    create_obj_model(device, asset_manager, &data, path)
+}
+
+pub fn create_obj_model(
+    device: &Device,
+    asset_manager: &mut AssetManager,
+    data: &ObjData,
+    path: &str,
+) -> Model {
+    puffin::profile_function!();
+
+    let mut model = Model {
+        meshes: vec![],
+        textures: vec![],
+        lights: vec![],
+        cameras: vec![],
+        skins: vec![],
+        animations: vec![],
+        nodes: SceneGraph::new(),
+    };
+
+    let node = model.nodes.add_node(path, Mat4::IDENTITY, None);
+
+    let materials: Vec<Material> = data
+        .materials
+        .iter()
+        .map(|material| load_material(device, asset_manager, material, &mut model.textures))
+        .collect();
+
+    for mesh in &data.meshes {
+        if mesh.indices.is_empty() {
+            continue;
+        }
+
+        let primitive =
+            asset_manager.mesh(device, path, mesh.indices.clone(), mesh.vertices.clone());
+
+        model.meshes.push(Mesh {
+            bounds: MeshBounds::from_vertices(&primitive.vertices),
+            world_bounds: MeshBounds::default(),
+            prev_world_transform: None,
+            primitive,
+            material: mesh
+                .material
+                .map_or_else(Material::default, |index| materials[index].clone()),
+            gpu_mesh: 0,
+            node,
+            deformation: None,
+            lod: 0,
+        });
+    }
+
+    model
+}
+
+// Adds the texture to the model textures and returns its index, missing and
+// unsupported files keep the default texture
+fn load_texture(
+    device: &Device,
+    asset_manager: &mut AssetManager,
+    path: &Option<String>,
+    color_space: ColorSpace,
+    textures: &mut Vec<Option<TextureHandle>>,
+) -> u32 {
+    match path {
+        Some(path) if std::path::Path::new(path).exists() => {
+            match asset_manager.load_texture(device, path, color_space) {
+                Ok(texture) => {
+                    textures.push(Some(texture));
+                    textures.len() as u32 - 1
+                }
+                Err(err) => {
+                    log::warn!("{}", err);
+                    DEFAULT_TEXTURE_MAP
+                }
+            }
+        }
+        Some(path) => {
+            log::warn!("Texture {} not found", path);
+            DEFAULT_TEXTURE_MAP
+        }
+        None => DEFAULT_TEXTURE_MAP,
+    }
+}
+
+// Packs the roughness and metallic maps into the green and blue channels like glTF.
+// Specular maps are used as inverted roughness when there is no roughness map.
+fn load_metallic_roughness_texture(
+    device: &Device,
+    asset_manager: &mut AssetManager,
+    material: &ObjMaterial,
+    textures: &mut Vec<Option<TextureHandle>>,
+) -> Option<u32> {
+    let open = |path: &Option<String>| {
+        let path = path.as_ref()?;
+        match image::open(path) {
+            Ok(image) => Some(image.to_luma8()),
+            Err(_err) => {
+                log::warn!("Texture {} not found", path);
+                None
+            }
+        }
+    };
+
+    let roughness = open(&material.roughness_map).or_else(|| {
+        open(&material.specular_map).map(|mut specular| {
+            image::imageops::invert(&mut specular);
+            specular
+        })
+    });
+    let metallic = open(&material.metallic_map);
+
+    let (width, height) = match (&roughness, &metallic) {
+        (None, None) => return None,
+        (Some(roughness), None) => roughness.dimensions(),
+        (None, Some(metallic)) => metallic.dimensions(),
+        (Some(roughness), Some(metallic)) => (
+            roughness.width().max(metallic.width()),
+            roughness.height().max(metallic.height()),
+        ),
+    };
+
+    let resize = |channel: Option<image::GrayImage>| {
+        channel.map(|channel| match channel.dimensions() == (width, height) {
+            true => channel,
+            false => image::imageops::resize(
+                &channel,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            ),
+        })
+    };
+
+    let roughness = resize(roughness);
+    let metallic = resize(metallic);
+
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        let sample = |channel: &Option<image::GrayImage>| {
+            channel
+                .as_ref()
+                .map_or(255, |channel| channel.get_pixel(x, y)[0])
+        };
+        image::Rgba([255, sample(&roughness), sample(&metallic), 255])
+    });
+
+    let data =
+        TextureData::from_image(&image::DynamicImage::ImageRgba8(packed), ColorSpace::Linear);
+    let key = AssetKey::from_texture_data(&material.name, &data);
+
+    match asset_manager.texture_from_data(device, &key, &data, &material.name) {
+        Ok(texture) => {
+            textures.push(Some(texture));
+            Some(textures.len() as u32 - 1)
+        }
+        Err(err) => {
+            log::warn!("{}", err);
+            None
+        }
+    }
+}
+
+fn load_material(
+    device: &Device,
+    asset_manager: &mut AssetManager,
+    material: &ObjMaterial,
+    textures: &mut Vec<Option<TextureHandle>>,
+) -> Material {
+    // Blinn-Phong specular exponent to roughness
+    let roughness = material
+        .roughness
+        .or_else(|| material.shininess.map(|ns| (2.0 / (ns + 2.0)).sqrt()))
+        .unwrap_or(0.5)
+        .clamp(0.0, 1.0);
+    let metallic = material.metallic.unwrap_or(0.0);
+
+    let mut result = Material {
+        diffuse_map: load_texture(
+            device,
+            asset_manager,
+            &material.diffuse_map,
+            ColorSpace::Srgb,
+            textures,
+        ),
+        normal_map: load_texture(
+            device,
+            asset_manager,
+            &material.normal_map,
+            ColorSpace::Linear,
+            textures,
+        ),
+        emissive_map: load_texture(
+            device,
+            asset_manager,
+            &material.emissive_map,
+            ColorSpace::Srgb,
+            textures,
+        ),
+        base_color_factor: material.diffuse.extend(material.dissolve),
+        metallic_factor: metallic,
+        roughness_factor: roughness,
+        emissive_factor: material.emission,
+        ..Default::default()
+    };
+
+    if let Some(texture) =
+        load_metallic_roughness_texture(device, asset_manager, material, textures)
+    {
+        result.metallic_roughness_map = texture;
+        // The factors scale the texture so they only keep their value when there is no map
+        if material.roughness_map.is_some() || material.specular_map.is_some() {
+            result.roughness_factor = 1.0;
+        }
+        if material.metallic_map.is_some() {
+            result.metallic_factor = 1.0;
+        }
+    }
+
+    if material.dissolve < 1.0 {
+        result.alpha_mode = AlphaMode::Blend;
+    }
+
+    // Emissive materials are light sources for the path tracer, e.g the ceiling light of
+    // the Cornell box
+    if material.emission.max_element() > 0.0 {
+        result.material_type = MaterialType::DiffuseLight;
+    } else if ILLUM_REFRACTION.contains(&material.illum) {
+        result.material_type = MaterialType::Dielectric;
+        result.material_property = material.optical_density.unwrap_or(1.5);
+    } else if ILLUM_REFLECTION.contains(&material.illum) || metallic >= 0.5 {
+        result.material_type = MaterialType::Metal;
+        result.material_property = roughness;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_floats_reads_the_first_n_tokens() {
+        assert_eq!(
+            parse_floats::<3>(&["1", "-2.5", "3e2", "4"], "test.obj", 0),
+            [1.0, -2.5, 300.0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid number on line 3")]
+    fn parse_floats_rejects_missing_values() {
+        parse_floats::<3>(&["1", "2"], "test.obj", 2);
+    }
+
+    #[test]
+    fn resolve_index_is_one_based() {
+        assert_eq!(resolve_index("1", 4, "test.obj", 0), 0);
+        assert_eq!(resolve_index("4", 4, "test.obj", 0), 3);
+    }
+
+    #[test]
+    fn resolve_index_counts_negative_indices_from_the_end() {
+        assert_eq!(resolve_index("-1", 4, "test.obj", 0), 3);
+        assert_eq!(resolve_index("-4", 4, "test.obj", 0), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid index 5")]
+    fn resolve_index_rejects_out_of_range_indices() {
+        resolve_index("5", 4, "test.obj", 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid index -5")]
+    fn resolve_index_rejects_out_of_range_negative_indices() {
+        resolve_index("-5", 4, "test.obj", 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid index 0")]
+    fn resolve_index_rejects_zero() {
+        resolve_index("0", 4, "test.obj", 0);
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0.5 2 0\nv 0 1 0\nvn 0 0 1\n\
+                   f 1//1 2//1 3//1 4//1 5//1\n";
+        let data = parse_obj(obj, "test.obj");
+
+        assert_eq!(data.meshes.len(), 1);
+        assert_eq!(data.meshes[0].indices, [0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn negative_face_indices_share_vertices_with_positive_ones() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   vn 0 0 1\n\
+                   f 1//1 2//1 3//1\n\
+                   f -4//-1 -2//-1 -1//-1\n";
+        let data = parse_obj(obj, "test.obj");
+        let mesh = &data.meshes[0];
+
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[3].pos, Vec4::new(0.0, 1.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices[3].normal, Vec4::new(0.0, 0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn texture_coordinates_are_flipped_vertically() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0.25 0.25\nf 1/1 2/1 3/1\n";
+        let data = parse_obj(obj, "test.obj");
+
+        assert_eq!(data.meshes[0].vertices[0].uv, Vec2::new(0.25, 0.75));
+    }
+
+    #[test]
+    fn only_missing_normals_are_generated() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+                   vn 0 1 0\n\
+                   f 1//1 2//1 3//1\n\
+                   f 1//1 3//1 4//1\n\
+                   f 1 4 5\n";
+        let data = parse_obj(obj, "test.obj");
+        let mesh = &data.meshes[0];
+
+        // The first vertex is used both with and without its normal
+        assert_eq!(mesh.vertices.len(), 7);
+        assert_eq!(mesh.indices[..6], [0, 1, 2, 0, 2, 3]);
+        for &index in &mesh.indices[..6] {
+            assert_eq!(
+                mesh.vertices[index as usize].normal,
+                Vec4::new(0.0, 1.0, 0.0, 0.0)
+            );
+        }
+        for &index in &mesh.indices[6..] {
+            assert_eq!(
+                mesh.vertices[index as usize].normal,
+                Vec4::new(1.0, 0.0, 0.0, 0.0)
+            );
+        }
+    }
+}
@@ -89,10 +89,10 @@ impl RenderPass {
                                 &textures[read.texture].texture,
                             );
                         } else if read.input_type == TextureResourceType::StorageImage {
-                            descriptor_set_read_resources.write_storage_image(
+                            descriptor_set_read_resources.write_storage_image_view(
                                 device,
                                 DescriptorIdentifier::Index(idx as u32),
-                                &textures[read.texture].texture.image,
+                                image_view(&textures[read.texture].texture.image, read.view),
                             );
                         }
                     }
@@ -200,10 +200,7 @@ impl RenderPass {
             .iter()
             .map(|image| {
                 vk::RenderingAttachmentInfo::builder()
-                    .image_view(match image.1 {
-                        ViewType::Full() => image.0.image_view,
-                        ViewType::Layer(layer) => image.0.layer_view(layer),
-                    })
+                    .image_view(image_view(&image.0, image.1))
                     .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                     .load_op(image.2)
                     .store_op(vk::AttachmentStoreOp::STORE)
@@ -218,10 +215,7 @@ impl RenderPass {
 
         let depth_attachment = if let Some(depth_attachment) = depth_attachment {
             vk::RenderingAttachmentInfo::builder()
-                .image_view(match depth_attachment.1 {
-                    ViewType::Full() => depth_attachment.0.image_view,
-                    ViewType::Layer(layer) => depth_attachment.0.layer_view(layer),
-                })
+                .image_view(image_view(&depth_attachment.0, depth_attachment.1))
                 .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                 .load_op(depth_attachment.2)
                 .store_op(vk::AttachmentStoreOp::STORE)
@@ -278,3 +272,11 @@ impl RenderPass {
         }
     }
 }
+
+fn image_view(image: &Image, view: ViewType) -> vk::ImageView {
+    match view {
+        ViewType::Full() => image.image_view,
+        ViewType::Layer(layer) => image.layer_view(layer),
+        ViewType::Mip(mip) => image.mip_view(mip),
+    }
+}
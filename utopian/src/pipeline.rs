@@ -12,6 +12,8 @@ pub struct PipelineDesc {
     pub raygen_path: Option<&'static str>,
     pub miss_path: Option<&'static str>,
     pub hit_path: Option<&'static str>,
+    // Optional, used for alpha tested geometry that is not forced opaque
+    pub any_hit_path: Option<&'static str>,
     pub vertex_input_binding_descriptions: Vec<vk::VertexInputBindingDescription>,
     pub vertex_input_attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
     pub color_attachment_formats: Vec<vk::Format>,
@@ -73,6 +75,7 @@ impl PartialEq for PipelineDesc {
             && self.raygen_path == other.raygen_path
             && self.miss_path == other.miss_path
             && self.hit_path == other.hit_path
+            && self.any_hit_path == other.any_hit_path
     }
 }
 
@@ -144,6 +147,7 @@ impl Pipeline {
                     desc.raygen_path.unwrap(),
                     desc.miss_path.unwrap(),
                     desc.hit_path.unwrap(),
+                    desc.any_hit_path,
                     bindless_descriptor_set_layout,
                 ),
             }
@@ -417,6 +421,7 @@ impl Pipeline {
         raygen_shader_path: &str,
         miss_shader_path: &str,
         closest_hit_shader_path: &str,
+        any_hit_shader_path: Option<&str>,
         bindless_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
     ) -> Result<
         (
@@ -430,13 +435,18 @@ impl Pipeline {
         let raygen_spv_file = shader::compile_glsl_shader(raygen_shader_path)?;
         let miss_spv_file = shader::compile_glsl_shader(miss_shader_path)?;
         let closest_hit_spv_file = shader::compile_glsl_shader(closest_hit_shader_path)?;
+        let any_hit_spv_file = any_hit_shader_path
+            .map(shader::compile_glsl_shader)
+            .transpose()?;
 
         let raygen_spv_file = raygen_spv_file.as_binary_u8();
         let miss_spv_file = miss_spv_file.as_binary_u8();
         let closest_hit_spv_file = closest_hit_spv_file.as_binary_u8();
+        let any_hit_spv_file = any_hit_spv_file.as_ref().map(|spv| spv.as_binary_u8());
 
-        let reflection =
-            shader::Reflection::new(&[raygen_spv_file, miss_spv_file, closest_hit_spv_file]);
+        let mut spv_files = vec![raygen_spv_file, miss_spv_file, closest_hit_spv_file];
+        spv_files.extend(any_hit_spv_file);
+        let reflection = shader::Reflection::new(&spv_files);
         let (pipeline_layout, descriptor_set_layouts, _) = shader::create_layouts_from_reflection(
             device,
             &reflection,
@@ -453,7 +463,7 @@ impl Pipeline {
             crate::shader::create_shader_module(closest_hit_spv_file, device);
 
         let shader_entry_name = c"main";
-        let shader_stage_create_infos = vec![
+        let mut shader_stage_create_infos = vec![
             vk::PipelineShaderStageCreateInfo {
                 module: raygen_shader_module,
                 p_name: shader_entry_name.as_ptr(),
@@ -474,6 +484,17 @@ impl Pipeline {
             },
         ];
 
+        if let Some(any_hit_spv_file) = any_hit_spv_file {
+            let any_hit_shader_module =
+                crate::shader::create_shader_module(Cursor::new(any_hit_spv_file), device);
+            shader_stage_create_infos.push(vk::PipelineShaderStageCreateInfo {
+                module: any_hit_shader_module,
+                p_name: shader_entry_name.as_ptr(),
+                stage: vk::ShaderStageFlags::ANY_HIT_KHR,
+                ..Default::default()
+            });
+        }
+
         Ok((
             shader_stage_create_infos,
             reflection,
@@ -487,6 +508,12 @@ impl Pipeline {
         shader_stage_create_infos: Vec<vk::PipelineShaderStageCreateInfo>,
         pipeline_layout: vk::PipelineLayout,
     ) -> vk::Pipeline {
+        // The any hit shader is the optional fourth stage, see create_raytracing_shader_modules
+        let any_hit_shader = match shader_stage_create_infos.len() {
+            4 => 3,
+            _ => ash::vk::SHADER_UNUSED_KHR,
+        };
+
         let shader_group_create_infos = [
             ash::vk::RayTracingShaderGroupCreateInfoKHR::builder()
                 .ty(ash::vk::RayTracingShaderGroupTypeKHR::GENERAL)
@@ -506,7 +533,7 @@ impl Pipeline {
                 .ty(ash::vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
                 .general_shader(ash::vk::SHADER_UNUSED_KHR) // Todo: not hardcode like this
                 .closest_hit_shader(2)
-                .any_hit_shader(ash::vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(any_hit_shader)
                 .intersection_shader(ash::vk::SHADER_UNUSED_KHR)
                 .build(),
         ];
@@ -614,6 +641,7 @@ impl PipelineDescBuilder {
                 raygen_path: None,
                 miss_path: None,
                 hit_path: None,
+                any_hit_path: None,
                 vertex_input_binding_descriptions: Vec::new(),
                 vertex_input_attribute_descriptions: Vec::new(),
                 color_attachment_formats: Vec::new(),
@@ -652,6 +680,11 @@ impl PipelineDescBuilder {
         self
     }
 
+    pub fn any_hit_path(mut self, path: &'static str) -> Self {
+        self.desc.any_hit_path = Some(path);
+        self
+    }
+
     pub fn vertex_input_binding_descriptions(
         mut self,
         descriptions: Vec<vk::VertexInputBindingDescription>,
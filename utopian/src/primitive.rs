@@ -4,8 +4,37 @@ use std::mem;
 
 use crate::buffer::*;
 use crate::device::*;
+use crate::lod::MeshLod;
 use crate::offset_of;
 
+/// How the vertices of a primitive are stored in its vertex buffer. The compact layouts
+/// store normals and tangents as 16-bit snorm and texture coordinates as half floats,
+/// and only store the color when the mesh has vertex colors.
+/// Must match the VERTEX_LAYOUT_* constants in bindless.glsl
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum VertexLayout {
+    // `Vertex` as is, the skinning pass reads and writes this layout
+    Full = 0,
+    Compact = 1,
+    CompactColor = 2,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CompactVertex {
+    pos: [f32; 3],
+    normal: [i16; 4],
+    tangent: [i16; 4],
+    uv: [half::f16; 2],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CompactColorVertex {
+    vertex: CompactVertex,
+    color: [u8; 4],
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Vertex {
@@ -21,6 +50,72 @@ pub struct Primitive {
     pub vertex_buffer: Buffer,
     pub indices: Vec<u32>,
     pub vertices: Vec<Vertex>,
+    pub vertex_layout: VertexLayout,
+    // 16-bit when all vertices can be indexed with it
+    pub index_type: vk::IndexType,
+    // Ranges of `index_buffer` from full to the lowest detail, the first one is `indices`
+    pub lods: Vec<MeshLod>,
+    // The level of detail that ray tracing acceleration structures are built from
+    pub blas_lod: usize,
+}
+
+fn snorm16(value: Vec4) -> [i16; 4] {
+    value
+        .to_array()
+        .map(|x| (x.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+}
+
+fn compact_vertex(vertex: &Vertex) -> CompactVertex {
+    CompactVertex {
+        pos: vertex.pos.truncate().to_array(),
+        normal: snorm16(vertex.normal),
+        tangent: snorm16(vertex.tangent),
+        uv: vertex.uv.to_array().map(half::f16::from_f32),
+    }
+}
+
+fn create_vertex_buffer<T: Copy>(device: &Device, vertices: &[T]) -> Buffer {
+    Buffer::new(
+        device,
+        Some(vertices),
+        std::mem::size_of_val(vertices) as u64,
+        vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    )
+}
+
+fn create_index_buffer<T: Copy>(device: &Device, indices: &[T]) -> Buffer {
+    Buffer::new(
+        device,
+        Some(indices),
+        std::mem::size_of_val(indices) as u64,
+        vk::BufferUsageFlags::INDEX_BUFFER
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    )
+}
+
+impl VertexLayout {
+    /// The smallest layout that can store `vertices` without dropping their colors
+    pub fn compact_for(vertices: &[Vertex]) -> VertexLayout {
+        match vertices.iter().all(|vertex| vertex.color == Vec4::ONE) {
+            true => VertexLayout::Compact,
+            false => VertexLayout::CompactColor,
+        }
+    }
+
+    pub fn stride(self) -> u32 {
+        match self {
+            VertexLayout::Full => mem::size_of::<Vertex>() as u32,
+            VertexLayout::Compact => mem::size_of::<CompactVertex>() as u32,
+            VertexLayout::CompactColor => mem::size_of::<CompactColorVertex>() as u32,
+        }
+    }
 }
 
 impl Vertex {
@@ -36,28 +131,77 @@ impl Vertex {
 }
 
 impl Primitive {
-    pub fn new(device: &Device, indices: Vec<u32>, vertices: Vec<Vertex>) -> Primitive {
-        let index_buffer = Buffer::new(
-            device,
-            Some(indices.as_slice()),
-            std::mem::size_of_val(&*indices) as u64,
-            vk::BufferUsageFlags::INDEX_BUFFER
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            gpu_allocator::MemoryLocation::GpuOnly,
-        );
-
-        let vertex_buffer = Buffer::new(
-            device,
-            Some(vertices.as_slice()),
-            std::mem::size_of_val(&*vertices) as u64,
-            vk::BufferUsageFlags::VERTEX_BUFFER
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            gpu_allocator::MemoryLocation::GpuOnly,
-        );
+    pub fn new(
+        device: &Device,
+        indices: Vec<u32>,
+        vertices: Vec<Vertex>,
+        vertex_layout: VertexLayout,
+    ) -> Primitive {
+        Primitive::with_lods(device, indices, vec![], vertices, vertex_layout)
+    }
+
+    /// `lod_indices` are the simplified index buffers after the full detail `indices`
+    /// together with their error, they are all stored in the same index buffer
+    pub fn with_lods(
+        device: &Device,
+        indices: Vec<u32>,
+        lod_indices: Vec<(Vec<u32>, f32)>,
+        vertices: Vec<Vertex>,
+        vertex_layout: VertexLayout,
+    ) -> Primitive {
+        let index_type = match vertices.len() <= 1 << 16 {
+            true => vk::IndexType::UINT16,
+            false => vk::IndexType::UINT32,
+        };
+
+        let mut lods = vec![MeshLod {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            error: 0.0,
+        }];
+        let mut all_indices = indices.clone();
+
+        for (lod_indices, error) in lod_indices {
+            lods.push(MeshLod {
+                first_index: all_indices.len() as u32,
+                index_count: lod_indices.len() as u32,
+                error,
+            });
+            all_indices.extend(lod_indices);
+        }
+
+        let index_buffer = match index_type {
+            vk::IndexType::UINT16 => {
+                let mut indices_u16: Vec<u16> = all_indices.iter().map(|&i| i as u16).collect();
+                // Shaders read the indices as 32-bit words
+                if indices_u16.len() % 2 == 1 {
+                    indices_u16.push(0);
+                }
+                create_index_buffer(device, &indices_u16)
+            }
+            _ => create_index_buffer(device, &all_indices),
+        };
+
+        let vertex_buffer = match vertex_layout {
+            VertexLayout::Full => create_vertex_buffer(device, &vertices),
+            VertexLayout::Compact => {
+                let compact: Vec<CompactVertex> = vertices.iter().map(compact_vertex).collect();
+                create_vertex_buffer(device, &compact)
+            }
+            VertexLayout::CompactColor => {
+                let compact: Vec<CompactColorVertex> = vertices
+                    .iter()
+                    .map(|vertex| CompactColorVertex {
+                        vertex: compact_vertex(vertex),
+                        color: vertex
+                            .color
+                            .to_array()
+                            .map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8),
+                    })
+                    .collect();
+                create_vertex_buffer(device, &compact)
+            }
+        };
 
         // Todo: device local index and vertex buffers
 
@@ -66,6 +210,21 @@ impl Primitive {
             vertex_buffer,
             indices,
             vertices,
+            vertex_layout,
+            index_type,
+            lods,
+            blas_lod: 0,
+        }
+    }
+
+    pub fn lod(&self, lod: usize) -> &MeshLod {
+        &self.lods[lod.min(self.lods.len() - 1)]
+    }
+
+    pub fn index_size(&self) -> u32 {
+        match self.index_type {
+            vk::IndexType::UINT16 => 2,
+            _ => 4,
         }
     }
 
@@ -1,6 +1,5 @@
 use ash::vk;
-use glam::Mat3;
-use std::mem;
+use glam::{Mat3, Mat4};
 
 use crate::buffer::*;
 use crate::descriptor_set::DescriptorIdentifier;
@@ -9,22 +8,30 @@ use crate::device::*;
 use crate::image::*;
 use crate::primitive::*;
 use crate::renderer::*;
+use crate::scene_graph::*;
 
 // The buffer sizes exactly fits the instance transform for all objects
 // added to the scene when `Raytracing::initialize` is called.
 // Todo: support adding/removing objects to the scene
 pub struct Tlas {
     pub handle: vk::AccelerationStructureKHR,
+    buffer: Buffer,
     instances_buffer: Buffer,
     scratch_buffer: Buffer,
     // `Buffer::update_memory` creates a temporary staging buffer which is expensive
     // so we have a persistent staging buffer here for now
     staging_instances_buffer: Buffer,
+    // CPU copy of the instance array so that only the instances with
+    // modified transforms needs to be updated
+    acceleration_instances: Vec<vk::AccelerationStructureInstanceKHR>,
 }
 
 pub struct Raytracing {
     pub top_level_acceleration: Option<Tlas>,
     bottom_level_accelerations: Vec<vk::AccelerationStructureKHR>,
+    blas_buffers: Vec<Buffer>,
+    // Only allocated for the BLAS of deformed meshes which are refitted every frame
+    blas_update_scratch_buffers: Vec<Option<Buffer>>,
     output_image: Image,
     _accumulation_image: Image,
     pipeline: crate::Pipeline,
@@ -75,23 +82,45 @@ impl Raytracing {
             &accumulation_image,
         );
 
-        Raytracing {
+        let mut raytracing = Raytracing {
             top_level_acceleration: None,
             bottom_level_accelerations: vec![],
+            blas_buffers: vec![],
+            blas_update_scratch_buffers: vec![],
             output_image,
             _accumulation_image: accumulation_image,
             pipeline,
             descriptor_set,
             screen_size,
-        }
+        };
+
+        // There is always a TLAS to bind, it is empty until the first scene has loaded
+        raytracing.initialize(device, &[], &SceneGraph::new());
+
+        raytracing
     }
 
-    pub fn initialize(&mut self, device: &Device, instances: &[ModelInstance]) {
+    pub fn initialize(
+        &mut self,
+        device: &Device,
+        instances: &[ModelInstance],
+        scene_graph: &SceneGraph,
+    ) {
+        // The previous acceleration structures can still be used by frames in flight
+        unsafe { device.handle.device_wait_idle().unwrap() };
+        self.destroy_acceleration_structures(device);
+
         for instance in instances {
             for mesh in &instance.model.meshes {
-                self.bottom_level_accelerations.push(
-                    Raytracing::create_bottom_level_acceleration_structure(device, &mesh.primitive),
-                );
+                let (blas, blas_buffer, update_scratch_buffer) =
+                    Raytracing::create_bottom_level_acceleration_structure(
+                        device,
+                        &mesh.primitive,
+                        mesh.deformation.is_some(),
+                    );
+                self.bottom_level_accelerations.push(blas);
+                self.blas_buffers.push(blas_buffer);
+                self.blas_update_scratch_buffers.push(update_scratch_buffer);
             }
         }
 
@@ -99,6 +128,7 @@ impl Raytracing {
             device,
             &self.bottom_level_accelerations,
             instances,
+            scene_graph,
         );
 
         self.descriptor_set.write_acceleration_structure(
@@ -110,10 +140,43 @@ impl Raytracing {
         self.top_level_acceleration = Some(tlas);
     }
 
-    pub fn create_bottom_level_acceleration_structure(
+    /// Note: the acceleration structures must not be used by any frames in flight
+    fn destroy_acceleration_structures(&mut self, device: &Device) {
+        for blas in self.bottom_level_accelerations.drain(..) {
+            unsafe {
+                device
+                    .acceleration_structure_ext
+                    .destroy_acceleration_structure(blas, None)
+            };
+        }
+
+        self.blas_buffers
+            .drain(..)
+            .chain(self.blas_update_scratch_buffers.drain(..).flatten())
+            .for_each(|buffer| buffer.destroy(device));
+
+        if let Some(tlas) = self.top_level_acceleration.take() {
+            unsafe {
+                device
+                    .acceleration_structure_ext
+                    .destroy_acceleration_structure(tlas.handle, None)
+            };
+
+            for buffer in [
+                tlas.buffer,
+                tlas.instances_buffer,
+                tlas.scratch_buffer,
+                tlas.staging_instances_buffer,
+            ] {
+                buffer.destroy(device);
+            }
+        }
+    }
+
+    fn blas_geometry(
         device: &Device,
         primitive: &Primitive,
-    ) -> vk::AccelerationStructureKHR {
+    ) -> vk::AccelerationStructureGeometryKHR {
         let vertex_buffer_device_address = vk::DeviceOrHostAddressConstKHR {
             device_address: primitive.vertex_buffer.get_device_address(device),
         };
@@ -121,29 +184,51 @@ impl Raytracing {
             device_address: primitive.index_buffer.get_device_address(device),
         };
 
-        let geometry = vk::AccelerationStructureGeometryKHR::builder()
+        vk::AccelerationStructureGeometryKHR::builder()
             .flags(vk::GeometryFlagsKHR::OPAQUE)
             .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
             .geometry(vk::AccelerationStructureGeometryDataKHR {
                 triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
                     .vertex_format(vk::Format::R32G32B32_SFLOAT)
                     .vertex_data(vertex_buffer_device_address)
-                    .vertex_stride(mem::size_of::<Vertex>() as _)
+                    .vertex_stride(primitive.vertex_layout.stride() as _)
                     .max_vertex(primitive.vertices.len() as _)
-                    .index_type(vk::IndexType::UINT32)
+                    .index_type(primitive.index_type)
                     .index_data(index_buffer_device_address)
                     .build(),
             })
-            .build();
+            .build()
+    }
+
+    fn blas_flags(allow_update: bool) -> vk::BuildAccelerationStructureFlagsKHR {
+        // Todo: PREFER_FAST_BUILD might be a better fit for meshes that deform a lot
+        match allow_update {
+            true => {
+                vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                    | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE
+            }
+            false => vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+        }
+    }
+
+    /// Returns the BLAS and the buffer that stores it together with the scratch buffer
+    /// used for refitting it when `allow_update` is set
+    pub fn create_bottom_level_acceleration_structure(
+        device: &Device,
+        primitive: &Primitive,
+        allow_update: bool,
+    ) -> (vk::AccelerationStructureKHR, Buffer, Option<Buffer>) {
+        let geometry = Self::blas_geometry(device, primitive);
 
         // Get size info
         let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .flags(ash::vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(Self::blas_flags(allow_update))
             .geometries(std::slice::from_ref(&geometry))
             .build();
 
-        let num_triangles = (primitive.indices.len() / 3) as u32;
+        let blas_lod = primitive.lod(primitive.blas_lod);
+        let num_triangles = blas_lod.index_count / 3;
 
         let build_sizes = unsafe {
             device
@@ -188,7 +273,7 @@ impl Raytracing {
 
         let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
             .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .flags(ash::vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(Self::blas_flags(allow_update))
             .geometries(std::slice::from_ref(&geometry))
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
             .dst_acceleration_structure(acceleration_structure)
@@ -199,6 +284,7 @@ impl Raytracing {
 
         let build_range_info = vec![ash::vk::AccelerationStructureBuildRangeInfoKHR::builder()
             .primitive_count(num_triangles)
+            .primitive_offset(blas_lod.first_index * primitive.index_size())
             .build()];
 
         unsafe {
@@ -213,39 +299,111 @@ impl Raytracing {
             });
         }
 
-        acceleration_structure
+        // The build has finished since `execute_and_submit` waits for the device
+        scratch_buffer.destroy(device);
+
+        let update_scratch_buffer = allow_update.then(|| {
+            Buffer::new::<u8>(
+                device,
+                None,
+                build_sizes.update_scratch_size,
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_allocator::MemoryLocation::GpuOnly,
+            )
+        });
+
+        (acceleration_structure, blas_buffer, update_scratch_buffer)
+    }
+
+    /// Refits the BLAS of all deformed meshes to the vertices written by the skinning pass
+    pub fn refit_deformed_blas(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        instances: &[ModelInstance],
+    ) {
+        puffin::profile_function!();
+
+        // The order of the meshes matches `initialize`
+        let meshes = instances
+            .iter()
+            .flat_map(|instance| instance.model.meshes.iter());
+
+        for ((mesh, blas), scratch_buffer) in meshes
+            .zip(&self.bottom_level_accelerations)
+            .zip(&self.blas_update_scratch_buffers)
+        {
+            let scratch_buffer = match scratch_buffer {
+                Some(scratch_buffer) => scratch_buffer,
+                None => continue,
+            };
+
+            let geometry = Self::blas_geometry(device, &mesh.primitive);
+
+            let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+                .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                .flags(Self::blas_flags(true))
+                .geometries(std::slice::from_ref(&geometry))
+                .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+                .src_acceleration_structure(*blas)
+                .dst_acceleration_structure(*blas)
+                .scratch_data(vk::DeviceOrHostAddressKHR {
+                    device_address: scratch_buffer.get_device_address(device),
+                })
+                .build();
+
+            let blas_lod = mesh.primitive.lod(mesh.primitive.blas_lod);
+            let build_range_info = vec![ash::vk::AccelerationStructureBuildRangeInfoKHR::builder()
+                .primitive_count(blas_lod.index_count / 3)
+                .primitive_offset(blas_lod.first_index * mesh.primitive.index_size())
+                .build()];
+
+            unsafe {
+                device
+                    .acceleration_structure_ext
+                    .cmd_build_acceleration_structures(
+                        command_buffer,
+                        std::slice::from_ref(&build_geometry_info),
+                        std::slice::from_ref(&build_range_info.as_slice()),
+                    );
+            }
+        }
+    }
+
+    fn instance_transform(world_matrix: Mat4) -> vk::TransformMatrixKHR {
+        let (scale, rotation, translation) = world_matrix.to_scale_rotation_translation();
+        let rotation_matrix = Mat3::from_quat(rotation);
+
+        vk::TransformMatrixKHR {
+            matrix: [
+                rotation_matrix.x_axis.x * scale.x,
+                rotation_matrix.y_axis.x * scale.y,
+                rotation_matrix.z_axis.x * scale.z,
+                translation.x,
+                rotation_matrix.x_axis.y * scale.x,
+                rotation_matrix.y_axis.y * scale.y,
+                rotation_matrix.z_axis.y * scale.z,
+                translation.y,
+                rotation_matrix.x_axis.z * scale.x,
+                rotation_matrix.y_axis.z * scale.y,
+                rotation_matrix.z_axis.z * scale.z,
+                translation.z,
+            ],
+        }
     }
 
     pub fn fill_instance_array(
         device: &Device,
         blas: &[vk::AccelerationStructureKHR],
         instances: &[ModelInstance],
+        scene_graph: &SceneGraph,
     ) -> Vec<vk::AccelerationStructureInstanceKHR> {
         let mut acceleration_instances: Vec<vk::AccelerationStructureInstanceKHR> = vec![];
         let mut blas_idx = 0;
 
         for instance in instances {
-            for (i, mesh) in instance.model.meshes.iter().enumerate() {
-                let world_matrix = instance.transform * instance.model.transforms[i];
-                let (scale, rotation, translation) = world_matrix.to_scale_rotation_translation();
-                let rotation_matrix = Mat3::from_quat(rotation);
-
-                let transform = vk::TransformMatrixKHR {
-                    matrix: [
-                        rotation_matrix.x_axis.x * scale.x,
-                        rotation_matrix.y_axis.x * scale.y,
-                        rotation_matrix.z_axis.x * scale.z,
-                        translation.x,
-                        rotation_matrix.x_axis.y * scale.x,
-                        rotation_matrix.y_axis.y * scale.y,
-                        rotation_matrix.z_axis.y * scale.z,
-                        translation.y,
-                        rotation_matrix.x_axis.z * scale.x,
-                        rotation_matrix.y_axis.z * scale.y,
-                        rotation_matrix.z_axis.z * scale.z,
-                        translation.z,
-                    ],
-                };
+            for mesh in &instance.model.meshes {
+                let transform = Self::instance_transform(scene_graph.world_transform(mesh.node));
 
                 let blas_device_address = unsafe {
                     device
@@ -257,6 +415,13 @@ impl Raytracing {
                         )
                 };
 
+                // Alpha tested and blended meshes run the any hit shader of the pipelines
+                // that have one, the geometry is opaque otherwise
+                let mut flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE;
+                if mesh.material.alpha_mode != crate::gltf_loader::AlphaMode::Opaque {
+                    flags |= vk::GeometryInstanceFlagsKHR::FORCE_NO_OPAQUE;
+                }
+
                 acceleration_instances.push(vk::AccelerationStructureInstanceKHR {
                     transform,
                     acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
@@ -265,7 +430,7 @@ impl Raytracing {
                     instance_custom_index_and_mask: vk::Packed24_8::new(mesh.gpu_mesh, 0xff),
                     instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
                         0,
-                        vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+                        flags.as_raw() as u8,
                     ),
                 });
 
@@ -280,13 +445,20 @@ impl Raytracing {
         device: &Device,
         blas: &[vk::AccelerationStructureKHR],
         instances: &[ModelInstance],
+        scene_graph: &SceneGraph,
     ) -> Tlas {
-        let acceleration_instances = Self::fill_instance_array(device, blas, instances);
+        let acceleration_instances =
+            Self::fill_instance_array(device, blas, instances, scene_graph);
+
+        // Room for at least one instance so that an empty scene still gets a valid TLAS,
+        // for example while the models are loading
+        let instances_size = (std::mem::size_of::<vk::AccelerationStructureInstanceKHR>()
+            * acceleration_instances.len().max(1)) as u64;
 
         let instances_buffer = Buffer::new(
             device,
             Some(acceleration_instances.as_slice()),
-            std::mem::size_of_val(&*acceleration_instances) as u64,
+            instances_size,
             vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                 | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
             gpu_allocator::MemoryLocation::GpuOnly,
@@ -294,7 +466,7 @@ impl Raytracing {
 
         let staging_instances_buffer = Buffer::create_buffer(
             device,
-            std::mem::size_of_val(&*acceleration_instances) as u64,
+            instances_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
             gpu_allocator::MemoryLocation::CpuToGpu,
         );
@@ -391,9 +563,11 @@ impl Raytracing {
 
         Tlas {
             handle: acceleration_structure,
+            buffer: tlas_buffer,
             instances_buffer,
             scratch_buffer,
             staging_instances_buffer,
+            acceleration_instances,
         }
     }
 
@@ -402,16 +576,28 @@ impl Raytracing {
         device: &Device,
         command_buffer: vk::CommandBuffer,
         instances: &[ModelInstance],
+        scene_graph: &SceneGraph,
     ) {
         puffin::profile_function!();
 
-        let acceleration_instances =
-            Self::fill_instance_array(device, &self.bottom_level_accelerations, instances);
-
         let tlas = self.top_level_acceleration.as_mut().unwrap();
 
+        // Only the instances whose world transform changed since the last rebuild are updated,
+        // the order of the instances matches `fill_instance_array`. Models that were added
+        // after `initialize` are not in the TLAS yet.
+        let meshes = instances
+            .iter()
+            .flat_map(|instance| instance.model.meshes.iter());
+
+        for (mesh, acceleration_instance) in meshes.zip(&mut tlas.acceleration_instances) {
+            if scene_graph.is_changed(mesh.node) {
+                acceleration_instance.transform =
+                    Self::instance_transform(scene_graph.world_transform(mesh.node));
+            }
+        }
+
         tlas.staging_instances_buffer
-            .update_memory(device, acceleration_instances.as_slice());
+            .update_memory(device, tlas.acceleration_instances.as_slice());
 
         tlas.staging_instances_buffer.copy_to_buffer(
             device,
@@ -444,7 +630,7 @@ impl Raytracing {
             .build();
 
         let build_range_info = vec![ash::vk::AccelerationStructureBuildRangeInfoKHR::builder()
-            .primitive_count(acceleration_instances.len() as u32)
+            .primitive_count(tlas.acceleration_instances.len() as u32)
             .build()];
 
         unsafe {
@@ -1,10 +1,24 @@
+use crate::animation::AnimationPlayer;
+use crate::bounds::Frustum;
+use crate::gltf_loader::Material;
+use crate::lod::LodSelection;
+use crate::model_loader::generate_uv_sphere;
+use crate::renderers::bloom::BloomSettings;
+use crate::renderers::present::TonemapSettings;
 use crate::*;
 use ash::vk;
 use glam::{Vec3, Vec4};
+use std::cell::Cell;
 
-pub const MAX_NUM_GPU_MATERIALS: usize = 1024;
-pub const MAX_NUM_GPU_MESHES: usize = 1024;
+// Initial size of the material, mesh and instance buffers, they grow with the scene
+pub const INITIAL_GPU_MESH_CAPACITY: usize = 1024;
 pub const MAX_NUM_GPU_LIGHTS: usize = 1024;
+// Every frame in flight has its own part of the instance buffer
+pub const MAX_NUM_FRAMES_IN_FLIGHT: usize = 4;
+// Must match INDIRECT_DRAW_MESH_INDEX in mesh_draw.glsl
+pub const INDIRECT_DRAW_MESH_INDEX: u32 = u32::MAX;
+// Must match NO_INSTANCE_INDEX in mesh_draw.glsl
+pub const NO_INSTANCE_INDEX: u32 = u32::MAX;
 
 /// All shaders share these common descriptor set indexes
 /// Every custom shader descriptor set needs to be starting from index 3
@@ -12,9 +26,23 @@ pub const DESCRIPTOR_SET_INDEX_BINDLESS: u32 = 0;
 pub const DESCRIPTOR_SET_INDEX_VIEW: u32 = 1;
 pub const DESCRIPTOR_SET_INDEX_INPUT_TEXTURES: u32 = 2;
 
+/// Meshes drawn and culled by `Renderer::draw_meshes` during the last recorded frame,
+/// summed over all passes. The GPU-driven draws are culled on the GPU and not counted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullingStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
 pub struct ModelInstance {
     pub model: Model,
-    pub transform: glam::Mat4,
+    // Root node in the renderer scene graph that all nodes of the model are attached to
+    pub root: NodeId,
+    pub animation_player: AnimationPlayer,
+    // Every model texture, None until it has been streamed in
+    textures: Vec<Option<TextureHandle>>,
+    // Bindless vertex buffer slots of the mesh deformation buffers
+    deformation_buffer_slots: Vec<u32>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -27,12 +55,24 @@ struct GpuMaterial {
     base_color_factor: Vec4,
     metallic_factor: f32,
     roughness_factor: f32,
-    padding: [f32; 2],
+    alpha_cutoff: f32,
+    alpha_mode: u32,
 
     // Ray tracing properties
     // x = type (0 = lambertian, 1 = metal, 2 = dielectric, 3 = diffuse light)
     // y = metal -> fuzz, dielectric -> index of refractions
     raytrace_properties: Vec4,
+
+    emissive_map: u32,
+    double_sided: u32,
+    transmission_factor: f32,
+    ior: f32,
+    emissive_factor: Vec4,
+    clearcoat_factor: f32,
+    clearcoat_roughness_factor: f32,
+    padding: [f32; 2],
+    // Two rows of a 2x3 matrix per texture
+    uv_transforms: [Vec4; 10],
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -41,21 +81,115 @@ struct GpuMesh {
     vertex_buffer: u32,
     index_buffer: u32,
     material: u32,
+    vertex_layout: u32,
+    index_size: u32,
+    // The BLAS is built from this part of the index buffer, see `Primitive::blas_lod`
+    blas_first_triangle: u32,
 }
 
+// Must match Instance in bindless.glsl
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
+struct GpuInstance {
+    world: glam::Mat4,
+    prev_world: glam::Mat4,
+    aabb_min: Vec3,
+    mesh: u32,
+    aabb_max: Vec3,
+    // Of the selected level of detail in the index buffer of the mesh
+    first_index: u32,
+    index_count: u32,
+    cullable: u32,
+    pad: [u32; 2],
+}
+
+// Bindless vertex buffer array indices of the buffers used by the skinning pass,
+// must match the push constants in skinning.comp
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct GpuDeformation {
+    pub rest_vertex_buffer: u32,
+    pub vertex_buffer: u32,
+    pub skin_vertex_buffer: u32,
+    pub morph_target_buffer: u32,
+    pub joint_matrices_buffer: u32,
+    pub morph_weights_buffer: u32,
+    pub num_vertices: u32,
+    pub num_morph_targets: u32,
+    pub skinned: u32,
+    // Offsets to the copies of the frame being recorded, see `update_deformations`
+    pub first_joint_matrix: u32,
+    pub first_morph_weight: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
 struct GpuLight {
     color: Vec4,
     position: Vec3,
     range: f32,
     direction: Vec3,
-    spot: f32,
-    attenuation: Vec3,
     light_type: f32,
-    intensity: Vec3,
+    intensity: Vec3, // color * intensity in physical units
     id: f32,
-    paddding: Vec4,
+    // spot light: x = cos(inner cone angle), y = cos(outer cone angle)
+    // rect area light: x = width, y = height
+    // disk area light: x = radius
+    params: Vec4,
+    tangent: Vec4,
+}
+
+impl GpuLight {
+    fn new(light: &Light, id: u32) -> GpuLight {
+        let mut params = Vec4::ZERO;
+        let mut tangent = Vec4::ZERO;
+
+        match *light {
+            Light::Point { .. } => {}
+            Light::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+                ..
+            } => {
+                params.x = inner_cone_angle.cos();
+                params.y = outer_cone_angle.cos();
+            }
+            Light::RectArea {
+                direction,
+                tangent: t,
+                width,
+                height,
+                ..
+            } => {
+                // Make sure that the tangent is orthogonal to the light direction
+                let direction = direction.normalize();
+                let t = (t - direction * t.dot(direction)).normalize();
+                tangent = t.extend(0.0);
+                params.x = width;
+                params.y = height;
+            }
+            Light::DiskArea {
+                direction, radius, ..
+            } => {
+                tangent = direction.normalize().any_orthonormal_vector().extend(0.0);
+                params.x = radius;
+            }
+            Light::Directional { .. } => {}
+        }
+
+        GpuLight {
+            color: light.color().extend(0.0),
+            position: light.position(),
+            // Both the light culling and the shading falloff use it
+            range: light.effective_range(),
+            direction: light.direction(),
+            light_type: light.light_type() as f32,
+            intensity: light.color() * light.intensity(),
+            id: id as f32,
+            params,
+            tangent,
+        }
+    }
 }
 
 pub struct Renderer {
@@ -63,22 +197,41 @@ pub struct Renderer {
     pub bindless_descriptor_set_layout: vk::DescriptorSetLayout,
     pub bindless_descriptor_set: vk::DescriptorSet,
     pub instances: Vec<ModelInstance>,
+    pub scene_graph: SceneGraph,
+    pub asset_manager: AssetManager,
+    // Drawn by the atmosphere pass
+    pub sky_sphere: MeshHandle,
     gpu_materials_buffer: Buffer,
     gpu_meshes_buffer: Buffer,
     gpu_lights_buffer: Buffer,
+    gpu_instances_buffer: Buffer,
+    // Number of meshes that the material, mesh and instance buffers have room for
+    gpu_mesh_capacity: usize,
     gpu_materials: Vec<GpuMaterial>,
     gpu_meshes: Vec<GpuMesh>,
     gpu_lights: Vec<GpuLight>,
+    gpu_deformations: Vec<GpuDeformation>,
+    // Textures that were uploaded after their model was added
+    default_textures: Vec<TextureHandle>,
     default_diffuse_map_index: u32,
     default_normal_map_index: u32,
     default_occlusion_map_index: u32,
     default_metallic_roughness_map_index: u32,
-    next_bindless_image_index: u32,
-    next_bindless_vertex_buffer_index: u32,
-    next_bindless_index_buffer_index: u32,
 
     // This should probably be somewhere else
     pub need_environment_map_update: bool,
+    pub lod_selection: LodSelection,
+    pub frustum_culling_enabled: bool,
+    // Updated by the passes while they record their draws
+    culling_stats: Cell<CullingStats>,
+    // Draw with the commands written by the culling passes instead of `draw_meshes`
+    pub gpu_driven_rendering: bool,
+    // The part of the instance buffer that belongs to the frame being recorded
+    first_gpu_instance: u32,
+    num_gpu_instances: u32,
+    // Used by the present pass of every render graph
+    pub tonemap_settings: TonemapSettings,
+    pub bloom_settings: BloomSettings,
 }
 
 #[allow(dead_code)]
@@ -94,22 +247,33 @@ pub struct ViewUniformData {
     pub samples_per_frame: u32,
     pub sun_dir: glam::Vec3,
     pub total_samples: u32,
+    // Sub-pixel offset of the projection in NDC, see `Camera::next_jitter`
+    pub jitter: glam::Vec2,
     pub num_bounces: u32,
     pub viewport_width: u32,
     pub viewport_height: u32,
     pub time: f32,
     pub num_lights: u32,
+    // Thin lens in world units, see `Camera::get_lens`. The focal distance is
+    // written by the focus pass.
+    pub aperture_diameter: f32,
+    pub focal_length: f32,
+    pub sensor_height: f32,
 
     // render settings
     pub shadows_enabled: u32,
     pub ssao_enabled: u32,
     pub fxaa_enabled: u32,
+    pub taa_enabled: u32,
+    pub dof_enabled: u32,
     pub cubemap_enabled: u32,
     pub ibl_enabled: u32,
     pub sky_enabled: u32,
     pub sun_shadow_enabled: u32,
     pub lights_enabled: u32,
     pub max_num_lights_used: u32,
+    // Shows the number of lights in each cluster, see `renderers::light_culling`
+    pub light_clusters_debug: u32,
     pub marching_cubes_enabled: u32,
     pub temporal_reuse_enabled: u32,
     pub spatial_reuse_enabled: u32,
@@ -125,21 +289,8 @@ impl Renderer {
         let bindless_descriptor_set =
             create_bindless_descriptor_set(device, bindless_descriptor_set_layout);
 
-        let gpu_materials_buffer = Buffer::new::<u8>(
-            device,
-            None,
-            (MAX_NUM_GPU_MATERIALS * std::mem::size_of::<GpuMaterial>()) as u64,
-            vk::BufferUsageFlags::STORAGE_BUFFER,
-            gpu_allocator::MemoryLocation::CpuToGpu,
-        );
-
-        let gpu_meshes_buffer = Buffer::new::<u8>(
-            device,
-            None,
-            (MAX_NUM_GPU_MESHES * std::mem::size_of::<GpuMesh>()) as u64,
-            vk::BufferUsageFlags::STORAGE_BUFFER,
-            gpu_allocator::MemoryLocation::CpuToGpu,
-        );
+        let (gpu_materials_buffer, gpu_meshes_buffer, gpu_instances_buffer) =
+            create_gpu_mesh_buffers(device, INITIAL_GPU_MESH_CAPACITY);
 
         let gpu_lights_buffer = Buffer::new::<u8>(
             device,
@@ -167,6 +318,12 @@ impl Renderer {
             5,
             &gpu_lights_buffer,
         );
+        DescriptorSet::write_raw_storage_buffer(
+            device,
+            bindless_descriptor_set,
+            6,
+            &gpu_instances_buffer,
+        );
 
         let raytracing = match device.raytracing_supported {
             true => Some(Raytracing::new(
@@ -177,201 +334,617 @@ impl Renderer {
             false => None,
         };
 
+        let mut asset_manager = AssetManager::new(bindless_descriptor_set);
+        let (sky_indices, sky_vertices) = generate_uv_sphere(1.0, 32, 16);
+        let sky_sphere = asset_manager.mesh(device, "sky_sphere", sky_indices, sky_vertices);
+
         Renderer {
             raytracing,
             bindless_descriptor_set_layout,
             bindless_descriptor_set,
             instances: vec![],
+            scene_graph: SceneGraph::new(),
+            asset_manager,
+            sky_sphere,
             gpu_materials: vec![],
             gpu_meshes: vec![],
             gpu_lights: vec![],
+            gpu_deformations: vec![],
+            default_textures: vec![],
             gpu_meshes_buffer,
             gpu_materials_buffer,
             gpu_lights_buffer,
-            next_bindless_image_index: 0,
-            next_bindless_vertex_buffer_index: 0,
-            next_bindless_index_buffer_index: 0,
+            gpu_instances_buffer,
+            gpu_mesh_capacity: INITIAL_GPU_MESH_CAPACITY,
             default_diffuse_map_index: 0,
             default_normal_map_index: 0,
             default_occlusion_map_index: 0,
             default_metallic_roughness_map_index: 0,
             need_environment_map_update: true,
+            lod_selection: LodSelection::default(),
+            frustum_culling_enabled: true,
+            culling_stats: Cell::new(CullingStats::default()),
+            gpu_driven_rendering: true,
+            first_gpu_instance: 0,
+            num_gpu_instances: 0,
+            tonemap_settings: TonemapSettings::default(),
+            bloom_settings: BloomSettings::default(),
         }
     }
 
     pub fn initialize(&mut self, device: &Device) {
-        let default_diffuse_map =
-            Texture::load(device, "utopian/data/textures/defaults/white_texture.png");
-        let default_normal_map =
-            Texture::load(device, "utopian/data/textures/defaults/flat_normal_map.png");
-        let default_occlusion_map =
-            Texture::load(device, "utopian/data/textures/defaults/white_texture.png");
-        let default_metallic_roughness_map = Texture::load(
-            device,
-            "utopian/data/textures/defaults/default_metallic_roughness.png",
-        );
+        let default_diffuse_map = self
+            .asset_manager
+            .load_texture(
+                device,
+                "utopian/data/textures/defaults/white_texture.png",
+                ColorSpace::Srgb,
+            )
+            .expect("Missing default texture");
+        let default_normal_map = self
+            .asset_manager
+            .load_texture(
+                device,
+                "utopian/data/textures/defaults/flat_normal_map.png",
+                ColorSpace::Linear,
+            )
+            .expect("Missing default texture");
+        let default_occlusion_map = self
+            .asset_manager
+            .load_texture(
+                device,
+                "utopian/data/textures/defaults/white_texture.png",
+                ColorSpace::Linear,
+            )
+            .expect("Missing default texture");
+        let default_metallic_roughness_map = self
+            .asset_manager
+            .load_texture(
+                device,
+                "utopian/data/textures/defaults/default_metallic_roughness.png",
+                ColorSpace::Linear,
+            )
+            .expect("Missing default texture");
 
-        self.default_diffuse_map_index = self.add_bindless_texture(device, &default_diffuse_map);
-        self.default_normal_map_index = self.add_bindless_texture(device, &default_normal_map);
-        self.default_occlusion_map_index =
-            self.add_bindless_texture(device, &default_occlusion_map);
-        self.default_metallic_roughness_map_index =
-            self.add_bindless_texture(device, &default_metallic_roughness_map);
-    }
+        self.default_diffuse_map_index = default_diffuse_map.bindless_index;
+        self.default_normal_map_index = default_normal_map.bindless_index;
+        self.default_occlusion_map_index = default_occlusion_map.bindless_index;
+        self.default_metallic_roughness_map_index = default_metallic_roughness_map.bindless_index;
 
-    pub fn add_model(&mut self, device: &Device, mut model: Model, transform: glam::Mat4) {
-        // Add the images from the new model to the bindless descriptor set and
-        // also update the mappings for each primitive to be indexes corresponding
-        // to the ordering in the bindless descriptor set texture array.
-        for mesh in &mut model.meshes {
-            let diffuse_bindless_index = match mesh.material.diffuse_map {
-                DEFAULT_TEXTURE_MAP => self.default_diffuse_map_index,
-                _ => self.add_bindless_texture(
-                    device,
-                    &model.textures[mesh.material.diffuse_map as usize],
-                ),
-            };
+        self.default_textures = vec![
+            default_diffuse_map,
+            default_normal_map,
+            default_occlusion_map,
+            default_metallic_roughness_map,
+        ];
+    }
 
-            let normal_bindless_index = match mesh.material.normal_map {
-                DEFAULT_TEXTURE_MAP => self.default_normal_map_index,
-                _ => self.add_bindless_texture(
-                    device,
-                    &model.textures[mesh.material.normal_map as usize],
-                ),
-            };
+    pub fn add_model(
+        &mut self,
+        device: &Device,
+        mut model: Model,
+        transform: glam::Mat4,
+    ) -> NodeId {
+        // Textures that have not been streamed in yet use the default texture for their
+        // usage until `set_model_texture` is called
+        let textures = model.textures.clone();
+        let mut deformation_buffer_slots = vec![];
 
-            let metallic_roughness_bindless_index = match mesh.material.metallic_roughness_map {
-                DEFAULT_TEXTURE_MAP => self.default_metallic_roughness_map_index,
-                _ => self.add_bindless_texture(
-                    device,
-                    &model.textures[mesh.material.metallic_roughness_map as usize],
-                ),
-            };
+        for mesh in &mut model.meshes {
+            let material_index = self.add_material(self.gpu_material(&mesh.material, &textures));
 
-            let occlusion_bindless_index = match mesh.material.occlusion_map {
-                DEFAULT_TEXTURE_MAP => self.default_occlusion_map_index,
-                _ => self.add_bindless_texture(
-                    device,
-                    &model.textures[mesh.material.occlusion_map as usize],
-                ),
-            };
-
-            let vertex_buffer_bindless_idx =
-                self.add_bindless_vertex_buffer(device, &mesh.primitive.vertex_buffer);
-            let index_buffer_bindless_idx =
-                self.add_bindless_index_buffer(device, &mesh.primitive.index_buffer);
-
-            let material_index = self.add_material(GpuMaterial {
-                diffuse_map: diffuse_bindless_index,
-                normal_map: normal_bindless_index,
-                metallic_roughness_map: metallic_roughness_bindless_index,
-                occlusion_map: occlusion_bindless_index,
-                base_color_factor: mesh.material.base_color_factor,
-                metallic_factor: mesh.material.metallic_factor,
-                roughness_factor: mesh.material.roughness_factor,
-                raytrace_properties: Vec4::new(
-                    mesh.material.material_type as u32 as f32,
-                    mesh.material.material_property,
-                    0.0,
-                    0.0,
-                ),
-                padding: [0.0; 2],
-            });
+            let vertex_buffer_bindless_idx = mesh.primitive.vertex_buffer_index;
+            let index_buffer_bindless_idx = mesh.primitive.index_buffer_index;
 
             let mesh_index = self.add_mesh(GpuMesh {
                 vertex_buffer: vertex_buffer_bindless_idx,
                 index_buffer: index_buffer_bindless_idx,
                 material: material_index,
+                vertex_layout: mesh.primitive.vertex_layout as u32,
+                index_size: mesh.primitive.index_size(),
+                blas_first_triangle: mesh.primitive.lod(mesh.primitive.blas_lod).first_index / 3,
             });
 
             mesh.gpu_mesh = mesh_index;
+
+            if let Some(deformation) = &mut mesh.deformation {
+                let mut add_buffer = |buffer: Option<&Buffer>| match buffer {
+                    Some(buffer) => {
+                        let slot = self
+                            .asset_manager
+                            .add_bindless_vertex_buffer(device, buffer);
+                        deformation_buffer_slots.push(slot);
+                        slot
+                    }
+                    // Buffers that are not used by a mesh are never accessed by the skinning pass
+                    None => 0,
+                };
+
+                let gpu_deformation = GpuDeformation {
+                    rest_vertex_buffer: add_buffer(Some(&deformation.rest_vertex_buffer)),
+                    vertex_buffer: vertex_buffer_bindless_idx,
+                    skin_vertex_buffer: add_buffer(deformation.skin_vertex_buffer.as_ref()),
+                    morph_target_buffer: add_buffer(deformation.morph_target_buffer.as_ref()),
+                    joint_matrices_buffer: add_buffer(deformation.joint_matrices_buffer.as_ref()),
+                    morph_weights_buffer: add_buffer(deformation.morph_weights_buffer.as_ref()),
+                    num_vertices: deformation.num_vertices,
+                    num_morph_targets: deformation.num_morph_targets,
+                    skinned: deformation.skin.is_some() as u32,
+                    first_joint_matrix: 0,
+                    first_morph_weight: 0,
+                };
+
+                deformation.gpu_deformation = self.gpu_deformations.len();
+                self.gpu_deformations.push(gpu_deformation);
+            }
         }
 
         // println!("{:?}", self.gpu_meshes);
         // println!("{:?}", self.gpu_materials);
 
+        self.reserve_gpu_meshes(device);
         self.gpu_meshes_buffer
             .update_memory(device, self.gpu_meshes.as_slice());
         self.gpu_materials_buffer
             .update_memory(device, self.gpu_materials.as_slice());
 
-        self.instances.push(ModelInstance { model, transform });
+        // Move the node hierarchy of the model into the scene graph
+        let root = self.scene_graph.add_node(
+            format!("instance_{}", self.instances.len()).as_str(),
+            transform,
+            None,
+        );
+        let node_offset = self
+            .scene_graph
+            .append(std::mem::take(&mut model.nodes), Some(root));
+
+        for mesh in &mut model.meshes {
+            mesh.node += node_offset;
+        }
+
+        for camera in &mut model.cameras {
+            camera.node += node_offset;
+        }
+
+        for skin in &mut model.skins {
+            skin.joints
+                .iter_mut()
+                .for_each(|joint| *joint += node_offset);
+        }
+
+        for channel in model
+            .animations
+            .iter_mut()
+            .flat_map(|clip| clip.channels.iter_mut())
+        {
+            channel.node += node_offset;
+        }
+
+        let animation_player =
+            AnimationPlayer::new(&model.animations, &self.scene_graph, &model.meshes);
+
+        self.scene_graph.update_world_transforms();
+
+        // The lights follow their node with `update_model_lights`
+        for model_light in &mut model.lights {
+            model_light.node += node_offset;
+            let world_transform = self.scene_graph.world_transform(model_light.node);
+            model_light.gpu_light =
+                self.add_light(device, model_light.light.transformed(world_transform));
+        }
+
+        self.instances.push(ModelInstance {
+            model,
+            root,
+            animation_player,
+            textures,
+            deformation_buffer_slots,
+        });
+
+        root
     }
 
-    fn add_bindless_texture(&mut self, device: &Device, texture: &Texture) -> u32 {
-        let new_image_index = self.next_bindless_image_index;
+    /// Starts playing the animation clip `name` on the model that was added with `root`.
+    /// Clips that are played at the same time are blended by their weights.
+    pub fn play_animation(&mut self, root: NodeId, name: &str, weight: f32, looping: bool) {
+        let instance = self
+            .instances
+            .iter_mut()
+            .find(|instance| instance.root == root)
+            .expect("No model instance with the given root node");
 
-        let descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.bindless_descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(new_image_index)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(std::slice::from_ref(&texture.descriptor_info))
-            .build();
+        match instance
+            .model
+            .animations
+            .iter()
+            .position(|clip| clip.name == name)
+        {
+            Some(clip) => instance.animation_player.play(clip, weight, looping),
+            None => log::warn!("Animation {} not found", name),
+        }
+    }
 
-        unsafe {
-            device
-                .handle
-                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[])
-        };
+    pub fn stop_animations(&mut self, root: NodeId) {
+        if let Some(instance) = self
+            .instances
+            .iter_mut()
+            .find(|instance| instance.root == root)
+        {
+            instance.animation_player.stop_all();
+        }
+    }
 
-        self.next_bindless_image_index += 1;
+    /// Advances all playing animations and applies the sampled poses to the scene graph
+    pub fn update_animations(&mut self, delta_time: f32) {
+        puffin::profile_function!();
 
-        new_image_index
+        for instance in &mut self.instances {
+            instance.animation_player.update(
+                &instance.model.animations,
+                delta_time,
+                &mut self.scene_graph,
+                &mut instance.model.meshes,
+            );
+        }
     }
 
-    fn add_bindless_vertex_buffer(&mut self, device: &Device, buffer: &Buffer) -> u32 {
-        let new_buffer_index = self.next_bindless_vertex_buffer_index;
+    /// Selects the level of detail of every mesh from how large its bounding sphere is on
+    /// screen. Note: requires up to date world transforms in the scene graph
+    pub fn update_lods(&mut self, view_data: &ViewUniformData) {
+        puffin::profile_function!();
 
-        let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(buffer.buffer)
-            .range(buffer.size)
-            .build();
+        // Pixels covered by one unit at a distance of one unit
+        let pixels_per_unit =
+            view_data.projection.y_axis.y * view_data.viewport_height as f32 * 0.5;
 
-        let descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.bindless_descriptor_set)
-            .dst_binding(1)
-            .dst_array_element(new_buffer_index)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .buffer_info(std::slice::from_ref(&buffer_info))
-            .build();
+        for instance in &mut self.instances {
+            for mesh in &mut instance.model.meshes {
+                mesh.lod = self.lod_selection.select_for_view(
+                    &mesh.primitive.lods,
+                    &mesh.bounds.sphere,
+                    self.scene_graph.world_transform(mesh.node),
+                    view_data.eye_pos,
+                    pixels_per_unit,
+                    mesh.lod,
+                );
+            }
+        }
+    }
 
-        unsafe {
-            device
-                .handle
-                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[])
-        };
+    /// Note: requires up to date world transforms in the scene graph
+    pub fn update_world_bounds(&mut self) {
+        puffin::profile_function!();
+
+        for instance in &mut self.instances {
+            for mesh in &mut instance.model.meshes {
+                let world_transform = self.scene_graph.world_transform(mesh.node);
+                mesh.world_bounds = mesh.bounds.transform(world_transform);
+            }
+        }
+    }
+
+    /// Moves the lights from the glTF models to the world transforms of their nodes.
+    /// Note: requires up to date world transforms in the scene graph
+    pub fn update_model_lights(&mut self, device: &Device) {
+        puffin::profile_function!();
 
-        self.next_bindless_vertex_buffer_index += 1;
+        let mut lights_changed = false;
+
+        for model_light in self
+            .instances
+            .iter()
+            .flat_map(|instance| instance.model.lights.iter())
+        {
+            let world_transform = self.scene_graph.world_transform(model_light.node);
+            let gpu_light = GpuLight::new(
+                &model_light.light.transformed(world_transform),
+                model_light.gpu_light,
+            );
+
+            if self.gpu_lights[model_light.gpu_light as usize] != gpu_light {
+                self.gpu_lights[model_light.gpu_light as usize] = gpu_light;
+                lights_changed = true;
+            }
+        }
 
-        new_buffer_index
+        if lights_changed {
+            self.gpu_lights_buffer
+                .update_memory(device, self.gpu_lights.as_slice());
+        }
     }
 
-    fn add_bindless_index_buffer(&mut self, device: &Device, buffer: &Buffer) -> u32 {
-        let new_buffer_index = self.next_bindless_index_buffer_index;
+    /// Writes every mesh to the part of the instance buffer that belongs to `frame`, the
+    /// culling passes create the draws from it.
+    /// Also moves the world transforms of the meshes to their previous ones.
+    /// Note: requires up to date world bounds and levels of detail
+    pub fn update_gpu_instances(&mut self, frame: usize) {
+        puffin::profile_function!();
 
-        let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(buffer.buffer)
-            .range(buffer.size)
-            .build();
+        let scene_graph = &self.scene_graph;
 
-        let descriptor_write = vk::WriteDescriptorSet::builder()
-            .dst_set(self.bindless_descriptor_set)
-            .dst_binding(2)
-            .dst_array_element(new_buffer_index)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .buffer_info(std::slice::from_ref(&buffer_info))
-            .build();
+        // There is one mesh in the mesh buffer for every mesh of every model instance, so
+        // the part of each frame has room for all of them, see `reserve_gpu_meshes`
+        let gpu_instances: Vec<GpuInstance> = self
+            .instances
+            .iter_mut()
+            .flat_map(|instance| instance.model.meshes.iter_mut())
+            .map(|mesh| {
+                let lod = mesh.primitive.lod(mesh.lod);
+                let world = scene_graph.world_transform(mesh.node);
+                let prev_world = mesh.prev_world_transform.replace(world).unwrap_or(world);
 
-        unsafe {
-            device
-                .handle
-                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[])
+                GpuInstance {
+                    world,
+                    prev_world,
+                    aabb_min: mesh.world_bounds.aabb.min,
+                    mesh: mesh.gpu_mesh,
+                    aabb_max: mesh.world_bounds.aabb.max,
+                    first_index: lod.first_index,
+                    index_count: lod.index_count,
+                    cullable: mesh.deformation.is_none() as u32,
+                    pad: [0; 2],
+                }
+            })
+            .collect();
+
+        self.first_gpu_instance = first_gpu_instance(frame, self.gpu_mesh_capacity);
+        self.num_gpu_instances = gpu_instances.len() as u32;
+
+        self.gpu_instances_buffer.update_mapped_memory_at(
+            self.first_gpu_instance as u64 * std::mem::size_of::<GpuInstance>() as u64,
+            &gpu_instances,
+        );
+    }
+
+    /// The first instance and the number of instances of the frame being recorded
+    pub fn gpu_instances(&self) -> (u32, u32) {
+        (self.first_gpu_instance, self.num_gpu_instances)
+    }
+
+    /// Number of meshes that the mesh and instance buffers have room for, which is also
+    /// the most draws that a culling pass can write
+    pub fn gpu_mesh_capacity(&self) -> usize {
+        self.gpu_mesh_capacity
+    }
+
+    /// Replaces the material, mesh and instance buffers with larger ones when the scene
+    /// has more meshes than they have room for. The old buffers can be used by frames in
+    /// flight so it waits for the device, this only happens while a scene is loading.
+    fn reserve_gpu_meshes(&mut self, device: &Device) {
+        let capacity = gpu_mesh_capacity(self.gpu_meshes.len(), self.gpu_mesh_capacity);
+
+        if capacity == self.gpu_mesh_capacity {
+            return;
+        }
+
+        log::info!(
+            "Growing the mesh buffers from {} to {} meshes",
+            self.gpu_mesh_capacity,
+            capacity
+        );
+
+        unsafe { device.handle.device_wait_idle().unwrap() };
+
+        let (gpu_materials_buffer, gpu_meshes_buffer, gpu_instances_buffer) =
+            create_gpu_mesh_buffers(device, capacity);
+
+        std::mem::replace(&mut self.gpu_materials_buffer, gpu_materials_buffer).destroy(device);
+        std::mem::replace(&mut self.gpu_meshes_buffer, gpu_meshes_buffer).destroy(device);
+        std::mem::replace(&mut self.gpu_instances_buffer, gpu_instances_buffer).destroy(device);
+
+        for (binding, buffer) in [
+            (3, &self.gpu_materials_buffer),
+            (4, &self.gpu_meshes_buffer),
+            (6, &self.gpu_instances_buffer),
+        ] {
+            DescriptorSet::write_raw_storage_buffer(
+                device,
+                self.bindless_descriptor_set,
+                binding,
+                buffer,
+            );
+        }
+
+        self.gpu_mesh_capacity = capacity;
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats.get()
+    }
+
+    pub fn reset_culling_stats(&mut self) {
+        self.culling_stats.set(CullingStats::default());
+    }
+
+    /// Uploads the joint matrices and morph target weights used by the skinning pass.
+    /// They are written to the copies of `frame` so that frames in flight keep theirs.
+    /// Note: requires up to date world transforms in the scene graph
+    pub fn update_deformations(&mut self, frame: usize) {
+        puffin::profile_function!();
+
+        let frame_slot = frame % MAX_NUM_FRAMES_IN_FLIGHT;
+
+        for instance in &mut self.instances {
+            for mesh in &mut instance.model.meshes {
+                let deformation = match &mut mesh.deformation {
+                    Some(deformation) => deformation,
+                    None => continue,
+                };
+
+                // The joint matrices are relative to the mesh node since the
+                // world transform of the node is applied when drawing the mesh
+                if let (Some(skin), Some(buffer)) =
+                    (deformation.skin, &mut deformation.joint_matrices_buffer)
+                {
+                    let skin = &instance.model.skins[skin];
+                    let inverse_mesh_transform =
+                        self.scene_graph.world_transform(mesh.node).inverse();
+                    let joint_matrices: Vec<glam::Mat4> = skin
+                        .joints
+                        .iter()
+                        .zip(&skin.inverse_bind_matrices)
+                        .map(|(&joint, inverse_bind_matrix)| {
+                            inverse_mesh_transform
+                                * self.scene_graph.world_transform(joint)
+                                * *inverse_bind_matrix
+                        })
+                        .collect();
+
+                    let first_joint_matrix = frame_slot * joint_matrices.len();
+                    buffer.update_mapped_memory_at(
+                        (first_joint_matrix * std::mem::size_of::<glam::Mat4>()) as u64,
+                        joint_matrices.as_slice(),
+                    );
+                    self.gpu_deformations[deformation.gpu_deformation].first_joint_matrix =
+                        first_joint_matrix as u32;
+                }
+
+                if let Some(buffer) = &mut deformation.morph_weights_buffer {
+                    let first_morph_weight = frame_slot * deformation.morph_weights.len();
+                    buffer.update_mapped_memory_at(
+                        (first_morph_weight * std::mem::size_of::<f32>()) as u64,
+                        deformation.morph_weights.as_slice(),
+                    );
+                    self.gpu_deformations[deformation.gpu_deformation].first_morph_weight =
+                        first_morph_weight as u32;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn deformations(&self) -> &[GpuDeformation] {
+        &self.gpu_deformations
+    }
+
+    pub fn has_deformed_meshes(&self) -> bool {
+        !self.gpu_deformations.is_empty()
+    }
+
+    /// Returns the cameras from all models together with their world transforms
+    pub fn cameras(&self) -> Vec<(&ModelCamera, glam::Mat4)> {
+        self.instances
+            .iter()
+            .flat_map(|instance| instance.model.cameras.iter())
+            .map(|camera| (camera, self.scene_graph.world_transform(camera.node)))
+            .collect()
+    }
+
+    pub fn find_camera(&self, name: &str) -> Option<(&ModelCamera, glam::Mat4)> {
+        self.cameras()
+            .into_iter()
+            .find(|(camera, _)| camera.name == name)
+    }
+
+    /// Removes all models and lights so that a new scene can be loaded. Meshes and
+    /// textures that are not used by anything else are freed.
+    pub fn clear_scene(&mut self, device: &Device) {
+        unsafe { device.handle.device_wait_idle().unwrap() };
+
+        for mut instance in self.instances.drain(..) {
+            for slot in instance.deformation_buffer_slots {
+                self.asset_manager.release_bindless_vertex_buffer(slot);
+            }
+
+            for mesh in &mut instance.model.meshes {
+                if let Some(deformation) = mesh.deformation.take() {
+                    deformation.destroy(device);
+                }
+            }
+        }
+
+        self.asset_manager.free_released_assets(device);
+
+        self.scene_graph = SceneGraph::new();
+        self.gpu_materials.clear();
+        self.gpu_meshes.clear();
+        self.gpu_lights.clear();
+        self.gpu_deformations.clear();
+
+        // The acceleration structures of the old scene are replaced by an empty one until
+        // the next scene has loaded
+        if let Some(raytracing) = &mut self.raytracing {
+            raytracing.initialize(device, &self.instances, &self.scene_graph);
+        }
+    }
+
+    /// Replaces the placeholder of texture `texture_index` in the model instances `roots`.
+    /// Note: the materials are updated in place, frames in flight may see either the
+    /// placeholder or `texture` so its upload has to be complete.
+    pub fn set_model_texture(
+        &mut self,
+        device: &Device,
+        roots: &[NodeId],
+        texture_index: usize,
+        texture: TextureHandle,
+    ) {
+        for instance_index in 0..self.instances.len() {
+            let instance = &mut self.instances[instance_index];
+            if !roots.contains(&instance.root) {
+                continue;
+            }
+
+            if instance.textures.len() <= texture_index {
+                instance.textures.resize(texture_index + 1, None);
+            }
+            instance.textures[texture_index] = Some(texture.clone());
+
+            let instance = &self.instances[instance_index];
+            for mesh in &instance.model.meshes {
+                let material_index = self.gpu_meshes[mesh.gpu_mesh as usize].material;
+                self.gpu_materials[material_index as usize] =
+                    self.gpu_material(&mesh.material, &instance.textures);
+            }
+        }
+
+        self.gpu_materials_buffer
+            .update_memory(device, self.gpu_materials.as_slice());
+    }
+
+    fn gpu_material(&self, material: &Material, textures: &[Option<TextureHandle>]) -> GpuMaterial {
+        let texture_index = |texture_map: u32, default_index: u32| {
+            textures
+                .get(texture_map as usize)
+                .and_then(|texture| texture.as_ref())
+                .map_or(default_index, |texture| texture.bindless_index)
         };
 
-        self.next_bindless_index_buffer_index += 1;
+        let mut uv_transforms = [Vec4::ZERO; 10];
+        for (i, uv_transform) in material.uv_transforms.iter().enumerate() {
+            let matrix = uv_transform.matrix();
+            uv_transforms[i * 2] = matrix.row(0).extend(0.0);
+            uv_transforms[i * 2 + 1] = matrix.row(1).extend(0.0);
+        }
 
-        new_buffer_index
+        GpuMaterial {
+            diffuse_map: texture_index(material.diffuse_map, self.default_diffuse_map_index),
+            normal_map: texture_index(material.normal_map, self.default_normal_map_index),
+            metallic_roughness_map: texture_index(
+                material.metallic_roughness_map,
+                self.default_metallic_roughness_map_index,
+            ),
+            occlusion_map: texture_index(material.occlusion_map, self.default_occlusion_map_index),
+            base_color_factor: material.base_color_factor,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            alpha_cutoff: material.alpha_cutoff,
+            alpha_mode: material.alpha_mode as u32,
+            raytrace_properties: Vec4::new(
+                material.material_type as u32 as f32,
+                material.material_property,
+                0.0,
+                0.0,
+            ),
+            // The emissive factor is multiplied with a white texture when there is no emissive map
+            emissive_map: texture_index(material.emissive_map, self.default_diffuse_map_index),
+            double_sided: material.double_sided as u32,
+            transmission_factor: material.transmission_factor,
+            ior: material.ior,
+            emissive_factor: material.emissive_factor.extend(0.0),
+            clearcoat_factor: material.clearcoat_factor,
+            clearcoat_roughness_factor: material.clearcoat_roughness_factor,
+            padding: [0.0; 2],
+            uv_transforms,
+        }
     }
 
     fn add_material(&mut self, gpu_material: GpuMaterial) -> u32 {
@@ -388,20 +961,9 @@ impl Renderer {
         gpu_index
     }
 
-    pub fn add_light(&mut self, device: &Device, position: Vec3, color: Vec3, range: f32) -> u32 {
+    pub fn add_light(&mut self, device: &Device, light: Light) -> u32 {
         let light_index = self.gpu_lights.len() as u32;
-        self.gpu_lights.push(GpuLight {
-            color: Vec4::new(color.x, color.y, color.z, 0.0),
-            position,
-            range,
-            direction: Vec3::new(0.0, 0.0, 0.0),
-            spot: 0.0,
-            attenuation: Vec3::new(0.0, 0.0, 0.1),
-            light_type: 1.0,
-            intensity: Vec3::new(1.0, 1.0, 1.0),
-            id: 0.0,
-            paddding: Vec4::new(0.0, 0.0, 0.0, 0.0),
-        });
+        self.gpu_lights.push(GpuLight::new(&light, light_index));
 
         self.gpu_lights_buffer
             .update_memory(device, self.gpu_lights.as_slice());
@@ -409,52 +971,212 @@ impl Renderer {
         light_index
     }
 
+    pub fn update_light(&mut self, device: &Device, light_index: u32, light: Light) {
+        self.gpu_lights[light_index as usize] = GpuLight::new(&light, light_index);
+
+        self.gpu_lights_buffer
+            .update_memory(device, self.gpu_lights.as_slice());
+    }
+
     pub fn get_num_lights(&self) -> u32 {
         self.gpu_lights.len() as u32
     }
 
+    /// Binds the index buffer of `primitive`, the vertex shaders load its vertices from the
+    /// bindless buffers with `load_vertex` in bindless.glsl
+    pub fn bind_primitive(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        primitive: &Primitive,
+    ) {
+        unsafe {
+            device.handle.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.buffer,
+                0,
+                primitive.index_type,
+            );
+        }
+    }
+
+    /// Draws the commands written by a culling pass, see `renderers::gpu_culling`. The draws
+    /// are not indexed, the vertex shaders load the indices and the vertices of each mesh
+    /// from the bindless buffers so no vertex or index buffers are bound.
+    pub fn draw_meshes_indirect(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline: &Pipeline,
+        draw_commands: &Buffer,
+        draw_count: &Buffer,
+    ) {
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                (
+                    glam::Mat4::IDENTITY,
+                    glam::Vec4::new(1.0, 0.5, 0.2, 1.0),
+                    INDIRECT_DRAW_MESH_INDEX,
+                    [0; 3],
+                ),
+            );
+
+            device.draw_indirect_count_ext.cmd_draw_indirect_count(
+                command_buffer,
+                draw_commands.buffer,
+                0,
+                draw_count.buffer,
+                0,
+                self.gpu_mesh_capacity as u32,
+                std::mem::size_of::<vk::DrawIndirectCommand>() as u32,
+            );
+        }
+    }
+
     pub fn draw_meshes(
         &self,
         device: &Device,
         command_buffer: vk::CommandBuffer,
-        pipeline_layout: vk::PipelineLayout,
+        pipeline: &Pipeline,
+        frustum: &Frustum,
     ) {
+        let mut stats = self.culling_stats.get();
+
         unsafe {
-            for instance in &self.instances {
-                for (i, mesh) in instance.model.meshes.iter().enumerate() {
-                    device.cmd_push_constants(
-                        command_buffer,
-                        pipeline_layout,
-                        (
-                            instance.transform * instance.model.transforms[i],
-                            glam::Vec4::new(1.0, 0.5, 0.2, 1.0),
-                            mesh.gpu_mesh,
-                            [0; 3],
-                        ),
-                    );
+            let meshes = self
+                .instances
+                .iter()
+                .flat_map(|instance| instance.model.meshes.iter());
 
-                    device.handle.cmd_bind_vertex_buffers(
-                        command_buffer,
-                        0,
-                        &[mesh.primitive.vertex_buffer.buffer],
-                        &[0],
-                    );
-                    device.handle.cmd_bind_index_buffer(
-                        command_buffer,
-                        mesh.primitive.index_buffer.buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.handle.cmd_draw_indexed(
-                        command_buffer,
-                        mesh.primitive.indices.len() as u32,
-                        1,
-                        0,
-                        0,
-                        1,
-                    );
+            for (mesh_index, mesh) in meshes.enumerate() {
+                // The bounds of deformed meshes only cover their rest pose
+                if self.frustum_culling_enabled
+                    && mesh.deformation.is_none()
+                    && !frustum.intersects_aabb(&mesh.world_bounds.aabb)
+                {
+                    stats.culled += 1;
+                    continue;
                 }
+
+                stats.drawn += 1;
+
+                // Same order as in `update_gpu_instances`
+                let instance_index =
+                    gpu_instance_index(self.first_gpu_instance, self.num_gpu_instances, mesh_index);
+
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline_layout,
+                    (
+                        self.scene_graph.world_transform(mesh.node),
+                        glam::Vec4::new(1.0, 0.5, 0.2, 1.0),
+                        mesh.gpu_mesh,
+                        [instance_index, 0, 0],
+                    ),
+                );
+
+                let lod = mesh.primitive.lod(mesh.lod);
+
+                self.bind_primitive(device, command_buffer, &mesh.primitive);
+                device.handle.cmd_draw_indexed(
+                    command_buffer,
+                    lod.index_count,
+                    1,
+                    lod.first_index,
+                    0,
+                    1,
+                );
             }
         }
+
+        self.culling_stats.set(stats);
+    }
+}
+
+// The material, mesh and instance buffers with room for `capacity` meshes. Every mesh has
+// its own material, and every frame in flight its own part of the instance buffer.
+fn create_gpu_mesh_buffers(device: &Device, capacity: usize) -> (Buffer, Buffer, Buffer) {
+    let create_buffer = |size: usize| {
+        Buffer::new::<u8>(
+            device,
+            None,
+            size as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_allocator::MemoryLocation::CpuToGpu,
+        )
+    };
+
+    (
+        create_buffer(capacity * std::mem::size_of::<GpuMaterial>()),
+        create_buffer(capacity * std::mem::size_of::<GpuMesh>()),
+        create_buffer(MAX_NUM_FRAMES_IN_FLIGHT * capacity * std::mem::size_of::<GpuInstance>()),
+    )
+}
+
+// Doubles `capacity` until there is room for `num_meshes`
+fn gpu_mesh_capacity(num_meshes: usize, mut capacity: usize) -> usize {
+    while capacity < num_meshes {
+        capacity *= 2;
+    }
+
+    capacity
+}
+
+// The part of the instance buffer that belongs to `frame` starts at this instance
+fn first_gpu_instance(frame: usize, capacity: usize) -> u32 {
+    ((frame % MAX_NUM_FRAMES_IN_FLIGHT) * capacity) as u32
+}
+
+// The instance of the `mesh_index`:th mesh of the scene, in the order of
+// `update_gpu_instances`. Meshes of models that were added after the instances of the
+// frame were written do not have one yet.
+fn gpu_instance_index(first_gpu_instance: u32, num_gpu_instances: u32, mesh_index: usize) -> u32 {
+    match mesh_index < num_gpu_instances as usize {
+        true => first_gpu_instance + mesh_index as u32,
+        false => NO_INSTANCE_INDEX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_mesh_capacity_grows_to_fit_the_scene() {
+        assert_eq!(gpu_mesh_capacity(0, 1024), 1024);
+        assert_eq!(gpu_mesh_capacity(1024, 1024), 1024);
+        assert_eq!(gpu_mesh_capacity(1025, 1024), 2048);
+        assert_eq!(gpu_mesh_capacity(5000, 1024), 8192);
+        // Never shrinks when the scene is cleared
+        assert_eq!(gpu_mesh_capacity(10, 4096), 4096);
+    }
+
+    #[test]
+    fn frames_in_flight_use_separate_parts_of_the_instance_buffer() {
+        let capacity = 1024;
+        let num_instances = MAX_NUM_FRAMES_IN_FLIGHT * capacity;
+
+        for frame in 0..MAX_NUM_FRAMES_IN_FLIGHT {
+            let first = first_gpu_instance(frame, capacity) as usize;
+            let next = first_gpu_instance(frame + 1, capacity) as usize;
+
+            assert_eq!(first, frame * capacity);
+            assert!(first + capacity <= num_instances);
+            // The next frame starts after this one or wraps around to the start
+            assert!(next == first + capacity || next == 0);
+        }
+
+        assert_eq!(first_gpu_instance(MAX_NUM_FRAMES_IN_FLIGHT, capacity), 0);
+    }
+
+    #[test]
+    fn meshes_without_an_instance_use_their_own_transform() {
+        let first = first_gpu_instance(2, 1024);
+
+        assert_eq!(gpu_instance_index(first, 3, 0), 2048);
+        assert_eq!(gpu_instance_index(first, 3, 2), 2050);
+        assert_eq!(gpu_instance_index(first, 3, 3), NO_INSTANCE_INDEX);
     }
 }
@@ -1,11 +1,10 @@
-use ash::vk;
 use glam::{Mat4, Vec3};
 
 pub fn setup_atmosphere_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
-    base: &crate::VulkanBase,
     atmosphere_output: crate::TextureId,
+    depth: crate::TextureId,
     environment_map: crate::TextureId,
     camera: &crate::camera::Camera,
     enabled: bool,
@@ -20,43 +19,32 @@ pub fn setup_atmosphere_pass(
             "atmosphere_pass",
             crate::PipelineDesc::builder()
                 .vertex_path("utopian/shaders/atmosphere/atmosphere.vert")
-                .fragment_path("utopian/shaders/atmosphere/atmosphere.frag")
-                .default_primitive_vertex_bindings()
-                .default_primitive_vertex_attributes(),
+                .fragment_path("utopian/shaders/atmosphere/atmosphere.frag"),
         )
         .load_write(atmosphere_output)
         .read(environment_map)
         .uniforms("ubo_constants", &(projection, world))
-        .external_depth_attachment(base.depth_image.clone(), vk::AttachmentLoadOp::LOAD)
+        .load_depth_attachment(depth)
         .render(
-            move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+            move |device, command_buffer, renderer, pass, resources| unsafe {
                 // Todo: This is a hack to get around the fact that we can't properly disable a pass
-                // Todo: Using the first model instanced added to the scene
                 if enabled {
-                    device.handle.cmd_bind_vertex_buffers(
-                        command_buffer,
-                        0,
-                        &[_renderer.instances[0].model.meshes[0]
-                            .primitive
-                            .vertex_buffer
-                            .buffer],
-                        &[0],
-                    );
-                    device.handle.cmd_bind_index_buffer(
+                    let pipeline = resources.pipeline(pass.pipeline_handle);
+                    let sky_sphere = &renderer.sky_sphere;
+
+                    // Must match the push constants in atmosphere.vert
+                    device.cmd_push_constants(
                         command_buffer,
-                        _renderer.instances[0].model.meshes[0]
-                            .primitive
-                            .index_buffer
-                            .buffer,
-                        0,
-                        vk::IndexType::UINT32,
+                        pipeline.pipeline_layout,
+                        (
+                            sky_sphere.vertex_buffer_index,
+                            sky_sphere.vertex_layout as u32,
+                        ),
                     );
+                    renderer.bind_primitive(device, command_buffer, sky_sphere);
                     device.handle.cmd_draw_indexed(
                         command_buffer,
-                        _renderer.instances[0].model.meshes[0]
-                            .primitive
-                            .indices
-                            .len() as u32,
+                        sky_sphere.indices.len() as u32,
                         1,
                         0,
                         0,
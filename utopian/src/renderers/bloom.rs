@@ -0,0 +1,144 @@
+use ash::vk;
+
+use crate::image::ImageDesc;
+
+// Number of downsampled levels, the first is half the resolution of the input
+const NUM_BLOOM_MIPS: u32 = 6;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    // How much of the blurred image is mixed into the color
+    pub intensity: f32,
+    // Radius of the upsampling tent filter in texels, larger values spread the bloom further
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            enabled: true,
+            intensity: 0.04,
+            radius: 1.0,
+        }
+    }
+}
+
+/// Blurs the HDR `color` by downsampling it with a 13 tap filter into a chain of half
+/// resolution textures, the first one with a Karis average against fireflies, and
+/// then upsampling it back with a tent filter while adding the levels together.
+/// Returns `color` with the bloom mixed in, or `color` itself when it is disabled.
+/// Must be composited before the exposure and tonemapping.
+pub fn setup_bloom_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    color: crate::TextureId,
+    settings: &BloomSettings,
+) -> crate::TextureId {
+    puffin::profile_function!();
+
+    if !settings.enabled {
+        return color;
+    }
+
+    let (width, height) = {
+        let image = &graph.resources.texture(color).texture.image;
+        (image.width(), image.height())
+    };
+
+    let format = vk::Format::R16G16B16A16_SFLOAT;
+
+    // Separate textures instead of mips since a pass can not read and write the same image
+    let create_mips = |graph: &mut crate::Graph, name: &str| {
+        (0..NUM_BLOOM_MIPS)
+            .map(|mip| {
+                let desc = ImageDesc::new_2d(
+                    (width >> (mip + 1)).max(1),
+                    (height >> (mip + 1)).max(1),
+                    format,
+                );
+                graph.create_texture(format!("{name}_{mip}").as_str(), device, desc)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let downsample_mips = create_mips(graph, "bloom_downsample");
+    let upsample_mips = create_mips(graph, "bloom_upsample");
+
+    let bloom_output = graph.create_texture(
+        "bloom_output",
+        device,
+        ImageDesc::new_2d(width, height, format),
+    );
+
+    let mut source = color;
+    for (mip, &target) in downsample_mips.iter().enumerate() {
+        let karis_average = mip == 0;
+
+        graph
+            .add_pass_from_desc(
+                format!("bloom_downsample_pass_{mip}").as_str(),
+                crate::PipelineDesc::builder()
+                    .vertex_path("utopian/shaders/common/fullscreen.vert")
+                    .fragment_path("utopian/shaders/bloom/bloom_downsample.frag"),
+            )
+            .read(source)
+            .write(target)
+            .uniforms("settings_bloom", &(karis_average as u32))
+            .render(
+                move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+                    device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
+                },
+            )
+            .build(device, graph);
+
+        source = target;
+    }
+
+    // The smallest level has nothing to be added to it and is upsampled directly
+    let mut lower_mip = downsample_mips[NUM_BLOOM_MIPS as usize - 1];
+    for mip in (0..NUM_BLOOM_MIPS as usize - 1).rev() {
+        graph
+            .add_pass_from_desc(
+                format!("bloom_upsample_pass_{mip}").as_str(),
+                crate::PipelineDesc::builder()
+                    .vertex_path("utopian/shaders/common/fullscreen.vert")
+                    .fragment_path("utopian/shaders/bloom/bloom_upsample.frag"),
+            )
+            .read(lower_mip)
+            .read(downsample_mips[mip])
+            .write(upsample_mips[mip])
+            .uniforms("settings_bloom", &settings.radius)
+            .render(
+                move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+                    device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
+                },
+            )
+            .build(device, graph);
+
+        lower_mip = upsample_mips[mip];
+    }
+
+    graph
+        .add_pass_from_desc(
+            "bloom_composite_pass",
+            crate::PipelineDesc::builder()
+                .vertex_path("utopian/shaders/common/fullscreen.vert")
+                .fragment_path("utopian/shaders/bloom/bloom_composite.frag"),
+        )
+        .read(color)
+        .read(upsample_mips[0])
+        .write(bloom_output)
+        .uniforms(
+            "settings_bloom",
+            &(settings.intensity, NUM_BLOOM_MIPS as f32),
+        )
+        .render(
+            move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+                device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
+            },
+        )
+        .build(device, graph);
+
+    bloom_output
+}
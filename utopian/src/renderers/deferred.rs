@@ -1,3 +1,5 @@
+use crate::renderers::light_culling::LightClusters;
+
 #[allow(dead_code)]
 struct PushConstants {
     world: glam::Mat4,
@@ -14,6 +16,7 @@ pub fn setup_deferred_pass(
     gbuffer_normal: crate::TextureId,
     gbuffer_albedo: crate::TextureId,
     gbuffer_pbr: crate::TextureId,
+    gbuffer_emissive: crate::TextureId,
     shadow_map: crate::TextureId,
     rt_shadows: crate::TextureId,
     rt_reflections: crate::TextureId,
@@ -22,6 +25,7 @@ pub fn setup_deferred_pass(
     specular_map: crate::TextureId,
     brdf_lut: crate::TextureId,
     cascade_data: ([glam::Mat4; 4], [f32; 4]),
+    light_clusters: LightClusters,
     deferred_output: crate::TextureId,
 ) {
     puffin::profile_function!();
@@ -37,6 +41,7 @@ pub fn setup_deferred_pass(
         .read(gbuffer_normal)
         .read(gbuffer_albedo)
         .read(gbuffer_pbr)
+        .read(gbuffer_emissive)
         .read(shadow_map)
         .read(rt_shadows)
         .read(rt_reflections)
@@ -44,6 +49,8 @@ pub fn setup_deferred_pass(
         .read(irradiance_map)
         .read(specular_map)
         .read(brdf_lut)
+        .read_buffer(light_clusters.light_counts)
+        .read_buffer(light_clusters.light_indices)
         .write(deferred_output)
         .uniforms("shadowmapParams", &(cascade_data))
         .render(
@@ -0,0 +1,102 @@
+use ash::vk;
+
+use crate::graph::BufferId;
+use crate::image::ImageDesc;
+
+/// Writes the focal distance of the camera lens to the returned buffer. With autofocus
+/// it is the view depth of the pixel under the cursor, otherwise `Lens::focal_distance`.
+pub fn setup_focus_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    depth: crate::TextureId,
+    camera: &crate::Camera,
+) -> BufferId {
+    puffin::profile_function!();
+
+    let focus = graph.create_buffer(
+        "focus",
+        device,
+        std::mem::size_of::<f32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    let autofocus_pixel = camera.get_autofocus_pixel();
+    let focal_distance = camera.get_lens().focal_distance;
+
+    graph
+        .add_pass_from_desc(
+            "focus_pass",
+            crate::PipelineDesc::builder()
+                .compute_path("utopian/shaders/depth_of_field/focus.comp"),
+        )
+        .read(depth)
+        .write_buffer(focus)
+        .render(
+            move |device, command_buffer, _renderer, pass, resources| unsafe {
+                let pipeline = resources.pipeline(pass.pipeline_handle);
+                let pixel = autofocus_pixel.unwrap_or_default();
+
+                // Must match the push constants in focus.comp
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline_layout,
+                    [
+                        pixel.x as f32,
+                        pixel.y as f32,
+                        autofocus_pixel.is_some() as u32 as f32,
+                        focal_distance,
+                    ],
+                );
+
+                device.handle.cmd_dispatch(command_buffer, 1, 1, 1);
+            },
+        )
+        .build(device, graph);
+
+    focus
+}
+
+/// Gather based bokeh depth of field. Every pixel gathers the samples in a spiral around it
+/// whose circle of confusion, computed from the depth and the camera lens, covers it.
+/// Returns the blurred color.
+pub fn setup_depth_of_field_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    color: crate::TextureId,
+    depth: crate::TextureId,
+    focus: BufferId,
+) -> crate::TextureId {
+    puffin::profile_function!();
+
+    let (width, height) = {
+        let image = &graph.resources.texture(color).texture.image;
+        (image.width(), image.height())
+    };
+
+    let dof_output = graph.create_texture(
+        "dof_output",
+        device,
+        ImageDesc::new_2d(width, height, vk::Format::R16G16B16A16_SFLOAT),
+    );
+
+    graph
+        .add_pass_from_desc(
+            "depth_of_field_pass",
+            crate::PipelineDesc::builder()
+                .vertex_path("utopian/shaders/common/fullscreen.vert")
+                .fragment_path("utopian/shaders/depth_of_field/depth_of_field.frag"),
+        )
+        .read(color)
+        .read(depth)
+        .read_buffer(focus)
+        .write(dof_output)
+        .render(
+            move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+                device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
+            },
+        )
+        .build(device, graph);
+
+    dof_output
+}
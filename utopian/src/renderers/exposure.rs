@@ -0,0 +1,171 @@
+use ash::vk;
+
+use crate::camera::{exposure_from_ev100, Exposure};
+use crate::graph::BufferId;
+
+// Must match NUM_HISTOGRAM_BINS in luminance_histogram.comp and exposure.comp
+const NUM_HISTOGRAM_BINS: u64 = 256;
+// Range of the histogram in log2 luminance
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const MAX_LOG_LUMINANCE: f32 = 12.0;
+
+/// Computes the exposure that scales the luminance of `color` before tonemapping and
+/// returns the buffer that it is written to. With `Exposure::Automatic` a histogram of
+/// the log luminance is built and the exposure adapts towards its average over time.
+pub fn setup_exposure_passes(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    color: crate::TextureId,
+    exposure: Exposure,
+) -> BufferId {
+    puffin::profile_function!();
+
+    let (width, height) = {
+        let image = &graph.resources.texture(color).texture.image;
+        (image.width(), image.height())
+    };
+
+    let histogram = graph.create_buffer(
+        "luminance_histogram",
+        device,
+        NUM_HISTOGRAM_BINS * std::mem::size_of::<u32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    // Exposure, average luminance and the time of the last update, kept between frames
+    // so that the automatic exposure can adapt
+    let exposure_buffer = graph.create_buffer(
+        "exposure",
+        device,
+        4 * std::mem::size_of::<f32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    let reset = graph.resources.buffer(exposure_buffer).prev_access == vk_sync::AccessType::Nothing;
+    let log_luminance_range = MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE;
+
+    let (automatic, fixed_exposure, compensation, min_ev100, max_ev100, adaptation_speed) =
+        match exposure {
+            Exposure::Automatic {
+                compensation,
+                min_ev100,
+                max_ev100,
+                adaptation_speed,
+            } => (
+                true,
+                1.0,
+                compensation,
+                min_ev100,
+                max_ev100,
+                adaptation_speed,
+            ),
+            _ => (
+                false,
+                exposure_from_ev100(exposure.ev100().unwrap()),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ),
+        };
+
+    if automatic {
+        graph
+            .add_pass_from_desc(
+                "luminance_histogram_pass",
+                crate::PipelineDesc::builder()
+                    .compute_path("utopian/shaders/exposure/luminance_histogram.comp"),
+            )
+            .read(color)
+            .write_buffer(histogram)
+            .render(
+                move |device, command_buffer, _renderer, pass, resources| unsafe {
+                    let pipeline = resources.pipeline(pass.pipeline_handle);
+
+                    // The bins are counters that every frame starts from zero
+                    vk_sync::cmd::pipeline_barrier(
+                        &device.handle,
+                        command_buffer,
+                        Some(vk_sync::GlobalBarrier {
+                            previous_accesses: &[vk_sync::AccessType::AnyShaderReadOther],
+                            next_accesses: &[vk_sync::AccessType::TransferWrite],
+                        }),
+                        &[],
+                        &[],
+                    );
+
+                    device.handle.cmd_fill_buffer(
+                        command_buffer,
+                        resources.buffer(histogram).buffer.buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        0,
+                    );
+
+                    vk_sync::cmd::pipeline_barrier(
+                        &device.handle,
+                        command_buffer,
+                        Some(vk_sync::GlobalBarrier {
+                            previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                            next_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                        }),
+                        &[],
+                        &[],
+                    );
+
+                    // Must match the push constants in luminance_histogram.comp
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline.pipeline_layout,
+                        [MIN_LOG_LUMINANCE, 1.0 / log_luminance_range],
+                    );
+
+                    device.handle.cmd_dispatch(
+                        command_buffer,
+                        width.div_ceil(16),
+                        height.div_ceil(16),
+                        1,
+                    );
+                },
+            )
+            .build(device, graph);
+    }
+
+    graph
+        .add_pass_from_desc(
+            "exposure_pass",
+            crate::PipelineDesc::builder().compute_path("utopian/shaders/exposure/exposure.comp"),
+        )
+        .read_buffer(histogram)
+        .write_buffer(exposure_buffer)
+        .render(
+            move |device, command_buffer, _renderer, pass, resources| unsafe {
+                let pipeline = resources.pipeline(pass.pipeline_handle);
+
+                // Must match the push constants in exposure.comp
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline_layout,
+                    [
+                        MIN_LOG_LUMINANCE,
+                        log_luminance_range,
+                        (width * height) as f32,
+                        automatic as u32 as f32,
+                        fixed_exposure,
+                        compensation,
+                        min_ev100,
+                        max_ev100,
+                        adaptation_speed,
+                        reset as u32 as f32,
+                    ],
+                );
+
+                device.handle.cmd_dispatch(command_buffer, 1, 1, 1);
+            },
+        )
+        .build(device, graph);
+
+    exposure_buffer
+}
@@ -1,4 +1,5 @@
-use ash::vk;
+use crate::bounds::Frustum;
+use crate::renderers::light_culling::LightClusters;
 
 #[allow(dead_code)]
 struct PushConstants {
@@ -8,33 +9,46 @@ struct PushConstants {
     pad: [u32; 3],
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup_forward_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
-    base: &crate::VulkanBase,
+    renderer: &crate::Renderer,
+    camera: &crate::Camera,
+    depth: crate::TextureId,
     forward_output: crate::TextureId,
     shadow_map: crate::TextureId,
     cascade_data: ([glam::Mat4; 4], [f32; 4]),
+    light_clusters: LightClusters,
 ) {
     puffin::profile_function!();
 
+    let draws = crate::renderers::gpu_culling::setup_culling_pass(
+        device,
+        graph,
+        renderer,
+        "forward",
+        Frustum::from_view_projection(camera.get_projection() * camera.get_view()),
+    );
+
     graph
         .add_pass_from_desc(
             "forward_pass",
             crate::PipelineDesc::builder()
                 .vertex_path("utopian/shaders/forward/forward.vert")
-                .fragment_path("utopian/shaders/forward/forward.frag")
-                .default_primitive_vertex_bindings()
-                .default_primitive_vertex_attributes(),
+                .fragment_path("utopian/shaders/forward/forward.frag"),
         )
         .read(shadow_map)
+        .read_buffer(light_clusters.light_counts)
+        .read_buffer(light_clusters.light_indices)
         .write(forward_output)
         .uniforms("shadowmapParams", &(cascade_data))
-        .external_depth_attachment(base.depth_image.clone(), vk::AttachmentLoadOp::CLEAR) // Todo: create own Depth image
+        .depth_attachment(depth)
+        .extra_barriers(&draws.barriers())
         .render(move |device, command_buffer, renderer, pass, resources| {
             let pipeline = resources.pipeline(pass.pipeline_handle);
 
-            renderer.draw_meshes(device, command_buffer, pipeline.pipeline_layout);
+            draws.draw(device, command_buffer, renderer, pipeline, resources);
         })
         .build(device, graph);
 }
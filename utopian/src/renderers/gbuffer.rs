@@ -1,52 +1,48 @@
-use ash::vk;
-
-#[allow(dead_code)]
-struct PushConstants {
-    world: glam::Mat4,
-    color: glam::Vec4,
-    mesh_index: u32,
-    pad: [u32; 3],
-}
+use crate::bounds::Frustum;
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup_gbuffer_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
-    base: &crate::VulkanBase,
+    renderer: &crate::Renderer,
+    camera: &crate::Camera,
+    depth: crate::TextureId,
     gbuffer_position: crate::TextureId,
     gbuffer_normal: crate::TextureId,
     gbuffer_albedo: crate::TextureId,
     gbuffer_pbr: crate::TextureId,
+    gbuffer_emissive: crate::TextureId,
+    gbuffer_velocity: crate::TextureId,
 ) {
     puffin::profile_function!();
 
-    // let depth_image = crate::Image::new(
-    //     device,
-    //     graph.resources[gbuffer_position].texture.image.width,
-    //     graph.resources[gbuffer_position].texture.image.height,
-    //     vk::Format::D32_SFLOAT,
-    //     vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-    //     vk::ImageAspectFlags::DEPTH,
-    // );
+    let draws = crate::renderers::gpu_culling::setup_culling_pass(
+        device,
+        graph,
+        renderer,
+        "gbuffer",
+        Frustum::from_view_projection(camera.get_projection() * camera.get_view()),
+    );
 
     graph
         .add_pass_from_desc(
             "gbuffer_pass",
             crate::PipelineDesc::builder()
                 .vertex_path("utopian/shaders/gbuffer/gbuffer.vert")
-                .fragment_path("utopian/shaders/gbuffer/gbuffer.frag")
-                .default_primitive_vertex_bindings()
-                .default_primitive_vertex_attributes(),
+                .fragment_path("utopian/shaders/gbuffer/gbuffer.frag"),
         )
         .write(gbuffer_position)
         .write(gbuffer_normal)
         .write(gbuffer_albedo)
         .write(gbuffer_pbr)
-        //.depth_attachment(depth_image)
-        .external_depth_attachment(base.depth_image.clone(), vk::AttachmentLoadOp::CLEAR) // Todo: create own Depth image
+        .write(gbuffer_emissive)
+        .write(gbuffer_velocity)
+        .depth_attachment(depth)
+        .extra_barriers(&draws.barriers())
         .render(move |device, command_buffer, renderer, pass, resources| {
             let pipeline = resources.pipeline(pass.pipeline_handle);
 
-            renderer.draw_meshes(device, command_buffer, pipeline.pipeline_layout);
+            draws.draw(device, command_buffer, renderer, pipeline, resources);
         })
         .build(device, graph);
 }
@@ -0,0 +1,148 @@
+use ash::vk;
+
+use crate::bounds::Frustum;
+use crate::graph::{BufferId, GraphResources};
+
+/// The mesh draws of one view, see `setup_culling_pass`
+#[derive(Clone, Copy)]
+pub struct MeshDraws {
+    draw_commands: BufferId,
+    draw_count: BufferId,
+    frustum: Frustum,
+}
+
+impl MeshDraws {
+    // For the `extra_barriers` of the pass that draws the meshes
+    pub fn barriers(&self) -> [(BufferId, vk_sync::AccessType); 2] {
+        [
+            (self.draw_commands, vk_sync::AccessType::IndirectBuffer),
+            (self.draw_count, vk_sync::AccessType::IndirectBuffer),
+        ]
+    }
+
+    /// Draws the commands written by the culling pass when GPU-driven rendering is enabled,
+    /// otherwise the meshes are culled and drawn one by one on the CPU
+    pub fn draw(
+        &self,
+        device: &crate::Device,
+        command_buffer: vk::CommandBuffer,
+        renderer: &crate::Renderer,
+        pipeline: &crate::Pipeline,
+        resources: &GraphResources,
+    ) {
+        match renderer.gpu_driven_rendering {
+            true => renderer.draw_meshes_indirect(
+                device,
+                command_buffer,
+                pipeline,
+                &resources.buffer(self.draw_commands).buffer,
+                &resources.buffer(self.draw_count).buffer,
+            ),
+            false => renderer.draw_meshes(device, command_buffer, pipeline, &self.frustum),
+        }
+    }
+}
+
+/// Culls the mesh instances against `frustum` on the GPU and writes an indirect draw
+/// command for every visible one. `name` has to be unique for every view.
+pub fn setup_culling_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    renderer: &crate::Renderer,
+    name: &str,
+    frustum: Frustum,
+) -> MeshDraws {
+    puffin::profile_function!();
+
+    let draw_commands = graph.create_buffer(
+        format!("{name}_draw_commands").as_str(),
+        device,
+        (renderer.gpu_mesh_capacity() * std::mem::size_of::<vk::DrawIndirectCommand>()) as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    let draw_count = graph.create_buffer(
+        format!("{name}_draw_count").as_str(),
+        device,
+        std::mem::size_of::<u32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::INDIRECT_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_DST,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    graph
+        .add_pass_from_desc(
+            format!("{name}_culling_pass").as_str(),
+            crate::PipelineDesc::builder().compute_path("utopian/shaders/culling/cull_meshes.comp"),
+        )
+        .write_buffer(draw_commands)
+        .write_buffer(draw_count)
+        .uniforms("culling", &frustum.planes)
+        .render(
+            move |device, command_buffer, renderer, pass, resources| unsafe {
+                if !renderer.gpu_driven_rendering {
+                    return;
+                }
+
+                let pipeline = resources.pipeline(pass.pipeline_handle);
+                let (first_instance, num_instances) = renderer.gpu_instances();
+
+                // The draw count is reset here since it is a counter that the shader
+                // increments for every visible mesh
+                vk_sync::cmd::pipeline_barrier(
+                    &device.handle,
+                    command_buffer,
+                    Some(vk_sync::GlobalBarrier {
+                        previous_accesses: &[vk_sync::AccessType::IndirectBuffer],
+                        next_accesses: &[vk_sync::AccessType::TransferWrite],
+                    }),
+                    &[],
+                    &[],
+                );
+
+                device.handle.cmd_fill_buffer(
+                    command_buffer,
+                    resources.buffer(draw_count).buffer.buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                );
+
+                vk_sync::cmd::pipeline_barrier(
+                    &device.handle,
+                    command_buffer,
+                    Some(vk_sync::GlobalBarrier {
+                        previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                        next_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                    }),
+                    &[],
+                    &[],
+                );
+
+                // Must match the push constants in cull_meshes.comp
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline_layout,
+                    (
+                        first_instance,
+                        num_instances,
+                        renderer.frustum_culling_enabled as u32,
+                        0_u32,
+                    ),
+                );
+
+                device
+                    .handle
+                    .cmd_dispatch(command_buffer, num_instances.div_ceil(64), 1, 1);
+            },
+        )
+        .build(device, graph);
+
+    MeshDraws {
+        draw_commands,
+        draw_count,
+        frustum,
+    }
+}
@@ -0,0 +1,118 @@
+use ash::vk;
+
+use crate::image::ImageDesc;
+
+// Must match the number of mip bindings in hiz.comp
+pub const MAX_HIZ_MIPS: u32 = 13;
+
+/// Builds a min/max depth pyramid from `depth` with a single compute dispatch. The red
+/// channel holds the closest and the green channel the farthest depth that every texel
+/// covers. Mip 0 is the depth size rounded down to a power of two so that every following
+/// mip is an exact 2x2 reduction. Read it with `texelFetch`, the sampler is not a min/max one.
+pub fn setup_hiz_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    depth: crate::TextureId,
+) -> crate::TextureId {
+    puffin::profile_function!();
+
+    let (depth_width, depth_height) = {
+        let image = &graph.resources.texture(depth).texture.image;
+        (image.width(), image.height())
+    };
+
+    let previous_power_of_two = |x: u32| 1 << (31 - x.max(1).leading_zeros());
+    let width = previous_power_of_two(depth_width);
+    let height = previous_power_of_two(depth_height);
+
+    let desc = ImageDesc::new_2d(width, height, vk::Format::R32G32_SFLOAT)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+        .full_mip_chain();
+    let num_mips = desc.mip_levels.min(MAX_HIZ_MIPS);
+
+    let hiz = graph.create_texture("hiz", device, desc.mip_levels(num_mips));
+
+    // Number of finished workgroups, the last one builds the mips that span several of them
+    let counter = graph.create_buffer(
+        "hiz_counter",
+        device,
+        std::mem::size_of::<u32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    // Every workgroup reduces a 32x32 tile of mip 0
+    let num_workgroups_x = width.div_ceil(32);
+    let num_workgroups_y = height.div_ceil(32);
+
+    let mut pass = graph
+        .add_pass_from_desc(
+            "hiz_pass",
+            crate::PipelineDesc::builder().compute_path("utopian/shaders/hiz/hiz.comp"),
+        )
+        .read(depth)
+        .write_buffer(counter);
+
+    // The shader declares all MAX_HIZ_MIPS bindings, the ones past the last mip get
+    // a valid view as well but are never written
+    for mip in 0..MAX_HIZ_MIPS {
+        pass = pass.image_write_mip(hiz, mip.min(num_mips - 1));
+    }
+
+    pass.render(
+        move |device, command_buffer, _renderer, pass, resources| unsafe {
+            let pipeline = resources.pipeline(pass.pipeline_handle);
+
+            vk_sync::cmd::pipeline_barrier(
+                &device.handle,
+                command_buffer,
+                Some(vk_sync::GlobalBarrier {
+                    previous_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                    next_accesses: &[vk_sync::AccessType::TransferWrite],
+                }),
+                &[],
+                &[],
+            );
+
+            device.handle.cmd_fill_buffer(
+                command_buffer,
+                resources.buffer(counter).buffer.buffer,
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+
+            vk_sync::cmd::pipeline_barrier(
+                &device.handle,
+                command_buffer,
+                Some(vk_sync::GlobalBarrier {
+                    previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                    next_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                }),
+                &[],
+                &[],
+            );
+
+            // Must match the push constants in hiz.comp
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                [
+                    depth_width,
+                    depth_height,
+                    width,
+                    height,
+                    num_mips,
+                    num_workgroups_x * num_workgroups_y,
+                ],
+            );
+
+            device
+                .handle
+                .cmd_dispatch(command_buffer, num_workgroups_x, num_workgroups_y, 1);
+        },
+    )
+    .build(device, graph);
+
+    hiz
+}
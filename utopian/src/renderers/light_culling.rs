@@ -0,0 +1,56 @@
+use ash::vk;
+
+use crate::graph::BufferId;
+
+// Must match the defines in clusters.glsl
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+
+/// The lights that affect every cluster of the view frustum, see `setup_light_culling_pass`
+#[derive(Clone, Copy)]
+pub struct LightClusters {
+    pub light_counts: BufferId,
+    pub light_indices: BufferId,
+}
+
+/// Divides the view frustum into screen space tiles and exponentially distributed depth
+/// slices and writes the indices of the lights whose range intersects each cluster.
+/// Directional and area lights have no range and are added to every cluster.
+pub fn setup_light_culling_pass(device: &crate::Device, graph: &mut crate::Graph) -> LightClusters {
+    puffin::profile_function!();
+
+    let num_clusters = CLUSTER_GRID.iter().product::<u32>() as u64;
+
+    let light_counts = graph.create_buffer(
+        "cluster_light_counts",
+        device,
+        num_clusters * std::mem::size_of::<u32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    let light_indices = graph.create_buffer(
+        "cluster_light_indices",
+        device,
+        num_clusters * MAX_LIGHTS_PER_CLUSTER as u64 * std::mem::size_of::<u32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_allocator::MemoryLocation::GpuOnly,
+    );
+
+    // One workgroup per cluster, its threads test the lights in parallel
+    graph
+        .add_pass_from_desc(
+            "light_culling_pass",
+            crate::PipelineDesc::builder()
+                .compute_path("utopian/shaders/light_culling/light_culling.comp"),
+        )
+        .write_buffer(light_counts)
+        .write_buffer(light_indices)
+        .dispatch(CLUSTER_GRID[0], CLUSTER_GRID[1], CLUSTER_GRID[2])
+        .build(device, graph);
+
+    LightClusters {
+        light_counts,
+        light_indices,
+    }
+}
@@ -1,15 +1,18 @@
 use ash::vk;
 use glam::Vec4;
 
+use crate::renderers::light_culling::LightClusters;
 use crate::{PipelineDesc, Vertex};
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup_marching_cubes_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
-    base: &crate::VulkanBase,
     deferred_output: crate::TextureId,
+    depth: crate::TextureId,
     shadow_map: crate::TextureId,
     cascade_data: ([glam::Mat4; 4], [f32; 4]),
+    light_clusters: LightClusters,
     _enabled: bool,
 ) {
     puffin::profile_function!();
@@ -86,15 +89,18 @@ pub fn setup_marching_cubes_pass(
         .add_pass_from_desc(
             "marching_cubes_forward_pass",
             crate::PipelineDesc::builder()
-                // Reusing the forward shader is not ideal since this mesh is not part of the bindless data
-                .vertex_path("utopian/shaders/forward/forward.vert")
+                // The forward vertex shader loads its vertices from the bindless data
+                // which this mesh is not part of
+                .vertex_path("utopian/shaders/marching_cubes/marching_cubes.vert")
                 .fragment_path("utopian/shaders/forward/forward.frag")
                 .default_primitive_vertex_bindings()
                 .default_primitive_vertex_attributes(),
         )
         .read(shadow_map)
+        .read_buffer(light_clusters.light_counts)
+        .read_buffer(light_clusters.light_indices)
         .load_write(deferred_output)
-        .external_depth_attachment(base.depth_image.clone(), vk::AttachmentLoadOp::LOAD)
+        .load_depth_attachment(depth)
         .extra_barriers(&[
             (draw_command_buffer, vk_sync::AccessType::IndirectBuffer),
             (vertex_buffer, vk_sync::AccessType::VertexBuffer),
@@ -3,23 +3,38 @@ use ash::vk;
 use crate::{image::ImageDesc, TextureId};
 
 pub mod atmosphere;
+pub mod bloom;
 pub mod deferred;
+pub mod depth_of_field;
+pub mod exposure;
 pub mod forward;
 pub mod gbuffer;
+pub mod gpu_culling;
+pub mod hiz;
 pub mod ibl;
+pub mod light_culling;
 pub mod marching_cubes;
 pub mod present;
 pub mod rt_reflections;
 pub mod rt_shadows;
 pub mod shadow;
+pub mod skinning;
 pub mod ssao;
+pub mod taa;
 
 pub fn create_gbuffer_textures(
     graph: &mut crate::Graph,
     device: &crate::Device,
     width: u32,
     height: u32,
-) -> (TextureId, TextureId, TextureId, TextureId) {
+) -> (
+    TextureId,
+    TextureId,
+    TextureId,
+    TextureId,
+    TextureId,
+    TextureId,
+) {
     (
         graph.create_texture(
             "gbuffer_position",
@@ -34,13 +49,44 @@ pub fn create_gbuffer_textures(
         graph.create_texture(
             "gbuffer_albedo",
             device,
-            ImageDesc::new_2d(width, height, vk::Format::R8G8B8A8_UNORM),
+            // sRGB to keep the precision of dark linear colors, it can not be a storage image
+            ImageDesc::new_2d(width, height, vk::Format::R8G8B8A8_SRGB).usage(
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ),
         ),
         graph.create_texture(
             "gbuffer_pbr",
             device,
             ImageDesc::new_2d(width, height, vk::Format::R32G32B32A32_SFLOAT),
         ),
+        graph.create_texture(
+            "gbuffer_emissive",
+            device,
+            ImageDesc::new_2d(width, height, vk::Format::R16G16B16A16_SFLOAT),
+        ),
+        graph.create_texture(
+            "gbuffer_velocity",
+            device,
+            ImageDesc::new_2d(width, height, vk::Format::R16G16_SFLOAT),
+        ),
+    )
+}
+
+pub fn create_depth_texture(
+    graph: &mut crate::Graph,
+    device: &crate::Device,
+    width: u32,
+    height: u32,
+) -> TextureId {
+    graph.create_texture(
+        "depth",
+        device,
+        ImageDesc::new_2d(width, height, vk::Format::D32_SFLOAT)
+            .aspect(vk::ImageAspectFlags::DEPTH)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED),
     )
 }
 
@@ -71,9 +117,16 @@ pub fn build_render_graph(
     let width = base.surface_resolution.width;
     let height = base.surface_resolution.height;
 
-    let (gbuffer_position, gbuffer_normal, gbuffer_albedo, gbuffer_pbr) =
-        create_gbuffer_textures(graph, device, width, height);
+    let (
+        gbuffer_position,
+        gbuffer_normal,
+        gbuffer_albedo,
+        gbuffer_pbr,
+        gbuffer_emissive,
+        gbuffer_velocity,
+    ) = create_gbuffer_textures(graph, device, width, height);
 
+    let depth = create_depth_texture(graph, device, width, height);
     let shadow_map = create_shadowmap_texture(graph, device);
 
     let deferred_output = graph.create_texture(
@@ -88,9 +141,12 @@ pub fn build_render_graph(
         ImageDesc::new_2d(width, height, vk::Format::R16_UNORM),
     );
 
+    crate::renderers::skinning::setup_skinning_pass(device, graph);
+
     let (cascade_matrices, cascade_depths) = crate::renderers::shadow::setup_shadow_pass(
         device,
         graph,
+        renderer,
         shadow_map,
         view_data.sun_dir,
         camera,
@@ -110,13 +166,20 @@ pub fn build_render_graph(
     crate::renderers::gbuffer::setup_gbuffer_pass(
         device,
         graph,
-        base,
+        renderer,
+        camera,
+        depth,
         gbuffer_position,
         gbuffer_normal,
         gbuffer_albedo,
         gbuffer_pbr,
+        gbuffer_emissive,
+        gbuffer_velocity,
     );
 
+    // Todo: not used by any pass yet
+    let _hiz = crate::renderers::hiz::setup_hiz_pass(device, graph, depth);
+
     let (environment_map, irradiance_map, specular_map, brdf_lut) =
         crate::renderers::ibl::setup_cubemap_pass(device, graph, renderer);
 
@@ -143,6 +206,8 @@ pub fn build_render_graph(
         view_data.ssao_enabled == 1,
     );
 
+    let light_clusters = crate::renderers::light_culling::setup_light_culling_pass(device, graph);
+
     crate::renderers::deferred::setup_deferred_pass(
         device,
         graph,
@@ -150,6 +215,7 @@ pub fn build_render_graph(
         gbuffer_normal,
         gbuffer_albedo,
         gbuffer_pbr,
+        gbuffer_emissive,
         shadow_map,
         rt_shadows,
         rt_reflections,
@@ -158,6 +224,7 @@ pub fn build_render_graph(
         specular_map,
         brdf_lut,
         (cascade_matrices, cascade_depths),
+        light_clusters,
         deferred_output,
     );
 
@@ -165,10 +232,11 @@ pub fn build_render_graph(
         crate::renderers::marching_cubes::setup_marching_cubes_pass(
             device,
             graph,
-            base,
             deferred_output,
+            depth,
             shadow_map,
             (cascade_matrices, cascade_depths),
+            light_clusters,
             true,
         );
     }
@@ -176,20 +244,60 @@ pub fn build_render_graph(
     crate::renderers::atmosphere::setup_atmosphere_pass(
         device,
         graph,
-        base,
         deferred_output,
+        depth,
         environment_map,
         camera,
         true,
     );
 
-    crate::renderers::present::setup_present_pass(device, graph, deferred_output);
+    let output = if view_data.taa_enabled == 1 {
+        crate::renderers::taa::setup_taa_pass(
+            device,
+            graph,
+            deferred_output,
+            depth,
+            gbuffer_velocity,
+        )
+    } else {
+        deferred_output
+    };
+
+    let output = if view_data.dof_enabled == 1 {
+        let focus =
+            crate::renderers::depth_of_field::setup_focus_pass(device, graph, depth, camera);
+        crate::renderers::depth_of_field::setup_depth_of_field_pass(
+            device, graph, output, depth, focus,
+        )
+    } else {
+        output
+    };
+
+    let output =
+        crate::renderers::bloom::setup_bloom_pass(device, graph, output, &renderer.bloom_settings);
+
+    let exposure = crate::renderers::exposure::setup_exposure_passes(
+        device,
+        graph,
+        output,
+        camera.get_exposure(),
+    );
+
+    crate::renderers::present::setup_present_pass(
+        device,
+        graph,
+        output,
+        exposure,
+        &renderer.tonemap_settings,
+    );
 }
 
 pub fn build_path_tracing_render_graph(
     graph: &mut crate::Graph,
     device: &crate::Device,
     base: &crate::VulkanBase,
+    renderer: &crate::Renderer,
+    camera: &crate::Camera,
 ) {
     puffin::profile_function!();
 
@@ -199,7 +307,7 @@ pub fn build_path_tracing_render_graph(
     let output_image = graph.create_texture(
         "pt_output_image",
         device,
-        ImageDesc::new_2d(width, height, vk::Format::B8G8R8A8_UNORM),
+        ImageDesc::new_2d(width, height, vk::Format::R16G16B16A16_SFLOAT),
     );
 
     let accumulation_image = graph.create_texture(
@@ -208,8 +316,16 @@ pub fn build_path_tracing_render_graph(
         ImageDesc::new_2d(width, height, vk::Format::R32G32B32A32_SFLOAT),
     );
 
-    let (gbuffer_position, gbuffer_normal, gbuffer_albedo, gbuffer_pbr) =
-        create_gbuffer_textures(graph, device, width, height);
+    let (
+        gbuffer_position,
+        gbuffer_normal,
+        gbuffer_albedo,
+        gbuffer_pbr,
+        gbuffer_emissive,
+        gbuffer_velocity,
+    ) = create_gbuffer_textures(graph, device, width, height);
+
+    let depth = create_depth_texture(graph, device, width, height);
 
     #[allow(dead_code)]
     struct Reservoir {
@@ -243,14 +359,20 @@ pub fn build_path_tracing_render_graph(
         gpu_allocator::MemoryLocation::GpuOnly,
     );
 
+    crate::renderers::skinning::setup_skinning_pass(device, graph);
+
     crate::renderers::gbuffer::setup_gbuffer_pass(
         device,
         graph,
-        base,
+        renderer,
+        camera,
+        depth,
         gbuffer_position,
         gbuffer_normal,
         gbuffer_albedo,
         gbuffer_pbr,
+        gbuffer_emissive,
+        gbuffer_velocity,
     );
 
     graph
@@ -342,6 +464,8 @@ pub fn build_path_tracing_render_graph(
     //     .trace_rays(width, height, 1)
     //     .build(device, graph);
 
+    let focus = crate::renderers::depth_of_field::setup_focus_pass(device, graph, depth, camera);
+
     graph
         .add_pass_from_desc(
             "reference_pt_pass",
@@ -354,24 +478,31 @@ pub fn build_path_tracing_render_graph(
         .read_buffer(spatial_reuse_reservoirs)
         .image_write(output_image)
         .image_write(accumulation_image)
+        .read_buffer(focus)
         .trace_rays(width, height, 1)
         .build(device, graph);
 
-    graph
-        .add_pass_from_desc(
-            "reference_pt_present_pass",
-            crate::PipelineDesc::builder()
-                .vertex_path("utopian/shaders/common/fullscreen.vert")
-                .fragment_path("utopian/shaders/blit/blit.frag"),
-        )
-        .read(output_image)
-        .presentation_pass(true)
-        .render(
-            move |device, command_buffer, _renderer, _pass, _resources| unsafe {
-                device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
-            },
-        )
-        .build(device, graph);
+    let output_image = crate::renderers::bloom::setup_bloom_pass(
+        device,
+        graph,
+        output_image,
+        &renderer.bloom_settings,
+    );
+
+    let exposure = crate::renderers::exposure::setup_exposure_passes(
+        device,
+        graph,
+        output_image,
+        camera.get_exposure(),
+    );
+
+    crate::renderers::present::setup_present_pass(
+        device,
+        graph,
+        output_image,
+        exposure,
+        &renderer.tonemap_settings,
+    );
 }
 
 pub fn build_hybrid_render_graph(
@@ -394,6 +525,7 @@ pub fn build_minimal_forward_render_graph(
     graph: &mut crate::Graph,
     device: &crate::Device,
     base: &crate::VulkanBase,
+    renderer: &crate::Renderer,
     view_data: &crate::ViewUniformData,
     camera: &crate::Camera,
 ) {
@@ -409,25 +541,57 @@ pub fn build_minimal_forward_render_graph(
         device,
         ImageDesc::new_2d(width, height, rgba32_fmt),
     );
+    let depth = create_depth_texture(graph, device, width, height);
     let shadow_map = create_shadowmap_texture(graph, device);
 
+    crate::renderers::skinning::setup_skinning_pass(device, graph);
+
     let (cascade_matrices, cascade_depths) = crate::renderers::shadow::setup_shadow_pass(
         device,
         graph,
+        renderer,
         shadow_map,
         view_data.sun_dir,
         camera,
         view_data.shadows_enabled == 1,
     );
 
+    let light_clusters = crate::renderers::light_culling::setup_light_culling_pass(device, graph);
+
     crate::renderers::forward::setup_forward_pass(
         device,
         graph,
-        base,
+        renderer,
+        camera,
+        depth,
         forward_output,
         shadow_map,
         (cascade_matrices, cascade_depths),
+        light_clusters,
     );
 
-    crate::renderers::present::setup_present_pass(device, graph, forward_output);
+    // Todo: not used by any pass yet
+    let _hiz = crate::renderers::hiz::setup_hiz_pass(device, graph, depth);
+
+    let forward_output = crate::renderers::bloom::setup_bloom_pass(
+        device,
+        graph,
+        forward_output,
+        &renderer.bloom_settings,
+    );
+
+    let exposure = crate::renderers::exposure::setup_exposure_passes(
+        device,
+        graph,
+        forward_output,
+        camera.get_exposure(),
+    );
+
+    crate::renderers::present::setup_present_pass(
+        device,
+        graph,
+        forward_output,
+        exposure,
+        &renderer.tonemap_settings,
+    );
 }
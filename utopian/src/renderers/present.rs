@@ -1,14 +1,61 @@
+use crate::graph::BufferId;
+use crate::texture::TextureData;
 use crate::PipelineDesc;
 
+/// Must match the TONEMAPPER_* defines in tonemapping.glsl
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    Clamp = 0,
+    AcesFitted = 1,
+    AgX = 2,
+    ReinhardExtended = 3,
+    PbrNeutral = 4,
+}
+
+#[derive(Clone, Debug)]
+pub struct TonemapSettings {
+    pub tonemapper: Tonemapper,
+    // The luminance that `Tonemapper::ReinhardExtended` maps to white
+    pub white_point: f32,
+    /// Color grading lookup table in the `.cube` format, applied after the tonemapping
+    /// to the sRGB encoded colors
+    pub lut_path: Option<String>,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        TonemapSettings {
+            tonemapper: Tonemapper::AcesFitted,
+            white_point: 4.0,
+            lut_path: None,
+        }
+    }
+}
+
+/// Applies FXAA, the exposure from `renderers::exposure`, tonemapping and color grading
+/// to `color_output` and writes it to the swapchain image
 pub fn setup_present_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
     color_output: crate::TextureId,
+    exposure: BufferId,
+    tonemap_settings: &TonemapSettings,
 ) {
     puffin::profile_function!();
 
     let fxaa_threshold = 0.45;
 
+    // The path is used as the name so that a new LUT is loaded when it changes
+    let color_grading_lut = match &tonemap_settings.lut_path {
+        Some(path) => graph.create_texture_from_data(path, device, || {
+            TextureData::load(path, crate::ColorSpace::Linear).unwrap_or_else(|err| {
+                log::error!("{}", err);
+                TextureData::identity_lut()
+            })
+        }),
+        None => graph.create_texture_from_data("identity_lut", device, TextureData::identity_lut),
+    };
+
     graph
         .add_pass_from_desc(
             "present_pass",
@@ -17,9 +64,19 @@ pub fn setup_present_pass(
                 .fragment_path("utopian/shaders/present/present.frag"),
         )
         .read(color_output)
+        .read_buffer(exposure)
+        .read(color_grading_lut)
         .uniforms(
-            "settings_fxaa",
-            &(glam::Vec4::new(1.0, 0.0, fxaa_threshold, 0.0)),
+            "settings_present",
+            &(
+                glam::Vec4::new(1.0, 0.0, fxaa_threshold, 0.0),
+                glam::Vec4::new(
+                    tonemap_settings.tonemapper as u32 as f32,
+                    tonemap_settings.white_point,
+                    0.0,
+                    0.0,
+                ),
+            ),
         )
         .presentation_pass(true)
         .render(
@@ -25,7 +25,8 @@ pub fn setup_rt_shadows_pass(
                 crate::PipelineDesc::builder()
                     .raygen_path("utopian/shaders/rt_shadows/rt_shadows.rgen")
                     .miss_path("utopian/shaders/rt_shadows/rt_shadows.rmiss")
-                    .hit_path("utopian/shaders/rt_shadows/rt_shadows.rchit"),
+                    .hit_path("utopian/shaders/rt_shadows/rt_shadows.rchit")
+                    .any_hit_path("utopian/shaders/rt_shadows/rt_shadows.rahit"),
             )
             .tlas(0)
             .read(gbuffer_position)
@@ -1,9 +1,11 @@
+use crate::bounds::Frustum;
 use crate::camera;
 use glam::{Mat4, Vec3, Vec4Swizzles};
 
 pub fn setup_shadow_pass(
     device: &crate::Device,
     graph: &mut crate::Graph,
+    renderer: &crate::Renderer,
     shadow_map: crate::TextureId,
     sun_dir: glam::Vec3,
     camera: &camera::Camera,
@@ -61,8 +63,9 @@ pub fn setup_shadow_pass(
             Vec3::new(-1.0, -1.0, 1.0),
         ];
 
-        // Project frustum corners into world space
-        let inv_cam = (camera.get_projection() * camera.get_view()).inverse();
+        // Project frustum corners into world space, without the jitter so that the
+        // cascades do not move every frame
+        let inv_cam = (camera.get_unjittered_projection() * camera.get_view()).inverse();
         for corner in &mut frustum_corners {
             let inv_corner = inv_cam * corner.extend(1.0);
             *corner = inv_corner.xyz() / inv_corner.w;
@@ -108,23 +111,30 @@ pub fn setup_shadow_pass(
 
         last_split_dist = split_dist;
 
+        let draws = crate::renderers::gpu_culling::setup_culling_pass(
+            device,
+            graph,
+            renderer,
+            format!("shadow_{i}").as_str(),
+            Frustum::from_view_projection(view_projection_matrix),
+        );
+
         graph
             .add_pass_from_desc(
                 format!("shadow_pass_{i}").as_str(),
                 crate::PipelineDesc::builder()
                     .vertex_path("utopian/shaders/shadow/shadow.vert")
-                    .fragment_path("utopian/shaders/shadow/shadow.frag")
-                    .default_primitive_vertex_bindings()
-                    .default_primitive_vertex_attributes(),
+                    .fragment_path("utopian/shaders/shadow/shadow.frag"),
             )
             .uniforms("cascade_view_projection", &view_projection_matrix)
             .depth_attachment_layer(shadow_map, i)
+            .extra_barriers(&draws.barriers())
             .render(move |device, command_buffer, renderer, pass, resources| {
                 // Todo: This is a hack to get around the fact that we can't properly disable a pass
                 if enabled {
                     let pipeline = resources.pipeline(pass.pipeline_handle);
 
-                    renderer.draw_meshes(device, command_buffer, pipeline.pipeline_layout);
+                    draws.draw(device, command_buffer, renderer, pipeline, resources);
                 }
             })
             .build(device, graph);
@@ -0,0 +1,64 @@
+/// Applies morph targets and skinning to the vertices of all deformed meshes.
+/// Has to be added before any pass that draws meshes or uses the TLAS.
+pub fn setup_skinning_pass(device: &crate::Device, graph: &mut crate::Graph) {
+    puffin::profile_function!();
+
+    let deformed_vertex_accesses = [
+        vk_sync::AccessType::VertexBuffer,
+        vk_sync::AccessType::AnyShaderReadOther,
+        vk_sync::AccessType::AccelerationStructureBuildRead,
+    ];
+
+    graph
+        .add_pass_from_desc(
+            "skinning_pass",
+            crate::PipelineDesc::builder().compute_path("utopian/shaders/skinning/skinning.comp"),
+        )
+        .render(
+            move |device, command_buffer, renderer, pass, resources| unsafe {
+                if !renderer.has_deformed_meshes() {
+                    return;
+                }
+
+                let pipeline = resources.pipeline(pass.pipeline_handle);
+
+                // The vertex buffers are not graph resources so the barriers are done here
+                vk_sync::cmd::pipeline_barrier(
+                    &device.handle,
+                    command_buffer,
+                    Some(vk_sync::GlobalBarrier {
+                        previous_accesses: &deformed_vertex_accesses,
+                        next_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                    }),
+                    &[],
+                    &[],
+                );
+
+                for deformation in renderer.deformations() {
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline.pipeline_layout,
+                        *deformation,
+                    );
+                    device.handle.cmd_dispatch(
+                        command_buffer,
+                        deformation.num_vertices.div_ceil(64),
+                        1,
+                        1,
+                    );
+                }
+
+                vk_sync::cmd::pipeline_barrier(
+                    &device.handle,
+                    command_buffer,
+                    Some(vk_sync::GlobalBarrier {
+                        previous_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                        next_accesses: &deformed_vertex_accesses,
+                    }),
+                    &[],
+                    &[],
+                );
+            },
+        )
+        .build(device, graph);
+}
@@ -0,0 +1,61 @@
+use ash::vk;
+
+use crate::image::ImageDesc;
+
+/// Resolves the jittered `color` together with the history of the previous frames. The
+/// history is reprojected with the velocity G-buffer target, clipped to the color range of
+/// the current neighborhood and rejected where it is outside of the screen or disoccluded.
+/// Returns the anti-aliased color.
+pub fn setup_taa_pass(
+    device: &crate::Device,
+    graph: &mut crate::Graph,
+    color: crate::TextureId,
+    depth: crate::TextureId,
+    gbuffer_velocity: crate::TextureId,
+) -> crate::TextureId {
+    puffin::profile_function!();
+
+    // How much the current frame contributes when the history is valid
+    let current_weight = 0.1_f32;
+
+    let (width, height) = {
+        let image = &graph.resources.texture(color).texture.image;
+        (image.width(), image.height())
+    };
+
+    // The alpha channel stores the motion of every pixel for the disocclusion test
+    let desc = ImageDesc::new_2d(width, height, vk::Format::R16G16B16A16_SFLOAT);
+    let taa_output = graph.create_texture("taa_output", device, desc);
+    let taa_history = graph.create_texture("taa_history", device, desc);
+
+    // Nothing to blend with before the history has been written once
+    let history_valid =
+        graph.resources.texture(taa_history).prev_access != vk_sync::AccessType::Nothing;
+
+    graph
+        .add_pass_from_desc(
+            "taa_pass",
+            crate::PipelineDesc::builder()
+                .vertex_path("utopian/shaders/common/fullscreen.vert")
+                .fragment_path("utopian/shaders/taa/taa.frag"),
+        )
+        .read(color)
+        .read(depth)
+        .read(gbuffer_velocity)
+        .read(taa_history)
+        .write(taa_output)
+        .uniforms("settings_taa", &(current_weight, history_valid as u32))
+        .render(
+            move |device, command_buffer, _renderer, _pass, _resources| unsafe {
+                device.handle.cmd_draw(command_buffer, 3, 1, 0, 0);
+            },
+        )
+        .copy_image(
+            taa_output,
+            taa_history,
+            crate::ImageCopyDescBuilder::new(width, height).build(),
+        )
+        .build(device, graph);
+
+    taa_output
+}
@@ -0,0 +1,315 @@
+use glam::Mat4;
+
+pub type NodeId = usize;
+
+pub struct Node {
+    pub name: String,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    local_transform: Mat4,
+    world_transform: Mat4,
+    dirty: bool,
+    changed: bool,
+}
+
+/// Transform hierarchy where every node has a local transform relative to its parent.
+/// World transforms are cached and only recalculated for the subtrees that have been
+/// modified since the last call to `update_world_transforms`.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+    has_changes: bool,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph::default()
+    }
+
+    pub fn add_node(
+        &mut self,
+        name: &str,
+        local_transform: Mat4,
+        parent: Option<NodeId>,
+    ) -> NodeId {
+        let id = self.nodes.len();
+
+        self.nodes.push(Node {
+            name: name.to_string(),
+            parent,
+            children: vec![],
+            local_transform,
+            world_transform: local_transform,
+            dirty: true,
+            changed: false,
+        });
+
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    /// Moves all nodes from `other` into this graph with the root nodes of `other`
+    /// attached to `parent`. Returns the offset that has to be added to the node ids
+    /// from `other` to get the corresponding ids in this graph.
+    pub fn append(&mut self, other: SceneGraph, parent: Option<NodeId>) -> NodeId {
+        let offset = self.nodes.len();
+
+        for mut node in other.nodes {
+            node.parent = node.parent.map(|p| p + offset).or(parent);
+            node.children.iter_mut().for_each(|child| *child += offset);
+            node.dirty = true;
+            self.nodes.push(node);
+        }
+
+        for root in other.roots {
+            match parent {
+                Some(parent) => self.nodes[parent].children.push(root + offset),
+                None => self.roots.push(root + offset),
+            }
+        }
+
+        offset
+    }
+
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        if let Some(parent) = parent {
+            assert!(
+                !self.is_descendant_of(parent, id),
+                "Node {} can not be parented to one of its descendants",
+                self.nodes[id].name
+            );
+        }
+
+        match self.nodes[id].parent {
+            Some(old_parent) => self.nodes[old_parent].children.retain(|&child| child != id),
+            None => self.roots.retain(|&root| root != id),
+        }
+
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        self.nodes[id].parent = parent;
+        self.nodes[id].dirty = true;
+    }
+
+    pub fn set_local_transform(&mut self, id: NodeId, transform: Mat4) {
+        self.nodes[id].local_transform = transform;
+        self.nodes[id].dirty = true;
+    }
+
+    pub fn local_transform(&self, id: NodeId) -> Mat4 {
+        self.nodes[id].local_transform
+    }
+
+    /// Note: only valid after `update_world_transforms` has been called
+    pub fn world_transform(&self, id: NodeId) -> Mat4 {
+        self.nodes[id].world_transform
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// Searches the subtree below `root` for a node with the given name
+    pub fn find_child(&self, root: NodeId, name: &str) -> Option<NodeId> {
+        self.nodes[root].children.iter().find_map(|&child| {
+            if self.nodes[child].name == name {
+                Some(child)
+            } else {
+                self.find_child(child, name)
+            }
+        })
+    }
+
+    pub fn is_descendant_of(&self, id: NodeId, ancestor: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.nodes[node].parent;
+        }
+        false
+    }
+
+    /// Recalculates the world transform of every dirty node and its descendants.
+    /// Returns true if any world transform was modified.
+    pub fn update_world_transforms(&mut self) -> bool {
+        puffin::profile_function!();
+
+        let mut any_updated = false;
+
+        for i in 0..self.roots.len() {
+            let root = self.roots[i];
+            any_updated |= self.update_node(root, Mat4::IDENTITY, false);
+        }
+
+        self.has_changes |= any_updated;
+
+        any_updated
+    }
+
+    fn update_node(&mut self, id: NodeId, parent_transform: Mat4, parent_dirty: bool) -> bool {
+        let node = &mut self.nodes[id];
+        let dirty = node.dirty || parent_dirty;
+
+        if dirty {
+            node.world_transform = parent_transform * node.local_transform;
+            node.dirty = false;
+            node.changed = true;
+        }
+
+        let world_transform = node.world_transform;
+        let mut any_updated = dirty;
+
+        for i in 0..self.nodes[id].children.len() {
+            let child = self.nodes[id].children[i];
+            any_updated |= self.update_node(child, world_transform, dirty);
+        }
+
+        any_updated
+    }
+
+    /// True if the world transform of the node has been modified since the
+    /// last call to `clear_changes`
+    pub fn is_changed(&self, id: NodeId) -> bool {
+        self.nodes[id].changed
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.has_changes
+    }
+
+    pub fn clear_changes(&mut self) {
+        if self.has_changes {
+            self.nodes.iter_mut().for_each(|node| node.changed = false);
+            self.has_changes = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    fn translation(x: f32, y: f32, z: f32) -> Mat4 {
+        Mat4::from_translation(Vec3::new(x, y, z))
+    }
+
+    #[test]
+    fn world_transforms_are_composed_from_the_parents() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node("root", Mat4::from_scale(Vec3::splat(2.0)), None);
+        let child = graph.add_node("child", translation(1.0, 0.0, 0.0), Some(root));
+        let grandchild = graph.add_node("grandchild", translation(0.0, 1.0, 0.0), Some(child));
+
+        assert!(graph.update_world_transforms());
+
+        let position = graph
+            .world_transform(grandchild)
+            .transform_point3(Vec3::ZERO);
+        assert_eq!(position, Vec3::new(2.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn dirty_nodes_update_their_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node("root", Mat4::IDENTITY, None);
+        let child = graph.add_node("child", translation(1.0, 0.0, 0.0), Some(root));
+        let other = graph.add_node("other", Mat4::IDENTITY, None);
+        graph.update_world_transforms();
+        graph.clear_changes();
+
+        assert!(!graph.update_world_transforms());
+
+        graph.set_local_transform(root, translation(0.0, 0.0, 5.0));
+        assert!(graph.update_world_transforms());
+
+        assert!(graph.is_changed(root));
+        assert!(graph.is_changed(child));
+        assert!(!graph.is_changed(other));
+        assert_eq!(
+            graph.world_transform(child).transform_point3(Vec3::ZERO),
+            Vec3::new(1.0, 0.0, 5.0)
+        );
+
+        graph.clear_changes();
+        assert!(!graph.has_changes());
+        assert!(!graph.is_changed(child));
+    }
+
+    #[test]
+    fn reparented_nodes_follow_their_new_parent() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node("a", translation(1.0, 0.0, 0.0), None);
+        let b = graph.add_node("b", translation(0.0, 1.0, 0.0), None);
+        let child = graph.add_node("child", Mat4::IDENTITY, Some(a));
+
+        graph.set_parent(child, Some(b));
+        graph.update_world_transforms();
+
+        assert!(graph.node(a).children.is_empty());
+        assert_eq!(graph.node(b).children, vec![child]);
+        assert_eq!(
+            graph.world_transform(child).transform_point3(Vec3::ZERO),
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn nodes_can_not_be_parented_to_their_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node("root", Mat4::IDENTITY, None);
+        let child = graph.add_node("child", Mat4::IDENTITY, Some(root));
+
+        graph.set_parent(root, Some(child));
+    }
+
+    #[test]
+    fn appended_graphs_are_offset_and_attached_to_the_parent() {
+        let mut graph = SceneGraph::new();
+        graph.add_node("unrelated", Mat4::IDENTITY, None);
+        let parent = graph.add_node("parent", translation(10.0, 0.0, 0.0), None);
+
+        let mut other = SceneGraph::new();
+        let other_root = other.add_node("mesh_root", Mat4::IDENTITY, None);
+        let other_child = other.add_node("mesh", translation(0.0, 1.0, 0.0), Some(other_root));
+
+        let offset = graph.append(other, Some(parent));
+
+        assert_eq!(offset, 2);
+        assert_eq!(graph.num_nodes(), 4);
+        assert_eq!(graph.node(other_root + offset).parent, Some(parent));
+        assert_eq!(
+            graph.node(other_child + offset).parent,
+            Some(other_root + offset)
+        );
+        assert_eq!(graph.node(parent).children, vec![other_root + offset]);
+        assert_eq!(graph.find_child(parent, "mesh"), Some(other_child + offset));
+
+        graph.update_world_transforms();
+        assert_eq!(
+            graph
+                .world_transform(other_child + offset)
+                .transform_point3(Vec3::ZERO),
+            Vec3::new(10.0, 1.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use glam::{Mat4, Quat, Vec3};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::asset_loader::*;
+use crate::asset_manager::AssetManager;
+use crate::device::*;
+use crate::gltf_loader::*;
+use crate::obj_loader::*;
+use crate::scene_graph::NodeId;
+use crate::Camera;
+use crate::Light;
+use crate::ModelLoader;
+use crate::Renderer;
+use crate::ViewUniformData;
+
+/// Scene description that is deserialized from a RON file, see
+/// `prototype/data/scenes/` for examples.
+#[derive(Deserialize)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub environment: EnvironmentDesc,
+    #[serde(default)]
+    pub models: Vec<ModelDesc>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+}
+
+#[derive(Deserialize)]
+pub struct CameraDesc {
+    pub position: Vec3,
+    pub target: Vec3,
+    // Name of a camera in one of the glTF models that overrides position and target
+    #[serde(default)]
+    pub gltf_camera: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EnvironmentDesc {
+    pub sun_dir: Vec3,
+    pub sky_enabled: bool,
+    pub ibl_enabled: bool,
+    pub cubemap_enabled: bool,
+}
+
+#[derive(Deserialize)]
+pub enum ModelSource {
+    Gltf(String),
+    Obj(String),
+    Cube,
+    Triangle,
+    UvSphere {
+        radius: f32,
+        sectors: u32,
+        stacks: u32,
+    },
+    Icosphere {
+        radius: f32,
+        subdivisions: u32,
+    },
+    Plane {
+        width: f32,
+        depth: f32,
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Capsule {
+        radius: f32,
+        height: f32,
+        segments: u32,
+        rings: u32,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TransformDesc {
+    pub position: Vec3,
+    pub rotation: Vec3, // Euler angles in degrees
+    pub scale: Vec3,
+}
+
+#[derive(Deserialize)]
+pub struct MaterialOverride {
+    // Applies to all meshes in the model if not set
+    #[serde(default)]
+    pub mesh: Option<usize>,
+    pub material_type: MaterialType,
+    #[serde(default)]
+    pub material_property: f32,
+}
+
+// Places a copy of the model at every point in a `count` sized grid
+#[derive(Deserialize)]
+pub struct GridDesc {
+    pub count: (u32, u32, u32),
+    pub spacing: Vec3,
+}
+
+#[derive(Deserialize)]
+pub struct ModelDesc {
+    pub source: ModelSource,
+    #[serde(default)]
+    pub transform: TransformDesc,
+    #[serde(default)]
+    pub material_overrides: Vec<MaterialOverride>,
+    #[serde(default)]
+    pub grid: Option<GridDesc>,
+    // Name of an animation clip in the glTF model that is looped from the start
+    #[serde(default)]
+    pub animation: Option<String>,
+}
+
+impl Default for EnvironmentDesc {
+    fn default() -> Self {
+        EnvironmentDesc {
+            sun_dir: Vec3::new(0.0, 0.9, 0.15),
+            sky_enabled: true,
+            ibl_enabled: true,
+            cubemap_enabled: true,
+        }
+    }
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            position: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl TransformDesc {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            self.scale,
+            Quat::from_euler(
+                glam::EulerRot::XYZ,
+                self.rotation.x.to_radians(),
+                self.rotation.y.to_radians(),
+                self.rotation.z.to_radians(),
+            ),
+            self.position,
+        )
+    }
+}
+
+impl EnvironmentDesc {
+    pub fn apply(&self, view_data: &mut ViewUniformData) {
+        view_data.sun_dir = self.sun_dir.normalize();
+        view_data.sky_enabled = self.sky_enabled as u32;
+        view_data.ibl_enabled = self.ibl_enabled as u32;
+        view_data.cubemap_enabled = self.cubemap_enabled as u32;
+    }
+}
+
+impl ModelDesc {
+    // `data` is the decoded file for glTF and OBJ sources
+    fn create_model(
+        &self,
+        device: &Device,
+        asset_manager: &mut AssetManager,
+        data: Option<&AssetData>,
+    ) -> Model {
+        let mut model = match (&self.source, data) {
+            (ModelSource::Gltf(path), Some(AssetData::Gltf(gltf_data))) => {
+                create_gltf_model(device, asset_manager, gltf_data, path)
+            }
+            (ModelSource::Obj(path), Some(AssetData::Obj(obj_data))) => {
+                create_obj_model(device, asset_manager, obj_data, path)
+            }
+            (ModelSource::Gltf(path) | ModelSource::Obj(path), _) => {
+                panic!("Model {} has not been decoded", path)
+            }
+            (ModelSource::Cube, _) => ModelLoader::load_cube(device, asset_manager),
+            (ModelSource::Triangle, _) => ModelLoader::load_triangle(device, asset_manager),
+            (
+                ModelSource::UvSphere {
+                    radius,
+                    sectors,
+                    stacks,
+                },
+                _,
+            ) => ModelLoader::load_uv_sphere(
+                device,
+                asset_manager,
+                *radius,
+                *sectors,
+                *stacks,
+                Material::default(),
+            ),
+            (
+                ModelSource::Icosphere {
+                    radius,
+                    subdivisions,
+                },
+                _,
+            ) => ModelLoader::load_icosphere(
+                device,
+                asset_manager,
+                *radius,
+                *subdivisions,
+                Material::default(),
+            ),
+            (
+                ModelSource::Plane {
+                    width,
+                    depth,
+                    subdivisions,
+                },
+                _,
+            ) => ModelLoader::load_plane(
+                device,
+                asset_manager,
+                *width,
+                *depth,
+                *subdivisions,
+                Material::default(),
+            ),
+            (
+                ModelSource::Cylinder {
+                    radius,
+                    height,
+                    segments,
+                },
+                _,
+            ) => ModelLoader::load_cylinder(
+                device,
+                asset_manager,
+                *radius,
+                *height,
+                *segments,
+                Material::default(),
+            ),
+            (
+                ModelSource::Cone {
+                    radius,
+                    height,
+                    segments,
+                },
+                _,
+            ) => ModelLoader::load_cone(
+                device,
+                asset_manager,
+                *radius,
+                *height,
+                *segments,
+                Material::default(),
+            ),
+            (
+                ModelSource::Capsule {
+                    radius,
+                    height,
+                    segments,
+                    rings,
+                },
+                _,
+            ) => ModelLoader::load_capsule(
+                device,
+                asset_manager,
+                *radius,
+                *height,
+                *segments,
+                *rings,
+                Material::default(),
+            ),
+            (
+                ModelSource::Torus {
+                    major_radius,
+                    minor_radius,
+                    major_segments,
+                    minor_segments,
+                },
+                _,
+            ) => ModelLoader::load_torus(
+                device,
+                asset_manager,
+                *major_radius,
+                *minor_radius,
+                *major_segments,
+                *minor_segments,
+                Material::default(),
+            ),
+        };
+
+        for material_override in &self.material_overrides {
+            for (i, mesh) in model.meshes.iter_mut().enumerate() {
+                if material_override.mesh.is_none() || material_override.mesh == Some(i) {
+                    mesh.material.material_type = material_override.material_type;
+                    mesh.material.material_property = material_override.material_property;
+                }
+            }
+        }
+
+        model
+    }
+
+    // Returns the scene graph root node of every added model
+    fn add_instances(
+        &self,
+        device: &Device,
+        renderer: &mut Renderer,
+        data: Option<&AssetData>,
+    ) -> Vec<NodeId> {
+        let transform = self.transform.matrix();
+        let mut roots = vec![];
+
+        match &self.grid {
+            Some(grid) => {
+                for x in 0..grid.count.0 {
+                    for y in 0..grid.count.1 {
+                        for z in 0..grid.count.2 {
+                            let offset = Vec3::new(x as f32, y as f32, z as f32) * grid.spacing;
+                            let model =
+                                self.create_model(device, &mut renderer.asset_manager, data);
+                            roots.push(renderer.add_model(
+                                device,
+                                model,
+                                Mat4::from_translation(offset) * transform,
+                            ));
+                        }
+                    }
+                }
+            }
+            None => {
+                let model = self.create_model(device, &mut renderer.asset_manager, data);
+                roots.push(renderer.add_model(device, model, transform));
+            }
+        }
+
+        if let Some(animation) = &self.animation {
+            for &root in &roots {
+                renderer.play_animation(root, animation, 1.0, true);
+            }
+        }
+
+        roots
+    }
+}
+
+// Fails instead of panicking since a scene file can be half saved when it is hot reloaded
+pub fn read_scene(path: &str) -> Result<SceneDesc, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("Reading scene {} failed with error: {}", path, err))?;
+
+    ron::from_str(&source)
+        .map_err(|err| format!("Parsing scene {} failed with error: {}", path, err))
+}
+
+/// A scene whose glTF and OBJ models are decoded in the background by the `AssetLoader`,
+/// `update` adds them to the renderer when they are ready. The models are added in
+/// the order they finish decoding, use `model_roots` to find the instances of a model.
+pub struct PendingScene {
+    path: String,
+    scene: SceneDesc,
+    // Index into `scene.models` of every glTF and OBJ file that is being decoded
+    pending_models: HashMap<AssetId, usize>,
+    // Scene graph roots of the instances of every model in `scene.models`
+    model_roots: Vec<Vec<NodeId>>,
+    // Index into `scene.models` and the error of every file that failed to decode
+    failed_models: Vec<(usize, String)>,
+}
+
+/// Adds all lights and procedural models from a scene read by `read_scene` to the renderer,
+/// moves the camera to the start position and queues the glTF and OBJ models for background
+/// loading.
+pub fn load_scene(
+    device: &Device,
+    scene: SceneDesc,
+    path: &str,
+    renderer: &mut Renderer,
+    camera: &mut Camera,
+    view_data: &mut ViewUniformData,
+    asset_loader: &mut AssetLoader,
+) -> PendingScene {
+    puffin::profile_function!();
+
+    camera.set_position_target(scene.camera.position, scene.camera.target);
+    scene.environment.apply(view_data);
+    renderer.need_environment_map_update = true;
+
+    let optimize_meshes = renderer.asset_manager.optimize_meshes;
+    let mut pending_models = HashMap::new();
+    let mut model_roots = vec![vec![]; scene.models.len()];
+
+    for (i, model_desc) in scene.models.iter().enumerate() {
+        match &model_desc.source {
+            ModelSource::Gltf(model_path) => {
+                pending_models.insert(asset_loader.load_gltf(model_path, optimize_meshes), i);
+            }
+            ModelSource::Obj(model_path) => {
+                pending_models.insert(asset_loader.load_obj(model_path, optimize_meshes), i);
+            }
+            _ => {
+                model_roots[i] = model_desc.add_instances(device, renderer, None);
+            }
+        }
+    }
+
+    for light in &scene.lights {
+        renderer.add_light(device, *light);
+    }
+
+    let pending_scene = PendingScene {
+        path: path.to_string(),
+        scene,
+        pending_models,
+        model_roots,
+        failed_models: vec![],
+    };
+
+    if pending_scene.is_loaded() {
+        pending_scene.finish(renderer, camera);
+    }
+
+    pending_scene
+}
+
+impl PendingScene {
+    /// Adds the models that have been decoded since the last call and queues their
+    /// textures for streaming. Returns true if any model was added.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        renderer: &mut Renderer,
+        camera: &mut Camera,
+        asset_loader: &mut AssetLoader,
+    ) -> bool {
+        puffin::profile_function!();
+
+        let mut added_models = false;
+
+        // Adding models only writes unused bindless descriptors and the mesh data is
+        // uploaded before the next frame, so the frames in flight are not waited for
+        for decoded in asset_loader.poll_decoded() {
+            // Results from a scene that has been reloaded since are ignored
+            let model_index = match self.pending_models.remove(&decoded.id) {
+                Some(model_index) => model_index,
+                None => continue,
+            };
+
+            match decoded.data {
+                Ok(data) => {
+                    let roots =
+                        self.scene.models[model_index].add_instances(device, renderer, Some(&data));
+                    if let AssetData::Gltf(gltf_data) = data {
+                        asset_loader.stream_textures(
+                            gltf_data.textures,
+                            roots.clone(),
+                            &decoded.path,
+                        );
+                    }
+                    self.model_roots[model_index] = roots;
+                    added_models = true;
+                }
+                Err(err) => {
+                    log::error!(
+                        "Model {} ({}) in scene {} failed to load: {}",
+                        model_index,
+                        decoded.path,
+                        self.path,
+                        err
+                    );
+                    self.failed_models.push((model_index, err));
+                }
+            }
+
+            if self.is_loaded() {
+                self.finish(renderer, camera);
+            }
+        }
+
+        added_models
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.pending_models.is_empty()
+    }
+
+    /// The models in the scene file that failed to decode, with their error. They are
+    /// left out of the scene.
+    pub fn failed_models(&self) -> &[(usize, String)] {
+        &self.failed_models
+    }
+
+    /// The instances of model `model_index` in the scene file, empty until it is loaded
+    pub fn model_roots(&self, model_index: usize) -> &[NodeId] {
+        self.model_roots
+            .get(model_index)
+            .map_or(&[], |roots| roots.as_slice())
+    }
+
+    fn finish(&self, renderer: &Renderer, camera: &mut Camera) {
+        if let Some(name) = &self.scene.camera.gltf_camera {
+            match renderer.find_camera(name) {
+                Some((gltf_camera, world_transform)) => {
+                    camera.set_from_model_camera(gltf_camera, world_transform)
+                }
+                None => log::warn!("Camera {} not found in scene {}", name, self.path),
+            }
+        }
+
+        // Includes the lights from the glTF models
+        log::info!(
+            "Loaded scene {} with {} models and {} lights",
+            self.path,
+            self.scene.models.len() - self.failed_models.len(),
+            renderer.get_num_lights()
+        );
+
+        if !self.failed_models.is_empty() {
+            log::warn!(
+                "{} models in scene {} failed to load",
+                self.failed_models.len(),
+                self.path
+            );
+        }
+    }
+}
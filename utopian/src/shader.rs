@@ -132,6 +132,8 @@ pub fn compile_glsl_shader(path: &str) -> Result<shaderc::CompilationArtifact, s
         shaderc::ShaderKind::Miss
     } else if path.ends_with(".rchit") {
         shaderc::ShaderKind::ClosestHit
+    } else if path.ends_with(".rahit") {
+        shaderc::ShaderKind::AnyHit
     } else if path.ends_with(".comp") {
         shaderc::ShaderKind::Compute
     } else {
@@ -55,3 +55,38 @@ pub fn image_pipeline_barrier(
 
     next_access
 }
+
+pub fn image_mips_pipeline_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: &Image,
+    base_mip_level: u32,
+    level_count: u32,
+    prev_access: vk_sync::AccessType,
+    next_access: vk_sync::AccessType,
+) -> vk_sync::AccessType {
+    vk_sync::cmd::pipeline_barrier(
+        &device.handle,
+        command_buffer,
+        None,
+        &[],
+        &[vk_sync::ImageBarrier {
+            previous_accesses: &[prev_access],
+            next_accesses: &[next_access],
+            previous_layout: vk_sync::ImageLayout::Optimal,
+            next_layout: vk_sync::ImageLayout::Optimal,
+            discard_contents: false,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: image.image,
+            range: vk::ImageSubresourceRange::builder()
+                .aspect_mask(image.desc.aspect_flags)
+                .base_mip_level(base_mip_level)
+                .level_count(level_count)
+                .layer_count(vk::REMAINING_ARRAY_LAYERS)
+                .build(),
+        }],
+    );
+
+    next_access
+}
@@ -0,0 +1,232 @@
+use glam::{Vec3, Vec4};
+use std::collections::HashMap;
+
+use crate::primitive::Vertex;
+
+/// Gives every triangle its face normal, used for meshes without normals like glTF
+/// requires. Vertices are no longer shared between triangles afterwards, the returned
+/// list has the original index of every new vertex so that other per vertex data
+/// can be remapped with `remap_vertex_data`.
+pub fn generate_flat_normals(indices: &mut Vec<u32>, vertices: &mut Vec<Vertex>) -> Vec<u32> {
+    let source_vertices = std::mem::take(indices);
+
+    let mut flat_vertices: Vec<Vertex> = source_vertices
+        .iter()
+        .map(|&index| vertices[index as usize])
+        .collect();
+
+    for triangle in flat_vertices.chunks_exact_mut(3) {
+        let p0 = triangle[0].pos.truncate();
+        let p1 = triangle[1].pos.truncate();
+        let p2 = triangle[2].pos.truncate();
+        let normal = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+
+        for vertex in triangle {
+            vertex.normal = normal.extend(0.0);
+        }
+    }
+
+    *indices = (0..flat_vertices.len() as u32).collect();
+    *vertices = flat_vertices;
+
+    source_vertices
+}
+
+/// Gives the vertices without a normal the face normal of their triangles and leaves the
+/// others as they are, for formats like OBJ where every face corner can omit its normal.
+/// Vertices without a normal are split between triangles facing different directions.
+pub fn generate_missing_normals(indices: &mut [u32], vertices: &mut Vec<Vertex>) {
+    let mut split_vertices: Vec<Vertex> = vec![];
+    let mut vertex_indices: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+
+    for triangle in indices.chunks_exact_mut(3) {
+        let p0 = vertices[triangle[0] as usize].pos.truncate();
+        let p1 = vertices[triangle[1] as usize].pos.truncate();
+        let p2 = vertices[triangle[2] as usize].pos.truncate();
+        let face_normal = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+
+        for index in triangle {
+            let vertex = vertices[*index as usize];
+            let normal = match vertex.normal == Vec4::ZERO {
+                true => face_normal,
+                false => vertex.normal.truncate(),
+            };
+
+            let key = (*index, normal.to_array().map(f32::to_bits));
+            *index = *vertex_indices.entry(key).or_insert_with(|| {
+                split_vertices.push(Vertex {
+                    normal: normal.extend(0.0),
+                    ..vertex
+                });
+                split_vertices.len() as u32 - 1
+            });
+        }
+    }
+
+    *vertices = split_vertices;
+}
+
+pub fn remap_vertex_data<T: Copy>(data: Vec<T>, source_vertices: Option<&[u32]>) -> Vec<T> {
+    match source_vertices {
+        Some(source_vertices) => source_vertices
+            .iter()
+            .map(|&index| data[index as usize])
+            .collect(),
+        None => data,
+    }
+}
+
+fn any_perpendicular(normal: Vec3) -> Vec3 {
+    let axis = match normal.x.abs() < 0.9 {
+        true => Vec3::X,
+        false => Vec3::Y,
+    };
+
+    normal.cross(axis).normalize_or_zero()
+}
+
+// Per triangle corner view of a mesh for the mikktspace crate
+struct MikkTSpaceGeometry<'a> {
+    indices: &'a [u32],
+    vertices: &'a [Vertex],
+    tangents: Vec<Vec4>,
+}
+
+impl<'a> MikkTSpaceGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a> mikktspace::Geometry for MikkTSpaceGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).pos.truncate().into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal.truncate().into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        // MikkTSpace works in a texture space where the v axis points up while glTF
+        // texture coordinates have it pointing down
+        let uv = self.vertex(face, vert).uv;
+        [uv.x, -uv.y]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = Vec4::from(tangent);
+    }
+}
+
+/// Generates tangents with MikkTSpace, which is what Blender and most baking tools use,
+/// so that tangent space normal maps look the same as in those tools. `w` is the
+/// handedness of the bitangent, `cross(normal, tangent) * w`.
+/// MikkTSpace gives every triangle corner its own tangent so vertices are split where
+/// the corners sharing them got different tangents, e.g at UV seams. Like with
+/// `generate_flat_normals` the original index of every new vertex is returned.
+pub fn generate_tangents(indices: &mut [u32], vertices: &mut Vec<Vertex>) -> Vec<u32> {
+    let mut geometry = MikkTSpaceGeometry {
+        indices,
+        vertices,
+        tangents: vec![Vec4::ZERO; indices.len()],
+    };
+
+    if !indices.is_empty() && !mikktspace::generate_tangents(&mut geometry) {
+        log::warn!("Failed to generate tangents for a mesh");
+    }
+
+    let tangents = geometry.tangents;
+
+    let mut split_vertices: Vec<Vertex> = vec![];
+    let mut source_vertices: Vec<u32> = vec![];
+    let mut vertex_indices: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+
+    for (index, mut tangent) in indices.iter_mut().zip(tangents) {
+        let vertex = vertices[*index as usize];
+        let normal = vertex.normal.truncate().normalize_or_zero();
+
+        // Vertices without any valid UV mapping still get an orthogonal tangent space
+        if tangent.truncate() == Vec3::ZERO {
+            tangent = any_perpendicular(normal).extend(1.0);
+        }
+
+        let key = (*index, tangent.to_array().map(f32::to_bits));
+        *index = *vertex_indices.entry(key).or_insert_with(|| {
+            split_vertices.push(Vertex { tangent, ..vertex });
+            source_vertices.push(*index);
+            split_vertices.len() as u32 - 1
+        });
+    }
+
+    *vertices = split_vertices;
+
+    source_vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    fn vertex(pos: Vec3, uv: Vec2) -> Vertex {
+        Vertex {
+            pos: pos.extend(0.0),
+            normal: Vec4::new(0.0, 0.0, 1.0, 0.0),
+            uv,
+            color: Vec4::ONE,
+            tangent: Vec4::ZERO,
+        }
+    }
+
+    #[test]
+    fn vertices_are_shared_when_the_tangents_agree() {
+        let mut indices = vec![0, 1, 2, 0, 2, 3];
+        let mut vertices = vec![
+            vertex(Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 1.0)),
+            vertex(Vec3::new(1.0, 0.0, 0.0), Vec2::new(1.0, 1.0)),
+            vertex(Vec3::new(1.0, 1.0, 0.0), Vec2::new(1.0, 0.0)),
+            vertex(Vec3::new(0.0, 1.0, 0.0), Vec2::new(0.0, 0.0)),
+        ];
+
+        let source_vertices = generate_tangents(&mut indices, &mut vertices);
+
+        assert_eq!(source_vertices, [0, 1, 2, 3]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        for vertex in &vertices {
+            assert!(vertex
+                .tangent
+                .abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 1.0), 1e-5));
+        }
+    }
+
+    #[test]
+    fn vertices_are_split_at_mirrored_uvs() {
+        let mut indices = vec![0, 1, 2, 0, 2, 3];
+        let mut vertices = vec![
+            vertex(Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 1.0)),
+            vertex(Vec3::new(1.0, 0.0, 0.0), Vec2::new(1.0, 1.0)),
+            vertex(Vec3::new(0.0, 1.0, 0.0), Vec2::new(0.0, 0.0)),
+            vertex(Vec3::new(-1.0, 0.0, 0.0), Vec2::new(1.0, 1.0)),
+        ];
+
+        let source_vertices = generate_tangents(&mut indices, &mut vertices);
+
+        assert_eq!(source_vertices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+        assert!(vertices[0]
+            .tangent
+            .abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 1.0), 1e-5));
+        assert!(vertices[3]
+            .tangent
+            .abs_diff_eq(Vec4::new(-1.0, 0.0, 0.0, -1.0), 1e-5));
+    }
+}
@@ -1,8 +1,16 @@
 use ash::vk;
 
-use crate::buffer::*;
 use crate::device::*;
 use crate::image::*;
+use crate::ktx2::Ktx2Image;
+
+/// How the color channels of a texture are encoded. Base color and emissive
+/// textures are sRGB, normal maps and other material data are linear.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+}
 
 pub struct Texture {
     pub image: Image,
@@ -10,40 +18,266 @@ pub struct Texture {
     pub descriptor_info: vk::DescriptorImageInfo,
 }
 
-impl Texture {
-    pub fn load(device: &Device, path: &str) -> Texture {
-        let image = match image::open(path) {
-            Ok(image) => image,
-            Err(_err) => panic!("Unable to load \"{}\"", path),
+/// Decoded texels that are ready to be uploaded, this can be created without a
+/// device so that decoding can be done on worker threads
+pub struct TextureData {
+    pub desc: ImageDesc,
+    // Tightly packed data of the first mip levels, the rest is generated on upload
+    pub mips: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn load(path: &str, color_space: ColorSpace) -> Result<TextureData, String> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+
+        match extension {
+            Some("ktx2") => TextureData::load_ktx2(path, color_space),
+            Some("hdr") => Ok(TextureData::load_hdr(path)),
+            Some("cube") => Ok(TextureData::load_cube_lut(path)),
+            _ => {
+                let image = image::open(path)
+                    .map_err(|err| format!("Unable to load \"{}\": {}", path, err))?;
+
+                Ok(TextureData::from_image(&image, color_space))
+            }
+        }
+    }
+
+    /// Full mip chain texture data, 16-bit images are kept as 16-bit and everything
+    /// else is expanded to rgba8. There are no 16-bit sRGB formats so those are
+    /// converted to linear on the CPU.
+    pub fn from_image(image: &image::DynamicImage, color_space: ColorSpace) -> TextureData {
+        use image::GenericImageView;
+        let (width, height) = image.dimensions();
+
+        let (format, pixels) = match image {
+            image::DynamicImage::ImageLuma16(_)
+            | image::DynamicImage::ImageLumaA16(_)
+            | image::DynamicImage::ImageRgb16(_)
+            | image::DynamicImage::ImageRgba16(_) => (
+                vk::Format::R16G16B16A16_UNORM,
+                image
+                    .to_rgba16()
+                    .into_raw()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &value)| match color_space {
+                        ColorSpace::Srgb if i % 4 != 3 => srgb_to_linear_u16(value),
+                        _ => value,
+                    })
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect(),
+            ),
+            _ => {
+                let format = match color_space {
+                    ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+                    ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+                };
+                (format, image.to_rgba8().into_raw())
+            }
         };
 
-        let image = image.to_rgba8();
-        let (width, height) = (image.width(), image.height());
-        let image_data = image.into_raw();
+        TextureData {
+            desc: sampled_image_desc(width, height, format).full_mip_chain(),
+            mips: vec![pixels],
+        }
+    }
+
+    fn load_hdr(path: &str) -> TextureData {
+        let decoder = std::fs::File::open(path)
+            .ok()
+            .and_then(|file| {
+                image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file)).ok()
+            })
+            .unwrap_or_else(|| panic!("Unable to load \"{}\"", path));
+
+        let metadata = decoder.metadata();
+        let pixels: Vec<u8> = match decoder.read_image_hdr() {
+            Ok(pixels) => pixels
+                .iter()
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+                .flat_map(|value| value.to_ne_bytes())
+                .collect(),
+            Err(_err) => panic!("Unable to decode \"{}\"", path),
+        };
+
+        TextureData {
+            desc: sampled_image_desc(
+                metadata.width,
+                metadata.height,
+                vk::Format::R32G32B32A32_SFLOAT,
+            )
+            .full_mip_chain(),
+            mips: vec![pixels],
+        }
+    }
 
-        let mut texture = Texture::create(
+    /// 3D color lookup table from a `.cube` file. The red coordinate varies fastest in
+    /// the file which matches the texel order of the 3D texture.
+    fn load_cube_lut(path: &str) -> TextureData {
+        let contents =
+            std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to load \"{}\"", path));
+
+        let parse = |value: &str| {
+            value
+                .parse::<f32>()
+                .unwrap_or_else(|_| panic!("Invalid LUT entry in \"{}\"", path))
+        };
+
+        let mut size = 0;
+        let mut texels: Vec<[f32; 3]> = vec![];
+
+        for line in contents.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            match tokens.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["LUT_3D_SIZE", value] => {
+                    size = value
+                        .parse()
+                        .unwrap_or_else(|_| panic!("Invalid LUT_3D_SIZE in \"{}\"", path))
+                }
+                [keyword @ ("DOMAIN_MIN" | "DOMAIN_MAX"), r, g, b] => {
+                    let expected = if *keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if [r, g, b].iter().any(|value| parse(value) != expected) {
+                        log::warn!("Only the [0, 1] domain is supported by \"{}\"", path);
+                    }
+                }
+                ["LUT_1D_SIZE", ..] => panic!("1D LUTs are not supported, \"{}\"", path),
+                [r, g, b] if r.parse::<f32>().is_ok() => {
+                    texels.push([parse(r), parse(g), parse(b)]);
+                }
+                // TITLE and other keywords
+                _ => {}
+            }
+        }
+
+        if size == 0 || texels.len() != (size * size * size) as usize {
+            panic!("Expected {}^3 LUT entries in \"{}\"", size, path);
+        }
+
+        TextureData::from_lut_texels(size, &texels)
+    }
+
+    /// Lookup table that leaves the colors unchanged, since the texels are linearly
+    /// interpolated a size of 2 is enough
+    pub fn identity_lut() -> TextureData {
+        let texels: Vec<[f32; 3]> = (0..8)
+            .map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32])
+            .collect();
+
+        TextureData::from_lut_texels(2, &texels)
+    }
+
+    fn from_lut_texels(size: u32, texels: &[[f32; 3]]) -> TextureData {
+        let pixels = texels
+            .iter()
+            .flat_map(|texel| [texel[0], texel[1], texel[2], 1.0])
+            .flat_map(|value| half::f16::from_f32(value).to_ne_bytes())
+            .collect();
+
+        TextureData {
+            desc: ImageDesc::new_3d(size, size, size, vk::Format::R16G16B16A16_SFLOAT),
+            mips: vec![pixels],
+        }
+    }
+
+    fn load_ktx2(path: &str, color_space: ColorSpace) -> Result<TextureData, String> {
+        let mut ktx2_image = Ktx2Image::load(path)?;
+
+        if color_space == ColorSpace::Srgb {
+            ktx2_image.format = srgb_format(ktx2_image.format);
+        }
+
+        let desc = sampled_image_desc(ktx2_image.width, ktx2_image.height, ktx2_image.format);
+
+        Ok(TextureData {
+            desc: match ktx2_image.generate_mips {
+                true => desc.full_mip_chain(),
+                false => desc.mip_levels(ktx2_image.levels.len() as u32),
+            },
+            mips: ktx2_image.levels,
+        })
+    }
+}
+
+impl Texture {
+    pub fn load(device: &Device, path: &str, color_space: ColorSpace) -> Result<Texture, String> {
+        Texture::from_data(device, &TextureData::load(path, color_space)?, path)
+    }
+
+    pub fn from_image(
+        device: &Device,
+        image: &image::DynamicImage,
+        color_space: ColorSpace,
+        debug_name: &str,
+    ) -> Result<Texture, String> {
+        Texture::from_data(
             device,
-            Some(&image_data),
-            ImageDesc::new_2d(width, height, vk::Format::R8G8B8A8_UNORM),
-            path,
-        );
+            &TextureData::from_image(image, color_space),
+            debug_name,
+        )
+    }
 
-        texture.image.set_debug_name(device, path);
+    // Unsupported formats are errors so that the caller can keep a default texture
+    pub fn from_data(
+        device: &Device,
+        data: &TextureData,
+        debug_name: &str,
+    ) -> Result<Texture, String> {
+        if !device.supports_sampled_format(data.desc.format) {
+            return Err(format!(
+                "The GPU does not support sampling {:?} used by \"{}\"",
+                data.desc.format, debug_name
+            ));
+        }
 
-        texture
+        let mips: Vec<&[u8]> = data.mips.iter().map(|mip| &mip[..]).collect();
+
+        Ok(Texture::create_with_mips(
+            device, &mips, data.desc, debug_name,
+        ))
     }
 
+    /// The remaining mip levels after `pixels` are generated with blits
     pub fn create(
         device: &Device,
         pixels: Option<&[u8]>,
         image_desc: ImageDesc,
         debug_name: &str,
     ) -> Texture {
+        let mips: Vec<&[u8]> = pixels.into_iter().collect();
+        Texture::create_with_mips(device, &mips, image_desc, debug_name)
+    }
+
+    /// Uploads the tightly packed data of the first `mips.len()` mip levels. If the
+    /// image has more mip levels than that they are generated with blits.
+    pub fn create_with_mips(
+        device: &Device,
+        mips: &[&[u8]],
+        mut image_desc: ImageDesc,
+        debug_name: &str,
+    ) -> Texture {
+        let num_uploaded_mips = (mips.len() as u32).min(image_desc.mip_levels);
+        let generate_mips = num_uploaded_mips > 0 && num_uploaded_mips < image_desc.mip_levels;
+
+        if generate_mips && !device.supports_linear_blit(image_desc.format) {
+            log::warn!(
+                "Mips can not be generated for {:?} used by \"{}\"",
+                image_desc.format,
+                debug_name
+            );
+            image_desc.mip_levels = num_uploaded_mips;
+        }
+
         let mut image = Image::new_from_desc(device, image_desc);
 
         image.set_debug_name(device, debug_name);
 
-        device.execute_and_submit(|device, cb| {
+        device.record_upload_commands(|device, cb| {
             crate::synch::image_pipeline_barrier(
                 device,
                 cb,
@@ -52,16 +286,19 @@ impl Texture {
                 vk_sync::AccessType::TransferWrite,
                 true,
             );
+        });
 
-            if let Some(pixels) = pixels {
-                let staging_buffer = Buffer::new(
-                    device,
-                    Some(pixels),
-                    std::mem::size_of_val(pixels) as u64,
-                    vk::BufferUsageFlags::TRANSFER_SRC,
-                    gpu_allocator::MemoryLocation::CpuToGpu,
-                );
-                staging_buffer.copy_to_image(device, cb, &image);
+        for (mip_level, mip) in mips[0..num_uploaded_mips as usize].iter().enumerate() {
+            device.upload(mip, |device, cb, staging_buffer, offset| {
+                staging_buffer.copy_to_image_mip(device, cb, &image, mip_level as u32, offset);
+            });
+        }
+
+        device.record_upload_commands(|device, cb| {
+            let mut prev_access = vk_sync::AccessType::TransferWrite;
+
+            if num_uploaded_mips > 0 && num_uploaded_mips < image.desc.mip_levels {
+                prev_access = Texture::generate_mips(device, cb, &image, num_uploaded_mips);
             }
 
             if Image::is_depth_image_fmt(image.desc.format) {
@@ -75,7 +312,7 @@ impl Texture {
                     device,
                     cb,
                     &image,
-                    vk_sync::AccessType::TransferWrite,
+                    prev_access,
                     vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
                     false,
                 );
@@ -116,4 +353,110 @@ impl Texture {
             descriptor_info,
         }
     }
+
+    /// Note: the texture must not be used by any frames in flight
+    pub fn destroy(self, device: &Device) {
+        unsafe { device.handle.destroy_sampler(self.sampler, None) };
+        self.image.destroy(device);
+    }
+
+    // Downsamples each mip level from the previous one, starting at `first_mip_level`.
+    // Leaves all mip levels readable as transfer source.
+    fn generate_mips(
+        device: &Device,
+        cb: vk::CommandBuffer,
+        image: &Image,
+        first_mip_level: u32,
+    ) -> vk_sync::AccessType {
+        let mip_extent = |mip_level: u32| vk::Offset3D {
+            x: (image.width() >> mip_level).max(1) as i32,
+            y: (image.height() >> mip_level).max(1) as i32,
+            z: 1,
+        };
+
+        let subresource = |mip_level: u32| vk::ImageSubresourceLayers {
+            aspect_mask: image.desc.aspect_flags,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: image.desc.array_layers,
+        };
+
+        // The uploaded mip levels are only read from
+        crate::synch::image_mips_pipeline_barrier(
+            device,
+            cb,
+            image,
+            0,
+            first_mip_level,
+            vk_sync::AccessType::TransferWrite,
+            vk_sync::AccessType::TransferRead,
+        );
+
+        for mip_level in first_mip_level..image.desc.mip_levels {
+            let blit = vk::ImageBlit {
+                src_subresource: subresource(mip_level - 1),
+                src_offsets: [vk::Offset3D::default(), mip_extent(mip_level - 1)],
+                dst_subresource: subresource(mip_level),
+                dst_offsets: [vk::Offset3D::default(), mip_extent(mip_level)],
+            };
+
+            unsafe {
+                device.handle.cmd_blit_image(
+                    cb,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+            }
+
+            crate::synch::image_mips_pipeline_barrier(
+                device,
+                cb,
+                image,
+                mip_level,
+                1,
+                vk_sync::AccessType::TransferWrite,
+                vk_sync::AccessType::TransferRead,
+            );
+        }
+
+        vk_sync::AccessType::TransferRead
+    }
+}
+
+fn srgb_to_linear_u16(value: u16) -> u16 {
+    let value = value as f32 / u16::MAX as f32;
+    let linear = if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    };
+
+    (linear * u16::MAX as f32).round() as u16
+}
+
+// Returns the sRGB variant of a format, formats without one are returned as is
+fn srgb_format(format: vk::Format) -> vk::Format {
+    match format {
+        vk::Format::R8G8B8A8_UNORM => vk::Format::R8G8B8A8_SRGB,
+        vk::Format::B8G8R8A8_UNORM => vk::Format::B8G8R8A8_SRGB,
+        vk::Format::BC1_RGB_UNORM_BLOCK => vk::Format::BC1_RGB_SRGB_BLOCK,
+        vk::Format::BC1_RGBA_UNORM_BLOCK => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        vk::Format::BC2_UNORM_BLOCK => vk::Format::BC2_SRGB_BLOCK,
+        vk::Format::BC3_UNORM_BLOCK => vk::Format::BC3_SRGB_BLOCK,
+        vk::Format::BC7_UNORM_BLOCK => vk::Format::BC7_SRGB_BLOCK,
+        _ => format,
+    }
+}
+
+// Block compressed formats can not be used as storage images or attachments
+fn sampled_image_desc(width: u32, height: u32, format: vk::Format) -> ImageDesc {
+    ImageDesc::new_2d(width, height, format).usage(
+        vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::SAMPLED,
+    )
 }
@@ -0,0 +1,299 @@
+use ash::vk;
+
+use crate::buffer::*;
+use crate::device::*;
+
+// Size of the shared staging allocation of a batch, larger uploads get their own buffer
+const STAGING_BUFFER_SIZE: u64 = 64 * 1024 * 1024;
+
+// Covers the alignment requirements of buffer copies and the texel block sizes
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Identifies a flushed batch of uploads
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct UploadTicket(u64);
+
+struct UploadBatch {
+    id: u64,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    staging_buffer: Option<Buffer>,
+    staging_offset: u64,
+    // Uploads that did not fit in the shared staging buffer
+    dedicated_staging_buffers: Vec<Buffer>,
+}
+
+/// Collects uploads into a shared staging allocation and records their copies into a
+/// single command buffer, which is submitted by `flush` with a fence that can be polled.
+/// Barriers around each batch order the copies with all work submitted before and after
+/// it on the same queue, so the uploaded resources can be used by any later submission.
+pub struct UploadBatcher {
+    command_pool: vk::CommandPool,
+    current: Option<UploadBatch>,
+    in_flight: Vec<UploadBatch>,
+    // Completed batches whose command buffer, fence and staging buffer are reused
+    free_batches: Vec<UploadBatch>,
+    next_id: u64,
+}
+
+impl UploadBatcher {
+    pub fn new(device: &ash::Device, queue_family_index: u32) -> UploadBatcher {
+        let pool_create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&pool_create_info, None)
+                .expect("Failed to create upload command pool")
+        };
+
+        UploadBatcher {
+            command_pool,
+            current: None,
+            in_flight: vec![],
+            free_batches: vec![],
+            next_id: 0,
+        }
+    }
+
+    /// Copies `data` to staging memory and calls `record_copy` with the staging buffer and
+    /// the offset of the data in it, to record the copy to the destination resource
+    pub fn upload<F>(&mut self, device: &Device, data: &[u8], record_copy: F)
+    where
+        F: FnOnce(&Device, vk::CommandBuffer, &Buffer, u64),
+    {
+        let size = data.len() as u64;
+
+        if size > STAGING_BUFFER_SIZE {
+            let mut staging_buffer = Buffer::new(
+                device,
+                Some(data),
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                gpu_allocator::MemoryLocation::CpuToGpu,
+            );
+            staging_buffer.set_debug_name(device, "dedicated_staging_buffer");
+
+            let batch = self.current_batch(device);
+            record_copy(device, batch.command_buffer, &staging_buffer, 0);
+            batch.dedicated_staging_buffers.push(staging_buffer);
+            return;
+        }
+
+        let fits_in_current = self.current.as_ref().is_none_or(|batch| {
+            batch.staging_offset.next_multiple_of(STAGING_ALIGNMENT) + size <= STAGING_BUFFER_SIZE
+        });
+
+        if !fits_in_current {
+            self.flush(device);
+        }
+
+        let batch = self.current_batch(device);
+        let offset = batch.staging_offset.next_multiple_of(STAGING_ALIGNMENT);
+        batch.staging_offset = offset + size;
+
+        let staging_buffer = batch.staging_buffer.get_or_insert_with(|| {
+            let mut staging_buffer = Buffer::create_buffer(
+                device,
+                STAGING_BUFFER_SIZE,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                gpu_allocator::MemoryLocation::CpuToGpu,
+            );
+            staging_buffer.set_debug_name(device, "upload_staging_buffer");
+            staging_buffer
+        });
+
+        unsafe {
+            let dst = staging_buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), dst.add(offset as usize), data.len());
+        }
+
+        record_copy(device, batch.command_buffer, staging_buffer, offset);
+    }
+
+    /// Records commands that do not need any staging data, e.g layout transitions
+    pub fn record<F: FnOnce(&Device, vk::CommandBuffer)>(&mut self, device: &Device, record: F) {
+        let batch = self.current_batch(device);
+        record(device, batch.command_buffer);
+    }
+
+    /// Submits the recorded uploads, returns None if nothing has been recorded
+    pub fn flush(&mut self, device: &Device) -> Option<UploadTicket> {
+        let batch = self.current.take()?;
+
+        unsafe {
+            // Makes the uploads visible to everything that is submitted after the batch
+            Self::memory_barrier(
+                device,
+                batch.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            );
+
+            device
+                .handle
+                .end_command_buffer(batch.command_buffer)
+                .expect("End upload command buffer failed");
+
+            let submit_info = vk::SubmitInfo::builder()
+                .command_buffers(std::slice::from_ref(&batch.command_buffer));
+
+            device
+                .handle
+                .queue_submit(device.queue, &[submit_info.build()], batch.fence)
+                .expect("Upload queue submit failed");
+        }
+
+        let ticket = UploadTicket(batch.id);
+        self.in_flight.push(batch);
+
+        Some(ticket)
+    }
+
+    /// Returns true when the batch of `ticket` has finished executing on the GPU
+    pub fn is_complete(&mut self, device: &Device, ticket: UploadTicket) -> bool {
+        self.recycle_completed_batches(device);
+
+        let is_recording = self
+            .current
+            .as_ref()
+            .is_some_and(|batch| batch.id == ticket.0);
+
+        !is_recording && !self.in_flight.iter().any(|batch| batch.id == ticket.0)
+    }
+
+    pub fn wait(&mut self, device: &Device, ticket: UploadTicket) {
+        if let Some(batch) = self.in_flight.iter().find(|batch| batch.id == ticket.0) {
+            unsafe {
+                device
+                    .handle
+                    .wait_for_fences(&[batch.fence], true, u64::MAX)
+                    .expect("Waiting for upload fence failed");
+            }
+        }
+
+        self.recycle_completed_batches(device);
+    }
+
+    fn recycle_completed_batches(&mut self, device: &Device) {
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            let completed = unsafe {
+                device
+                    .handle
+                    .get_fence_status(self.in_flight[i].fence)
+                    .unwrap_or(false)
+            };
+
+            if completed {
+                let mut batch = self.in_flight.swap_remove(i);
+
+                for staging_buffer in batch.dedicated_staging_buffers.drain(..) {
+                    staging_buffer.destroy(device);
+                }
+
+                self.free_batches.push(batch);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn current_batch(&mut self, device: &Device) -> &mut UploadBatch {
+        if self.current.is_none() {
+            self.recycle_completed_batches(device);
+
+            let mut batch = match self.free_batches.pop() {
+                Some(batch) => batch,
+                None => self.create_batch(device),
+            };
+
+            batch.id = self.next_id;
+            batch.staging_offset = 0;
+            self.next_id += 1;
+
+            unsafe {
+                device
+                    .handle
+                    .reset_fences(&[batch.fence])
+                    .expect("Reset upload fence failed");
+
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+                device
+                    .handle
+                    .begin_command_buffer(batch.command_buffer, &begin_info)
+                    .expect("Begin upload command buffer failed");
+
+                // Resources may be overwritten that are still used by earlier submissions
+                Self::memory_barrier(
+                    device,
+                    batch.command_buffer,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                );
+            }
+
+            self.current = Some(batch);
+        }
+
+        self.current.as_mut().unwrap()
+    }
+
+    fn create_batch(&self, device: &Device) -> UploadBatch {
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+
+        unsafe {
+            let command_buffer = device
+                .handle
+                .allocate_command_buffers(&command_buffer_allocate_info)
+                .expect("Failed to allocate upload command buffer")[0];
+
+            let fence = device
+                .handle
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .expect("Failed to create upload fence");
+
+            UploadBatch {
+                id: 0,
+                command_buffer,
+                fence,
+                staging_buffer: None,
+                staging_offset: 0,
+                dedicated_staging_buffers: vec![],
+            }
+        }
+    }
+
+    unsafe fn memory_barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        src_stage_mask: vk::PipelineStageFlags,
+        src_access_mask: vk::AccessFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) {
+        let memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .build();
+
+        device.handle.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        );
+    }
+}
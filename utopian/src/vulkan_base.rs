@@ -249,9 +249,23 @@ impl VulkanBase {
         u32,
     ) {
         unsafe {
-            let surface_format = surface_loader
+            let surface_formats = surface_loader
                 .get_physical_device_surface_formats(physical_device, surface)
-                .expect("Error getting device surface formats")[0];
+                .expect("Error getting device surface formats");
+
+            // Rendering is linear and the present pass encodes to sRGB, so an
+            // sRGB swapchain format would apply the encoding twice
+            let surface_format = surface_formats
+                .iter()
+                .find(|surface_format| {
+                    surface_format.format == vk::Format::B8G8R8A8_UNORM
+                        || surface_format.format == vk::Format::R8G8B8A8_UNORM
+                })
+                .copied()
+                .unwrap_or_else(|| {
+                    log::warn!("No UNORM swapchain format, using {:?}", surface_formats[0]);
+                    surface_formats[0]
+                });
 
             let surface_capabilities = surface_loader
                 .get_physical_device_surface_capabilities(physical_device, surface)
@@ -479,6 +493,9 @@ impl VulkanBase {
     }
 
     pub fn submit_commands(&self, frame_index: usize) {
+        // Uploads queued during the frame are submitted ahead of the frame that uses them
+        self.device.flush_uploads();
+
         unsafe {
             puffin::profile_scope!("queue_submit");
 